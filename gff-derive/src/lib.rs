@@ -18,83 +18,656 @@ impl syn::parse::Parse for GFFStructId {
     }
 }
 
-#[proc_macro_derive(GFFStruct, attributes(GFFStructId))]
+/// How a struct field is mapped to a GFF field
+enum FieldKind<'a> {
+    /// The GFF field must be present
+    Required,
+    /// `#[gff(default)]`: use `Default::default()` if the GFF field is missing
+    Default,
+    /// `Option<T>`: omitted when `None`, and `None` if the GFF field is missing
+    Optional(&'a syn::Type),
+    /// `#[gff(skip)]`: never packed, always `Default::default()`
+    Skip,
+    /// `#[gff(struct_id_field)]`: holds the struct type id instead of a GFF field
+    StructId,
+}
+
+/// A named struct field, with its `#[gff(...)]` attributes
+struct GffField<'a> {
+    ident: &'a syn::Ident,
+    ty: &'a syn::Type,
+    label: String,
+    kind: FieldKind<'a>,
+}
+
+impl<'a> GffField<'a> {
+    /// Type of the GFF field value (`T` for an `Option<T>`)
+    fn value_ty(&self) -> &'a syn::Type {
+        match self.kind {
+            FieldKind::Optional(inner) => inner,
+            _ => self.ty,
+        }
+    }
+
+    /// Whether the GFF field value is a `Box<T>`
+    ///
+    /// `TryFrom`/`TryInto` cannot be implemented for `Box<T>`, so it is
+    /// converted through `T`.
+    fn boxed(&self) -> bool {
+        wrapped_type(self.value_ty(), "Box").is_some()
+    }
+}
+
+/// Get `T` from a `Wrapper<T>` type, e.g. `Option<T>`
+fn wrapped_type<'a>(ty: &'a syn::Type, wrapper: &str) -> Option<&'a syn::Type> {
+    let path = match ty {
+        syn::Type::Path(type_path) if type_path.qself.is_none() => &type_path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => {
+            match &args.args[0] {
+                syn::GenericArgument::Type(inner) => Some(inner),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Parse named fields and their `#[gff(...)]` attributes
+///
+/// Supported attributes are `rename = "Label"`, `default`, `skip` and
+/// `struct_id_field`. Labels longer than 16 bytes are rejected.
+fn parse_fields(named_fields: &syn::FieldsNamed) -> syn::Result<Vec<GffField<'_>>> {
+    let fields = named_fields.named.iter().map(|field| {
+        let ident = field.ident.as_ref().unwrap();
+        let mut label = ident.to_string();
+        let mut label_span = ident.span();
+        let mut default = false;
+        let mut skip = false;
+        let mut struct_id_field = false;
+
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("gff")) {
+            let list = match attr.parse_meta()? {
+                syn::Meta::List(list) => list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected #[gff(...)]")),
+            };
+            for nested in list.nested {
+                match nested {
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                        match &nv.lit {
+                            syn::Lit::Str(lit) => {
+                                label = lit.value();
+                                label_span = lit.span();
+                            },
+                            lit => return Err(syn::Error::new_spanned(lit, "expected a string")),
+                        }
+                    },
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("default") => {
+                        default = true;
+                    },
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("skip") => {
+                        skip = true;
+                    },
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("struct_id_field") => {
+                        struct_id_field = true;
+                    },
+                    nested => return Err(syn::Error::new_spanned(nested, "unknown gff attribute")),
+                }
+            }
+        }
+
+        if !skip && !struct_id_field && label.len() > 16 {
+            return Err(syn::Error::new(label_span,
+                format!("GFF label \"{}\" is longer than 16 bytes", label)));
+        }
+        let kind = if skip {
+            FieldKind::Skip
+        } else if struct_id_field {
+            FieldKind::StructId
+        } else if let Some(inner) = wrapped_type(&field.ty, "Option") {
+            FieldKind::Optional(inner)
+        } else if default {
+            FieldKind::Default
+        } else {
+            FieldKind::Required
+        };
+        Ok(GffField { ident, ty: &field.ty, label, kind })
+    }).collect::<syn::Result<Vec<_>>>()?;
+
+    let mut struct_id_fields = fields.iter().filter(|field| matches!(field.kind, FieldKind::StructId));
+    if let (Some(_), Some(field)) = (struct_id_fields.next(), struct_id_fields.next()) {
+        return Err(syn::Error::new_spanned(field.ident, "duplicate #[gff(struct_id_field)]"));
+    }
+    Ok(fields)
+}
+
+/// Struct type id handling for a struct, from its attributes
+struct StructId<'a> {
+    /// `#[GFFStructId(id)]`, or `0xFFFFFFFF`
+    id: proc_macro2::TokenStream,
+    /// `#[gff(check_struct_id)]`: the parsed id must match `id`
+    check: bool,
+    /// `#[gff(struct_id_field)]` field, which overrides `id`
+    field: Option<&'a syn::Ident>,
+}
+
+impl<'a> StructId<'a> {
+    fn new(attrs: &[syn::Attribute], fields: &[GffField<'a>]) -> syn::Result<Self> {
+        let id = match struct_id(attrs) {
+            Some(id) => { let id = id?; quote! { #id } },
+            None => quote! { 0xFFFFFFFF },
+        };
+        let check = has_gff_flag(attrs, "check_struct_id")?;
+        let field = fields.iter()
+            .find(|field| matches!(field.kind, FieldKind::StructId))
+            .map(|field| field.ident);
+        if let (true, Some(field)) = (check, field) {
+            return Err(syn::Error::new_spanned(field,
+                "#[gff(struct_id_field)] cannot be used with #[gff(check_struct_id)]"));
+        }
+        Ok(StructId { id, check, field })
+    }
+
+    /// Struct type id of `self`
+    fn value(&self) -> proc_macro2::TokenStream {
+        match self.field {
+            Some(ident) => quote! { self.#ident },
+            None => self.id.clone(),
+        }
+    }
+
+    /// Statement checking a parsed struct type id, if requested
+    fn check(&self, st_type: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        if !self.check {
+            return quote! {};
+        }
+        let id = &self.id;
+        quote! {
+            if #st_type != #id {
+                return Err(::gff::error::DataError::new(
+                    ::gff::error::DataErrorKind::UnexpectedStructType {
+                        expected: #id,
+                        found: #st_type,
+                    }));
+            }
+        }
+    }
+}
+
+/// Get the `#[GFFStructId(id)]` attribute, if any
+fn struct_id(attrs: &[syn::Attribute]) -> Option<syn::Result<syn::LitInt>> {
+    let attribute = attrs.iter().find(
+        |a| a.path.segments.len() == 1 && a.path.segments[0].ident == "GFFStructId"
+    )?;
+    Some(syn::parse2(attribute.tokens.clone()).map(|id: GFFStructId| id.0))
+}
+
+/// Check for a `#[gff(flag)]` attribute, `flag` being the only one allowed
+fn has_gff_flag(attrs: &[syn::Attribute], flag: &str) -> syn::Result<bool> {
+    let mut found = false;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("gff")) {
+        let list = match attr.parse_meta()? {
+            syn::Meta::List(list) => list,
+            meta => return Err(syn::Error::new_spanned(meta, "expected #[gff(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident(flag) => found = true,
+                nested => return Err(syn::Error::new_spanned(nested, "unknown gff attribute")),
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Compile error for a derive input of the wrong kind
+fn unsupported(ident: &syn::Ident, msg: &str) -> TokenStream {
+    TokenStream::from(syn::Error::new_spanned(ident, msg).to_compile_error())
+}
+
+/// Field initializers reading each field from the `s` GffStruct
+fn deserialize_fields(fields: &[GffField]) -> Vec<proc_macro2::TokenStream> {
+    fields.iter().map(|field| {
+        let ident = field.ident;
+        let label = &field.label;
+        let mut convert = quote! {
+            std::convert::TryFrom::try_from(value)
+                .map_err(|e: ::gff::error::DataError| e.at_label(#label))?
+        };
+        if field.boxed() {
+            convert = quote! { Box::new(#convert) };
+        }
+        match field.kind {
+            FieldKind::Required => quote! {
+                #ident: {
+                    let value = s.fields.get(#label)
+                        .ok_or_else(|| ::gff::error::DataError::new(
+                            ::gff::error::DataErrorKind::MissingField
+                        ).at_label(#label))?;
+                    #convert
+                }
+            },
+            FieldKind::Default => quote! {
+                #ident: match s.fields.get(#label) {
+                    Some(value) => #convert,
+                    None => std::default::Default::default(),
+                }
+            },
+            FieldKind::Optional(_) => quote! {
+                #ident: match s.fields.get(#label) {
+                    Some(value) => Some(#convert),
+                    None => None,
+                }
+            },
+            FieldKind::Skip => quote! {
+                #ident: std::default::Default::default()
+            },
+            FieldKind::StructId => quote! {
+                #ident: s.st_type
+            },
+        }
+    }).collect()
+}
+
+/// Statements building a GffStruct with id `struct_id` from each field
+///
+/// `value` gives a reference to the value of a field.
+fn serialize_fields(
+    fields: &[GffField],
+    struct_id: &proc_macro2::TokenStream,
+    value: impl Fn(&syn::Ident) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let inserts = fields.iter().map(|field| {
+        let value = value(field.ident);
+        let label = &field.label;
+        let value_ref = if field.boxed() { quote! { &**value } } else { quote! { value } };
+        let convert = quote! {
+            std::convert::TryInto::<::gff::common::GffFieldValue>::try_into(#value_ref)
+                .map_err(|e: ::gff::error::DataError| e.at_label(#label))?
+        };
+        match field.kind {
+            FieldKind::Required | FieldKind::Default => quote! {
+                let value = #value;
+                fields.insert(#label.to_string(), #convert);
+            },
+            FieldKind::Optional(_) => quote! {
+                if let Some(value) = #value {
+                    fields.insert(#label.to_string(), #convert);
+                }
+            },
+            FieldKind::Skip | FieldKind::StructId => quote! {},
+        }
+    });
+    quote! {
+        #[allow(unused_mut)]
+        let mut fields = ::gff::common::IndexMap::new();
+        #( #inserts )*
+        Ok(::gff::common::GffStruct {
+            st_type: #struct_id,
+            fields,
+        })
+    }
+}
+
+/// Derive `Serialize`/`Deserialize` for a struct with named fields,
+/// or for an enum of structs.
+///
+/// The struct type id is given with `#[GFFStructId(id)]`, and defaults
+/// to `0xFFFFFFFF`. It is ignored when deserializing, unless the struct
+/// is marked `#[gff(check_struct_id)]`.
+///
+/// Fields accept the following attributes:
+/// - `#[gff(rename = "Label")]`: use another GFF label than the field name
+/// - `#[gff(default)]`: use `Default::default()` when the label is missing
+/// - `#[gff(skip)]`: never serialize, always use `Default::default()`
+/// - `#[gff(struct_id_field)]`: a `u32` field holding the struct type id,
+///   used instead of `#[GFFStructId(id)]`
+///
+/// `Option<T>` fields are omitted when `None`, and `None` when missing.
+/// `Box<T>` fields are converted as `T`.
+///
+/// For an enum, each variant carries its own `#[GFFStructId(id)]`,
+/// and the variant is chosen from the struct type id. A variant can have
+/// named fields, no fields, or wrap a type implementing
+/// `Serialize`/`Deserialize`. A `#[gff(other)]` variant holding a
+/// `GffStruct` keeps structs with an unknown id, otherwise they are
+/// an error. The enum itself takes no attribute:
+///
+/// ```compile_fail
+/// #[derive(gff_derive::GFFStruct)]
+/// #[GFFStructId(5)]
+/// enum Kind {
+///     #[GFFStructId(5)]
+///     Door,
+/// }
+/// ```
+///
+/// These attributes are also used by `GFFStructPack` and `GFFStructUnpack`.
+/// Labels longer than 16 bytes are rejected:
+///
+/// ```compile_fail
+/// #[derive(gff_derive::GFFStruct)]
+/// #[GFFStructId(0)]
+/// struct Creature {
+///     #[gff(rename = "ThisLabelIsTooLong")]
+///     label: u8,
+/// }
+/// ```
+///
+/// Unknown attributes are rejected too:
+///
+/// ```compile_fail
+/// #[derive(gff_derive::GFFStruct)]
+/// #[gff(check_struct_ids)]
+/// struct Creature {
+///     label: u8,
+/// }
+/// ```
+#[proc_macro_derive(GFFStruct, attributes(GFFStructId, gff))]
 pub fn derive_gff_struct(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
 
-    // parse attribute GFFStructId
-    let attribute = input.attrs.iter().find(
-        |a| a.path.segments.len() == 1 && a.path.segments[0].ident == "GFFStructId"
-    ).expect("GFFStructId attribute required for deriving GFFStruct");
-    let parameters: GFFStructId = syn::parse2(attribute.tokens.clone())
-        .expect("Invalid GFFStructId attribute!");
+    let struct_name = &input.ident;
+    let body = match &input.data {
+        syn::Data::Enum(data_enum) => derive_gff_enum_struct(struct_name, &input.attrs, data_enum),
+        syn::Data::Union(_) =>
+            Err(syn::Error::new_spanned(struct_name, "expected a struct or an enum, got a union")),
+        syn::Data::Struct(data_struct) => {
+            match &data_struct.fields {
+                syn::Fields::Unnamed(_) =>
+                    Err(syn::Error::new_spanned(struct_name, "expected named fields, got unnamed ones")),
+                syn::Fields::Unit =>
+                    Err(syn::Error::new_spanned(struct_name, "expected named fields, got a unit struct")),
+                syn::Fields::Named(named_fields) => {
+                    parse_fields(named_fields).and_then(|fields| {
+                        let struct_id = StructId::new(&input.attrs, &fields)?;
+                        let check_struct_id = struct_id.check(quote! { s.st_type });
+                        let deserialize_fields = deserialize_fields(&fields);
+                        let serialize = serialize_fields(&fields, &struct_id.value(),
+                            |ident| quote! { &self.#ident });
+                        Ok(quote! {
+                            impl ::gff::common::Deserialize for #struct_name {
+                                fn deserialize(s: &::gff::common::GffStruct)
+                                    -> Result<Self, ::gff::error::DataError> where Self: std::marker::Sized {
+                                    #check_struct_id
+                                    Ok(#struct_name {
+                                        #( #deserialize_fields ),*
+                                    })
+                                }
+                            }
+                            impl ::gff::common::Serialize for #struct_name {
+                                fn serialize(&self) -> Result<::gff::common::GffStruct, ::gff::error::DataError> {
+                                    #serialize
+                                }
+                            }
+                        })
+                    })
+                }
+            }
+        }
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(err) => return TokenStream::from(err.to_compile_error()),
+    };
+
+    // Build the output, possibly using quasi-quotation
+    let expanded = quote! {
+        #body
+
+        /* deserializing from GffStruct to custom structure. */
+        impl std::convert::TryFrom<&::gff::common::GffFieldValue> for #struct_name {
+            type Error = ::gff::error::DataError;
+
+            fn try_from(value: &::gff::common::GffFieldValue) -> Result<Self, Self::Error> {
+                match value {
+                    ::gff::common::GffFieldValue::Struct(s) =>
+                        ::gff::common::Deserialize::deserialize(s),
+                    _ => Err(::gff::error::DataError::unexpected_type(
+                        ::gff::common::GffFieldType::Struct, value)),
+                }
+            }
+        }
+
+        /* serializing from custom structure to GffStruct. */
+        impl std::convert::TryInto<::gff::common::GffFieldValue> for &#struct_name {
+            type Error = ::gff::error::DataError;
+
+            fn try_into(self) -> Result<::gff::common::GffFieldValue, Self::Error> {
+                Ok(::gff::common::GffFieldValue::Struct(::gff::common::Serialize::serialize(self)?))
+            }
+        }
+    };
+
+    // Hand the output tokens back to the compiler
+    TokenStream::from(expanded)
+}
+
+/// `Serialize`/`Deserialize` for an enum, dispatching on the struct type id
+fn derive_gff_enum_struct(enum_name: &syn::Ident, attrs: &[syn::Attribute], data_enum: &syn::DataEnum)
+    -> syn::Result<proc_macro2::TokenStream>
+{
+    /* ids are given and checked per variant, the enum itself takes no attribute */
+    if let Some(attr) = attrs.iter().find(|a| a.path.is_ident("GFFStructId")) {
+        return Err(syn::Error::new_spanned(attr,
+            "GFFStructId attribute expected on each variant, not on the enum"));
+    }
+    if let Some(attr) = attrs.iter().find(|a| a.path.is_ident("gff")) {
+        return Err(syn::Error::new_spanned(attr, "unknown gff attribute"));
+    }
+
+    let mut deserialize_arms = vec![];
+    let mut serialize_arms = vec![];
+    let mut other = None;
+
+    for variant in &data_enum.variants {
+        let ident = &variant.ident;
+        if has_gff_flag(&variant.attrs, "other")? {
+            match &variant.fields {
+                syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {},
+                _ => return Err(syn::Error::new_spanned(variant,
+                    "expected a #[gff(other)] variant holding a GffStruct")),
+            }
+            if other.is_some() {
+                return Err(syn::Error::new_spanned(variant, "duplicate #[gff(other)] variant"));
+            }
+            other = Some(ident);
+            serialize_arms.push(quote! {
+                #enum_name::#ident(s) => Ok(s.clone()),
+            });
+            continue;
+        }
+
+        let struct_id = struct_id(&variant.attrs).unwrap_or_else(|| Err(syn::Error::new_spanned(
+            variant, "GFFStructId attribute required on each variant")))?;
+        match &variant.fields {
+            syn::Fields::Named(named_fields) => {
+                let fields = parse_fields(named_fields)?;
+                if let Some(field) = fields.iter().find(|field| matches!(field.kind, FieldKind::StructId)) {
+                    return Err(syn::Error::new_spanned(field.ident,
+                        "#[gff(struct_id_field)] is not supported in enum variants"));
+                }
+                let deserialize_fields = deserialize_fields(&fields);
+                let serialize = serialize_fields(&fields, &quote! { #struct_id },
+                    |ident| quote! { #ident });
+                let idents = fields.iter()
+                    .filter(|field| !matches!(field.kind, FieldKind::Skip))
+                    .map(|field| field.ident);
+                deserialize_arms.push(quote! {
+                    #struct_id => Ok(#enum_name::#ident {
+                        #( #deserialize_fields ),*
+                    }),
+                });
+                serialize_arms.push(quote! {
+                    #enum_name::#ident { #( #idents, )* .. } => {
+                        #serialize
+                    },
+                });
+            },
+            syn::Fields::Unit => {
+                deserialize_arms.push(quote! {
+                    #struct_id => Ok(#enum_name::#ident),
+                });
+                serialize_arms.push(quote! {
+                    #enum_name::#ident => Ok(::gff::common::GffStruct {
+                        st_type: #struct_id,
+                        fields: ::gff::common::IndexMap::new(),
+                    }),
+                });
+            },
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                deserialize_arms.push(quote! {
+                    #struct_id => Ok(#enum_name::#ident(::gff::common::Deserialize::deserialize(s)?)),
+                });
+                serialize_arms.push(quote! {
+                    #enum_name::#ident(value) => {
+                        let mut s = ::gff::common::Serialize::serialize(value)?;
+                        s.st_type = #struct_id;
+                        Ok(s)
+                    },
+                });
+            },
+            syn::Fields::Unnamed(_) => return Err(syn::Error::new_spanned(variant,
+                "expected named fields, no fields, or a single unnamed field")),
+        }
+    }
+
+    let fallback = match other {
+        Some(ident) => quote! { _ => Ok(#enum_name::#ident(s.clone())), },
+        None => quote! {
+            st_type => Err(::gff::error::DataError::new(
+                ::gff::error::DataErrorKind::UnknownStructType(st_type))),
+        },
+    };
+
+    Ok(quote! {
+        impl ::gff::common::Deserialize for #enum_name {
+            fn deserialize(s: &::gff::common::GffStruct)
+                -> Result<Self, ::gff::error::DataError> where Self: std::marker::Sized {
+                match s.st_type {
+                    #( #deserialize_arms )*
+                    #fallback
+                }
+            }
+        }
+        impl ::gff::common::Serialize for #enum_name {
+            fn serialize(&self) -> Result<::gff::common::GffStruct, ::gff::error::DataError> {
+                match self {
+                    #( #serialize_arms )*
+                }
+            }
+        }
+    })
+}
+
+/// Derive `PackField`/`PackStruct`, to pack a struct with named fields
+/// without building a `GffStruct` first.
+///
+/// The attributes are the ones of `GFFStruct`. Enums are not supported:
+///
+/// ```compile_fail
+/// #[derive(gff_derive::GFFStructPack)]
+/// enum Kind {
+///     Door,
+/// }
+/// ```
+#[proc_macro_derive(GFFStructPack, attributes(GFFStructId, gff))]
+pub fn derive_gff_struct_pack(input: TokenStream) -> TokenStream {
+    // Parse the input tokens into a syntax tree
+    let input = parse_macro_input!(input as DeriveInput);
 
     let struct_name = &input.ident;
+    let attrs = input.attrs;
     let input = input.data;
-    let struct_id = parameters.0;
 
     match input {
-        syn::Data::Enum(_) => { panic!("Expected struct, got enum"); }
-        syn::Data::Union(_) => { panic!("Expected struct, got union"); }
+        syn::Data::Enum(_) => unsupported(struct_name, "expected a struct, got an enum"),
+        syn::Data::Union(_) => unsupported(struct_name, "expected a struct, got a union"),
         syn::Data::Struct(data_struct) => {
             match data_struct.fields {
-                syn::Fields::Unnamed(_) => { panic!("Expected named fields, got unnamed"); }
-                syn::Fields::Unit => { panic!("Expected named fields, got unit"); }
+                syn::Fields::Unnamed(_) => unsupported(struct_name, "expected named fields, got unnamed ones"),
+                syn::Fields::Unit => unsupported(struct_name, "expected named fields, got a unit struct"),
                 syn::Fields::Named(named_fields) => {
-                    let fields : Vec<&syn::Ident> = named_fields.named.iter().map(|field| {
-                        field.ident.as_ref().unwrap()
-                    }).collect();
-                    let keys : Vec<String> = fields.iter().map(|ident| {
-                        ident.to_string()
-                    }).collect();
+                    let fields = match parse_fields(&named_fields) {
+                        Ok(fields) => fields,
+                        Err(err) => return TokenStream::from(err.to_compile_error()),
+                    };
+                    let struct_id = match StructId::new(&attrs, &fields) {
+                        Ok(struct_id) => struct_id.value(),
+                        Err(err) => return TokenStream::from(err.to_compile_error()),
+                    };
 
-                    // Build the output, possibly using quasi-quotation
-                    let expanded = quote! {
-                        /* deserializing from GffStruct to custom structure. */
-                        impl std::convert::TryFrom<&GffFieldValue> for #struct_name {
-                            type Error = &'static str;
-
-                            fn try_from(value: &GffFieldValue) -> Result<Self, self::Error> {
-                                match value {
-                                    ::gff::common::GffFieldValue::Struct(s) => 
-                                        ::gff::common::Deserialize::deserialize(s),
-                                    _ => Err("Expected Struct"),
-                                }
-                            }
+                    let field_counts = fields.iter().filter_map(|field| {
+                        let ident = field.ident;
+                        match field.kind {
+                            FieldKind::Required | FieldKind::Default => Some(quote! { 1 }),
+                            FieldKind::Optional(_) => Some(quote! { self.#ident.is_some() as u32 }),
+                            FieldKind::Skip | FieldKind::StructId => None,
                         }
-                        impl ::gff::common::Deserialize for #struct_name {
-                            fn deserialize(s: &::gff::common::GffStruct)
-                                -> Result<Self, &'static str> where Self: std::marker::Sized {
-                                Ok(#struct_name {
-                                    #(
-                                        #fields : std::convert::TryFrom::try_from(
-                                            s.fields.get(#keys)
-                                                    .ok_or("key not found")?
-                                        )?
-                                    ),*
-                                })
-                            }
+                    });
+
+                    let pack_fields = fields.iter().map(|field| {
+                        let ident = field.ident;
+                        let label = &field.label;
+                        let pack = quote! {
+                            field_indices.push(::gff::packer::PackField::pack_field(
+                                value, #label, packer, structs, st_idx
+                            ).map_err(|e: ::gff::error::DataError| e.at_label(#label))?);
+                        };
+                        match field.kind {
+                            FieldKind::Required | FieldKind::Default => quote! {
+                                let value = &self.#ident;
+                                #pack
+                            },
+                            FieldKind::Optional(_) => quote! {
+                                if let Some(value) = &self.#ident {
+                                    #pack
+                                }
+                            },
+                            FieldKind::Skip | FieldKind::StructId => quote! {},
                         }
+                    });
 
-                        /* serializing from custom structure to GffStruct. */
-                        impl ::gff::common::Serialize for #struct_name {
-                            fn serialize(&self) -> Result<GffStruct, &'static str> {
-                                Ok(GffStruct {
-                                    st_type: #struct_id,
-                                    fields: HashMap::from([
-                                        #(
-                                            (#keys.to_string(), (&self.#fields).try_into()?)
-                                        ),*
-                                    ])
-                                })
+                    // Build the output, possibly using quasi-quotation
+                    let expanded = quote! {
+                        impl<'a, W: std::io::Write> ::gff::packer::PackField<'a, W> for #struct_name {
+                            fn pack_field(&'a self, label: &str, packer: &mut ::gff::packer::Packer<W>,
+                                structs: &mut ::gff::packer::StructQueue<'a, W>, st_idx: &mut u32)
+                                -> Result<u32, ::gff::error::DataError>
+                            {
+                                let label_idx = packer.pack_label(label)?;
+
+                                *st_idx += 1;
+                                let field_idx = packer.pack_val_4(14, label_idx, &(*st_idx).to_le_bytes());
+                                structs.push(self, label);
+                                Ok(field_idx)
                             }
                         }
-                        impl std::convert::TryInto<GffFieldValue> for &#struct_name {
-                            type Error = &'static str;
 
-                            fn try_into(self) -> Result<GffFieldValue, self::Error> {
-                                Ok(GffFieldValue::Struct(::gff::common::Serialize::serialize(self)?))
+                        /* packing custom structure without intermediary representation. */
+                        impl<'a, W: std::io::Write> ::gff::packer::PackStruct<'a, W> for #struct_name {
+                            fn pack(&'a self, packer: &mut ::gff::packer::Packer<W>,
+                                structs: &mut ::gff::packer::StructQueue<'a, W>,
+                                st_idx: &mut u32)
+                                -> Result<(), ::gff::error::DataError>
+                            {
+                                let field_count: u32 = 0 #( + #field_counts )*;
+                                // pack struct id, field indices / field id, field count
+                                packer.begin_struct(#struct_id as u32, field_count);
+                                // pack fields
+                                #[allow(unused_mut)]
+                                let mut field_indices: Vec<u32> = Vec::with_capacity(field_count as usize);
+                                #( #pack_fields )*
+                                // pack field indices
+                                packer.end_struct(&field_indices);
+                                Ok(())
                             }
                         }
                     };
@@ -107,91 +680,111 @@ pub fn derive_gff_struct(input: TokenStream) -> TokenStream {
     }
 }
 
-#[proc_macro_derive(GFFStructPack, attributes(GFFStructId))]
-pub fn derive_gff_struct_pack(input: TokenStream) -> TokenStream {
+/// Derive `UnpackField`/`UnpackStruct`, to unpack a struct with named
+/// fields without building a `GffStruct` first.
+///
+/// The attributes are the ones of `GFFStruct`. Enums are not supported:
+///
+/// ```compile_fail
+/// #[derive(gff_derive::GFFStructUnpack)]
+/// enum Kind {
+///     Door,
+/// }
+/// ```
+#[proc_macro_derive(GFFStructUnpack, attributes(GFFStructId, gff))]
+pub fn derive_gff_struct_unpack(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
 
-    // parse attribute GFFStructId
-    let attribute = input.attrs.iter().find(
-        |a| a.path.segments.len() == 1 && a.path.segments[0].ident == "GFFStructId"
-    ).expect("GFFStructId attribute required for deriving GFFStruct");
-    let parameters: GFFStructId = syn::parse2(attribute.tokens.clone())
-        .expect("Invalid GFFStructId attribute!");
-
     let struct_name = &input.ident;
+    let attrs = input.attrs;
     let input = input.data;
-    let struct_id = parameters.0;
 
     match input {
-        syn::Data::Enum(_) => { panic!("Expected struct, got enum"); }
-        syn::Data::Union(_) => { panic!("Expected struct, got union"); }
+        syn::Data::Enum(_) => unsupported(struct_name, "expected a struct, got an enum"),
+        syn::Data::Union(_) => unsupported(struct_name, "expected a struct, got a union"),
         syn::Data::Struct(data_struct) => {
-            let field_count = data_struct.fields.len();
             match data_struct.fields {
-                syn::Fields::Unnamed(_) => { panic!("Expected named fields, got unnamed"); }
-                syn::Fields::Unit => { panic!("Expected named fields, got unit"); }
+                syn::Fields::Unnamed(_) => unsupported(struct_name, "expected named fields, got unnamed ones"),
+                syn::Fields::Unit => unsupported(struct_name, "expected named fields, got a unit struct"),
                 syn::Fields::Named(named_fields) => {
-                    let fields : Vec<&syn::Ident> = named_fields.named.iter().map(|field| {
-                        field.ident.as_ref().unwrap()
-                    }).collect();
-                    let keys : Vec<String> = fields.iter().map(|ident| {
-                        ident.to_string()
-                    }).collect();
-
-                    let pack_field_idx = match field_count {
-                        0..=1 => quote! {
-                            packer.data.structs.extend_from_slice(
-                                &(packer.data.header.fields.1 as u32).to_le_bytes()
-                            );
-                        },
-                        _ => quote! {
-                            packer.data.structs.extend_from_slice(
-                                &(packer.data.header.field_indices.1 as u32).to_le_bytes()
-                            );
-                        },
+                    let fields = match parse_fields(&named_fields) {
+                        Ok(fields) => fields,
+                        Err(err) => return TokenStream::from(err.to_compile_error()),
                     };
+                    let check_struct_id = match StructId::new(&attrs, &fields) {
+                        Ok(struct_id) => struct_id.check(quote! { reader.st_type() }),
+                        Err(err) => return TokenStream::from(err.to_compile_error()),
+                    };
+                    let unpacked : Vec<(&GffField, syn::Ident)> = fields.iter()
+                        .filter(|field| !matches!(field.kind, FieldKind::Skip | FieldKind::StructId))
+                        .enumerate()
+                        .map(|(i, field)| (field, format_ident!("field_{}", i)))
+                        .collect();
+
+                    let declare_values = unpacked.iter().map(|(field, value)| {
+                        let ty = field.value_ty();
+                        quote! { let mut #value: Option<#ty> = None; }
+                    });
+                    let unpack_values = unpacked.iter().map(|(field, value)| {
+                        let ty = field.value_ty();
+                        let label = &field.label;
+                        quote! {
+                            #label => #value = Some(
+                                <#ty as ::gff::parser::UnpackField>::unpack_field(field)
+                                    .map_err(|e: ::gff::error::DataError| e.at_label(#label))?
+                            ),
+                        }
+                    });
+                    let mut values = unpacked.iter().map(|(_, value)| value);
+                    let build_fields = fields.iter().map(|field| {
+                        let ident = field.ident;
+                        let label = &field.label;
+                        match field.kind {
+                            FieldKind::Skip => return quote! { #ident: std::default::Default::default() },
+                            FieldKind::StructId => return quote! { #ident: reader.st_type() },
+                            _ => {},
+                        }
+                        let value = values.next().unwrap();
+                        match field.kind {
+                            FieldKind::Required => quote! {
+                                #ident: #value.ok_or_else(|| ::gff::error::DataError::new(
+                                    ::gff::error::DataErrorKind::MissingField
+                                ).at_label(#label))?
+                            },
+                            FieldKind::Default => quote! { #ident: #value.unwrap_or_default() },
+                            _ => quote! { #ident: #value },
+                        }
+                    }).collect::<Vec<_>>();
 
                     // Build the output, possibly using quasi-quotation
                     let expanded = quote! {
-                        impl <'a, W: std::io::Write> gff::packer::PackField<'a, W> for #struct_name {
-                            fn pack_field(&'a self, label: String, packer: &mut gff::packer::Packer<W>,
-                                structs: &mut Vec<&'a dyn gff::packer::PackStruct<W>>, st_idx: &mut u32)
-                                -> ()
+                        impl ::gff::parser::UnpackField for #struct_name {
+                            fn unpack_field(field: ::gff::parser::FieldReader<'_, '_>)
+                                -> Result<Self, ::gff::error::DataError>
                             {
-                                let label_idx = packer.pack_label(&label).unwrap();
-
-                                *st_idx += 1;
-                                packer.pack_val_4(14, label_idx, &(*st_idx).to_le_bytes());
-                                structs.push(self);
+                                field.unpack_struct()
                             }
                         }
 
-                        /* serializing from custom structure to GffStruct. */
-                        impl<'a, W: std::io::Write> ::gff::packer::PackStruct<'a, W> for #struct_name {
-                            fn pack(&'a self, packer: &mut ::gff::packer::Packer<W>,
-                                structs: &mut Vec<&'a dyn ::gff::packer::PackStruct<W>>,
-                                st_idx: &mut u32)
-                                -> ()
+                        /* unpacking custom structure without intermediary representation. */
+                        impl ::gff::parser::UnpackStruct for #struct_name {
+                            fn unpack(reader: &mut ::gff::parser::StructReader<'_, '_>)
+                                -> Result<Self, ::gff::error::DataError>
                             {
-                                // pack struct id
-                                packer.data.structs.extend_from_slice(
-                                    &(#struct_id as u32).to_le_bytes()
-                                );
-                                // pack field indices / field id
-                                #pack_field_idx
-                                // pack field count
-                                packer.data.structs.extend_from_slice(
-                                    &(#field_count as u32).to_le_bytes()
-                                );
-                                // pack fields
-                                #(
-                                    gff::packer::PackField::pack_field(
-                                        &self.#fields, #keys.to_string(),
-                                        packer, structs, st_idx
-                                    );
-                                )*;
-                                // Ok()
+                                #check_struct_id
+                                #( #declare_values )*
+                                while let Some(field) = reader.next_field()? {
+                                    // unknown labels are skipped
+                                    #[allow(clippy::match_single_binding)]
+                                    match field.label() {
+                                        #( #unpack_values )*
+                                        _ => {},
+                                    }
+                                }
+                                Ok(#struct_name {
+                                    #( #build_fields ),*
+                                })
                             }
                         }
                     };
@@ -205,3 +798,136 @@ pub fn derive_gff_struct_pack(input: TokenStream) -> TokenStream {
 }
 
 /* }}} */
+/* {{{ GFF Enums */
+
+/// Derive conversions for a fieldless enum stored as an integer field.
+///
+/// The enum must be `#[repr(u8)]`, `#[repr(u16)]` or `#[repr(u32)]`,
+/// and is stored as a Byte, Word or DWord. Variants are matched on their
+/// discriminant.
+///
+/// A variant marked `#[gff(other)]` is used for unknown values. It must
+/// hold a single field (e.g. `Other(u8)`), so that the raw value is kept
+/// and packed back. Without it, unknown values are an error.
+#[proc_macro_derive(GFFEnum, attributes(gff))]
+pub fn derive_gff_enum(input: TokenStream) -> TokenStream {
+    // Parse the input tokens into a syntax tree
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let enum_name = &input.ident;
+    let repr = input.attrs.iter()
+        .filter(|a| a.path.is_ident("repr"))
+        .find_map(|a| match a.parse_meta() {
+            Ok(syn::Meta::List(list)) => list.nested.iter().find_map(|nested| match nested {
+                syn::NestedMeta::Meta(syn::Meta::Path(path)) => path.get_ident().cloned(),
+                _ => None,
+            }),
+            _ => None,
+        });
+    let repr = match repr {
+        Some(repr) if repr == "u8" || repr == "u16" || repr == "u32" => repr,
+        _ => return TokenStream::from(syn::Error::new_spanned(enum_name,
+            "expected #[repr(u8)], #[repr(u16)] or #[repr(u32)]").to_compile_error()),
+    };
+
+    let data_enum = match input.data {
+        syn::Data::Enum(data_enum) => data_enum,
+        syn::Data::Struct(_) => return unsupported(enum_name, "expected an enum, got a struct"),
+        syn::Data::Union(_) => return unsupported(enum_name, "expected an enum, got a union"),
+    };
+
+    let mut variants = vec![];
+    let mut values = vec![];
+    let mut other = None;
+    // implicit discriminants follow the previous one, like rustc does
+    let mut discriminant = quote! { 0 };
+    for variant in &data_enum.variants {
+        if let Some((_, expr)) = &variant.discriminant {
+            discriminant = quote! { #expr };
+        }
+        let is_other = match has_gff_flag(&variant.attrs, "other") {
+            Ok(is_other) => is_other,
+            Err(err) => return TokenStream::from(err.to_compile_error()),
+        };
+        let ident = &variant.ident;
+        match &variant.fields {
+            syn::Fields::Unit if !is_other => {
+                variants.push(ident);
+                values.push(discriminant.clone());
+            },
+            syn::Fields::Unnamed(fields) if is_other && fields.unnamed.len() == 1 => {
+                if other.is_some() {
+                    return TokenStream::from(syn::Error::new_spanned(variant,
+                        "duplicate #[gff(other)] variant").to_compile_error());
+                }
+                other = Some(quote! { Ok(#enum_name::#ident(raw)) });
+            },
+            /* a unit variant would drop the unknown value */
+            _ => return TokenStream::from(syn::Error::new_spanned(variant,
+                "expected a fieldless variant, or a #[gff(other)] variant with one field")
+                .to_compile_error()),
+        }
+        discriminant = quote! { (#discriminant) + 1 };
+    }
+    let other_variants = data_enum.variants.iter()
+        .filter(|variant| !matches!(variant.fields, syn::Fields::Unit))
+        .map(|variant| {
+            let ident = &variant.ident;
+            quote! { #enum_name::#ident(raw) => *raw, }
+        });
+    let other = other.unwrap_or_else(|| quote! {
+        Err(::gff::error::DataError::new(::gff::error::DataErrorKind::UnknownValue(raw as u32)))
+    });
+    let to_raw = quote! {
+        match self {
+            #( #enum_name::#variants => #values, )*
+            #( #other_variants )*
+        }
+    };
+
+    // Build the output, possibly using quasi-quotation
+    let expanded = quote! {
+        impl std::convert::TryFrom<&::gff::common::GffFieldValue> for #enum_name {
+            type Error = ::gff::error::DataError;
+
+            fn try_from(value: &::gff::common::GffFieldValue) -> Result<Self, Self::Error> {
+                let raw: #repr = std::convert::TryFrom::try_from(value)?;
+                #(
+                    if raw == #values {
+                        return Ok(#enum_name::#variants);
+                    }
+                )*
+                #other
+            }
+        }
+        impl std::convert::TryInto<::gff::common::GffFieldValue> for &#enum_name {
+            type Error = ::gff::error::DataError;
+
+            fn try_into(self) -> Result<::gff::common::GffFieldValue, Self::Error> {
+                let raw: #repr = #to_raw;
+                std::convert::TryInto::try_into(&raw)
+            }
+        }
+        impl<'a, W: std::io::Write> ::gff::packer::PackField<'a, W> for #enum_name {
+            fn pack_field(&'a self, label: &str, packer: &mut ::gff::packer::Packer<W>,
+                _structs: &mut ::gff::packer::StructQueue<'a, W>, _st_idx: &mut u32)
+                -> Result<u32, ::gff::error::DataError>
+            {
+                let raw: #repr = #to_raw;
+                ::gff::packer::PackValue::pack_value(&raw, label, packer)
+            }
+        }
+        impl ::gff::parser::UnpackField for #enum_name {
+            fn unpack_field(field: ::gff::parser::FieldReader<'_, '_>)
+                -> Result<Self, ::gff::error::DataError>
+            {
+                std::convert::TryFrom::try_from(&field.value()?)
+            }
+        }
+    };
+
+    // Hand the output tokens back to the compiler
+    TokenStream::from(expanded)
+}
+
+/* }}} */
@@ -1,14 +1,22 @@
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use gff::common::GffStruct;
     use gff::common::GffFieldValue;
-    use std::collections::HashMap;
+    use gff::common::IndexMap;
     use gff::common::Deserialize;
     use gff::common::Serialize;
+    #[allow(clippy::single_component_path_imports)]
     use gff_derive;
-    use std::convert::TryInto;
-
-    type Error = &'static str;
+    use gff::common::GffFile;
+    use gff::common::GffLang;
+    use gff::common::GffGender;
+    use gff::common::ResRef;
+    use gff::common::LocString;
+    use gff::common::VoidData;
+    use gff::common::Encodings;
+    use gff::packer::Packer;
+    use gff::parser::GffParser;
 
     macro_rules! test_serialize_deserialize {
         ( $type:ty, $struct: expr, $gff_struct: expr ) => {
@@ -33,7 +41,7 @@ mod tests {
 
         let gff_struct = GffStruct {
             st_type: 0x12345678,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a1"), GffFieldValue::Float(1.5)),
                 (String::from("b1"), GffFieldValue::Double(2.0)),
             ]),
@@ -58,7 +66,7 @@ mod tests {
 
         let gff_struct = GffStruct {
             st_type: 0x12345678,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a1"), GffFieldValue::Byte(1)),
                 (String::from("b1"), GffFieldValue::Word(2)),
                 (String::from("c1"), GffFieldValue::DWord(3)),
@@ -87,7 +95,7 @@ mod tests {
 
         let gff_struct = GffStruct {
             st_type: 0x12345678,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a1"), GffFieldValue::Char(-1)),
                 (String::from("b1"), GffFieldValue::Short(-2)),
                 (String::from("c1"), GffFieldValue::Int(-3)),
@@ -122,7 +130,7 @@ mod tests {
         }
         let ss = GffStruct {
             st_type: 0x55555555,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a"), GffFieldValue::Char(-1)),
                 (String::from("b"), GffFieldValue::Byte(1)),
                 (String::from("c"), GffFieldValue::Short(-1)),
@@ -135,7 +143,7 @@ mod tests {
         };
         let gff_struct = GffStruct {
             st_type: 0xAAAAAAAA,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a"), GffFieldValue::Struct(ss)),
             ]),
         };
@@ -169,13 +177,13 @@ mod tests {
         }
         let ss = GffStruct {
             st_type: 0x12345678,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a"), GffFieldValue::Char(-1)),
             ]),
         };
         let gff_struct = GffStruct {
             st_type: 0x87654321,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("a"), GffFieldValue::List(vec![ss])),
             ]),
         };
@@ -186,12 +194,653 @@ mod tests {
         test_serialize_deserialize!(TestStruct4, struc, &gff_struct);
     }
 
+    #[test]
+    fn test_error_path() {
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x12345678)]
+        struct TestSubStruct5 {
+            a: i8,
+            b: u16,
+        }
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x87654321)]
+        struct TestStruct5 {
+            list: Vec<TestSubStruct5>,
+        }
+        let ok = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("a"), GffFieldValue::Char(-1)),
+                (String::from("b"), GffFieldValue::Word(1)),
+            ]),
+        };
+        let bad_type = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("a"), GffFieldValue::Char(-1)),
+                (String::from("b"), GffFieldValue::Int(1)),
+            ]),
+        };
+        let missing = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("b"), GffFieldValue::Word(1)),
+            ]),
+        };
+        let gff_struct = GffStruct {
+            st_type: 0x87654321,
+            fields: IndexMap::from([
+                (String::from("list"), GffFieldValue::List(vec![ok, bad_type, missing])),
+            ]),
+        };
+        let err = TestStruct5::deserialize(&gff_struct).unwrap_err();
+        assert_eq!(err.to_string(), "list[1].b: expected Word, found Int");
+
+        let gff_struct = GffStruct {
+            st_type: 0x87654321,
+            fields: IndexMap::new(),
+        };
+        let err = TestStruct5::deserialize(&gff_struct).unwrap_err();
+        assert_eq!(err.to_string(), "list: missing field");
+    }
+
     #[test]
     fn test_direct_pack() {
-        #[derive(gff_derive::GFFStructPack)]
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack)]
         #[GFFStructId(0x12345678)]
-        struct TestStruct1 {
-            a: u8
+        struct TestSubStruct6 {
+            tag: String,
+            list: Vec<TestEmptyStruct6>,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack)]
+        #[GFFStructId(0x87654321)]
+        struct TestEmptyStruct6 {
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack)]
+        #[GFFStructId(0xFFFFFFFF)]
+        struct TestStruct6 {
+            a: u8,
+            b: i8,
+            c: u16,
+            d: i16,
+            e: u32,
+            f: i32,
+            g: u64,
+            h: i64,
+            i: f32,
+            j: f64,
+            first_name: String,
+            sub: TestSubStruct6,
+            list: Vec<TestSubStruct6>,
+            empty: TestEmptyStruct6,
+        }
+        let sub = |tag: &str, count| TestSubStruct6 {
+            tag: tag.to_string(),
+            list: (0..count).map(|_| TestEmptyStruct6 {}).collect(),
+        };
+        let struc = TestStruct6 {
+            a: 1, b: -1, c: 2, d: -2, e: 3, f: -3, g: 4, h: -4, i: 5.5, j: -5.5,
+            first_name: String::from("Jean"),
+            sub: sub("nw_bandit", 1),
+            list: vec![sub("first", 2), sub("second", 0), sub("third", 3)],
+            empty: TestEmptyStruct6 {},
+        };
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"UTC ", *b"V3.2", &struc).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"UTC ", struc.serialize().unwrap())).unwrap();
+        let intermediary = packer.writer.into_inner().unwrap();
+
+        assert_eq!(direct, intermediary);
+        let parsed = GffParser::parse(direct, &*Encodings::NeverwinterNights).unwrap();
+        assert_eq!(parsed.root, struc.serialize().unwrap());
+    }
+
+    #[test]
+    fn test_direct_unpack() {
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructUnpack, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x12345678)]
+        struct TestSubStruct7 {
+            a: i8,
+            b: u16,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructUnpack, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x87654321)]
+        struct TestStruct7 {
+            name: String,
+            sub: TestSubStruct7,
+            list: Vec<TestSubStruct7>,
+        }
+        let sub = |a, b| GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("a"), GffFieldValue::Char(a)),
+                (String::from("unknown"), GffFieldValue::CExoString(String::from("skipped"))),
+                (String::from("b"), b),
+            ]),
+        };
+        let gff_struct = GffStruct {
+            st_type: 0x87654321,
+            fields: IndexMap::from([
+                (String::from("list"), GffFieldValue::List(vec![
+                    sub(-2, GffFieldValue::Word(2)),
+                    sub(-3, GffFieldValue::Word(3)),
+                ])),
+                (String::from("sub"), GffFieldValue::Struct(sub(-1, GffFieldValue::Word(1)))),
+                (String::from("name"), GffFieldValue::CExoString(String::from("test"))),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"TEST", gff_struct)).unwrap();
+        let data = packer.writer.into_inner().unwrap();
+
+        let struc: TestStruct7 = GffParser::unpack(data, &*Encodings::NeverwinterNights).unwrap();
+        assert_eq!(struc, TestStruct7 {
+            name: String::from("test"),
+            sub: TestSubStruct7 { a: -1, b: 1 },
+            list: vec![TestSubStruct7 { a: -2, b: 2 }, TestSubStruct7 { a: -3, b: 3 }],
+        });
+
+        let gff_struct = GffStruct {
+            st_type: 0x87654321,
+            fields: IndexMap::from([
+                (String::from("name"), GffFieldValue::CExoString(String::from("test"))),
+                (String::from("sub"), GffFieldValue::Struct(sub(-1, GffFieldValue::Word(1)))),
+                (String::from("list"), GffFieldValue::List(vec![
+                    sub(-2, GffFieldValue::Word(2)),
+                    sub(-3, GffFieldValue::Int(3)),
+                ])),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"TEST", gff_struct)).unwrap();
+        let data = packer.writer.into_inner().unwrap();
+
+        let err = GffParser::unpack::<TestStruct7>(data, &*Encodings::NeverwinterNights).unwrap_err();
+        assert_eq!(err.to_string(), "list[1].b: expected Word, found Int");
+    }
+
+    #[test]
+    fn test_direct_unpack_sample() {
+        #[allow(non_snake_case)]
+        #[derive(gff_derive::GFFStructUnpack, std::cmp::PartialEq, Debug)]
+        struct Feat {
+            Feat: u16,
+        }
+        #[allow(non_snake_case)]
+        #[derive(gff_derive::GFFStructUnpack, std::cmp::PartialEq, Debug)]
+        struct Character {
+            Tag: String,
+            Age: i32,
+            Gold: u32,
+            ChallengeRating: f32,
+            FeatList: Vec<Feat>,
         }
+        let data = std::fs::read("../gff/test-data/test.bic").unwrap();
+        let tree = GffParser::parse(data.clone(), &*Encodings::NeverwinterNights).unwrap();
+        let character: Character = GffParser::unpack(data, &*Encodings::NeverwinterNights).unwrap();
+
+        let fields = &tree.root.fields;
+        assert_eq!(GffFieldValue::CExoString(character.Tag), fields["Tag"]);
+        assert_eq!(GffFieldValue::Int(character.Age), fields["Age"]);
+        assert_eq!(GffFieldValue::DWord(character.Gold), fields["Gold"]);
+        assert_eq!(GffFieldValue::Float(character.ChallengeRating), fields["ChallengeRating"]);
+        match &fields["FeatList"] {
+            GffFieldValue::List(feats) => {
+                assert_eq!(feats.len(), character.FeatList.len());
+                for (feat, st) in character.FeatList.iter().zip(feats) {
+                    assert_eq!(GffFieldValue::Word(feat.Feat), st.fields["Feat"]);
+                }
+            }
+            _ => panic!("FeatList is not a list"),
+        }
+    }
+
+    #[test]
+    fn test_resref_locstring_void() {
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x12345678)]
+        struct TestStruct8 {
+            resref: ResRef,
+            name: LocString,
+            data: VoidData,
+        }
+        let gff_struct = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("resref"), GffFieldValue::CResRef(String::from("nw_it_gold001"))),
+                (String::from("name"), GffFieldValue::CExoLocString(0xFFFFFFFF, IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("Gold")),
+                    ((GffLang::French, GffGender::Male), String::from("Or")),
+                ]))),
+                (String::from("data"), GffFieldValue::Void(vec![1, 2, 3])),
+            ]),
+        };
+        let struc = TestStruct8 {
+            resref: ResRef::new("nw_it_gold001").unwrap(),
+            name: LocString {
+                strings: IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("Gold")),
+                    ((GffLang::French, GffGender::Male), String::from("Or")),
+                ]),
+                ..LocString::default()
+            },
+            data: VoidData(vec![1, 2, 3]),
+        };
+        test_serialize_deserialize!(TestStruct8, struc, &gff_struct);
+        assert_eq!(struc.name.get(GffLang::French, GffGender::Male), Some("Or"));
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"UTI ", *b"V3.2", &struc).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"UTI ", gff_struct)).unwrap();
+        assert_eq!(direct, packer.writer.into_inner().unwrap());
+
+        let unpacked: TestStruct8 = GffParser::unpack(direct, &*Encodings::NeverwinterNights).unwrap();
+        assert_eq!(unpacked, struc);
+    }
+
+    #[test]
+    fn test_field_attributes() {
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x12345678)]
+        struct TestStruct9 {
+            #[gff(rename = "Appearance_Type")]
+            appearance: u16,
+            #[gff(default)]
+            gold: u32,
+            #[gff(skip)]
+            cache: String,
+            #[gff(rename = "Tag")]
+            tag: Option<String>,
+        }
+        let gff_struct = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("Appearance_Type"), GffFieldValue::Word(6)),
+                (String::from("cache"), GffFieldValue::CExoString(String::from("ignored"))),
+            ]),
+        };
+        let struc = TestStruct9::deserialize(&gff_struct).unwrap();
+        assert_eq!(struc, TestStruct9 {
+            appearance: 6,
+            gold: 0,
+            cache: String::new(),
+            tag: None,
+        });
+        assert_eq!(struc.serialize().unwrap(), GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("Appearance_Type"), GffFieldValue::Word(6)),
+                (String::from("gold"), GffFieldValue::DWord(0)),
+            ]),
+        });
+
+        let pack_unpack = |struc: &TestStruct9| {
+            let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+            packer.pack_direct(*b"UTC ", *b"V3.2", struc).unwrap();
+            let direct = packer.writer.into_inner().unwrap();
+
+            let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+            packer.pack(&GffFile::new(*b"UTC ", struc.serialize().unwrap())).unwrap();
+            assert_eq!(direct, packer.writer.into_inner().unwrap());
+
+            GffParser::unpack::<TestStruct9>(direct, &*Encodings::NeverwinterNights).unwrap()
+        };
+        let struc = TestStruct9 {
+            appearance: 6,
+            gold: 100,
+            cache: String::from("not packed"),
+            tag: Some(String::from("nw_bandit")),
+        };
+        assert_eq!(pack_unpack(&struc), TestStruct9 { cache: String::new(), ..struc });
+        let struc = TestStruct9 { tag: None, ..struc };
+        assert_eq!(pack_unpack(&struc), TestStruct9 { cache: String::new(), ..struc });
+
+        let gff_struct = GffStruct {
+            st_type: 0x12345678,
+            fields: IndexMap::from([
+                (String::from("gold"), GffFieldValue::DWord(1)),
+            ]),
+        };
+        let err = TestStruct9::deserialize(&gff_struct).unwrap_err();
+        assert_eq!(err.to_string(), "Appearance_Type: missing field");
+    }
+
+    #[test]
+    fn test_enum_fields() {
+        #[derive(gff_derive::GFFEnum, std::cmp::PartialEq, Debug)]
+        #[repr(u8)]
+        enum Gender {
+            Male = 0,
+            Female,
+        }
+        #[derive(gff_derive::GFFEnum, std::cmp::PartialEq, Debug)]
+        #[repr(u32)]
+        enum Race {
+            Dwarf = 0,
+            Elf = 1,
+            Human = 6,
+            #[gff(other)]
+            Other(u32),
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0xFFFFFFFF)]
+        #[allow(non_snake_case)]
+        struct Creature {
+            Gender: Gender,
+            Race: Race,
+        }
+        let gff_struct = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Gender"), GffFieldValue::Byte(1)),
+                (String::from("Race"), GffFieldValue::DWord(6)),
+            ]),
+        };
+        test_serialize_deserialize!(Creature, Creature {
+            Gender: Gender::Female,
+            Race: Race::Human,
+        }, &gff_struct);
+
+        let struc = Creature { Gender: Gender::Male, Race: Race::Other(27) };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"BIC ", *b"V3.2", &struc).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"BIC ", struc.serialize().unwrap())).unwrap();
+        assert_eq!(direct, packer.writer.into_inner().unwrap());
+        assert_eq!(GffParser::unpack::<Creature>(direct, &*Encodings::NeverwinterNights).unwrap(),
+            struc);
+
+        /* unknown values are kept by the other variant, or an error */
+        let unknown = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Gender"), GffFieldValue::Byte(2)),
+                (String::from("Race"), GffFieldValue::DWord(27)),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"BIC ", unknown)).unwrap();
+        let err = GffParser::unpack::<Creature>(packer.writer.into_inner().unwrap(),
+            &*Encodings::NeverwinterNights).unwrap_err();
+        assert_eq!(err.to_string(), "Gender: unknown enum value 2");
+        assert_eq!(Race::try_from(&GffFieldValue::DWord(27)).unwrap(), Race::Other(27));
+
+        let err = Gender::try_from(&GffFieldValue::Byte(2)).unwrap_err();
+        assert_eq!(err.to_string(), "unknown enum value 2");
+        let err = Gender::try_from(&GffFieldValue::Word(1)).unwrap_err();
+        assert_eq!(err.to_string(), "expected Byte, found Word");
+    }
+
+    #[test]
+    fn test_enum_structs() {
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0x10)]
+        #[allow(non_snake_case)]
+        struct Placeable {
+            Tag: String,
+        }
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        enum Object {
+            #[GFFStructId(4)]
+            Creature { Tag: String, #[gff(rename = "Appearance_Type")] appearance: u16 },
+            #[GFFStructId(5)]
+            Door,
+            #[GFFStructId(9)]
+            Placeable(Placeable),
+            #[gff(other)]
+            Other(GffStruct),
+        }
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        enum Known {
+            #[GFFStructId(5)]
+            Door,
+        }
+        #[derive(gff_derive::GFFStruct, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(0xFFFFFFFF)]
+        #[allow(non_snake_case)]
+        struct Area {
+            Objects: Vec<Object>,
+        }
+
+        let gff_struct = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Objects"), GffFieldValue::List(vec![
+                    GffStruct {
+                        st_type: 4,
+                        fields: IndexMap::from([
+                            (String::from("Tag"), GffFieldValue::CExoString(String::from("nw_bandit"))),
+                            (String::from("Appearance_Type"), GffFieldValue::Word(6)),
+                        ]),
+                    },
+                    GffStruct { st_type: 5, fields: IndexMap::new() },
+                    GffStruct {
+                        st_type: 9,
+                        fields: IndexMap::from([
+                            (String::from("Tag"), GffFieldValue::CExoString(String::from("chest"))),
+                        ]),
+                    },
+                    GffStruct {
+                        st_type: 7,
+                        fields: IndexMap::from([
+                            (String::from("Unknown"), GffFieldValue::Byte(1)),
+                        ]),
+                    },
+                ])),
+            ]),
+        };
+        test_serialize_deserialize!(Area, Area {
+            Objects: vec![
+                Object::Creature { Tag: String::from("nw_bandit"), appearance: 6 },
+                Object::Door,
+                Object::Placeable(Placeable { Tag: String::from("chest") }),
+                Object::Other(GffStruct {
+                    st_type: 7,
+                    fields: IndexMap::from([
+                        (String::from("Unknown"), GffFieldValue::Byte(1)),
+                    ]),
+                }),
+            ],
+        }, &gff_struct);
+
+        assert_eq!(Known::deserialize(&GffStruct { st_type: 5, fields: IndexMap::new() }).unwrap(),
+            Known::Door);
+        let err = Known::deserialize(&GffStruct { st_type: 7, fields: IndexMap::new() }).unwrap_err();
+        assert_eq!(err.to_string(), "unknown struct type 0x7");
+    }
+
+    #[test]
+    fn test_struct_id() {
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        struct Item {
+            #[gff(struct_id_field)]
+            id: u32,
+            Tag: String,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        struct Creature {
+            ItemList: Vec<Item>,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructUnpack, std::cmp::PartialEq, Debug)]
+        #[GFFStructId(4)]
+        #[gff(check_struct_id)]
+        #[allow(non_snake_case)]
+        struct Checked {
+            Tag: String,
+        }
+
+        let item = |st_type, tag: &str| GffStruct {
+            st_type,
+            fields: IndexMap::from([
+                (String::from("Tag"), GffFieldValue::CExoString(String::from(tag))),
+            ]),
+        };
+        let gff_struct = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("ItemList"), GffFieldValue::List(vec![
+                    item(0, "nw_it_gold001"),
+                    item(1, "nw_it_torch001"),
+                ])),
+            ]),
+        };
+        let struc = Creature {
+            ItemList: vec![
+                Item { id: 0, Tag: String::from("nw_it_gold001") },
+                Item { id: 1, Tag: String::from("nw_it_torch001") },
+            ],
+        };
+        test_serialize_deserialize!(Creature, struc, &gff_struct);
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"UTC ", *b"V3.2", &struc).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"UTC ", gff_struct)).unwrap();
+        assert_eq!(direct, packer.writer.into_inner().unwrap());
+        assert_eq!(GffParser::unpack::<Creature>(direct, &*Encodings::NeverwinterNights).unwrap(),
+            struc);
+
+        assert_eq!(Checked::deserialize(&item(4, "chest")).unwrap(),
+            Checked { Tag: String::from("chest") });
+        let err = Checked::deserialize(&item(5, "chest")).unwrap_err();
+        assert_eq!(err.to_string(), "expected struct type 0x4, found 0x5");
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"UTP ", item(0xFFFFFFFF, "chest"))).unwrap();
+        let err = GffParser::unpack::<Checked>(packer.writer.into_inner().unwrap(),
+            &*Encodings::NeverwinterNights).unwrap_err();
+        assert_eq!(err.to_string(), "expected struct type 0x4, found 0xffffffff");
+    }
+
+    #[test]
+    fn test_generic_types() {
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        struct Feat {
+            Feat: u16,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        struct Creature {
+            IsPC: bool,
+            Lootable: Option<bool>,
+            Feat: Box<Feat>,
+            Feats: [Feat; 2],
+            FeatList: Vec<Feat>,
+            Spouse: Option<Box<Feat>>,
+        }
+        #[derive(gff_derive::GFFStruct, gff_derive::GFFStructPack, gff_derive::GFFStructUnpack,
+            std::cmp::PartialEq, Debug)]
+        #[allow(non_snake_case)]
+        struct Vars {
+            Counts: HashMap<String, i32>,
+            Ordered: IndexMap<String, u32>,
+        }
+
+        let feat = |feat| GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([(String::from("Feat"), GffFieldValue::Word(feat))]),
+        };
+        let gff_struct = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("IsPC"), GffFieldValue::Byte(1)),
+                (String::from("Lootable"), GffFieldValue::Byte(0)),
+                (String::from("Feat"), GffFieldValue::Struct(feat(1))),
+                (String::from("Feats"), GffFieldValue::List(vec![feat(2), feat(3)])),
+                (String::from("FeatList"), GffFieldValue::List(vec![feat(4)])),
+                (String::from("Spouse"), GffFieldValue::Struct(feat(5))),
+            ]),
+        };
+        let struc = Creature {
+            IsPC: true,
+            Lootable: Some(false),
+            Feat: Box::new(Feat { Feat: 1 }),
+            Feats: [Feat { Feat: 2 }, Feat { Feat: 3 }],
+            FeatList: vec![Feat { Feat: 4 }],
+            Spouse: Some(Box::new(Feat { Feat: 5 })),
+        };
+        test_serialize_deserialize!(Creature, struc, &gff_struct);
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"UTC ", *b"V3.2", &struc).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"UTC ", struc.serialize().unwrap())).unwrap();
+        assert_eq!(direct, packer.writer.into_inner().unwrap());
+        assert_eq!(GffParser::unpack::<Creature>(direct, &*Encodings::NeverwinterNights).unwrap(),
+            struc);
+
+        let mut gff_struct = gff_struct;
+        gff_struct.fields.insert(String::from("Feats"), GffFieldValue::List(vec![feat(2)]));
+        let err = Creature::deserialize(&gff_struct).unwrap_err();
+        assert_eq!(err.to_string(), "Feats: expected 2 structs, found 1");
+
+        let var = |name: &str, value| GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([
+                (String::from("Name"), GffFieldValue::CExoString(String::from(name))),
+                (String::from("Type"), GffFieldValue::DWord(1)),
+                (String::from("Value"), GffFieldValue::Int(value)),
+            ]),
+        };
+        let vars = Vars {
+            Counts: HashMap::from([(String::from("quest_state"), 2), (String::from("kills"), 5)]),
+            Ordered: IndexMap::from([(String::from("b"), 1), (String::from("a"), 2)]),
+        };
+        let gff_struct = vars.serialize().unwrap();
+        /* hash map entries are sorted by key */
+        assert_eq!(gff_struct.fields["Counts"], GffFieldValue::List(vec![var("kills", 5), var("quest_state", 2)]));
+        let ordered = match &gff_struct.fields["Ordered"] {
+            GffFieldValue::List(entries) => entries,
+            _ => unreachable!(),
+        };
+        assert_eq!(ordered[1].fields["Name"], GffFieldValue::CExoString(String::from("a")));
+        assert_eq!(ordered[1].fields["Type"], GffFieldValue::DWord(4));
+        assert_eq!(ordered[1].fields["Value"], GffFieldValue::DWord(2));
+        assert_eq!(Vars::deserialize(&gff_struct).unwrap(), vars);
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"IFO ", *b"V3.2", &vars).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"IFO ", gff_struct)).unwrap();
+        assert_eq!(direct, packer.writer.into_inner().unwrap());
+        assert_eq!(GffParser::unpack::<Vars>(direct, &*Encodings::NeverwinterNights).unwrap(), vars);
+
+        let mut entry = var("quest_state", 2);
+        entry.fields.shift_remove("Value");
+        let gff_struct = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Counts"), GffFieldValue::List(vec![entry])),
+                (String::from("Ordered"), GffFieldValue::List(vec![])),
+            ]),
+        };
+        let err = Vars::deserialize(&gff_struct).unwrap_err();
+        assert_eq!(err.to_string(), "Counts[0].Value: missing field");
     }
 }
@@ -11,7 +11,14 @@ use encoding_rs::{
     SHIFT_JIS,     // 932
 };
 
-use std::collections::HashMap;
+pub use indexmap::IndexMap;
+
+use crate::error::{
+    DataError,
+    DataErrorKind,
+    LabelPath,
+    PathElement,
+};
 
 /* {{{ GFF header */
 
@@ -94,13 +101,26 @@ pub enum GffGender {
     Female = 1,
 }
 
+/// Id of a localized string, as stored in GFF, from its language and gender
+pub fn locstring_id(lang: GffLang, gender: GffGender) -> u32 {
+    lang as u32 * 2 + gender as u32
+}
+
+/// Language and gender of a localized string, from its id
+pub fn locstring_lang(id: u32) -> Option<(GffLang, GffGender)> {
+    Some((GffLang::try_from(id / 2).ok()?, GffGender::try_from((id % 2) as u8).ok()?))
+}
+
 /// Intermediary representation of a packed struct field
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum GffFieldValue {
     /// A basic [`u8`] value
     Byte(u8),
     /// A localized string
-    CExoLocString(u32, HashMap<(GffLang, GffGender), String>),
+    ///
+    /// Holds a string reference into the talk table (`0xFFFFFFFF` if none),
+    /// and the substrings in the order they were read/will be written.
+    CExoLocString(u32, IndexMap<(GffLang, GffGender), String>),
     /// A non-localized string
     CExoString(String),
     /// A basic [`i8`] value
@@ -132,14 +152,70 @@ pub enum GffFieldValue {
     /// Note: vectors of other types cannot be packed
     /// and must be wrapped into a struct.
     List(Vec<GffStruct>),
-    Invalid,
+}
+
+/// Type of a packed struct field, with its GFF type id
+#[derive(Debug, std::cmp::Eq, PartialEq,
+    std::hash::Hash, num_enum::TryFromPrimitive,
+    Copy, Clone)]
+#[repr(u32)]
+pub enum GffFieldType {
+    Byte          = 0,
+    Char          = 1,
+    Word          = 2,
+    Short         = 3,
+    DWord         = 4,
+    Int           = 5,
+    DWord64       = 6,
+    Int64         = 7,
+    Float         = 8,
+    Double        = 9,
+    CExoString    = 10,
+    CResRef       = 11,
+    CExoLocString = 12,
+    Void          = 13,
+    Struct        = 14,
+    List          = 15,
+}
+
+impl std::fmt::Display for GffFieldType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl GffFieldValue {
+    /// Get the GFF type of this value
+    pub fn field_type(&self) -> GffFieldType {
+        match self {
+            GffFieldValue::Byte(_) => GffFieldType::Byte,
+            GffFieldValue::CExoLocString(_, _) => GffFieldType::CExoLocString,
+            GffFieldValue::CExoString(_) => GffFieldType::CExoString,
+            GffFieldValue::Char(_) => GffFieldType::Char,
+            GffFieldValue::CResRef(_) => GffFieldType::CResRef,
+            GffFieldValue::Double(_) => GffFieldType::Double,
+            GffFieldValue::DWord(_) => GffFieldType::DWord,
+            GffFieldValue::DWord64(_) => GffFieldType::DWord64,
+            GffFieldValue::Float(_) => GffFieldType::Float,
+            GffFieldValue::Int(_) => GffFieldType::Int,
+            GffFieldValue::Int64(_) => GffFieldType::Int64,
+            GffFieldValue::Short(_) => GffFieldType::Short,
+            GffFieldValue::Void(_) => GffFieldType::Void,
+            GffFieldValue::Word(_) => GffFieldType::Word,
+            GffFieldValue::Struct(_) => GffFieldType::Struct,
+            GffFieldValue::List(_) => GffFieldType::List,
+        }
+    }
 }
 
 /// Intermediary representation of a packed struct
-#[derive(PartialEq)]
+///
+/// Fields keep their insertion order, which is the order they
+/// were parsed in, and the order they will be packed in.
+#[derive(Clone, PartialEq)]
 pub struct GffStruct {
     pub st_type: u32,
-    pub fields: HashMap<String, GffFieldValue>,
+    pub fields: IndexMap<String, GffFieldValue>,
 }
 
 impl std::fmt::Debug for GffStruct {
@@ -149,12 +225,335 @@ impl std::fmt::Debug for GffStruct {
         let mut res = f.debug_struct(&format!("GffStruct (0x{:x})", self.st_type));
 
         for key in keys {
-            res.field(key, &*self.fields.get(key).unwrap());
+            res.field(key, self.fields.get(key).unwrap());
         }
         res.finish()
     }
 }
 
+/// Define a function finding the struct at a path relative to `st`,
+/// through shared or mutable references
+///
+/// The path is a sequence of struct field labels, and of list field
+/// labels followed by an index.
+macro_rules! struct_at {
+    ($name:ident, $get:ident $(, $mut:tt)?) => {
+        fn $name<'a>(st: &'a $($mut)? GffStruct, path: &[PathElement]) -> Result<&'a $($mut)? GffStruct, DataError> {
+            match path {
+                [] => Ok(st),
+                [PathElement::Label(label), PathElement::Index(index), rest @ ..] => {
+                    let list = match st.fields.$get(label) {
+                        Some(GffFieldValue::List(list)) => list,
+                        Some(value) => return Err(DataError::unexpected_type(GffFieldType::List, value).at_label(label)),
+                        None => return Err(DataError::new(DataErrorKind::MissingField).at_label(label)),
+                    };
+                    let len = list.len();
+                    let sub = list.$get(*index).ok_or_else(|| {
+                        DataError::new(DataErrorKind::IndexOutOfRange { index: *index, len })
+                            .at_index(*index).at_label(label)
+                    })?;
+                    $name(sub, rest).map_err(|e| e.at_index(*index).at_label(label))
+                },
+                [PathElement::Label(label), rest @ ..] => match st.fields.$get(label) {
+                    Some(GffFieldValue::Struct(sub)) => $name(sub, rest).map_err(|e| e.at_label(label)),
+                    Some(value) => Err(DataError::unexpected_type(GffFieldType::Struct, value).at_label(label)),
+                    None => Err(DataError::new(DataErrorKind::MissingField).at_label(label)),
+                },
+                [PathElement::Index(_), ..] =>
+                    Err(DataError::new(DataErrorKind::InvalidPath(LabelPath(path.to_vec()).to_string()))),
+            }
+        }
+    };
+}
+
+struct_at!(struct_at, get);
+struct_at!(struct_at_mut, get_mut, mut);
+
+/// List field and index at the end of a path
+fn list_at_mut<'a>(st: &'a mut GffStruct, path: &LabelPath)
+    -> Result<(&'a mut Vec<GffStruct>, usize), DataError>
+{
+    let (label, index, parent) = match path.0.as_slice() {
+        [parent @ .., PathElement::Label(label), PathElement::Index(index)] => (label, *index, parent),
+        _ => return Err(DataError::new(DataErrorKind::InvalidPath(path.to_string()))),
+    };
+    let parent_path = LabelPath(parent.to_vec());
+    match struct_at_mut(st, parent)?.fields.get_mut(label) {
+        Some(GffFieldValue::List(list)) => Ok((list, index)),
+        Some(value) => Err(DataError::unexpected_type(GffFieldType::List, value)
+            .at_label(label).at_path(&parent_path)),
+        None => Err(DataError::new(DataErrorKind::MissingField).at_label(label).at_path(&parent_path)),
+    }
+}
+
+/// Struct field label at the end of a path, and its parent struct
+fn field_at_mut<'a, 'p>(st: &'a mut GffStruct, path: &'p LabelPath)
+    -> Result<(&'a mut GffStruct, &'p str), DataError>
+{
+    match path.0.as_slice() {
+        [parent @ .., PathElement::Label(label)] => Ok((struct_at_mut(st, parent)?, label)),
+        _ => Err(DataError::new(DataErrorKind::InvalidPath(path.to_string()))),
+    }
+}
+
+/// Editing by path, e.g. `ItemList[2].Tag`
+///
+/// Errors are located at the first element of the path that
+/// could not be resolved.
+impl GffStruct {
+    /// Struct at a path, either a struct field or a struct of a list
+    ///
+    /// The empty path is this struct.
+    pub fn struct_at(&self, path: &LabelPath) -> Result<&GffStruct, DataError> {
+        struct_at(self, &path.0)
+    }
+
+    /// Mutable struct at a path, see [`GffStruct::struct_at`]
+    pub fn struct_at_mut(&mut self, path: &LabelPath) -> Result<&mut GffStruct, DataError> {
+        struct_at_mut(self, &path.0)
+    }
+
+    /// Field at a path, which must end with a label
+    pub fn get_path(&self, path: &LabelPath) -> Result<&GffFieldValue, DataError> {
+        match path.0.as_slice() {
+            [parent @ .., PathElement::Label(label)] => struct_at(self, parent)?
+                .fields.get(label)
+                .ok_or_else(|| DataError::new(DataErrorKind::MissingField).at_path(path)),
+            _ => Err(DataError::new(DataErrorKind::InvalidPath(path.to_string()))),
+        }
+    }
+
+    /// Mutable field at a path, see [`GffStruct::get_path`]
+    pub fn get_path_mut(&mut self, path: &LabelPath) -> Result<&mut GffFieldValue, DataError> {
+        let (parent, label) = field_at_mut(self, path)?;
+        parent.fields.get_mut(label)
+            .ok_or_else(|| DataError::new(DataErrorKind::MissingField).at_path(path))
+    }
+
+    /// Set the field at a path, returning its previous value
+    ///
+    /// The parent struct must exist. A new field is added after the
+    /// existing ones, a replaced field keeps its position.
+    pub fn set_path(&mut self, path: &LabelPath, value: GffFieldValue)
+        -> Result<Option<GffFieldValue>, DataError>
+    {
+        let (parent, label) = field_at_mut(self, path)?;
+        if label.len() > 16 {
+            return Err(DataError::new(DataErrorKind::LabelTooLong).at_path(path));
+        }
+        Ok(parent.fields.insert(label.to_string(), value))
+    }
+
+    /// Remove the field or list struct at a path, returning it
+    ///
+    /// A struct removed from a list is returned as a
+    /// [`GffFieldValue::Struct`]. The other fields and structs keep
+    /// their order.
+    pub fn remove_path(&mut self, path: &LabelPath) -> Result<GffFieldValue, DataError> {
+        if let Some(PathElement::Index(_)) = path.0.last() {
+            let (list, index) = list_at_mut(self, path)?;
+            if index >= list.len() {
+                return Err(DataError::new(DataErrorKind::IndexOutOfRange { index, len: list.len() })
+                    .at_path(path));
+            }
+            return Ok(GffFieldValue::Struct(list.remove(index)));
+        }
+        let (parent, label) = field_at_mut(self, path)?;
+        parent.fields.shift_remove(label)
+            .ok_or_else(|| DataError::new(DataErrorKind::MissingField).at_path(path))
+    }
+
+    /// Insert a struct in a list, at the index ending the path
+    ///
+    /// The index can be the length of the list, to append the struct.
+    pub fn insert_path(&mut self, path: &LabelPath, st: GffStruct) -> Result<(), DataError> {
+        let (list, index) = list_at_mut(self, path)?;
+        if index > list.len() {
+            return Err(DataError::new(DataErrorKind::IndexOutOfRange { index, len: list.len() })
+                .at_path(path));
+        }
+        list.insert(index, st);
+        Ok(())
+    }
+}
+
+/// Intermediary representation of a whole GFF document
+///
+/// Besides the root [`GffStruct`], this keeps the file type
+/// (e.g. `b"BIC "`, `b"UTC "`) and version found in the header,
+/// so that a parsed file can be repacked with the same signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GffFile {
+    /// File type, padded with spaces to 4 bytes
+    pub file_type: [u8; 4],
+    /// File version, usually `b"V3.2"`
+    pub version: [u8; 4],
+    /// Top-level struct
+    pub root: GffStruct,
+}
+
+impl GffFile {
+    /// Create a new document of the given type, using the default version.
+    pub fn new(file_type: [u8; 4], root: GffStruct) -> Self {
+        GffFile {
+            file_type,
+            version: GffHeader::default().version,
+            root,
+        }
+    }
+}
+
+/// A resource reference, packed as a [`GffFieldValue::CResRef`]
+///
+/// Resource names are at most 16 characters long, and only contain
+/// ASCII letters, digits and `_`. This is checked on creation. They
+/// keep their case, but are lowercased when packed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ResRef(String);
+
+impl ResRef {
+    /// Create a resource reference, checking its length and characters
+    pub fn new(name: impl Into<String>) -> Result<Self, DataError> {
+        let name = name.into();
+        if name.len() > 16 {
+            return Err(DataError::new(DataErrorKind::ResRefTooLong));
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(DataError::new(DataErrorKind::InvalidResRef));
+        }
+        Ok(ResRef(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for ResRef {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ResRef::new(s)
+    }
+}
+
+impl std::fmt::Display for ResRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for ResRef {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<ResRef> for String {
+    fn from(resref: ResRef) -> Self {
+        resref.0
+    }
+}
+
+/// A localized string, packed as a [`GffFieldValue::CExoLocString`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocString {
+    /// String reference into the talk table (`0xFFFFFFFF` if none)
+    pub str_ref: u32,
+    /// Substrings, in the order they were read/will be written
+    pub strings: IndexMap<(GffLang, GffGender), String>,
+}
+
+impl LocString {
+    /// Get the substring for a language and gender
+    pub fn get(&self, lang: GffLang, gender: GffGender) -> Option<&str> {
+        self.strings.get(&(lang, gender)).map(String::as_str)
+    }
+}
+
+impl Default for LocString {
+    fn default() -> Self {
+        LocString {
+            str_ref: 0xFFFFFFFF,
+            strings: IndexMap::new(),
+        }
+    }
+}
+
+/// Raw data, packed as a [`GffFieldValue::Void`]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct VoidData(pub Vec<u8>);
+
+impl From<Vec<u8>> for VoidData {
+    fn from(data: Vec<u8>) -> Self {
+        VoidData(data)
+    }
+}
+
+impl From<VoidData> for Vec<u8> {
+    fn from(data: VoidData) -> Self {
+        data.0
+    }
+}
+
+impl AsRef<[u8]> for VoidData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Layout of a packed GFF file
+///
+/// This is captured by [`crate::parser::GffParser::parse_with_layout`],
+/// and used by [`crate::packer::Packer::pack_with_layout`] to place
+/// structs, fields, labels and data where they were in the original file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GffLayout {
+    /// Label table, in original order, including padding bytes
+    pub labels: Vec<[u8; 16]>,
+    /// Number of entries in the structs array
+    pub struct_count: u32,
+    /// Number of entries in the fields array
+    pub field_count: u32,
+    /// Size of the field data block, in bytes
+    pub field_data_size: u32,
+    /// Size of the field indices block, in bytes
+    pub field_indices_size: u32,
+    /// Size of the list indices block, in bytes
+    pub list_indices_size: u32,
+    /// Struct placements, in parsing order
+    ///
+    /// Structs are visited depth-first: a struct is followed by the
+    /// structs of its fields, in field order.
+    pub structs: Vec<StructLayout>,
+}
+
+/// Placement of a struct, see [`GffLayout`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StructLayout {
+    /// Index in the structs array
+    pub index: u32,
+    /// Raw field index, field indices offset, or filler for empty structs
+    pub field_offset: u32,
+    /// Field placements, in field order
+    pub fields: Vec<FieldLayout>,
+}
+
+/// Placement of a field, see [`GffLayout`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldLayout {
+    /// Index in the fields array
+    pub index: u32,
+    /// Original field type id
+    pub field_type: u32,
+    /// Index in the label table
+    pub label: u32,
+    /// Raw data dword: value, data offset, struct index or list offset
+    pub data: u32,
+    /// Size of the value in the field data block, or length of a list
+    pub size: u32,
+}
+
 /* }}} */
 /* {{{ Encodings */
 
@@ -196,6 +595,98 @@ impl std::ops::Deref for Encodings {
     }
 }
 
+/* }}} */
+/* {{{ Text formats */
+
+/// Key of the document type, in the JSON and text formats
+pub const DATA_TYPE_KEY: &str = "__data_type";
+/// Key of a struct type id, in the JSON and text formats
+pub const STRUCT_ID_KEY: &str = "__struct_id";
+
+/// Type id of structs without an explicit `__struct_id`
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+pub(crate) const DEFAULT_STRUCT_ID: u32 = 0xFFFFFFFF;
+
+/// Field type names in the JSON and text formats, by GFF type id
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+pub(crate) const FIELD_TYPES: &[&str] = &[
+    "byte", "char", "word", "short", "dword", "int", "dword64", "int64",
+    "float", "double", "cexostring", "resref", "cexolocstring", "void",
+    "struct", "list",
+];
+
+/// Error of a JSON or text parser, at `location` (line, column) if known
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+pub(crate) fn syntax_error(location: Option<(usize, usize)>, message: String) -> DataError {
+    /* drop the position, already in the error */
+    let message = match message.rfind(" at line ") {
+        Some(pos) => message[..pos].to_string(),
+        None => message,
+    };
+    match location {
+        Some((line, column)) => DataError::new(DataErrorKind::Syntax { line, column, message }),
+        None => DataError::new(DataErrorKind::Custom(message)),
+    }
+}
+
+/* }}} */
+/* {{{ Maps */
+
+/// Labels of the fields in the structs of a serialized map
+///
+/// Maps are serialized as lists of local variables, as in the
+/// `VarTable` of game objects: each struct holds the key as `Name`,
+/// the kind of value as `Type`, and the value as `Value`.
+pub(crate) const MAP_KEY: &str = "Name";
+pub(crate) const MAP_TYPE: &str = "Type";
+pub(crate) const MAP_VALUE: &str = "Value";
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Value of a local variable, the only kind of value a map can hold
+///
+/// This is implemented for `i32` (Int), `f32` (Float), `String`
+/// (CExoString) and `u32` (object id, stored as a DWord), and cannot be
+/// implemented outside of this crate. Locations are not supported, and
+/// other types do not convert:
+///
+/// ```compile_fail
+/// use std::collections::HashMap;
+/// use gff::common::GffFieldValue;
+///
+/// let bytes = HashMap::from([(String::from("a"), 1u8)]);
+/// let _: Result<GffFieldValue, _> = (&bytes).try_into();
+/// ```
+pub trait LocalVariable: sealed::Sealed
+    + for<'a> std::convert::TryFrom<&'a GffFieldValue, Error = DataError>
+{
+    /// Local variable type, stored as `Type`
+    const VAR_TYPE: u32;
+
+    /// Value of the variable, stored as `Value`
+    fn value(&self) -> GffFieldValue;
+}
+
+macro_rules! local_variable {
+    ( $type:ty, $gff_type:ident, $var_type:expr ) => {
+        impl sealed::Sealed for $type {}
+        impl LocalVariable for $type {
+            const VAR_TYPE: u32 = $var_type;
+
+            fn value(&self) -> GffFieldValue {
+                GffFieldValue::$gff_type(self.clone())
+            }
+        }
+    }
+}
+
+local_variable!(i32, Int, 1);
+local_variable!(f32, Float, 2);
+local_variable!(String, CExoString, 3);
+local_variable!(u32, DWord, 4);
+
 /* }}} */
 /* {{{ Public traits */
 
@@ -209,7 +700,7 @@ impl std::ops::Deref for Encodings {
 /// the struct implement [`crate::common::Deserialize`].
 pub trait Deserialize {
     fn deserialize(from: &GffStruct)
-        -> Result<Self, &'static str> where Self: std::marker::Sized;
+        -> Result<Self, DataError> where Self: std::marker::Sized;
 }
 
 /// Serialize trait.
@@ -222,7 +713,7 @@ pub trait Deserialize {
 /// the struct implement [`crate::common::Serialize`]
 pub trait Serialize {
     fn serialize(&self)
-        -> Result<GffStruct, &'static str> where Self: std::marker::Sized;
+        -> Result<GffStruct, DataError> where Self: std::marker::Sized;
 }
 
 /* }}} */
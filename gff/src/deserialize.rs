@@ -1,21 +1,34 @@
 //! Implementation of deserialization for basic types
 
+use std::collections::HashMap;
+
 use crate::common::{
+    IndexMap,
+    GffFieldType,
     GffFieldValue,
+    GffStruct,
+    ResRef,
+    LocString,
+    VoidData,
     Deserialize,
+    MAP_KEY,
+    MAP_TYPE,
+    MAP_VALUE,
+    LocalVariable,
 };
+use crate::error::{DataError, DataErrorKind};
 
 macro_rules! gff_try_from {
     ( $gff_type:ident, $type:ident ) => {
         impl std::convert::TryFrom<&GffFieldValue> for $type {
-            type Error = &'static str;
+            type Error = DataError;
 
             fn try_from(value: &GffFieldValue)
                 -> Result<Self, Self::Error>
             {
                 match value {
                     GffFieldValue::$gff_type(val) => Ok(*val),
-                    _ => Err("expected $gff_type"),
+                    _ => Err(DataError::unexpected_type(GffFieldType::$gff_type, value)),
                 }
             }
         }
@@ -33,28 +46,156 @@ gff_try_from!(Int,     i32);
 gff_try_from!(DWord64, u64);
 gff_try_from!(Int64,   i64);
 
+/// A Byte, `true` if not 0
+impl std::convert::TryFrom<&GffFieldValue> for bool {
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        match value {
+            GffFieldValue::Byte(val) => Ok(*val != 0),
+            _ => Err(DataError::unexpected_type(GffFieldType::Byte, value)),
+        }
+    }
+}
+
 impl std::convert::TryFrom<&GffFieldValue> for String {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
         match value {
             GffFieldValue::CExoString(s) => Ok(s.to_string()),
-            _ => Err("expect CExoString"),
+            _ => Err(DataError::unexpected_type(GffFieldType::CExoString, value)),
+        }
+    }
+}
+
+impl std::convert::TryFrom<&GffFieldValue> for ResRef {
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        match value {
+            GffFieldValue::CResRef(s) => ResRef::new(s.as_str()),
+            _ => Err(DataError::unexpected_type(GffFieldType::CResRef, value)),
+        }
+    }
+}
+
+impl std::convert::TryFrom<&GffFieldValue> for LocString {
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        match value {
+            GffFieldValue::CExoLocString(str_ref, strings) => Ok(LocString {
+                str_ref: *str_ref,
+                strings: strings.clone(),
+            }),
+            _ => Err(DataError::unexpected_type(GffFieldType::CExoLocString, value)),
+        }
+    }
+}
+
+impl std::convert::TryFrom<&GffFieldValue> for VoidData {
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        match value {
+            GffFieldValue::Void(data) => Ok(VoidData(data.clone())),
+            _ => Err(DataError::unexpected_type(GffFieldType::Void, value)),
         }
     }
 }
 
 impl<T> std::convert::TryFrom<&GffFieldValue> for Vec<T> where T: Deserialize {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
         match value {
             GffFieldValue::List(v) => {
                 v.iter()
-                    .map(|x| { T::deserialize(x) })
+                    .enumerate()
+                    .map(|(i, x)| { T::deserialize(x).map_err(|e| e.at_index(i)) })
                     .collect::<Result<Vec<T>, Self::Error>>()
             },
-            _ => Err("Expected GffFieldValue::List"),
+            _ => Err(DataError::unexpected_type(GffFieldType::List, value)),
         }
     }
 }
+
+/// A list with exactly `N` structs
+impl<T, const N: usize> std::convert::TryFrom<&GffFieldValue> for [T; N] where T: Deserialize {
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        let v = Vec::<T>::try_from(value)?;
+        let found = v.len();
+        v.try_into().map_err(|_| DataError::new(DataErrorKind::UnexpectedLength {
+            expected: N,
+            found,
+        }))
+    }
+}
+
+/// A present field, a missing field being `None`
+///
+/// As a missing field cannot be seen from its value, this is handled
+/// by the struct, e.g. with `gff_derive::GFFStruct`.
+impl<T> std::convert::TryFrom<&GffFieldValue> for Option<T>
+    where for<'a> T: std::convert::TryFrom<&'a GffFieldValue, Error = DataError>
+{
+    type Error = DataError;
+
+    fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+        T::try_from(value).map(Some)
+    }
+}
+
+impl<T> Deserialize for Box<T> where T: Deserialize {
+    fn deserialize(from: &GffStruct) -> Result<Self, DataError> {
+        T::deserialize(from).map(Box::new)
+    }
+}
+
+fn map_field<'a>(entry: &'a GffStruct, label: &str) -> Result<&'a GffFieldValue, DataError> {
+    entry.fields.get(label)
+        .ok_or_else(|| DataError::new(DataErrorKind::MissingField).at_label(label))
+}
+
+/// Key and value of a local variable, checking its name and type
+fn map_entry<V: LocalVariable>(entry: &GffStruct) -> Result<(String, V), DataError> {
+    let key = String::try_from(map_field(entry, MAP_KEY)?)
+        .map_err(|e| e.at_label(MAP_KEY))?;
+    let found = u32::try_from(map_field(entry, MAP_TYPE)?)
+        .map_err(|e| e.at_label(MAP_TYPE))?;
+    if found != V::VAR_TYPE {
+        return Err(DataError::new(DataErrorKind::UnexpectedVariableType {
+            expected: V::VAR_TYPE,
+            found,
+        }).at_label(MAP_TYPE));
+    }
+    let value = V::try_from(map_field(entry, MAP_VALUE)?)
+        .map_err(|e| e.at_label(MAP_VALUE))?;
+    Ok((key, value))
+}
+
+macro_rules! gff_try_from_map {
+    ( $map:ident ) => {
+        /// A list of local variables, with a `Name`, a `Type` and a `Value` field
+        impl<V: LocalVariable> std::convert::TryFrom<&GffFieldValue> for $map<String, V> {
+            type Error = DataError;
+
+            fn try_from(value: &GffFieldValue) -> Result<Self, Self::Error> {
+                let entries = match value {
+                    GffFieldValue::List(v) => v,
+                    _ => return Err(DataError::unexpected_type(GffFieldType::List, value)),
+                };
+                entries.iter()
+                    .enumerate()
+                    .map(|(i, entry)| map_entry(entry).map_err(|e| e.at_index(i)))
+                    .collect()
+            }
+        }
+    }
+}
+
+gff_try_from_map!(HashMap);
+gff_try_from_map!(IndexMap);
@@ -0,0 +1,684 @@
+//! Structural differences between two trees of structs
+//!
+//! [`diff`] compares two structs field by field, and reports the
+//! fields that were added, removed or changed, by their label path.
+//! A field whose type changed (e.g. `Int` to `DWord`) is reported as a
+//! [`Change::TypeChanged`], not as a change of value. Struct fields are
+//! compared recursively, so a change deep in a tree is reported at its
+//! own path.
+//!
+//! The structs of a list are matched by their index, or by the value
+//! of a key field such as `Tag` (see [`DiffOptions`]). Matched structs
+//! are compared recursively, the others are reported as removed or
+//! added structs.
+//!
+//! The differences are also a patch: [`apply`] replays them on the old
+//! struct to get the new one.
+//!
+//! ```
+//! # use gff::common::{IndexMap, GffFieldValue, GffStruct};
+//! # use gff::diff::{diff, Change};
+//! let old = GffStruct {
+//!     st_type: 0xFFFFFFFF,
+//!     fields: IndexMap::from([(String::from("Gold"), GffFieldValue::DWord(100))]),
+//! };
+//! let mut new = old.clone();
+//! new.fields.insert(String::from("Gold"), GffFieldValue::DWord(0));
+//!
+//! let diffs = diff(&old, &new);
+//! assert_eq!(diffs[0].path.to_string(), "Gold");
+//! assert_eq!(diffs[0].change, Change::Changed {
+//!     old: GffFieldValue::DWord(100),
+//!     new: GffFieldValue::DWord(0),
+//! });
+//! assert_eq!(diffs[0].to_string(), "~ Gold: DWord(100) -> DWord(0)");
+//!
+//! let mut patched = old.clone();
+//! gff::diff::apply(&mut patched, &diffs).unwrap();
+//! assert_eq!(patched, new);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::common::{GffFieldValue, GffStruct};
+use crate::error::{DataError, DataErrorKind, LabelPath, PathElement};
+
+/// Key of [`DiffOptions::keys`] applying to every list
+pub const ANY_LIST: &str = "*";
+
+/// How lists are compared
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Key field of the structs, by list label
+    ///
+    /// The structs of these lists are matched by the value of their
+    /// key field rather than by their index, so that inserting a
+    /// struct does not change all the ones after it. The
+    /// [`ANY_LIST`] label applies to the lists not given here.
+    pub keys: HashMap<String, String>,
+}
+
+impl DiffOptions {
+    /// Match the structs of the `list` lists by their `field` field
+    pub fn key(mut self, list: impl Into<String>, field: impl Into<String>) -> Self {
+        self.keys.insert(list.into(), field.into());
+        self
+    }
+
+    pub(crate) fn key_of(&self, list: &str) -> Option<&str> {
+        self.keys.get(list).or_else(|| self.keys.get(ANY_LIST)).map(String::as_str)
+    }
+}
+
+/// A change at a label path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A field, or a struct of a list, that is only in the new struct
+    Added(GffFieldValue),
+    /// A field, or a struct of a list, that is only in the old struct
+    Removed(GffFieldValue),
+    /// A field whose value changed, but not its type
+    Changed { old: GffFieldValue, new: GffFieldValue },
+    /// A field whose type changed
+    TypeChanged { old: GffFieldValue, new: GffFieldValue },
+    /// A struct whose type id changed, its fields being compared apart
+    StructType { old: u32, new: u32 },
+}
+
+/// A difference between two structs
+///
+/// Structs of lists are added and removed as [`GffFieldValue::Struct`]
+/// values, at a path ending with their index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub path: LabelPath,
+    pub change: Change,
+}
+
+/// Compare two structs, matching the structs of lists by index
+///
+/// Differences are in the order of the old struct, with the added
+/// fields after the other ones. In a list, the changes of the matched
+/// structs come first, then the removed structs from the last one, and
+/// the added structs, at their index in the new list. The paths are
+/// those of the old struct, except for the added list structs.
+pub fn diff(old: &GffStruct, new: &GffStruct) -> Vec<Difference> {
+    diff_with(old, new, &DiffOptions::default())
+}
+
+/// Compare two structs, see [`diff`]
+pub fn diff_with(old: &GffStruct, new: &GffStruct, options: &DiffOptions) -> Vec<Difference> {
+    let mut differ = Differ { options, path: LabelPath::default(), diffs: Vec::new() };
+    differ.structs(old, new);
+    differ.diffs
+}
+
+struct Differ<'o> {
+    options: &'o DiffOptions,
+    /// Path of the compared value
+    path: LabelPath,
+    diffs: Vec<Difference>,
+}
+
+impl Differ<'_> {
+    fn push(&mut self, change: Change) {
+        self.diffs.push(Difference { path: self.path.clone(), change });
+    }
+
+    fn structs(&mut self, old: &GffStruct, new: &GffStruct) {
+        if old.st_type != new.st_type {
+            self.push(Change::StructType { old: old.st_type, new: new.st_type });
+        }
+        for (label, old_value) in &old.fields {
+            self.path.0.push(PathElement::Label(label.clone()));
+            match new.fields.get(label) {
+                Some(new_value) => self.values(old_value, new_value),
+                None => self.push(Change::Removed(old_value.clone())),
+            }
+            self.path.0.pop();
+        }
+        for (label, new_value) in &new.fields {
+            if !old.fields.contains_key(label) {
+                self.path.0.push(PathElement::Label(label.clone()));
+                self.push(Change::Added(new_value.clone()));
+                self.path.0.pop();
+            }
+        }
+    }
+
+    fn values(&mut self, old: &GffFieldValue, new: &GffFieldValue) {
+        match (old, new) {
+            (GffFieldValue::Struct(old), GffFieldValue::Struct(new)) => self.structs(old, new),
+            (GffFieldValue::List(old), GffFieldValue::List(new)) => self.lists(old, new),
+            _ if old.field_type() != new.field_type() =>
+                self.push(Change::TypeChanged { old: old.clone(), new: new.clone() }),
+            _ if !same_value(old, new) =>
+                self.push(Change::Changed { old: old.clone(), new: new.clone() }),
+            _ => {},
+        }
+    }
+
+    fn lists(&mut self, old: &[GffStruct], new: &[GffStruct]) {
+        let key = match self.path.0.last() {
+            Some(PathElement::Label(label)) => self.options.key_of(label),
+            _ => None,
+        };
+        let pairs = match key {
+            Some(key) => keyed_pairs(old, new, key),
+            None => (0..old.len().min(new.len())).map(|i| (i, i)).collect(),
+        };
+        let kept = in_order(&pairs);
+
+        let mut old_kept = vec![false; old.len()];
+        let mut new_kept = vec![false; new.len()];
+        for &(i, j) in &kept {
+            old_kept[i] = true;
+            new_kept[j] = true;
+            self.path.0.push(PathElement::Index(i));
+            self.structs(&old[i], &new[j]);
+            self.path.0.pop();
+        }
+        for i in (0..old.len()).rev().filter(|i| !old_kept[*i]) {
+            self.path.0.push(PathElement::Index(i));
+            self.push(Change::Removed(GffFieldValue::Struct(old[i].clone())));
+            self.path.0.pop();
+        }
+        for j in (0..new.len()).filter(|j| !new_kept[*j]) {
+            self.path.0.push(PathElement::Index(j));
+            self.push(Change::Added(GffFieldValue::Struct(new[j].clone())));
+            self.path.0.pop();
+        }
+    }
+}
+
+/// Key field value of a struct in a list, usable in hash maps
+///
+/// Floats are compared by their bits, so that keys are equal to
+/// themselves. Structs, lists and localized strings hash their length,
+/// as their fields are not ordered.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct KeyValue<'a>(pub(crate) &'a GffFieldValue);
+
+impl PartialEq for KeyValue<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        same_value(self.0, other.0)
+    }
+}
+
+/// Equality of values, comparing floats by their bits
+///
+/// Unlike `==`, a value holding NaN is equal to itself.
+pub(crate) fn same_value(a: &GffFieldValue, b: &GffFieldValue) -> bool {
+    match (a, b) {
+        (GffFieldValue::Float(a), GffFieldValue::Float(b)) => a.to_bits() == b.to_bits(),
+        (GffFieldValue::Double(a), GffFieldValue::Double(b)) => a.to_bits() == b.to_bits(),
+        (GffFieldValue::Struct(a), GffFieldValue::Struct(b)) => same_struct(a, b),
+        (GffFieldValue::List(a), GffFieldValue::List(b)) =>
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_struct(a, b)),
+        (a, b) => a == b,
+    }
+}
+
+/// Equality of structs, comparing floats by their bits, see [`same_value`]
+pub(crate) fn same_struct(a: &GffStruct, b: &GffStruct) -> bool {
+    a.st_type == b.st_type && a.fields.len() == b.fields.len()
+        && a.fields.iter().all(|(label, value)|
+            b.fields.get(label).is_some_and(|other| same_value(value, other)))
+}
+
+impl Eq for KeyValue<'_> {}
+
+impl Hash for KeyValue<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.field_type().hash(state);
+        match self.0 {
+            GffFieldValue::Byte(v) => v.hash(state),
+            GffFieldValue::Char(v) => v.hash(state),
+            GffFieldValue::Word(v) => v.hash(state),
+            GffFieldValue::Short(v) => v.hash(state),
+            GffFieldValue::DWord(v) => v.hash(state),
+            GffFieldValue::Int(v) => v.hash(state),
+            GffFieldValue::DWord64(v) => v.hash(state),
+            GffFieldValue::Int64(v) => v.hash(state),
+            GffFieldValue::Float(v) => v.to_bits().hash(state),
+            GffFieldValue::Double(v) => v.to_bits().hash(state),
+            GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => v.hash(state),
+            GffFieldValue::CExoLocString(str_ref, strings) => (str_ref, strings.len()).hash(state),
+            GffFieldValue::Void(v) => v.hash(state),
+            GffFieldValue::Struct(st) => st.fields.len().hash(state),
+            GffFieldValue::List(list) => list.len().hash(state),
+        }
+    }
+}
+
+/// Key of a struct in a list, `None` if it has no key field
+pub(crate) fn key_value<'a>(st: &'a GffStruct, key: &str) -> Option<KeyValue<'a>> {
+    st.fields.get(key).map(KeyValue)
+}
+
+/// Pairs of old and new indices of the structs with the same key
+///
+/// Structs with the same key are paired in order, and structs without
+/// the key field are paired with each other. Pairs are sorted by old
+/// index.
+fn keyed_pairs(old: &[GffStruct], new: &[GffStruct], key: &str) -> Vec<(usize, usize)> {
+    let mut unused: HashMap<Option<KeyValue>, VecDeque<usize>> = HashMap::new();
+    for (i, old_st) in old.iter().enumerate() {
+        unused.entry(key_value(old_st, key)).or_default().push_back(i);
+    }
+    let mut pairs = Vec::new();
+    for (j, new_st) in new.iter().enumerate() {
+        if let Some(i) = unused.get_mut(&key_value(new_st, key)).and_then(VecDeque::pop_front) {
+            pairs.push((i, j));
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Largest subset of pairs whose new indices are in order
+///
+/// The structs of the other pairs moved, and are removed and added
+/// again. This is a longest increasing subsequence.
+fn in_order(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    /* tails[k]: pair ending the best subsequence of length k + 1 */
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+    for (p, &(_, j)) in pairs.iter().enumerate() {
+        let k = tails.partition_point(|&t| pairs[t].1 < j);
+        if k > 0 {
+            previous[p] = Some(tails[k - 1]);
+        }
+        match tails.get_mut(k) {
+            Some(tail) => *tail = p,
+            None => tails.push(p),
+        }
+    }
+    let mut kept = Vec::with_capacity(tails.len());
+    let mut p = tails.last().copied();
+    while let Some(i) = p {
+        kept.push(pairs[i]);
+        p = previous[i];
+    }
+    kept.reverse();
+    kept
+}
+
+/* {{{ Patching */
+
+/// Apply differences to a struct, in order
+///
+/// Applying the differences found by [`diff`] to the old struct gives
+/// the new one, with the added fields after the other ones. Removed
+/// and changed values, and struct ids, must be the old ones, and added
+/// fields must not exist yet. On error, the struct is left with the
+/// differences before the faulty one applied.
+pub fn apply(st: &mut GffStruct, diffs: &[Difference]) -> Result<(), DataError> {
+    diffs.iter().try_for_each(|diff| apply_one(st, diff))
+}
+
+/// Check the value at a path before changing it
+///
+/// Floats are compared by their bits, so that a NaN value matches.
+fn expect(st: &GffStruct, path: &LabelPath, expected: &GffFieldValue) -> Result<(), DataError> {
+    let found = match (path.0.last(), expected) {
+        (Some(PathElement::Index(_)), GffFieldValue::Struct(expected)) =>
+            same_struct(st.struct_at(path)?, expected),
+        _ => same_value(st.get_path(path)?, expected),
+    };
+    match found {
+        true => Ok(()),
+        false => Err(DataError::new(DataErrorKind::PatchMismatch).at_path(path)),
+    }
+}
+
+fn apply_one(st: &mut GffStruct, diff: &Difference) -> Result<(), DataError> {
+    let path = &diff.path;
+    match &diff.change {
+        Change::Added(GffFieldValue::Struct(sub)) if matches!(path.0.last(), Some(PathElement::Index(_))) =>
+            st.insert_path(path, sub.clone()),
+        Change::Added(value) => {
+            if st.get_path(path).is_ok() {
+                return Err(DataError::new(DataErrorKind::PatchMismatch).at_path(path));
+            }
+            st.set_path(path, value.clone()).map(drop)
+        },
+        Change::Removed(value) => {
+            expect(st, path, value)?;
+            st.remove_path(path).map(drop)
+        },
+        Change::Changed { old, new } | Change::TypeChanged { old, new } => {
+            expect(st, path, old)?;
+            st.set_path(path, new.clone()).map(drop)
+        },
+        Change::StructType { old, new } => {
+            let sub = st.struct_at_mut(path)?;
+            if sub.st_type != *old {
+                return Err(DataError::new(DataErrorKind::UnexpectedStructType { expected: *old, found: sub.st_type })
+                    .at_path(path));
+            }
+            sub.st_type = *new;
+            Ok(())
+        },
+    }
+}
+
+/* }}} */
+/* {{{ Rendering */
+
+/// Short form of a value, summarizing structs, lists and data
+pub(crate) struct Summary<'a>(pub(crate) &'a GffFieldValue);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            GffFieldValue::Struct(st) => write!(f, "Struct(0x{:x}, {} fields)", st.st_type, st.fields.len()),
+            GffFieldValue::List(list) => write!(f, "List({} structs)", list.len()),
+            GffFieldValue::Void(data) => write!(f, "Void({} bytes)", data.len()),
+            value => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// One line per difference, e.g. `~ Gold: DWord(100) -> DWord(0)`
+///
+/// Lines start with `+` for added values, `-` for removed ones, `~`
+/// for changed ones and `!` for type changes.
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = match self.path.0.is_empty() {
+            true => String::from("(root)"),
+            false => self.path.to_string(),
+        };
+        match &self.change {
+            Change::Added(value) => write!(f, "+ {}: {}", path, Summary(value)),
+            Change::Removed(value) => write!(f, "- {}: {}", path, Summary(value)),
+            Change::Changed { old, new } => write!(f, "~ {}: {} -> {}", path, Summary(old), Summary(new)),
+            Change::TypeChanged { old, new } => write!(f, "! {}: {} -> {}", path, Summary(old), Summary(new)),
+            Change::StructType { old, new } => write!(f, "~ {}: struct id 0x{:x} -> 0x{:x}", path, old, new),
+        }
+    }
+}
+
+/// Convert differences to JSON, with the `json` feature
+///
+/// Each difference is an object with its `path`, its `change`
+/// (`added`, `removed`, `changed`, `type_changed` or `struct_type`),
+/// and its `value`, or its `old` and `new` values. Values are fields
+/// in the format of [`crate::json`], and struct ids are numbers.
+#[cfg(feature = "json")]
+pub fn to_json(diffs: &[Difference]) -> serde_json::Value {
+    use serde_json::{Map, Value};
+    use crate::json::field_to_value;
+
+    diffs.iter().map(|diff| {
+        let mut obj = Map::new();
+        obj.insert(String::from("path"), Value::from(diff.path.to_string()));
+        let mut insert = |key: &str, value: Value| obj.insert(String::from(key), value);
+        match &diff.change {
+            Change::Added(value) => {
+                insert("change", Value::from("added"));
+                insert("value", field_to_value(value));
+            },
+            Change::Removed(value) => {
+                insert("change", Value::from("removed"));
+                insert("value", field_to_value(value));
+            },
+            Change::Changed { old, new } => {
+                insert("change", Value::from("changed"));
+                insert("old", field_to_value(old));
+                insert("new", field_to_value(new));
+            },
+            Change::TypeChanged { old, new } => {
+                insert("change", Value::from("type_changed"));
+                insert("old", field_to_value(old));
+                insert("new", field_to_value(new));
+            },
+            Change::StructType { old, new } => {
+                insert("change", Value::from("struct_type"));
+                insert("old", Value::from(*old));
+                insert("new", Value::from(*new));
+            },
+        }
+        Value::Object(obj)
+    }).collect()
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Encodings, IndexMap, GffFieldValue, GffStruct};
+    use crate::parser::GffParser;
+    use super::{apply, diff, diff_with, same_struct, Change, DiffOptions};
+
+    fn st(st_type: u32, fields: Vec<(&str, GffFieldValue)>) -> GffStruct {
+        GffStruct {
+            st_type,
+            fields: fields.into_iter().map(|(label, value)| (String::from(label), value)).collect(),
+        }
+    }
+
+    fn item(tag: &str, stack: u16) -> GffStruct {
+        st(0, vec![
+            ("Tag", GffFieldValue::CExoString(String::from(tag))),
+            ("StackSize", GffFieldValue::Word(stack)),
+        ])
+    }
+
+    fn lines(old: &GffStruct, new: &GffStruct, options: &DiffOptions) -> Vec<String> {
+        diff_with(old, new, options).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_01_fields() {
+        let old = st(0xFFFFFFFF, vec![
+            ("Gold", GffFieldValue::DWord(100)),
+            ("Appearance", GffFieldValue::Word(6)),
+            ("Subrace", GffFieldValue::CExoString(String::from("elf"))),
+            ("Tint", GffFieldValue::Struct(st(0, vec![("r", GffFieldValue::Byte(1))]))),
+            ("Data", GffFieldValue::Void(vec![1, 2, 3])),
+            ("Scale", GffFieldValue::Float(f32::NAN)),
+        ]);
+        let new = st(0xFFFFFFFF, vec![
+            ("Deity", GffFieldValue::CExoString(String::from("Tyr"))),
+            ("Data", GffFieldValue::Void(vec![1, 2, 3])),
+            ("Scale", GffFieldValue::Float(f32::NAN)),
+            ("Tint", GffFieldValue::Struct(st(1, vec![("r", GffFieldValue::Byte(2))]))),
+            ("Appearance", GffFieldValue::DWord(6)),
+            ("Gold", GffFieldValue::DWord(0)),
+        ]);
+        assert!(diff(&old, &old).is_empty());
+        assert_eq!(lines(&old, &new, &DiffOptions::default()), [
+            "~ Gold: DWord(100) -> DWord(0)",
+            "! Appearance: Word(6) -> DWord(6)",
+            "- Subrace: CExoString(\"elf\")",
+            "~ Tint: struct id 0x0 -> 0x1",
+            "~ Tint.r: Byte(1) -> Byte(2)",
+            "+ Deity: CExoString(\"Tyr\")",
+        ]);
+        let diffs = diff(&old, &new);
+        assert_eq!(diffs[1].change, Change::TypeChanged {
+            old: GffFieldValue::Word(6),
+            new: GffFieldValue::DWord(6),
+        });
+        assert_eq!(diffs[3].change, Change::StructType { old: 0, new: 1 });
+        assert_eq!(diffs[4].path.to_string(), "Tint.r");
+
+        /* structs and lists are summarized */
+        let new = st(0, vec![
+            ("Tint", GffFieldValue::List(vec![])),
+            ("Data", GffFieldValue::Void(vec![])),
+        ]);
+        assert_eq!(lines(&old, &new, &DiffOptions::default())[..4], [
+            "~ (root): struct id 0xffffffff -> 0x0",
+            "- Gold: DWord(100)",
+            "- Appearance: Word(6)",
+            "- Subrace: CExoString(\"elf\")",
+        ]);
+        assert_eq!(lines(&old, &new, &DiffOptions::default())[4..], [
+            "! Tint: Struct(0x0, 1 fields) -> List(0 structs)",
+            "~ Data: Void(3 bytes) -> Void(0 bytes)",
+            "- Scale: Float(NaN)",
+        ]);
+    }
+
+    #[test]
+    fn test_02_lists() {
+        let list = |items: Vec<GffStruct>| st(0xFFFFFFFF, vec![("ItemList", GffFieldValue::List(items))]);
+        let old = list(vec![item("a", 1), item("b", 1), item("c", 1), item("d", 1)]);
+        let new = list(vec![item("x", 1), item("a", 2), item("c", 1), item("b", 1), item("d", 1)]);
+
+        /* by index, the inserted struct shifts the others */
+        assert_eq!(lines(&old, &new, &DiffOptions::default()), [
+            "~ ItemList[0].Tag: CExoString(\"a\") -> CExoString(\"x\")",
+            "~ ItemList[1].Tag: CExoString(\"b\") -> CExoString(\"a\")",
+            "~ ItemList[1].StackSize: Word(1) -> Word(2)",
+            "~ ItemList[3].Tag: CExoString(\"d\") -> CExoString(\"b\")",
+            "+ ItemList[4]: Struct(0x0, 2 fields)",
+        ]);
+
+        /* by key, moved structs are removed and added again */
+        let by_tag = DiffOptions::default().key("ItemList", "Tag");
+        assert_eq!(lines(&old, &new, &by_tag), [
+            "~ ItemList[0].StackSize: Word(1) -> Word(2)",
+            "- ItemList[1]: Struct(0x0, 2 fields)",
+            "+ ItemList[0]: Struct(0x0, 2 fields)",
+            "+ ItemList[3]: Struct(0x0, 2 fields)",
+        ]);
+        let diffs = diff_with(&old, &new, &by_tag);
+        assert_eq!(diffs[1].change, Change::Removed(GffFieldValue::Struct(item("b", 1))));
+        assert_eq!(diffs[2].change, Change::Added(GffFieldValue::Struct(item("x", 1))));
+        assert_eq!(lines(&old, &new, &DiffOptions::default().key("*", "Tag")), lines(&old, &new, &by_tag));
+        assert_eq!(lines(&old, &new, &DiffOptions::default().key("Other", "Tag")).len(), 5);
+
+        /* duplicate and missing keys are paired in order */
+        let old = list(vec![item("a", 1), item("a", 2), st(0, vec![]), item("b", 1)]);
+        let new = list(vec![st(0, vec![]), item("a", 1), item("a", 3)]);
+        assert_eq!(lines(&old, &new, &by_tag), [
+            "~ ItemList[1].StackSize: Word(2) -> Word(3)",
+            "- ItemList[3]: Struct(0x0, 2 fields)",
+            "- ItemList[2]: Struct(0x0, 0 fields)",
+            "+ ItemList[0]: Struct(0x0, 0 fields)",
+        ]);
+    }
+
+    #[test]
+    fn test_03_sample() {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let mut old = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap().root;
+        if let Some(GffFieldValue::List(items)) = old.fields.get_mut("ItemList") {
+            for (i, item) in items.iter_mut().enumerate() {
+                item.fields.insert(String::from("Id"), GffFieldValue::DWord(i as u32));
+            }
+        }
+        let mut new = old.clone();
+        assert!(diff(&old, &new).is_empty());
+
+        new.set_path(&"ItemList[3].Tag".parse().unwrap(), GffFieldValue::CExoString(String::from("new"))).unwrap();
+        new.remove_path(&"ItemList[0]".parse().unwrap()).unwrap();
+        new.remove_path(&"Gold".parse().unwrap()).unwrap();
+        new.insert_path(&"ItemList[10]".parse().unwrap(), GffStruct { st_type: 1, fields: IndexMap::new() }).unwrap();
+        assert!(diff(&old, &new).len() > 75);
+        let lines = lines(&old, &new, &DiffOptions::default().key("ItemList", "Id"));
+        assert_eq!(lines[0], "- Gold: DWord(27826)");
+        assert_eq!(lines[1], "~ ItemList[3].Tag: CExoString(\"x2_it_dyeM18\") -> CExoString(\"new\")");
+        assert!(lines[2].starts_with("- ItemList[0]: Struct(0x0, "));
+        assert_eq!(lines[3..], ["+ ItemList[10]: Struct(0x1, 0 fields)"]);
+    }
+
+    #[test]
+    fn test_04_apply() {
+        let patched = |old: &GffStruct, new: &GffStruct, options: &DiffOptions| {
+            let mut st = old.clone();
+            apply(&mut st, &diff_with(old, new, options)).unwrap();
+            st
+        };
+        let list = |items: Vec<GffStruct>| st(0xFFFFFFFF, vec![
+            ("ItemList", GffFieldValue::List(items)),
+            ("Tint", GffFieldValue::Struct(st(0, vec![("r", GffFieldValue::Byte(1))]))),
+        ]);
+        let old = list(vec![item("a", 1), item("b", 1), item("c", 1), item("a", 2), st(0, vec![])]);
+        let mut new = list(vec![item("x", 1), item("c", 2), item("a", 1), st(1, vec![]), item("b", 1)]);
+        new.fields.insert(String::from("Gold"), GffFieldValue::Int(5));
+        new.set_path(&"Tint.r".parse().unwrap(), GffFieldValue::Word(1)).unwrap();
+        new.struct_at_mut(&"Tint".parse().unwrap()).unwrap().st_type = 3;
+        for options in [DiffOptions::default(), DiffOptions::default().key("ItemList", "Tag")] {
+            assert_eq!(patched(&old, &new, &options), new);
+            assert_eq!(patched(&new, &old, &options), old);
+        }
+
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let sample = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap().root;
+        let mut changed = sample.clone();
+        changed.remove_path(&"ItemList[5]".parse().unwrap()).unwrap();
+        changed.remove_path(&"ClassList".parse().unwrap()).unwrap();
+        changed.insert_path(&"ItemList[0]".parse().unwrap(), item("new", 1)).unwrap();
+        for options in [DiffOptions::default(), DiffOptions::default().key("*", "ObjectId")] {
+            assert_eq!(patched(&sample, &changed, &options), changed);
+            assert_eq!(patched(&changed, &sample, &options), sample);
+        }
+
+        /* the patched values must be the old ones */
+        let diffs = diff(&old, &new);
+        let mut other = old.clone();
+        other.set_path(&"ItemList[1].StackSize".parse().unwrap(), GffFieldValue::Word(9)).unwrap();
+        assert_eq!(apply(&mut other, &diffs).unwrap_err().to_string(),
+            "ItemList[1].StackSize: value does not match the patch");
+        let mut other = old.clone();
+        other.fields.insert(String::from("Gold"), GffFieldValue::Int(5));
+        assert_eq!(apply(&mut other, &diffs).unwrap_err().to_string(), "Gold: value does not match the patch");
+        assert_eq!(apply(&mut new.clone(), &diffs).unwrap_err().to_string(),
+            "ItemList[0].Tag: value does not match the patch");
+        let mut other = old.clone();
+        other.struct_at_mut(&"Tint".parse().unwrap()).unwrap().st_type = 2;
+        assert_eq!(apply(&mut other, &diff(&old, &new)[5..]).unwrap_err().to_string(),
+            "Tint: expected struct type 0x0, found 0x2");
+
+        /* NaN values match themselves */
+        let nan = || GffFieldValue::Float(f32::NAN);
+        let old = st(0xFFFFFFFF, vec![
+            ("Scale", nan()),
+            ("Height", nan()),
+            ("ItemList", GffFieldValue::List(vec![item("a", 1), st(0, vec![("Weight", nan())])])),
+        ]);
+        let new = st(0xFFFFFFFF, vec![
+            ("Scale", GffFieldValue::Float(1.0)),
+            ("ItemList", GffFieldValue::List(vec![item("a", 1)])),
+        ]);
+        assert_eq!(patched(&old, &new, &DiffOptions::default()), new);
+        assert!(same_struct(&patched(&new, &old, &DiffOptions::default()), &old));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_05_json() {
+        let old = st(0xFFFFFFFF, vec![
+            ("Gold", GffFieldValue::DWord(100)),
+            ("Appearance", GffFieldValue::Word(6)),
+            ("ItemList", GffFieldValue::List(vec![item("a", 1)])),
+        ]);
+        let new = st(0, vec![
+            ("Gold", GffFieldValue::DWord(0)),
+            ("Appearance", GffFieldValue::DWord(6)),
+            ("ItemList", GffFieldValue::List(vec![])),
+            ("Deity", GffFieldValue::CExoString(String::from("Tyr"))),
+        ]);
+        let json = super::to_json(&diff(&old, &new));
+        assert_eq!(json, serde_json::json!([
+            { "path": "", "change": "struct_type", "old": 0xFFFFFFFFu32, "new": 0 },
+            { "path": "Gold", "change": "changed",
+                "old": { "type": "dword", "value": 100 }, "new": { "type": "dword", "value": 0 } },
+            { "path": "Appearance", "change": "type_changed",
+                "old": { "type": "word", "value": 6 }, "new": { "type": "dword", "value": 6 } },
+            { "path": "ItemList[0]", "change": "removed", "value": {
+                "type": "struct", "__struct_id": 0, "value": {
+                    "Tag": { "type": "cexostring", "value": "a" },
+                    "StackSize": { "type": "word", "value": 1 },
+                } } },
+            { "path": "Deity", "change": "added", "value": { "type": "cexostring", "value": "Tyr" } },
+        ]));
+    }
+}
@@ -0,0 +1,375 @@
+//! Error types used by GFF
+
+use std::fmt;
+
+use crate::common::{
+    GffFieldType,
+    GffFieldValue,
+};
+
+/// Zone of a packed GFF file
+///
+/// Used to locate where an error occurred in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GffSection {
+    Header,
+    Structs,
+    Fields,
+    Labels,
+    FieldData,
+    FieldIndices,
+    ListIndices,
+}
+
+impl fmt::Display for GffSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            GffSection::Header => "header",
+            GffSection::Structs => "structs",
+            GffSection::Fields => "fields",
+            GffSection::Labels => "labels",
+            GffSection::FieldData => "field data",
+            GffSection::FieldIndices => "field indices",
+            GffSection::ListIndices => "list indices",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Error returned when parsing a packed GFF file
+///
+/// Every variant carries the section, and the absolute byte offset
+/// (from the start of the file) at which the faulty value was read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GffError {
+    /// A header offset or count does not match the actual data layout
+    InvalidHeader { section: GffSection, offset: u32 },
+    /// The data ends before a value could be fully read
+    Truncated { section: GffSection, offset: u32 },
+    /// A struct index points past the end of the structs array
+    StructIndexOutOfRange { index: u32, section: GffSection, offset: u32 },
+    /// A field index points past the end of the fields array
+    FieldIndexOutOfRange { index: u32, section: GffSection, offset: u32 },
+    /// A label index is out of range, or the label is not valid UTF-8
+    BadLabel { index: u32, section: GffSection, offset: u32 },
+    /// A struct is referenced more than once
+    CyclicReference { index: u32, section: GffSection, offset: u32 },
+    /// Structs are nested deeper than the parser allows
+    NestingTooDeep { section: GffSection, offset: u32 },
+    /// A field has a type id outside of the known range
+    UnknownFieldType { field_type: u32, section: GffSection, offset: u32 },
+    /// A localized string uses a language with no known encoding
+    UnknownLanguage { language: Option<u32>, section: GffSection, offset: u32 },
+}
+
+impl GffError {
+    /// Section in which the error occurred
+    pub fn section(&self) -> GffSection {
+        match *self {
+            GffError::InvalidHeader { section, .. }
+            | GffError::Truncated { section, .. }
+            | GffError::StructIndexOutOfRange { section, .. }
+            | GffError::FieldIndexOutOfRange { section, .. }
+            | GffError::BadLabel { section, .. }
+            | GffError::CyclicReference { section, .. }
+            | GffError::NestingTooDeep { section, .. }
+            | GffError::UnknownFieldType { section, .. }
+            | GffError::UnknownLanguage { section, .. } => section,
+        }
+    }
+
+    /// Absolute byte offset at which the error occurred
+    pub fn offset(&self) -> u32 {
+        match *self {
+            GffError::InvalidHeader { offset, .. }
+            | GffError::Truncated { offset, .. }
+            | GffError::StructIndexOutOfRange { offset, .. }
+            | GffError::FieldIndexOutOfRange { offset, .. }
+            | GffError::BadLabel { offset, .. }
+            | GffError::CyclicReference { offset, .. }
+            | GffError::NestingTooDeep { offset, .. }
+            | GffError::UnknownFieldType { offset, .. }
+            | GffError::UnknownLanguage { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for GffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GffError::InvalidHeader { .. } =>
+                write!(f, "invalid header entry for")?,
+            GffError::Truncated { .. } =>
+                write!(f, "unexpected end of data in")?,
+            GffError::StructIndexOutOfRange { index, .. } =>
+                write!(f, "struct index {} out of range in", index)?,
+            GffError::FieldIndexOutOfRange { index, .. } =>
+                write!(f, "field index {} out of range in", index)?,
+            GffError::BadLabel { index, .. } =>
+                write!(f, "bad label {} in", index)?,
+            GffError::CyclicReference { index, .. } =>
+                write!(f, "struct {} referenced more than once in", index)?,
+            GffError::NestingTooDeep { .. } =>
+                write!(f, "structs nested too deep in")?,
+            GffError::UnknownFieldType { field_type, .. } =>
+                write!(f, "unknown field type {} in", field_type)?,
+            GffError::UnknownLanguage { language: Some(lang), .. } =>
+                write!(f, "unknown language {} in", lang)?,
+            GffError::UnknownLanguage { language: None, .. } =>
+                write!(f, "no encoding for strings in")?,
+        }
+        write!(f, " {} section (offset 0x{:x})", self.section(), self.offset())
+    }
+}
+
+impl std::error::Error for GffError {}
+
+/// Element of a [`LabelPath`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathElement {
+    /// Field of a struct
+    Label(String),
+    /// Struct of a list
+    Index(usize),
+}
+
+/// Location of a field in a tree of structs
+///
+/// Displayed as `ItemList[3].PropertiesList[0].CostValue`, and parsed
+/// back from the same syntax: an index always follows the label of a
+/// list field.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LabelPath(pub Vec<PathElement>);
+
+impl LabelPath {
+    /// Path to a field of the struct at this path
+    pub fn join_label(&self, label: &str) -> LabelPath {
+        let mut path = self.clone();
+        path.0.push(PathElement::Label(label.to_string()));
+        path
+    }
+
+    /// Path to a struct of the list at this path
+    pub fn join_index(&self, index: usize) -> LabelPath {
+        let mut path = self.clone();
+        path.0.push(PathElement::Index(index));
+        path
+    }
+}
+
+impl std::str::FromStr for LabelPath {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DataError::new(DataErrorKind::InvalidPath(s.to_string()));
+        let mut path = LabelPath::default();
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(index) = rest.strip_prefix('[') {
+                let end = index.find(']').ok_or_else(invalid)?;
+                if !matches!(path.0.last(), Some(PathElement::Label(_))) {
+                    return Err(invalid());
+                }
+                path.0.push(PathElement::Index(index[..end].parse().map_err(|_| invalid())?));
+                rest = &index[end + 1..];
+            } else {
+                let label = match rest.strip_prefix('.') {
+                    Some(label) if !path.0.is_empty() => label,
+                    None if path.0.is_empty() => rest,
+                    _ => return Err(invalid()),
+                };
+                let end = label.find(['.', '[']).unwrap_or(label.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                path.0.push(PathElement::Label(label[..end].to_string()));
+                rest = &label[end..];
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl fmt::Display for LabelPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, elem) in self.0.iter().enumerate() {
+            match elem {
+                PathElement::Label(label) if i == 0 => write!(f, "{}", label)?,
+                PathElement::Label(label) => write!(f, ".{}", label)?,
+                PathElement::Index(idx) => write!(f, "[{}]", idx)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cause of a [`DataError`]
+#[derive(Debug)]
+pub enum DataErrorKind {
+    /// A required field is missing from a struct
+    MissingField,
+    /// A field does not have the expected GFF type
+    UnexpectedType { expected: GffFieldType, found: GffFieldType },
+    /// A label is longer than 16 bytes
+    LabelTooLong,
+    /// A resource reference is longer than 16 bytes
+    ResRefTooLong,
+    /// A resource reference contains characters other than
+    /// ASCII letters, digits and `_`
+    InvalidResRef,
+    /// No encoding is known for a language (`None` for [`CExoString`])
+    ///
+    /// [`CExoString`]: crate::common::GffFieldValue::CExoString
+    UnknownLanguage(Option<u32>),
+    /// A string contains characters that its encoding cannot represent
+    Unencodable,
+    /// An integer does not match any variant of an enum
+    UnknownValue(u32),
+    /// A struct type id does not match any variant of an enum
+    UnknownStructType(u32),
+    /// Any other error, e.g. from serde
+    Custom(String),
+    /// A text document is malformed (lines and columns start at 1)
+    Syntax { line: usize, column: usize, message: String },
+    /// A list does not have the expected number of structs
+    UnexpectedLength { expected: usize, found: usize },
+    /// A struct type id is not the expected one
+    UnexpectedStructType { expected: u32, found: u32 },
+    /// The local variable type of a map entry is not the one of the map values
+    UnexpectedVariableType { expected: u32, found: u32 },
+    /// A list index is past the end of the list
+    IndexOutOfRange { index: usize, len: usize },
+    /// A field path is malformed, or does not point to the expected
+    /// kind of value
+    InvalidPath(String),
+    /// A value is not the one a patch expects, or a field added by a
+    /// patch already exists
+    PatchMismatch,
+    /// A [`GffLayout`] places structs, fields or data outside of their
+    /// packed blocks
+    ///
+    /// [`GffLayout`]: crate::common::GffLayout
+    InvalidLayout,
+    /// Writing packed data failed
+    Io(std::io::Error),
+    /// Reading packed data failed
+    Parse(GffError),
+}
+
+/// Error returned when packing, unpacking, serializing or deserializing data
+///
+/// The path locates the faulty field from the top-level struct.
+#[derive(Debug)]
+pub struct DataError {
+    pub path: LabelPath,
+    pub kind: DataErrorKind,
+}
+
+impl DataError {
+    pub fn new(kind: DataErrorKind) -> Self {
+        DataError {
+            path: LabelPath::default(),
+            kind,
+        }
+    }
+
+    /// Error for a value that does not have the expected type
+    pub fn unexpected_type(expected: GffFieldType, found: &GffFieldValue) -> Self {
+        DataError::new(DataErrorKind::UnexpectedType {
+            expected,
+            found: found.field_type(),
+        })
+    }
+
+    /// Prepend a struct field label to the error path
+    pub fn at_label(mut self, label: &str) -> Self {
+        self.path.0.insert(0, PathElement::Label(label.to_string()));
+        self
+    }
+
+    /// Prepend a list index to the error path
+    pub fn at_index(mut self, index: usize) -> Self {
+        self.path.0.insert(0, PathElement::Index(index));
+        self
+    }
+
+    /// Prepend a whole path to the error path
+    pub fn at_path(mut self, path: &LabelPath) -> Self {
+        self.path.0.splice(0..0, path.0.iter().cloned());
+        self
+    }
+}
+
+impl From<std::io::Error> for DataError {
+    fn from(err: std::io::Error) -> Self {
+        DataError::new(DataErrorKind::Io(err))
+    }
+}
+
+impl From<GffError> for DataError {
+    fn from(err: GffError) -> Self {
+        DataError::new(DataErrorKind::Parse(err))
+    }
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.path.0.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        match &self.kind {
+            DataErrorKind::MissingField =>
+                write!(f, "missing field"),
+            DataErrorKind::UnexpectedType { expected, found } =>
+                write!(f, "expected {}, found {}", expected, found),
+            DataErrorKind::LabelTooLong =>
+                write!(f, "label is longer than 16 bytes"),
+            DataErrorKind::ResRefTooLong =>
+                write!(f, "resref is longer than 16 bytes"),
+            DataErrorKind::InvalidResRef =>
+                write!(f, "resref contains invalid characters"),
+            DataErrorKind::UnknownLanguage(Some(lang)) =>
+                write!(f, "unknown language {}", lang),
+            DataErrorKind::UnknownLanguage(None) =>
+                write!(f, "no encoding for strings"),
+            DataErrorKind::Unencodable =>
+                write!(f, "string cannot be encoded"),
+            DataErrorKind::UnknownValue(val) =>
+                write!(f, "unknown enum value {}", val),
+            DataErrorKind::UnknownStructType(st_type) =>
+                write!(f, "unknown struct type 0x{:x}", st_type),
+            DataErrorKind::Custom(msg) =>
+                write!(f, "{}", msg),
+            DataErrorKind::Syntax { line, column, message } =>
+                write!(f, "line {}, column {}: {}", line, column, message),
+            DataErrorKind::UnexpectedLength { expected, found } =>
+                write!(f, "expected {} structs, found {}", expected, found),
+            DataErrorKind::UnexpectedStructType { expected, found } =>
+                write!(f, "expected struct type 0x{:x}, found 0x{:x}", expected, found),
+            DataErrorKind::UnexpectedVariableType { expected, found } =>
+                write!(f, "expected variable type {}, found {}", expected, found),
+            DataErrorKind::IndexOutOfRange { index, len } =>
+                write!(f, "index {} out of range for a list of {} structs", index, len),
+            DataErrorKind::InvalidPath(path) =>
+                write!(f, "invalid path `{}`", path),
+            DataErrorKind::PatchMismatch =>
+                write!(f, "value does not match the patch"),
+            DataErrorKind::InvalidLayout =>
+                write!(f, "layout does not fit the packed data"),
+            DataErrorKind::Io(err) =>
+                write!(f, "write error: {}", err),
+            DataErrorKind::Parse(err) =>
+                write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for DataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            DataErrorKind::Io(err) => Some(err),
+            DataErrorKind::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
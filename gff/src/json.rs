@@ -0,0 +1,325 @@
+//! JSON representation of GFF documents, with the `json` feature
+//!
+//! The layout follows the JSON of neverwinter.nim (`nwn_gff -k json`),
+//! but is not checked against its output. A struct is an object holding
+//! its type id as `__struct_id` (omitted for the `0xFFFFFFFF` default),
+//! and its fields. Each field is an object with its `type` and `value`:
+//!
+//! ```json
+//! {
+//!   "__data_type": "UTC ",
+//!   "Tag": { "type": "cexostring", "value": "nw_bandit" },
+//!   "FirstName": { "type": "cexolocstring", "id": 1234, "value": { "0": "Bandit" } },
+//!   "Tint": { "type": "struct", "__struct_id": 0, "value": { "r": { "type": "byte", "value": 255 } } },
+//!   "ItemList": { "type": "list", "value": [ { "__struct_id": 0, "Repos_PosX": { "type": "word", "value": 0 } } ] },
+//!   "Data": { "type": "void", "value64": "AQID" }
+//! }
+//! ```
+//!
+//! Localized strings are keyed by their language and gender id, and
+//! their `id` is the string reference, omitted if none. Void data is
+//! base64 encoded.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Map, Value};
+
+use crate::common::{
+    locstring_id,
+    locstring_lang,
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffFile,
+    GffGender,
+    GffLang,
+    GffStruct,
+    DEFAULT_STRUCT_ID,
+    FIELD_TYPES,
+};
+use crate::error::{DataError, DataErrorKind};
+
+pub use crate::common::{DATA_TYPE_KEY, STRUCT_ID_KEY};
+
+fn invalid(msg: impl Into<String>) -> DataError {
+    DataError::new(DataErrorKind::Custom(msg.into()))
+}
+
+fn syntax_error(err: serde_json::Error) -> DataError {
+    let location = (err.line() != 0).then(|| (err.line(), err.column()));
+    crate::common::syntax_error(location, err.to_string())
+}
+
+/* {{{ Writing */
+
+/// Convert a document to JSON
+pub fn to_value(file: &GffFile) -> Value {
+    let mut obj = Map::new();
+    obj.insert(DATA_TYPE_KEY.to_string(),
+        Value::from(String::from_utf8_lossy(&file.file_type).into_owned()));
+    write_struct(&file.root, &mut obj);
+    Value::Object(obj)
+}
+
+/// Convert a document to a pretty-printed JSON string
+pub fn to_string(file: &GffFile) -> String {
+    // serializing a Value cannot fail
+    serde_json::to_string_pretty(&to_value(file)).unwrap()
+}
+
+/// Convert a struct to JSON
+pub fn struct_to_value(st: &GffStruct) -> Value {
+    let mut obj = Map::new();
+    write_struct(st, &mut obj);
+    Value::Object(obj)
+}
+
+fn write_struct(st: &GffStruct, obj: &mut Map<String, Value>) {
+    if st.st_type != DEFAULT_STRUCT_ID {
+        obj.insert(STRUCT_ID_KEY.to_string(), struct_id_value(st.st_type));
+    }
+    for (label, value) in &st.fields {
+        obj.insert(label.clone(), field_to_value(value));
+    }
+}
+
+/// Struct ids are signed, `0xFFFFFFFF` being `-1`
+fn struct_id_value(st_type: u32) -> Value {
+    Value::from(st_type as i32)
+}
+
+pub(crate) fn field_to_value(value: &GffFieldValue) -> Value {
+    let mut obj = Map::new();
+    obj.insert(String::from("type"), Value::from(FIELD_TYPES[value.field_type() as usize]));
+    let value = match value {
+        GffFieldValue::Byte(v) => Value::from(*v),
+        GffFieldValue::Char(v) => Value::from(*v),
+        GffFieldValue::Word(v) => Value::from(*v),
+        GffFieldValue::Short(v) => Value::from(*v),
+        GffFieldValue::DWord(v) => Value::from(*v),
+        GffFieldValue::Int(v) => Value::from(*v),
+        GffFieldValue::DWord64(v) => Value::from(*v),
+        GffFieldValue::Int64(v) => Value::from(*v),
+        GffFieldValue::Float(v) => Value::from(*v),
+        GffFieldValue::Double(v) => Value::from(*v),
+        GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => Value::from(v.as_str()),
+        GffFieldValue::CExoLocString(str_ref, strings) => {
+            if *str_ref != 0xFFFFFFFF {
+                obj.insert(String::from("id"), Value::from(*str_ref));
+            }
+            Value::Object(strings.iter()
+                .map(|((lang, gender), s)|
+                    (locstring_id(*lang, *gender).to_string(), Value::from(s.as_str())))
+                .collect())
+        },
+        GffFieldValue::Void(v) => {
+            obj.insert(String::from("value64"), Value::from(BASE64.encode(v)));
+            return Value::Object(obj);
+        },
+        GffFieldValue::Struct(st) => {
+            obj.insert(STRUCT_ID_KEY.to_string(), struct_id_value(st.st_type));
+            let mut fields = Map::new();
+            for (label, value) in &st.fields {
+                fields.insert(label.clone(), field_to_value(value));
+            }
+            Value::Object(fields)
+        },
+        GffFieldValue::List(structs) =>
+            Value::Array(structs.iter().map(struct_to_value).collect()),
+    };
+    obj.insert(String::from("value"), value);
+    Value::Object(obj)
+}
+
+/* }}} */
+/* {{{ Reading */
+
+/// Convert JSON to a document
+///
+/// The document has the default version, JSON not holding it.
+pub fn from_value(value: &Value) -> Result<GffFile, DataError> {
+    let obj = value.as_object().ok_or_else(|| invalid("expected an object"))?;
+    let data_type = obj.get(DATA_TYPE_KEY)
+        .ok_or_else(|| DataError::new(DataErrorKind::MissingField).at_label(DATA_TYPE_KEY))?;
+    let file_type = data_type.as_str()
+        .and_then(|s| <[u8; 4]>::try_from(s.as_bytes()).ok())
+        .ok_or_else(|| invalid("expected a 4 characters string").at_label(DATA_TYPE_KEY))?;
+    Ok(GffFile::new(file_type, read_struct(obj, DEFAULT_STRUCT_ID)?))
+}
+
+/// Parse a JSON string to a document
+pub fn from_str(s: &str) -> Result<GffFile, DataError> {
+    from_value(&serde_json::from_str(s).map_err(syntax_error)?)
+}
+
+/// Convert JSON to a struct
+pub fn struct_from_value(value: &Value) -> Result<GffStruct, DataError> {
+    let obj = value.as_object().ok_or_else(|| invalid("expected an object"))?;
+    read_struct(obj, DEFAULT_STRUCT_ID)
+}
+
+fn read_struct_id(value: &Value) -> Result<u32, DataError> {
+    value.as_i64()
+        .filter(|id| (i32::MIN as i64..=u32::MAX as i64).contains(id))
+        .map(|id| id as u32)
+        .ok_or_else(|| invalid("expected a struct id"))
+}
+
+fn read_struct(obj: &Map<String, Value>, st_type: u32) -> Result<GffStruct, DataError> {
+    let mut st = GffStruct { st_type, fields: IndexMap::with_capacity(obj.len()) };
+    for (label, value) in obj {
+        if label == STRUCT_ID_KEY {
+            st.st_type = read_struct_id(value)?;
+        } else if !label.starts_with("__") {
+            if label.len() > 16 {
+                return Err(DataError::new(DataErrorKind::LabelTooLong).at_label(label));
+            }
+            st.fields.insert(label.clone(), field_from_value(value).map_err(|e| e.at_label(label))?);
+        }
+    }
+    Ok(st)
+}
+
+fn number<T: TryFrom<i128>>(value: &Value) -> Result<T, DataError> {
+    value.as_i64().map(i128::from)
+        .or_else(|| value.as_u64().map(i128::from))
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| invalid("expected an integer in range"))
+}
+
+fn float(value: &Value) -> Result<f64, DataError> {
+    value.as_f64().ok_or_else(|| invalid("expected a number"))
+}
+
+fn string(value: &Value) -> Result<String, DataError> {
+    value.as_str().map(String::from).ok_or_else(|| invalid("expected a string"))
+}
+
+fn field_from_value(value: &Value) -> Result<GffFieldValue, DataError> {
+    let obj = value.as_object().ok_or_else(|| invalid("expected a field object"))?;
+    let field_type = obj.get("type").and_then(Value::as_str)
+        .ok_or_else(|| invalid("expected a field type"))?;
+    let field_type = FIELD_TYPES.iter().position(|name| *name == field_type)
+        .and_then(|ty| GffFieldType::try_from(ty as u32).ok())
+        .ok_or_else(|| invalid(format!("unknown field type {}", field_type)))?;
+    let key = if field_type == GffFieldType::Void { "value64" } else { "value" };
+    let value = obj.get(key).ok_or_else(|| invalid(format!("missing {}", key)))?;
+
+    match field_type {
+        GffFieldType::Byte => number(value).map(GffFieldValue::Byte),
+        GffFieldType::Char => number(value).map(GffFieldValue::Char),
+        GffFieldType::Word => number(value).map(GffFieldValue::Word),
+        GffFieldType::Short => number(value).map(GffFieldValue::Short),
+        GffFieldType::DWord => number(value).map(GffFieldValue::DWord),
+        GffFieldType::Int => number(value).map(GffFieldValue::Int),
+        GffFieldType::DWord64 => number(value).map(GffFieldValue::DWord64),
+        GffFieldType::Int64 => number(value).map(GffFieldValue::Int64),
+        GffFieldType::Float => float(value).map(|v| GffFieldValue::Float(v as f32)),
+        GffFieldType::Double => float(value).map(GffFieldValue::Double),
+        GffFieldType::CExoString => string(value).map(GffFieldValue::CExoString),
+        GffFieldType::CResRef => string(value).map(GffFieldValue::CResRef),
+        GffFieldType::CExoLocString => {
+            let str_ref = match obj.get("id") {
+                Some(id) => number(id).map_err(|_| invalid("expected a string reference"))?,
+                None => 0xFFFFFFFF,
+            };
+            read_locstring(value).map(|strings| GffFieldValue::CExoLocString(str_ref, strings))
+        },
+        GffFieldType::Void => value.as_str()
+            .and_then(|s| BASE64.decode(s).ok())
+            .map(GffFieldValue::Void)
+            .ok_or_else(|| invalid("expected base64 data")),
+        GffFieldType::Struct => {
+            let st_type = match obj.get(STRUCT_ID_KEY) {
+                Some(id) => read_struct_id(id)?,
+                None => DEFAULT_STRUCT_ID,
+            };
+            value.as_object()
+                .ok_or_else(|| invalid("expected an object"))
+                .and_then(|fields| read_struct(fields, st_type))
+                .map(GffFieldValue::Struct)
+        },
+        GffFieldType::List => value.as_array()
+            .ok_or_else(|| invalid("expected an array"))
+            .and_then(|structs| structs.iter().enumerate()
+                .map(|(i, st)| struct_from_value(st).map_err(|e| e.at_index(i)))
+                .collect::<Result<Vec<_>, _>>())
+            .map(GffFieldValue::List),
+    }
+}
+
+fn read_locstring(value: &Value) -> Result<IndexMap<(GffLang, GffGender), String>, DataError> {
+    let obj = value.as_object().ok_or_else(|| invalid("expected an object"))?;
+    obj.iter()
+        .map(|(key, s)| {
+            let lang_gender = key.parse().ok()
+                .and_then(locstring_lang)
+                .ok_or_else(|| invalid(format!("unknown language id {}", key)))?;
+            Ok((lang_gender, string(s)?))
+        })
+        .collect()
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Encodings, GffFieldValue, GffLang, GffGender};
+    use crate::packer::Packer;
+    use crate::parser::GffParser;
+
+    #[test]
+    fn test_01_round_trip_sample() {
+        crate::tests::round_trip_sample(super::to_string, super::from_str);
+    }
+
+    /* hand-written, with every field type */
+    #[test]
+    fn test_02_field_types() {
+        let json = std::fs::read_to_string("test-data/types.utc.json").unwrap();
+        let file = super::from_str(&json).unwrap();
+        assert_eq!(&file.file_type, b"UTC ");
+        assert_eq!(file.root.st_type, 0xFFFFFFFF);
+        assert_eq!(file.root.fields["Appearance_Type"], GffFieldValue::Word(6));
+        assert_eq!(file.root.fields["Data"], GffFieldValue::Void(vec![0, 1, 2, 255]));
+        match &file.root.fields["Description"] {
+            GffFieldValue::CExoLocString(str_ref, strings) => {
+                assert_eq!(*str_ref, 12345);
+                assert_eq!(strings[&(GffLang::French, GffGender::Female)], "Une bandite.");
+            },
+            value => panic!("unexpected {:?}", value),
+        }
+        match &file.root.fields["ItemList"] {
+            GffFieldValue::List(items) => assert_eq!(items[1].st_type, 1),
+            value => panic!("unexpected {:?}", value),
+        }
+
+        /* same JSON, through packed data */
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&file).unwrap();
+        let data = packer.writer.into_inner().unwrap();
+        let res = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap();
+        assert_eq!(super::to_string(&res), json.trim_end());
+    }
+
+    #[test]
+    fn test_03_errors() {
+        let err = super::from_str("{\n  \"__data_type\": \"UTC \",\n  \"Tag\": }").unwrap_err();
+        assert_eq!(err.to_string(), "line 3, column 10: expected value");
+
+        let err = super::from_str(r#"{ "Tag": {} }"#).unwrap_err();
+        assert_eq!(err.to_string(), "__data_type: missing field");
+
+        let err = super::from_str(r#"{ "__data_type": "UTC ",
+            "Gold": { "type": "byte", "value": 300 } }"#).unwrap_err();
+        assert_eq!(err.to_string(), "Gold: expected an integer in range");
+
+        let err = super::from_str(r#"{ "__data_type": "UTC ", "ItemList": { "type": "list",
+            "value": [ { "Tag": { "type": "string", "value": "" } } ] } }"#).unwrap_err();
+        assert_eq!(err.to_string(), "ItemList[0].Tag: unknown field type string");
+
+        let err = super::from_str(r#"{ "__data_type": "UTC ",
+            "this_label_is_too_long": { "type": "byte", "value": 0 } }"#).unwrap_err();
+        assert_eq!(err.to_string(), "this_label_is_too_long: label is longer than 16 bytes");
+    }
+}
@@ -3,32 +3,50 @@
 extern crate encoding_rs;
 
 pub mod common;
+pub mod error;
 pub mod deserialize;
 pub mod serialize;
 pub mod parser;
 pub mod packer;
+pub mod path;
+pub mod diff;
+pub mod merge;
+pub mod visit;
+#[cfg(feature = "serde")]
+pub mod serde;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(any(feature = "yaml", feature = "toml"))]
+pub mod text;
+#[cfg(feature = "xml")]
+pub mod xml;
 
 
 #[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+pub(crate) mod tests {
     use std::io::prelude::*;
     use std::fs::File;
     use crate::parser::GffParser;
     use crate::packer::Packer;
     use crate::common::{
+        IndexMap,
+        GffFile,
+        GffLayout,
+        FieldLayout,
         GffFieldValue,
         GffStruct,
         GffGender,
         GffLang,
+        ResRef,
         Encodings,
     };
+    use crate::error::LabelPath;
 
-    fn test_pack_unpack(input: &GffStruct) {
+    fn test_pack_unpack(input: &GffFile) {
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
 
-        packer.pack(&input).unwrap();
+        packer.pack(input).unwrap();
 
         let data = packer.writer.into_inner().unwrap();
         let encoding = &*Encodings::NeverwinterNights;
@@ -40,12 +58,64 @@ mod tests {
     fn test_1_field(val: GffFieldValue) {
         let val = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([(String::from("field1"), val)]),
+            fields: IndexMap::from([(String::from("field1"), val)]),
         };
-        test_pack_unpack(&val);
+        test_pack_unpack(&GffFile::new(*b"TEST", val));
+    }
+
+    /// A document with every field type, for the text formats
+    #[cfg(any(feature = "yaml", feature = "toml", feature = "xml"))]
+    pub(crate) fn sample() -> GffFile {
+        GffFile::new(*b"UTC ", GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Tag"), GffFieldValue::CExoString(String::from("nw_bandit"))),
+                (String::from("Appearance"), GffFieldValue::Word(6)),
+                (String::from("GoodEvil"), GffFieldValue::Char(-12)),
+                (String::from("Experience"), GffFieldValue::DWord64(u64::MAX)),
+                (String::from("Scale"), GffFieldValue::Float(0.1)),
+                (String::from("Comment"), GffFieldValue::CExoString(String::from("1234\n\"two\" lines"))),
+                (String::from("Conversation"), GffFieldValue::CResRef(String::new())),
+                (String::from("FirstName"), GffFieldValue::CExoLocString(12345, IndexMap::from([
+                    ((GffLang::French, GffGender::Female), String::from("Bandite")),
+                    ((GffLang::English, GffGender::Male), String::from("Bandit")),
+                ]))),
+                (String::from("Data"), GffFieldValue::Void(vec![0, 1, 2, 255])),
+                (String::from("Tint_Hair"), GffFieldValue::Struct(GffStruct {
+                    st_type: 0,
+                    fields: IndexMap::from([(String::from("a"), GffFieldValue::Byte(255))]),
+                })),
+                (String::from("VarTable"), GffFieldValue::List(vec![])),
+                (String::from("ItemList"), GffFieldValue::List(vec![
+                    GffStruct {
+                        st_type: 0,
+                        fields: IndexMap::from([
+                            (String::from("InventoryRes"), GffFieldValue::CResRef(String::from("nw_wswss001"))),
+                        ]),
+                    },
+                    GffStruct { st_type: 0xFFFFFFFF, fields: IndexMap::new() },
+                ])),
+            ]),
+        })
+    }
+
+    /// Round trip test.bic through a text format, keeping the field order
+    #[cfg(any(feature = "json", feature = "yaml", feature = "toml", feature = "xml"))]
+    pub(crate) fn round_trip_sample(to_string: fn(&GffFile) -> String,
+        from_str: fn(&str) -> Result<GffFile, crate::error::DataError>)
+    {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let file = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap();
+
+        let text = to_string(&file);
+        let res = from_str(&text).unwrap();
+        assert_eq!(res, file);
+        /* same order */
+        assert_eq!(to_string(&res), text);
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_001_all_single_fields() {
         test_1_field(GffFieldValue::Byte(1));
         test_1_field(GffFieldValue::Char(1));
@@ -63,7 +133,7 @@ mod tests {
         test_1_field(GffFieldValue::Void(b"qweasdzxc".to_vec()));
         test_1_field(GffFieldValue::CExoLocString(
                 0xFFFFFFFF,
-                HashMap::from([
+                IndexMap::from([
                     ((GffLang::English, GffGender::Male), String::from("Hello sir")),
                     ((GffLang::English, GffGender::Female), String::from("Hello milady")),
                     ((GffLang::French, GffGender::Male), String::from("Salut bogosse")),
@@ -74,7 +144,7 @@ mod tests {
             GffFieldValue::Struct(
                 GffStruct {
                     st_type: 0xFFFFFFFF,
-                    fields: HashMap::from([
+                    fields: IndexMap::from([
                                 (String::from("field2"), GffFieldValue::Byte(1))
                     ])
                 }
@@ -84,12 +154,12 @@ mod tests {
             GffFieldValue::List(vec![
                 GffStruct {
                     st_type: 0xFFFFFFFF,
-                    fields: HashMap::from([
+                    fields: IndexMap::from([
                                 (String::from("field2"), GffFieldValue::Byte(0xAA))
                     ])
                 }, GffStruct {
                     st_type: 0xFFFFFFFF,
-                    fields: HashMap::from([
+                    fields: IndexMap::from([
                                 (String::from("field2"), GffFieldValue::Byte(0x55))
                     ])
                 }]
@@ -106,4 +176,264 @@ mod tests {
         let v1 = GffParser::parse(buffer, &*Encodings::NeverwinterNights).unwrap();
         test_pack_unpack(&v1);
     }
+
+    #[test]
+    fn test_003_keep_file_type_and_version() {
+        let input = GffFile {
+            file_type: *b"UTC ",
+            version: *b"V3.2",
+            root: GffStruct {
+                st_type: 0xFFFFFFFF,
+                fields: IndexMap::from([
+                    (String::from("Tag"), GffFieldValue::CExoString(String::from("nw_bandit")))
+                ]),
+            },
+        };
+        test_pack_unpack(&input);
+
+        let output = Vec::new();
+        let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
+        packer.pack(&input).unwrap();
+        let data = packer.writer.into_inner().unwrap();
+        assert_eq!(&data[0..8], b"UTC V3.2");
+    }
+
+    fn assert_same_order(a: &GffStruct, b: &GffStruct) {
+        assert!(a.fields.keys().eq(b.fields.keys()));
+        for (va, vb) in a.fields.values().zip(b.fields.values()) {
+            match (va, vb) {
+                (GffFieldValue::Struct(sa), GffFieldValue::Struct(sb)) =>
+                    assert_same_order(sa, sb),
+                (GffFieldValue::List(la), GffFieldValue::List(lb)) =>
+                    la.iter().zip(lb).for_each(|(sa, sb)| assert_same_order(sa, sb)),
+                (GffFieldValue::CExoLocString(_, la), GffFieldValue::CExoLocString(_, lb)) =>
+                    assert!(la.keys().eq(lb.keys())),
+                _ => {},
+            }
+        }
+    }
+
+    fn pack(input: &GffFile) -> Vec<u8> {
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(input).unwrap();
+        packer.writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_004_keep_field_order() {
+        let mut f = File::open("test-data/test.bic").unwrap();
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer).unwrap();
+        let v1 = GffParser::parse(buffer, &*Encodings::NeverwinterNights).unwrap();
+
+        let data = pack(&v1);
+        assert_eq!(data, pack(&v1));
+
+        let v2 = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap();
+        assert_same_order(&v1.root, &v2.root);
+
+        let input = GffFile::new(*b"TEST", GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("b"), GffFieldValue::Byte(1)),
+                (String::from("c"), GffFieldValue::Byte(2)),
+                (String::from("a"), GffFieldValue::Byte(3)),
+            ]),
+        });
+        let output = GffParser::parse(pack(&input), &*Encodings::NeverwinterNights).unwrap();
+        assert!(output.root.fields.keys().eq(["b", "c", "a"]));
+    }
+
+    fn pack_with_layout(input: &GffFile, layout: &GffLayout) -> Vec<u8> {
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_with_layout(input, layout).unwrap();
+        packer.writer.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_005_byte_exact_round_trip() {
+        let mut f = File::open("test-data/test.bic").unwrap();
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer).unwrap();
+        let encoding = &*Encodings::NeverwinterNights;
+        let (mut v1, layout) = GffParser::parse_with_layout(buffer.clone(), encoding).unwrap();
+        assert_eq!(v1, GffParser::parse(buffer.clone(), encoding).unwrap());
+
+        /* unmodified: same bytes */
+        assert_eq!(pack_with_layout(&v1, &layout), buffer);
+
+        /* modified in place, or appended to the field data */
+        v1.root.fields.insert(String::from("FirstName"), GffFieldValue::CExoLocString(
+            0xFFFFFFFF,
+            IndexMap::from([
+                ((GffLang::English, GffGender::Male), String::from("A much longer first name")),
+            ])
+        ));
+        v1.root.fields.insert(String::from("Str"), GffFieldValue::Byte(18));
+        let data = pack_with_layout(&v1, &layout);
+        assert_eq!(data.len(), buffer.len() + 12 + 8 + 24);
+        assert_eq!(GffParser::parse(data, encoding).unwrap(), v1);
+
+        /* different shape: falls back to the default layout */
+        v1.root.fields.insert(String::from("NewField"), GffFieldValue::DWord(1));
+        let data = pack_with_layout(&v1, &layout);
+        assert_eq!(data, pack(&v1));
+        assert_eq!(GffParser::parse(data, encoding).unwrap(), v1);
+
+        /* data shared by two fields is not overwritten */
+        let (_, mut layout) = GffParser::parse_with_layout(buffer.clone(), encoding).unwrap();
+        let mut v1 = GffParser::parse(buffer.clone(), encoding).unwrap();
+        let field = |label: &str| v1.root.fields.get_index_of(label).unwrap();
+        let (deity, familiar) = (field("Deity"), field("FamiliarName"));
+        layout.structs[0].fields[deity] = FieldLayout {
+            index: layout.structs[0].fields[deity].index,
+            ..layout.structs[0].fields[familiar].clone()
+        };
+        v1.root.fields.insert(String::from("Deity"), GffFieldValue::CExoString(String::from("Greal")));
+        v1.root.fields.insert(String::from("FamiliarName"), GffFieldValue::CExoString(String::from("Grael")));
+        let data = pack_with_layout(&v1, &layout);
+        assert_eq!(GffParser::parse(data, encoding).unwrap(), v1);
+
+        /* placements out of the packed blocks */
+        layout.structs[0].index = 1 << 30;
+        let mut packer = Packer::new(Vec::new(), encoding);
+        assert_eq!(packer.pack_with_layout(&v1, &layout).unwrap_err().to_string(),
+            "layout does not fit the packed data");
+        layout.structs[0].index = 0;
+        layout.structs[1].fields[0].index = u32::MAX;
+        let mut packer = Packer::new(Vec::new(), encoding);
+        assert_eq!(packer.pack_with_layout(&v1, &layout).unwrap_err().to_string(),
+            "ClassList[0].Class: layout does not fit the packed data");
+    }
+
+    #[test]
+    fn test_006_pack_direct_sample() {
+        let mut f = File::open("test-data/test.bic").unwrap();
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer).unwrap();
+        let v1 = GffParser::parse(buffer, &*Encodings::NeverwinterNights).unwrap();
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(v1.file_type, v1.version, &v1.root).unwrap();
+        assert_eq!(packer.writer.into_inner().unwrap(), pack(&v1));
+    }
+
+    #[test]
+    fn test_007_resref() {
+        assert_eq!(ResRef::new("nw_it_gold001").unwrap().as_str(), "nw_it_gold001");
+        assert_eq!("Art_Dague04".parse::<ResRef>().unwrap().to_string(), "Art_Dague04");
+        assert_eq!(ResRef::new("a_resref_too_long").unwrap_err().to_string(),
+            "resref is longer than 16 bytes");
+        assert_eq!(ResRef::new("test.bic").unwrap_err().to_string(),
+            "resref contains invalid characters");
+        assert_eq!(ResRef::try_from(&GffFieldValue::CResRef(String::from("with space")))
+            .unwrap_err().to_string(), "resref contains invalid characters");
+
+        /* packed lowercased, unless reusing the parsed layout */
+        let buffer = std::fs::read("test-data/test.bic").unwrap();
+        let encoding = &*Encodings::NeverwinterNights;
+        let (mut v1, layout) = GffParser::parse_with_layout(buffer, encoding).unwrap();
+        v1.root.fields.insert(String::from("Portrait"),
+            GffFieldValue::CResRef(String::from("PO_EL_F_19_")));
+        assert_eq!(GffParser::parse(pack_with_layout(&v1, &layout), encoding).unwrap(), v1);
+        let res = GffParser::parse(pack(&v1), encoding).unwrap();
+        assert_eq!(res.root.fields["Portrait"], GffFieldValue::CResRef(String::from("po_el_f_19_")));
+    }
+
+    #[test]
+    fn test_008_label_path() {
+        for path in ["Gold", "ItemList[2].Tag", "ClassList[0].KnownList0[12].Spell", "Tint.a"] {
+            assert_eq!(path.parse::<LabelPath>().unwrap().to_string(), path);
+        }
+        assert_eq!("".parse::<LabelPath>().unwrap(), LabelPath::default());
+        for path in ["[0]", "Tag.", ".Tag", "List[0]Tag", "List[0][1]", "List[a]", "List[0", "A..B"] {
+            assert_eq!(path.parse::<LabelPath>().unwrap_err().to_string(),
+                format!("invalid path `{}`", path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_009_edit_by_path() {
+        let path = |s: &str| s.parse::<LabelPath>().unwrap();
+        let item = |tag: &str| GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([(String::from("Tag"), GffFieldValue::CExoString(String::from(tag)))]),
+        };
+        let mut root = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Gold"), GffFieldValue::DWord(100)),
+                (String::from("ItemList"), GffFieldValue::List(vec![item("a"), item("b")])),
+                (String::from("Deity"), GffFieldValue::CExoString(String::new())),
+            ]),
+        };
+
+        assert_eq!(root.get_path(&path("ItemList[1].Tag")).unwrap(),
+            &GffFieldValue::CExoString(String::from("b")));
+        assert_eq!(root.struct_at(&path("ItemList[0]")).unwrap(), &item("a"));
+        *root.get_path_mut(&path("Gold")).unwrap() = GffFieldValue::DWord(0);
+        assert_eq!(root.set_path(&path("Deity"), GffFieldValue::CExoString(String::from("Tyr"))).unwrap(),
+            Some(GffFieldValue::CExoString(String::new())));
+        assert_eq!(root.set_path(&path("ItemList[0].Stolen"), GffFieldValue::Byte(1)).unwrap(), None);
+        root.insert_path(&path("ItemList[2]"), item("c")).unwrap();
+        root.insert_path(&path("ItemList[0]"), item("z")).unwrap();
+        assert_eq!(root.remove_path(&path("ItemList[2]")).unwrap(), GffFieldValue::Struct(item("b")));
+        assert_eq!(root.remove_path(&path("Gold")).unwrap(), GffFieldValue::DWord(0));
+
+        let mut first = item("a");
+        first.fields.insert(String::from("Stolen"), GffFieldValue::Byte(1));
+        assert_eq!(root, GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("ItemList"), GffFieldValue::List(vec![item("z"), first, item("c")])),
+                (String::from("Deity"), GffFieldValue::CExoString(String::from("Tyr"))),
+            ]),
+        });
+
+        fn err<T: std::fmt::Debug>(res: Result<T, crate::error::DataError>) -> String {
+            res.unwrap_err().to_string()
+        }
+        assert_eq!(err(root.get_path(&path("ItemList[3].Tag"))),
+            "ItemList[3]: index 3 out of range for a list of 3 structs");
+        assert_eq!(err(root.get_path(&path("ItemList[1].Name"))), "ItemList[1].Name: missing field");
+        assert_eq!(err(root.get_path(&path("Deity.Name"))), "Deity: expected Struct, found CExoString");
+        assert_eq!(err(root.get_path(&path("Deity[0].Name"))), "Deity: expected List, found CExoString");
+        assert_eq!(err(root.get_path(&path("ItemList[0]"))), "invalid path `ItemList[0]`");
+        assert_eq!(err(root.insert_path(&path("ItemList[4]"), item("d"))),
+            "ItemList[4]: index 4 out of range for a list of 3 structs");
+        assert_eq!(err(root.insert_path(&path("Items[0]"), item("d"))), "Items: missing field");
+        assert_eq!(err(root.set_path(&path("a_label_too_long_"), GffFieldValue::Byte(0))),
+            "a_label_too_long_: label is longer than 16 bytes");
+    }
+
+    #[test]
+    fn test_010_local_variables() {
+        let mut f = File::open("test-data/test.bic").unwrap();
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer).unwrap();
+        let res = GffParser::parse(buffer, &*Encodings::NeverwinterNights).unwrap();
+        let path = |s: &str| s.parse::<LabelPath>().unwrap();
+
+        /* maps are read from and written as the VarTable of the game */
+        let var_table = res.root.get_path(&path("ItemList[22].ItemList[1].VarTable")).unwrap();
+        let vars = IndexMap::<String, i32>::try_from(var_table).unwrap();
+        assert_eq!(vars.keys().collect::<Vec<_>>(), ["byscript", "valeur", "lvl", "PermanentUsure", "random"]);
+        assert_eq!(&TryInto::<GffFieldValue>::try_into(&vars).unwrap(), var_table);
+
+        fn err<T: std::fmt::Debug>(res: Result<T, crate::error::DataError>) -> String {
+            res.unwrap_err().to_string()
+        }
+        let floats = IndexMap::from([(String::from("a"), 1.5f32)]);
+        let mut var_table = TryInto::<GffFieldValue>::try_into(&floats).unwrap();
+        if let GffFieldValue::List(entries) = &mut var_table {
+            entries[0].fields.insert(String::from("Type"), GffFieldValue::DWord(1));
+        }
+        assert_eq!(err(IndexMap::<String, f32>::try_from(&var_table)),
+            "[0].Type: expected variable type 2, found 1");
+        if let GffFieldValue::List(entries) = &mut var_table {
+            entries[0].fields.insert(String::from("Name"), GffFieldValue::DWord(1));
+        }
+        assert_eq!(err(IndexMap::<String, f32>::try_from(&var_table)),
+            "[0].Name: expected CExoString, found DWord");
+    }
 }
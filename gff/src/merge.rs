@@ -0,0 +1,532 @@
+//! Three-way merge of structs
+//!
+//! [`merge`] combines the changes made to a common `base` struct in
+//! two versions of it, `ours` and `theirs`. A field changed on one side
+//! only takes the changed value. A struct or a list changed on both
+//! sides is merged recursively, and any other field changed on both
+//! sides, in different ways, is a [`Conflict`]: it keeps our value,
+//! and is reported by its label path.
+//!
+//! The structs of a list are matched by a key field (see
+//! [`DiffOptions`]), or else aligned on the structs that each side left
+//! unchanged. Structs added on both sides are all kept, ours first.
+//!
+//! ```
+//! # use gff::common::{IndexMap, GffFieldValue, GffStruct};
+//! let base = GffStruct {
+//!     st_type: 0xFFFFFFFF,
+//!     fields: IndexMap::from([
+//!         (String::from("Gold"), GffFieldValue::DWord(100)),
+//!         (String::from("XP"), GffFieldValue::DWord(0)),
+//!     ]),
+//! };
+//! let mut ours = base.clone();
+//! ours.fields.insert(String::from("Gold"), GffFieldValue::DWord(50));
+//! let mut theirs = base.clone();
+//! theirs.fields.insert(String::from("XP"), GffFieldValue::DWord(1000));
+//!
+//! let merged = gff::merge::merge(&base, &ours, &theirs);
+//! assert!(merged.conflicts.is_empty());
+//! assert_eq!(merged.result.fields["Gold"], GffFieldValue::DWord(50));
+//! assert_eq!(merged.result.fields["XP"], GffFieldValue::DWord(1000));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::common::{IndexMap, GffFieldValue, GffStruct};
+use crate::diff::{key_value, same_struct, same_value, DiffOptions, KeyValue, Summary};
+use crate::error::{LabelPath, PathElement};
+
+/// A value changed on both sides, in different ways
+///
+/// Values are `None` where the field is missing. Conflicting structs of
+/// a list are [`GffFieldValue::Struct`] values, at their index in our
+/// list, or in theirs if we removed them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub path: LabelPath,
+    pub base: Option<GffFieldValue>,
+    pub ours: Option<GffFieldValue>,
+    pub theirs: Option<GffFieldValue>,
+}
+
+/// Result of a three-way merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    /// Merged struct, with our values for the conflicts
+    pub result: GffStruct,
+    /// Conflicts, in the order of the merged struct
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Merge the changes made to `base` in `ours` and `theirs`, aligning
+/// the structs of lists on their unchanged structs
+pub fn merge(base: &GffStruct, ours: &GffStruct, theirs: &GffStruct) -> Merge {
+    merge_with(base, ours, theirs, &DiffOptions::default())
+}
+
+/// Merge the changes made to `base` in `ours` and `theirs`, see [`merge`]
+pub fn merge_with(base: &GffStruct, ours: &GffStruct, theirs: &GffStruct, options: &DiffOptions) -> Merge {
+    let mut merger = Merger { options, path: LabelPath::default(), conflicts: Vec::new() };
+    let result = merger.structs(base, ours, theirs);
+    Merge { result, conflicts: merger.conflicts }
+}
+
+/// The value if at most one side changed it, `None` for a conflict
+///
+/// Values are compared with `same`, so that floats can be compared
+/// by their bits.
+fn pick<'a, T>(base: Option<&'a T>, ours: Option<&'a T>, theirs: Option<&'a T>,
+    same: impl Fn(&T, &T) -> bool)
+    -> Option<Option<&'a T>>
+{
+    let eq = |a: Option<&T>, b: Option<&T>| match (a, b) {
+        (Some(a), Some(b)) => same(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    };
+    if eq(ours, theirs) || eq(theirs, base) {
+        Some(ours)
+    } else if eq(ours, base) {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+/// Identity of a struct in a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ItemId<'a> {
+    /// Key field value, and number of structs with the same key before it
+    Key(Option<KeyValue<'a>>, usize),
+    /// Index of the same struct in the base list
+    Base(usize),
+    /// Index of a struct added by one side
+    Added(Side, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Side {
+    Ours,
+    Theirs,
+}
+
+fn keyed_ids<'a>(list: &'a [GffStruct], key: &str) -> Vec<ItemId<'a>> {
+    let mut counts = HashMap::new();
+    list.iter().map(|st| {
+        let value = key_value(st, key);
+        let count = counts.entry(value).or_insert(0);
+        *count += 1;
+        ItemId::Key(value, *count - 1)
+    }).collect()
+}
+
+fn aligned_ids(base: &[GffStruct], list: &[GffStruct], side: Side) -> Vec<ItemId<'static>> {
+    align(base, list).into_iter().enumerate()
+        .map(|(i, b)| b.map_or(ItemId::Added(side, i), ItemId::Base))
+        .collect()
+}
+
+/// Index of each struct of a list, by its identity
+fn positions<'a>(ids: &[ItemId<'a>]) -> HashMap<ItemId<'a>, usize> {
+    ids.iter().enumerate().map(|(i, id)| (*id, i)).collect()
+}
+
+/// Index in `base` of each struct of `list`, if it is one of them
+///
+/// Structs are first aligned on the longest common subsequence of
+/// equal structs. Between two aligned structs, the others are changed
+/// in place if both lists have as many of them, and are otherwise
+/// removed and added: a struct that the other side changed is then a
+/// conflict, rather than being merged with the wrong one.
+fn align(base: &[GffStruct], list: &[GffStruct]) -> Vec<Option<usize>> {
+    let mut aligned = vec![None; list.len()];
+    let prefix = base.iter().zip(list).take_while(|(b, l)| same_struct(b, l)).count();
+    let suffix = base[prefix..].iter().rev().zip(list[prefix..].iter().rev())
+        .take_while(|(b, l)| same_struct(b, l))
+        .count();
+    for (i, at) in aligned[..prefix].iter_mut().enumerate() {
+        *at = Some(i);
+    }
+    for k in 1..=suffix {
+        aligned[list.len() - k] = Some(base.len() - k);
+    }
+
+    /* lengths[i][j]: longest common subsequence of base[i..] and list[j..] */
+    let (base_mid, list_mid) = (&base[prefix..base.len() - suffix], &list[prefix..list.len() - suffix]);
+    let (n, m) = (base_mid.len(), list_mid.len());
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = match same_struct(&base_mid[i], &list_mid[j]) {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+    let mut changed = |base_gap: std::ops::Range<usize>, list_gap: std::ops::Range<usize>| {
+        if base_gap.len() == list_gap.len() {
+            for (b, l) in base_gap.zip(list_gap) {
+                aligned[prefix + l] = Some(prefix + b);
+            }
+        }
+    };
+    let (mut i, mut j, mut gap) = (0, 0, (0, 0));
+    while i < n && j < m {
+        if same_struct(&base_mid[i], &list_mid[j]) {
+            changed(gap.0..i, gap.1..j);
+            changed(i..i + 1, j..j + 1);
+            i += 1;
+            j += 1;
+            gap = (i, j);
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    changed(gap.0..n, gap.1..m);
+    aligned
+}
+
+struct Merger<'o> {
+    options: &'o DiffOptions,
+    /// Path of the merged value
+    path: LabelPath,
+    conflicts: Vec<Conflict>,
+}
+
+impl Merger<'_> {
+    fn conflict(&mut self, base: Option<GffFieldValue>, ours: Option<GffFieldValue>, theirs: Option<GffFieldValue>) {
+        self.conflicts.push(Conflict { path: self.path.clone(), base, ours, theirs });
+    }
+
+    /// Merge structs that both sides changed
+    ///
+    /// Structs whose type id changed on both sides are a conflict.
+    fn structs(&mut self, base: &GffStruct, ours: &GffStruct, theirs: &GffStruct) -> GffStruct {
+        let Some(Some(st_type)) = pick(Some(&base.st_type), Some(&ours.st_type), Some(&theirs.st_type), u32::eq) else {
+            self.conflict(
+                Some(GffFieldValue::Struct(base.clone())),
+                Some(GffFieldValue::Struct(ours.clone())),
+                Some(GffFieldValue::Struct(theirs.clone())));
+            return ours.clone();
+        };
+        let labels = ours.fields.keys()
+            .chain(theirs.fields.keys().filter(|label| !ours.fields.contains_key(*label)));
+        let mut fields = IndexMap::with_capacity(ours.fields.len());
+        for label in labels {
+            self.path.0.push(PathElement::Label(label.clone()));
+            if let Some(value) = self.fields(base.fields.get(label), ours.fields.get(label), theirs.fields.get(label)) {
+                fields.insert(label.clone(), value);
+            }
+            self.path.0.pop();
+        }
+        GffStruct { st_type: *st_type, fields }
+    }
+
+    fn fields(&mut self, base: Option<&GffFieldValue>, ours: Option<&GffFieldValue>, theirs: Option<&GffFieldValue>)
+        -> Option<GffFieldValue>
+    {
+        if let Some(value) = pick(base, ours, theirs, same_value) {
+            return value.cloned();
+        }
+        match (base, ours, theirs) {
+            (Some(GffFieldValue::Struct(base)), Some(GffFieldValue::Struct(ours)), Some(GffFieldValue::Struct(theirs))) =>
+                Some(GffFieldValue::Struct(self.structs(base, ours, theirs))),
+            (Some(GffFieldValue::List(base)), Some(GffFieldValue::List(ours)), Some(GffFieldValue::List(theirs))) =>
+                Some(GffFieldValue::List(self.lists(base, ours, theirs))),
+            _ => {
+                self.conflict(base.cloned(), ours.cloned(), theirs.cloned());
+                ours.cloned()
+            },
+        }
+    }
+
+    /// Merge a struct of a list, `None` if it is removed
+    fn items(&mut self, base: Option<&GffStruct>, ours: Option<&GffStruct>, theirs: Option<&GffStruct>)
+        -> Option<GffStruct>
+    {
+        if let Some(st) = pick(base, ours, theirs, same_struct) {
+            return st.cloned();
+        }
+        match (base, ours, theirs) {
+            (Some(base), Some(ours), Some(theirs)) => Some(self.structs(base, ours, theirs)),
+            _ => {
+                let value = |st: Option<&GffStruct>| st.cloned().map(GffFieldValue::Struct);
+                self.conflict(value(base), value(ours), value(theirs));
+                ours.cloned()
+            },
+        }
+    }
+
+    /// Merge the struct at `index` of a list, see [`Merger::items`]
+    fn item_at(&mut self, index: usize, base: Option<&GffStruct>, ours: Option<&GffStruct>, theirs: Option<&GffStruct>)
+        -> Option<GffStruct>
+    {
+        self.path.0.push(PathElement::Index(index));
+        let st = self.items(base, ours, theirs);
+        self.path.0.pop();
+        st
+    }
+
+    /// Merge lists that both sides changed
+    ///
+    /// The merged list has our order, the structs that only they added
+    /// being inserted after the struct before them in their list, and
+    /// after the structs that we added there.
+    fn lists(&mut self, base: &[GffStruct], ours: &[GffStruct], theirs: &[GffStruct]) -> Vec<GffStruct> {
+        let key = match self.path.0.last() {
+            Some(PathElement::Label(label)) => self.options.key_of(label),
+            _ => None,
+        };
+        let (base_ids, our_ids, their_ids) = match key {
+            Some(key) => (keyed_ids(base, key), keyed_ids(ours, key), keyed_ids(theirs, key)),
+            None => (
+                (0..base.len()).map(ItemId::Base).collect(),
+                aligned_ids(base, ours, Side::Ours),
+                aligned_ids(base, theirs, Side::Theirs),
+            ),
+        };
+        let (base_at, our_at, their_at) = (positions(&base_ids), positions(&our_ids), positions(&their_ids));
+
+        /* structs that only they have, by the struct before them that we have */
+        let mut added: HashMap<Option<ItemId>, Vec<usize>> = HashMap::new();
+        let mut before = None;
+        for (t, id) in their_ids.iter().enumerate() {
+            match our_at.contains_key(id) {
+                true => before = Some(*id),
+                false => added.entry(before).or_default().push(t),
+            }
+        }
+
+        let mut merged = Vec::with_capacity(ours.len());
+        let mut before = None;
+        for (i, id) in our_ids.iter().enumerate() {
+            if base_at.contains_key(id) || their_at.contains_key(id) {
+                for t in added.remove(&before).unwrap_or_default() {
+                    merged.extend(self.item_at(t, base_at.get(&their_ids[t]).map(|b| &base[*b]), None, Some(&theirs[t])));
+                }
+            }
+            merged.extend(self.item_at(i,
+                base_at.get(id).map(|b| &base[*b]),
+                Some(&ours[i]),
+                their_at.get(id).map(|t| &theirs[*t])));
+            if their_at.contains_key(id) {
+                before = Some(*id);
+            }
+        }
+        for t in added.remove(&before).unwrap_or_default() {
+            merged.extend(self.item_at(t, base_at.get(&their_ids[t]).map(|b| &base[*b]), None, Some(&theirs[t])));
+        }
+        merged
+    }
+}
+
+/// `Tag: ours CExoString("a"), theirs CExoString("b"), base CExoString("c")`,
+/// with `none` for missing values
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path.0.is_empty() {
+            true => write!(f, "(root):")?,
+            false => write!(f, "{}:", self.path)?,
+        }
+        for (side, value) in [("ours", &self.ours), ("theirs", &self.theirs), ("base", &self.base)] {
+            if side != "ours" {
+                write!(f, ",")?;
+            }
+            match value {
+                Some(value) => write!(f, " {} {}", side, Summary(value))?,
+                None => write!(f, " {} none", side)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Encodings, GffFieldValue, GffStruct};
+    use crate::diff::DiffOptions;
+    use crate::parser::GffParser;
+    use super::{merge, merge_with};
+
+    fn st(st_type: u32, fields: Vec<(&str, GffFieldValue)>) -> GffStruct {
+        GffStruct {
+            st_type,
+            fields: fields.into_iter().map(|(label, value)| (String::from(label), value)).collect(),
+        }
+    }
+
+    fn item(tag: &str, stack: u16) -> GffStruct {
+        st(0, vec![
+            ("Tag", GffFieldValue::CExoString(String::from(tag))),
+            ("StackSize", GffFieldValue::Word(stack)),
+        ])
+    }
+
+    fn tags(st: &GffStruct) -> Vec<String> {
+        let Some(GffFieldValue::List(items)) = st.fields.get("ItemList") else { unreachable!() };
+        items.iter().map(|item| match (&item.fields["Tag"], &item.fields["StackSize"]) {
+            (GffFieldValue::CExoString(tag), GffFieldValue::Word(stack)) => format!("{}:{}", tag, stack),
+            _ => unreachable!(),
+        }).collect()
+    }
+
+    #[test]
+    fn test_01_fields() {
+        let base = st(0xFFFFFFFF, vec![
+            ("Gold", GffFieldValue::DWord(100)),
+            ("XP", GffFieldValue::DWord(0)),
+            ("Deity", GffFieldValue::CExoString(String::from("Tyr"))),
+            ("Tint", GffFieldValue::Struct(st(0, vec![
+                ("r", GffFieldValue::Byte(0)),
+                ("g", GffFieldValue::Byte(0)),
+            ]))),
+        ]);
+        let mut ours = base.clone();
+        ours.set_path(&"Gold".parse().unwrap(), GffFieldValue::DWord(50)).unwrap();
+        ours.set_path(&"Tint.r".parse().unwrap(), GffFieldValue::Byte(1)).unwrap();
+        ours.set_path(&"Age".parse().unwrap(), GffFieldValue::Int(20)).unwrap();
+        ours.remove_path(&"Deity".parse().unwrap()).unwrap();
+        let mut theirs = base.clone();
+        theirs.set_path(&"XP".parse().unwrap(), GffFieldValue::DWord(1000)).unwrap();
+        theirs.set_path(&"Tint.g".parse().unwrap(), GffFieldValue::Byte(2)).unwrap();
+        theirs.set_path(&"Age".parse().unwrap(), GffFieldValue::Int(20)).unwrap();
+        theirs.set_path(&"Subrace".parse().unwrap(), GffFieldValue::CExoString(String::from("elf"))).unwrap();
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, []);
+        let expected = st(0xFFFFFFFF, vec![
+            ("Gold", GffFieldValue::DWord(50)),
+            ("XP", GffFieldValue::DWord(1000)),
+            ("Tint", GffFieldValue::Struct(st(0, vec![
+                ("r", GffFieldValue::Byte(1)),
+                ("g", GffFieldValue::Byte(2)),
+            ]))),
+            ("Age", GffFieldValue::Int(20)),
+            ("Subrace", GffFieldValue::CExoString(String::from("elf"))),
+        ]);
+        assert_eq!(merged.result, expected);
+        assert_eq!(merged.result.fields.keys().collect::<Vec<_>>(), ["Gold", "XP", "Tint", "Age", "Subrace"]);
+        assert_eq!(merge(&base, &ours, &theirs), merge(&base, &ours, &theirs));
+        assert_eq!(merge(&base, &base, &theirs).result, theirs);
+        assert_eq!(merge(&base, &ours, &base).result, ours);
+
+        /* conflicts keep our values */
+        theirs.set_path(&"Gold".parse().unwrap(), GffFieldValue::Int(10)).unwrap();
+        theirs.set_path(&"Tint.r".parse().unwrap(), GffFieldValue::Byte(3)).unwrap();
+        theirs.set_path(&"Deity".parse().unwrap(), GffFieldValue::CExoString(String::from("Lathander"))).unwrap();
+        theirs.st_type = 1;
+        let merged = merge(&base, &ours, &theirs);
+        let conflicts: Vec<String> = merged.conflicts.iter().map(|c| c.to_string()).collect();
+        assert_eq!(conflicts, [
+            "Gold: ours DWord(50), theirs Int(10), base DWord(100)",
+            "Tint.r: ours Byte(1), theirs Byte(3), base Byte(0)",
+            "Deity: ours none, theirs CExoString(\"Lathander\"), base CExoString(\"Tyr\")",
+        ]);
+        assert_eq!(merged.result.st_type, 1);
+        assert_eq!(merged.result.fields["Gold"], GffFieldValue::DWord(50));
+        assert!(!merged.result.fields.contains_key("Deity"));
+
+        ours.st_type = 2;
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert!(merged.conflicts[0].to_string()
+            .starts_with("(root): ours Struct(0x2, 4 fields), theirs Struct(0x1, 6 fields)"));
+        assert_eq!(merged.result, ours);
+    }
+
+    #[test]
+    fn test_02_lists() {
+        let list = |items: Vec<GffStruct>| st(0xFFFFFFFF, vec![("ItemList", GffFieldValue::List(items))]);
+        let base = list(vec![item("a", 1), item("b", 1), item("c", 1), item("d", 1)]);
+        let ours = list(vec![item("x", 1), item("a", 2), item("b", 1), item("c", 1), item("d", 1)]);
+        let theirs = list(vec![item("a", 1), item("c", 3), item("y", 1), item("d", 1), item("z", 1)]);
+        let by_tag = DiffOptions::default().key("ItemList", "Tag");
+
+        let merged = merge_with(&base, &ours, &theirs, &by_tag);
+        assert_eq!(merged.conflicts, []);
+        assert_eq!(tags(&merged.result), ["x:1", "a:2", "c:3", "y:1", "d:1", "z:1"]);
+        assert_eq!(merge_with(&base, &theirs, &ours, &by_tag).result,
+            list(vec![item("x", 1), item("a", 2), item("c", 3), item("y", 1), item("d", 1), item("z", 1)]));
+
+        /* a struct changed on one side and removed on the other */
+        let theirs = list(vec![item("b", 1), item("c", 1), item("d", 1)]);
+        let merged = merge_with(&base, &ours, &theirs, &by_tag);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].path.to_string(), "ItemList[1]");
+        assert_eq!(merged.conflicts[0].theirs, None);
+        assert_eq!(tags(&merged.result), ["x:1", "a:2", "b:1", "c:1", "d:1"]);
+
+        /* without a key, the structs are aligned on the unchanged ones */
+        let ours = list(vec![item("a", 2), item("b", 1), item("c", 1), item("d", 1), item("x", 1)]);
+        let theirs = list(vec![item("a", 1), item("b", 1), item("c", 3), item("d", 1), item("y", 1)]);
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, []);
+        assert_eq!(tags(&merged.result), ["a:2", "b:1", "c:3", "d:1", "x:1", "y:1"]);
+
+        /* a struct removed on one side, and the next one changed on the other */
+        let ours = list(vec![item("b", 1), item("c", 1), item("d", 1)]);
+        let theirs = list(vec![item("a", 1), item("b", 2), item("c", 1), item("d", 1)]);
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, []);
+        assert_eq!(tags(&merged.result), ["b:2", "c:1", "d:1"]);
+        assert_eq!(merge(&base, &theirs, &ours).result, merged.result);
+
+        /* a struct removed and the next one changed on one side, ambiguous */
+        let ours = list(vec![item("b", 2), item("c", 1), item("d", 1)]);
+        let theirs = list(vec![item("a", 1), item("b", 3), item("c", 1), item("d", 1)]);
+        let merged = merge(&base, &ours, &theirs);
+        let conflicts: Vec<String> = merged.conflicts.iter().map(|c| c.path.to_string()).collect();
+        assert_eq!(conflicts, ["ItemList[1]"]);
+        assert_eq!(merged.conflicts[0].ours, None);
+        assert_eq!(tags(&merged.result), ["b:2", "c:1", "d:1"]);
+
+        /* duplicate keys are matched in order */
+        let base = list(vec![item("a", 1), item("a", 2)]);
+        let ours = list(vec![item("a", 1), item("a", 3)]);
+        let theirs = list(vec![item("b", 1), item("a", 1), item("a", 2)]);
+        assert_eq!(tags(&merge_with(&base, &ours, &theirs, &by_tag).result), ["b:1", "a:1", "a:3"]);
+    }
+
+    #[test]
+    fn test_03_sample() {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let base = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap().root;
+        let mut ours = base.clone();
+        ours.remove_path(&"ItemList[3]".parse().unwrap()).unwrap();
+        ours.set_path(&"Gold".parse().unwrap(), GffFieldValue::DWord(0)).unwrap();
+        let mut theirs = base.clone();
+        theirs.set_path(&"ItemList[10].Stolen".parse().unwrap(), GffFieldValue::Byte(1)).unwrap();
+        theirs.insert_path(&"ItemList[0]".parse().unwrap(), item("new", 1)).unwrap();
+
+        let merged = merge_with(&base, &ours, &theirs, &DiffOptions::default().key("ItemList", "ObjectId"));
+        assert_eq!(merged.conflicts, []);
+        let mut expected = ours.clone();
+        expected.set_path(&"ItemList[9].Stolen".parse().unwrap(), GffFieldValue::Byte(1)).unwrap();
+        expected.insert_path(&"ItemList[0]".parse().unwrap(), item("new", 1)).unwrap();
+        assert_eq!(merged.result, expected);
+    }
+
+    #[test]
+    fn test_04_unchanged_nan() {
+        let nan = |label| (label, GffFieldValue::Float(f32::NAN));
+        let base = st(0xFFFFFFFF, vec![
+            nan("ChallengeRating"),
+            ("Gold", GffFieldValue::DWord(100)),
+            ("Tint", GffFieldValue::Struct(st(0, vec![nan("a")]))),
+            ("ItemList", GffFieldValue::List(vec![st(0, vec![nan("Weight")]), item("a", 1)])),
+        ]);
+        let mut ours = base.clone();
+        ours.set_path(&"Gold".parse().unwrap(), GffFieldValue::DWord(50)).unwrap();
+        let mut theirs = base.clone();
+        theirs.set_path(&"ItemList[1].StackSize".parse().unwrap(), GffFieldValue::Word(2)).unwrap();
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts, []);
+        let mut expected = ours.clone();
+        expected.set_path(&"ItemList[1].StackSize".parse().unwrap(), GffFieldValue::Word(2)).unwrap();
+        assert!(crate::diff::same_struct(&merged.result, &expected));
+    }
+}
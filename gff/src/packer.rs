@@ -1,17 +1,32 @@
 //! Packer for the GFF format
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::borrow::Cow;
 
 use crate::common::{
+    IndexMap,
+    GffFile,
+    GffLayout,
+    FieldLayout,
+    StructLayout,
     GffHeader,
     GffStruct,
     GffFieldValue,
     GffLang,
     GffGender,
+    ResRef,
+    LocString,
+    VoidData,
     EncodingFn,
+    LocalVariable,
 };
+use crate::error::{
+    DataError,
+    DataErrorKind,
+    LabelPath,
+};
+use crate::serialize::{serialize_map, sorted_entries};
 
 /// Data generated from a `GffStruct` by the packer.
 pub struct PackData {
@@ -112,27 +127,63 @@ impl <'enc, 'input, W: std::io::Write> Packer<'enc, W> {
 
     /* {{{ Pack functions */
 
-    /// Pack a GffStruct.
+    /// Pack a GffFile.
     ///
     /// This is used as the entry point of data packing.
+    /// The file type and version are written as-is in the header.
+    /// Resource references are lowercased, as in the game files.
+    pub fn pack(&mut self, input: &'input GffFile)
+        -> Result<(), DataError>
+    {
+        self.data.header.gff_type = input.file_type;
+        self.data.header.version = input.version;
+
+        let mut structs: VecDeque<(&GffStruct, LabelPath)> =
+            VecDeque::from([(&input.root, LabelPath::default())]);
+        let mut current_st_idx = 0;
+
+        while let Some((struct_to_write, path)) = structs.pop_front() {
+            self.pack_struct(struct_to_write, &path, &mut structs, &mut current_st_idx)
+                .map_err(|e| e.at_path(&path))?;
+        }
+
+        self.finalize();
+        self.write()?;
+
+        Ok(())
+    }
+
+    /// Pack a struct directly, without building a [`GffStruct`] first.
     ///
-    pub fn pack(&mut self, input: &'input GffStruct)
-        -> Result<(), &'static str>
+    /// This is the entry point of direct packing. Sub-structs are queued
+    /// by [`PackField::pack_field`] and packed afterwards, so the output
+    /// is identical to what [`Packer::pack`] produces from the serialized
+    /// struct, resource references included.
+    /// The file type and version are written as-is in the header,
+    /// as with [`Packer::pack`].
+    pub fn pack_direct<'a>(&mut self, file_type: [u8; 4], version: [u8; 4],
+        input: &'a impl PackStruct<'a, W>)
+        -> Result<(), DataError> where W: 'a
     {
-        let mut structs: Vec<&GffStruct> = vec![input];
+        self.data.header.gff_type = file_type;
+        self.data.header.version = version;
+
+        let mut structs = StructQueue {
+            queue: VecDeque::from([(QueuedStruct::Struct(input), LabelPath::default())]),
+            path: LabelPath::default(),
+        };
         let mut current_st_idx = 0;
 
-        loop {
-            let st_count = structs.len();
-            if st_count == 0 {
-                break;
-            }
-            let struct_to_write = structs.remove(0);
-            self.pack_struct(struct_to_write, &mut structs, &mut current_st_idx)?;
+        while let Some((struct_to_write, path)) = structs.queue.pop_front() {
+            structs.path = path;
+            match struct_to_write {
+                QueuedStruct::Struct(st) => st.pack(self, &mut structs, &mut current_st_idx),
+                QueuedStruct::Variable(var) => self.pack_variable(&var),
+            }.map_err(|e| e.at_path(&structs.path))?;
         }
 
         self.finalize();
-        self.write().map_err(|_e| "failed to write packed data")?;
+        self.write()?;
 
         Ok(())
     }
@@ -142,81 +193,95 @@ impl <'enc, 'input, W: std::io::Write> Packer<'enc, W> {
     /// This packs all basic field data.
     /// Structs/Lists of structs will be pushed in a vec and
     /// packed afterwards.
-    fn pack_struct(&mut self, input: &'input GffStruct,
-        structs: &mut Vec<&'input GffStruct>, current_st_idx: &mut u32)
-        -> Result<(), &'static str>
+    fn pack_struct(&mut self, input: &'input GffStruct, path: &LabelPath,
+        structs: &mut VecDeque<(&'input GffStruct, LabelPath)>, current_st_idx: &mut u32)
+        -> Result<(), DataError>
     {
-        /* write struct type */
-        self.data.structs.extend_from_slice(&input.st_type.to_le_bytes());
-        match input.fields.len() {
-            0 => {
-                self.data.structs.extend_from_slice(
-                    &(self.data.header.fields.1 as u32).to_le_bytes()
-                );
-                self.data.structs.extend_from_slice(
-                    &0u32.to_le_bytes()
-                );
-            },
-            1 => {
-                self.data.structs.extend_from_slice(
-                    &(self.data.header.fields.1 as u32).to_le_bytes()
-                );
-                self.data.structs.extend_from_slice(
-                    &1u32.to_le_bytes()
-                );
-            },
-            field_count => {
-                self.data.structs.extend_from_slice(
-                    &(self.data.header.field_indices.1 as u32).to_le_bytes()
-                );
-                self.data.structs.extend_from_slice(
-                    &(field_count as u32).to_le_bytes()
-                );
-            }
-        };
+        self.begin_struct(input.st_type, input.fields.len() as u32);
 
-        self.data.header.structs.1 += 1;
         let mut field_indices = vec![];
         for (field, value) in &input.fields {
             let field_id = self.pack_field(
-                field, value, structs, current_st_idx
-            )?;
+                field, value, path, structs, current_st_idx
+            ).map_err(|e| e.at_label(field))?;
 
             field_indices.push(field_id);
         }
 
+        self.end_struct(&field_indices);
+        Ok(())
+    }
+
+    /// Pack a local variable, queued by the [`PackField`] of a map.
+    ///
+    /// Its fields only hold values, so it has no sub-struct to queue.
+    fn pack_variable(&mut self, var: &GffStruct) -> Result<(), DataError> {
+        self.begin_struct(var.st_type, var.fields.len() as u32);
+        let field_indices = var.fields.iter().map(|(label, value)| {
+            let label_idx = self.pack_label(label)?;
+            match value {
+                GffFieldValue::DWord(val) => Ok(self.pack_val_4(4, label_idx, &val.to_le_bytes())),
+                GffFieldValue::Int(val) => Ok(self.pack_val_4(5, label_idx, &val.to_le_bytes())),
+                GffFieldValue::Float(val) => Ok(self.pack_val_4(8, label_idx, &val.to_le_bytes())),
+                _ => self.pack_data_value(label_idx, value),
+            }.map_err(|e| e.at_label(label))
+        }).collect::<Result<Vec<u32>, DataError>>()?;
+        self.end_struct(&field_indices);
+        Ok(())
+    }
+
+    /// Pack a struct type, field offset and field count into the structs block.
+    ///
+    /// The fields of the struct must be packed right after this,
+    /// and their indices given to [`Packer::end_struct`].
+    pub fn begin_struct(&mut self, st_type: u32, field_count: u32) {
+        /* write struct type */
+        self.data.structs.extend_from_slice(&st_type.to_le_bytes());
+        let field_offset = match field_count {
+            0..=1 => self.data.header.fields.1,
+            _ => self.data.header.field_indices.1,
+        };
+        self.data.structs.extend_from_slice(&field_offset.to_le_bytes());
+        self.data.structs.extend_from_slice(&field_count.to_le_bytes());
+        self.data.header.structs.1 += 1;
+    }
+
+    /// Pack the indices of a struct's fields into the field_indices block.
+    ///
+    /// Nothing is written for structs with less than 2 fields,
+    /// which reference their field directly.
+    pub fn end_struct(&mut self, field_indices: &[u32]) {
         if field_indices.len() > 1 {
             /* write fields indices into field_indices array */
             for field_indice in field_indices {
                 self.data.field_indices.extend_from_slice(
-                    &(field_indice as u32).to_le_bytes()
+                    &field_indice.to_le_bytes()
                 );
                 self.data.header.field_indices.1 += 4;
             }
         }
-        Ok(())
     }
 
     ///  Pack a field label into the labels block.
     ///
     /// A field must be <= 16 chars, and will be padded with 0 if shorter.
     pub fn pack_label(&mut self, label: &str)
-        -> Result<u32, &'static str>
+        -> Result<u32, DataError>
     {
-        let max_label_idx = self.labels.len();
+        let label_data = label.as_bytes();
+        if label_data.len() > 16 {
+            return Err(DataError::new(DataErrorKind::LabelTooLong));
+        }
+
+        let next_label_idx = self.data.header.labels.1;
         let label_idx = self.labels
             .entry(label.to_string())
-            .or_insert(max_label_idx as u32);
+            .or_insert(next_label_idx);
 
         /* new label needs to be written */
-        if *label_idx == self.data.header.labels.1 {
+        if *label_idx == next_label_idx {
             self.data.header.labels.1 += 1;
 
-            let label_data = label.to_string().into_bytes();
-            if label_data.len() > 16 {
-                return Err("label too long");
-            }
-
             self.data.labels.reserve(16);
             for i in 0..16 {
                 if label_data.len() > i {
@@ -230,178 +295,227 @@ impl <'enc, 'input, W: std::io::Write> Packer<'enc, W> {
     }
 
     /// Pack a struct field name and associated value.
+    ///
+    /// `path` is the location of the parent struct, and is used to
+    /// locate errors in sub-structs.
     fn pack_field(&mut self, field_name: &str, field_value: &'input GffFieldValue,
-        structs: &mut Vec<&'input GffStruct>, current_st_idx: &mut u32)
-        -> Result<u32, &'static str>
+        path: &LabelPath, structs: &mut VecDeque<(&'input GffStruct, LabelPath)>,
+        current_st_idx: &mut u32)
+        -> Result<u32, DataError>
     {
         let label_idx = self.pack_label(field_name)?;
 
-        self.data.header.fields.1 += 1;
-
         match field_value {
             GffFieldValue::Byte(val) => {
-                self.pack_val_1(0, label_idx, *val);
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_1(0, label_idx, *val))
             }
             GffFieldValue::Char(val) => {
-                self.pack_val_1(1, label_idx, *val as u8);
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_1(1, label_idx, *val as u8))
             }
             GffFieldValue::Word(val) => {
-                self.pack_val_2(2, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_2(2, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::Short(val) => {
-                self.pack_val_2(3, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_2(3, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::DWord(val) => {
-                self.pack_val_4(4, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_4(4, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::Int(val) => {
-                self.pack_val_4(5, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_4(5, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::DWord64(val) => {
-                self.pack_val_8(6, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_8(6, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::Int64(val) => {
-                self.pack_val_8(7, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_8(7, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::Float(val) => {
-                self.pack_val_4(8, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_4(8, label_idx, &val.to_le_bytes()))
             }
             GffFieldValue::Double(val) => {
-                self.pack_val_8(9, label_idx, &val.to_le_bytes());
-                Ok(self.data.header.fields.1 - 1)
+                Ok(self.pack_val_8(9, label_idx, &val.to_le_bytes()))
             }
-            GffFieldValue::CExoString(s) => {
-                let encodings = self.encodings;
-                let encoding = encodings(None).unwrap();
-
-                self.pack_data_offset(10, label_idx);
-
-                let (str_data, _, _) = encoding.encode(s);
-                self.pack_data_u32(str_data.len() as u32);
-                self.pack_data_slice(&str_data);
-                Ok(self.data.header.fields.1 - 1)
-            }
-            GffFieldValue::CResRef(s) => {
-                self.pack_data_offset(11, label_idx);
-
-                let s = s.to_lowercase();
-                let str_data = s.as_bytes();
-                if str_data.len() > 16 {
-                    Err("ResRef too long")
-                } else {
-                    self.data.field_data.push(str_data.len() as u8);
-                    self.data.header.field_data.1 += 1;
-                    self.pack_data_slice(str_data);
-                    Ok(self.data.header.fields.1 - 1)
-                }
-            }
-            GffFieldValue::CExoLocString(str_ref, val) => {
-                self.pack_data_offset(12, label_idx);
-
-                // string ref + string count
-                let mut total_len: u32 = 8;
-                let encodings = self.encodings;
-
-                let val_encoded: Vec<(GffLang, GffGender, Cow<'_, [u8]>)> =
-                    val.iter().map(|((lang, gender), s)| {
-                        let encoding = encodings(Some(*lang as u32)).unwrap();
-                        let (s_vec, _, _) = encoding.encode(s);
-                        // gender-lang + length + string
-                        total_len += 8 + s_vec.len() as u32;
-                        (*lang, *gender, s_vec)
-                }).collect();
-
-                // total data size
-                self.pack_data_u32(total_len);
-                // string ref
-                self.pack_data_u32(*str_ref);
-                // string count
-                self.pack_data_u32(val.len() as u32);
-
-                for (lang, gender, s) in val_encoded {
-                    let gender = gender as u32;
-                    let lang = lang as u32;
-                    // gender-lang
-                    self.pack_data_u32(gender + 2 * lang);
-                    // length
-                    self.pack_data_u32(s.len() as u32);
-                    // string
-                    self.pack_data_slice(&s);
-
-                }
-                Ok(self.data.header.fields.1 - 1)
-            }
-            GffFieldValue::Void(val) => {
-                self.pack_data_offset(13, label_idx);
-
-                self.pack_data_u32(val.len() as u32);
-                self.pack_data_slice(val);
-                Ok(self.data.header.fields.1 - 1)
+            GffFieldValue::CExoString(_) | GffFieldValue::CResRef(_)
+            | GffFieldValue::CExoLocString(_, _) | GffFieldValue::Void(_) => {
+                self.pack_data_value(label_idx, field_value)
             }
             GffFieldValue::Struct(st) => {
                 *current_st_idx += 1;
-                self.pack_val_4(14, label_idx,
+                let field_idx = self.pack_val_4(14, label_idx,
                     &(*current_st_idx).to_le_bytes()
                 );
-                structs.push(st);
-                Ok(self.data.header.fields.1 - 1)
+                structs.push_back((st, path.join_label(field_name)));
+                Ok(field_idx)
             }
             GffFieldValue::List(vec) => {
-                self.pack_val_4(15, label_idx,
+                let field_idx = self.pack_val_4(15, label_idx,
                     &self.data.header.list_indices.1.to_le_bytes());
                 self.pack_list_u32(vec.len() as u32);
-                for st in vec {
+                let path = path.join_label(field_name);
+                for (i, st) in vec.iter().enumerate() {
                     *current_st_idx += 1;
                     self.pack_list_u32(*current_st_idx);
-                    structs.push(st);
+                    structs.push_back((st, path.join_index(i)));
                 }
-                Ok(self.data.header.fields.1 - 1)
+                Ok(field_idx)
             }
-            _ => Err("Not handled yet")
         }
     }
 
-    /// Pack a field type, field label, and 1 byte of data into the fields block.
+    /// Pack a field type, field label, and value into the field_data block.
     ///
-    /// The 1 byte of data will be padded with 3 bytes of zeros.
-    fn pack_val_1(&mut self, ftype: u32, label_idx: u32, val: u8) {
+    /// Returns the index of the new field.
+    fn pack_data_value(&mut self, label_idx: u32, value: &GffFieldValue)
+        -> Result<u32, DataError>
+    {
+        let data = self.encode_field_data(value, true)?.unwrap_or_default();
+        Ok(self.pack_data_field(value.field_type() as u32, label_idx, &data))
+    }
+
+    /// Pack a field type, field label, and encoded data into the field_data block.
+    ///
+    /// Returns the index of the new field.
+    fn pack_data_field(&mut self, ftype: u32, label_idx: u32, data: &[u8]) -> u32 {
+        let field_idx = self.pack_data_offset(ftype, label_idx);
+        self.pack_data_slice(data);
+        field_idx
+    }
+
+    /// Encode a value stored in the field_data block.
+    ///
+    /// Returns `None` for values stored directly in the fields block,
+    /// and for structs and lists.
+    /// Resource references are lowercased if `lowercase_resref` is set.
+    fn encode_field_data(&self, value: &GffFieldValue, lowercase_resref: bool)
+        -> Result<Option<Vec<u8>>, DataError>
+    {
+        let mut data = vec![];
+        match value {
+            GffFieldValue::DWord64(val) => data.extend_from_slice(&val.to_le_bytes()),
+            GffFieldValue::Int64(val) => data.extend_from_slice(&val.to_le_bytes()),
+            GffFieldValue::Double(val) => data.extend_from_slice(&val.to_le_bytes()),
+            GffFieldValue::CExoString(s) => {
+                let str_data = self.encode(None, s)?;
+                data.extend_from_slice(&(str_data.len() as u32).to_le_bytes());
+                data.extend_from_slice(&str_data);
+            }
+            GffFieldValue::CResRef(s) => self.encode_resref(s, lowercase_resref, &mut data)?,
+            GffFieldValue::CExoLocString(str_ref, val) =>
+                self.encode_locstring(*str_ref, val, &mut data)?,
+            GffFieldValue::Void(val) => {
+                data.extend_from_slice(&(val.len() as u32).to_le_bytes());
+                data.extend_from_slice(val);
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(data))
+    }
+
+    /// Encode a resource reference, prefixed with its length.
+    fn encode_resref(&self, s: &str, lowercase: bool, data: &mut Vec<u8>)
+        -> Result<(), DataError>
+    {
+        let s = if lowercase { Cow::Owned(s.to_lowercase()) } else { Cow::Borrowed(s) };
+        // resrefs are stored as raw bytes, so that
+        // parsing them back gives the same chars
+        let str_data = s.chars()
+            .map(|c| u8::try_from(c as u32).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| DataError::new(DataErrorKind::Unencodable))?;
+        if str_data.len() > 16 {
+            return Err(DataError::new(DataErrorKind::ResRefTooLong));
+        }
+        data.push(str_data.len() as u8);
+        data.extend_from_slice(&str_data);
+        Ok(())
+    }
+
+    /// Encode a localized string, prefixed with its total size.
+    fn encode_locstring(&self, str_ref: u32, val: &IndexMap<(GffLang, GffGender), String>,
+        data: &mut Vec<u8>)
+        -> Result<(), DataError>
+    {
+        // string ref + string count
+        let mut total_len: u32 = 8;
+
+        let val_encoded: Vec<(GffLang, GffGender, Cow<'_, [u8]>)> =
+            val.iter().map(|((lang, gender), s)| {
+                let s_vec = self.encode(Some(*lang as u32), s)?;
+                // gender-lang + length + string
+                total_len += 8 + s_vec.len() as u32;
+                Ok((*lang, *gender, s_vec))
+        }).collect::<Result<_, DataError>>()?;
+
+        // total data size
+        data.extend_from_slice(&total_len.to_le_bytes());
+        // string ref
+        data.extend_from_slice(&str_ref.to_le_bytes());
+        // string count
+        data.extend_from_slice(&(val.len() as u32).to_le_bytes());
+
+        for (lang, gender, s) in val_encoded {
+            let gender = gender as u32;
+            let lang = lang as u32;
+            // gender-lang
+            data.extend_from_slice(&(gender + 2 * lang).to_le_bytes());
+            // length
+            data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            // string
+            data.extend_from_slice(&s);
+        }
+        Ok(())
+    }
+
+    /// Encode a string for the given language (`None` for a [`GffFieldValue::CExoString`])
+    fn encode<'s>(&self, lang: Option<u32>, s: &'s str)
+        -> Result<Cow<'s, [u8]>, DataError>
+    {
+        let encodings = self.encodings;
+        let encoding = encodings(lang)
+            .map_err(|_| DataError::new(DataErrorKind::UnknownLanguage(lang)))?;
+        let (data, _, had_errors) = encoding.encode(s);
+        if had_errors {
+            return Err(DataError::new(DataErrorKind::Unencodable));
+        }
+        Ok(data)
+    }
+
+    /// Pack a field type, field label, and 4 bytes of data into the fields block.
+    ///
+    /// Returns the index of the new field.
+    fn pack_field_entry(&mut self, ftype: u32, label_idx: u32, val: &[u8; 4]) -> u32 {
         self.data.fields.extend_from_slice(&ftype.to_le_bytes());
         self.data.fields.extend_from_slice(&label_idx.to_le_bytes());
-        self.data.fields.push(val);
-        self.data.fields.extend_from_slice(&[0u8; 3]);
+        self.data.fields.extend_from_slice(val);
+        self.data.header.fields.1 += 1;
+        self.data.header.fields.1 - 1
+    }
+
+    /// Pack a field type, field label, and 1 byte of data into the fields block.
+    ///
+    /// The 1 byte of data will be padded with 3 bytes of zeros.
+    fn pack_val_1(&mut self, ftype: u32, label_idx: u32, val: u8) -> u32 {
+        self.pack_field_entry(ftype, label_idx, &[val, 0, 0, 0])
     }
 
     /// Pack a field type, field label, and 2 bytes of data into the fields block.
     ///
     /// The 2 bytes of data will be padded with 2 bytes of zeros.
-    fn pack_val_2(&mut self, ftype: u32, label_idx: u32, val: &[u8; 2]) {
-        self.data.fields.extend_from_slice(&ftype.to_le_bytes());
-        self.data.fields.extend_from_slice(&label_idx.to_le_bytes());
-        self.data.fields.extend_from_slice(val);
-        self.data.fields.extend_from_slice(&[0u8; 2]);
+    fn pack_val_2(&mut self, ftype: u32, label_idx: u32, val: &[u8; 2]) -> u32 {
+        self.pack_field_entry(ftype, label_idx, &[val[0], val[1], 0, 0])
     }
 
     /// Pack a field type, field label, and 4 bytes of data into the fields block.
-    pub fn pack_val_4(&mut self, ftype: u32, label_idx: u32, val: &[u8; 4]) {
-        self.data.fields.extend_from_slice(&ftype.to_le_bytes());
-        self.data.fields.extend_from_slice(&label_idx.to_le_bytes());
-        self.data.fields.extend_from_slice(val);
+    pub fn pack_val_4(&mut self, ftype: u32, label_idx: u32, val: &[u8; 4]) -> u32 {
+        self.pack_field_entry(ftype, label_idx, val)
     }
 
     /// Pack a field type, field label, and data_offset into the fields block.
-    fn pack_data_offset(&mut self, ftype: u32, label_idx: u32) {
-        self.pack_val_4(ftype, label_idx,
+    fn pack_data_offset(&mut self, ftype: u32, label_idx: u32) -> u32 {
+        self.pack_field_entry(ftype, label_idx,
             &(self.data.field_data.len() as u32).to_le_bytes()
-        );
+        )
     }
 
     /// Pack a field type, field label, and 8 bytes of data.
@@ -409,10 +523,11 @@ impl <'enc, 'input, W: std::io::Write> Packer<'enc, W> {
     /// The field type, field label, and field_data offset will be stored
     /// in the fields block, and the 8 bytes of data will be packed into
     /// the field_data block.
-    fn pack_val_8(&mut self, ftype: u32, label_idx: u32, val: &[u8; 8]) {
-        self.pack_data_offset(ftype, label_idx);
+    fn pack_val_8(&mut self, ftype: u32, label_idx: u32, val: &[u8; 8]) -> u32 {
+        let field_idx = self.pack_data_offset(ftype, label_idx);
         self.data.field_data.extend_from_slice(val);
         self.data.header.field_data.1 += 8;
+        field_idx
     }
 
     /// Pack an u32 into the field_data block.
@@ -434,54 +549,325 @@ impl <'enc, 'input, W: std::io::Write> Packer<'enc, W> {
     }
 
     /* }}} */
+    /* {{{ Layout-preserving pack functions */
+
+    /// Pack a GffFile, reusing the layout of the file it was parsed from.
+    ///
+    /// Structs, fields, labels and field data are placed where they were
+    /// in the original file, see [`crate::parser::GffParser::parse_with_layout`].
+    /// If nothing was modified, the output is identical to the parsed input.
+    /// Unlike [`Packer::pack`], resource references keep their case.
+    ///
+    /// Modified values are written in place when they keep the same size,
+    /// and appended to the field data block otherwise, or if another field
+    /// shares their data. If structs, lists or fields were added or
+    /// removed, this falls back to [`Packer::pack`].
+    pub fn pack_with_layout(&mut self, input: &'input GffFile, layout: &GffLayout)
+        -> Result<(), DataError>
+    {
+        let mut next_st = 0;
+        if !Self::layout_matches(&input.root, layout, &mut next_st)
+            || next_st != layout.structs.len() {
+            return self.pack(input);
+        }
+
+        self.data.header.gff_type = input.file_type;
+        self.data.header.version = input.version;
+
+        self.data.structs = vec![0; layout.struct_count as usize * 12];
+        self.data.header.structs.1 = layout.struct_count;
+        self.data.fields = vec![0; layout.field_count as usize * 12];
+        self.data.header.fields.1 = layout.field_count;
+        for (idx, label) in layout.labels.iter().enumerate() {
+            if let Some(name) = label_name(label) {
+                self.labels.entry(name.to_string()).or_insert(idx as u32);
+            }
+            self.data.labels.extend_from_slice(label);
+        }
+        self.data.header.labels.1 = layout.labels.len() as u32;
+        self.data.field_data = vec![0; layout.field_data_size as usize];
+        self.data.header.field_data.1 = layout.field_data_size;
+        self.data.field_indices = vec![0; layout.field_indices_size as usize];
+        self.data.header.field_indices.1 = layout.field_indices_size;
+        self.data.list_indices = vec![0; layout.list_indices_size as usize];
+        self.data.header.list_indices.1 = layout.list_indices_size;
+
+        let mut cursor = LayoutCursor {
+            layout,
+            next_st: 0,
+            written: vec![false; layout.field_data_size as usize],
+        };
+        self.pack_struct_layout(&input.root, &mut cursor)?;
+
+        self.finalize();
+        self.write()?;
+
+        Ok(())
+    }
+
+    /// Check that a struct tree has the same shape as the captured layout.
+    ///
+    /// Structs must have the same number of fields, with structs and lists
+    /// at the same positions, and lists must keep their length.
+    fn layout_matches(input: &GffStruct, layout: &GffLayout, next_st: &mut usize) -> bool {
+        let st_layout = match layout.structs.get(*next_st) {
+            Some(st_layout) => st_layout,
+            None => return false,
+        };
+        *next_st += 1;
+        if st_layout.fields.len() != input.fields.len() {
+            return false;
+        }
+        input.fields.values().zip(&st_layout.fields).all(|(value, f)| match value {
+            GffFieldValue::Struct(st) =>
+                f.field_type == 14 && Self::layout_matches(st, layout, next_st),
+            GffFieldValue::List(vec) =>
+                f.field_type == 15 && f.size as usize == vec.len()
+                && vec.iter().all(|st| Self::layout_matches(st, layout, next_st)),
+            _ => f.field_type != 14 && f.field_type != 15,
+        })
+    }
+
+    /// Pack a GffStruct at its original place.
+    ///
+    /// Sub-structs are packed immediately, in the order they were parsed.
+    fn pack_struct_layout(&mut self, input: &'input GffStruct, cursor: &mut LayoutCursor)
+        -> Result<(), DataError>
+    {
+        let st_layout = cursor.next_struct()?;
+
+        let entry = st_layout.index.saturating_mul(12);
+        write_u32(&mut self.data.structs, entry, input.st_type)?;
+        write_u32(&mut self.data.structs, entry.saturating_add(4), st_layout.field_offset)?;
+        write_u32(&mut self.data.structs, entry.saturating_add(8), input.fields.len() as u32)?;
+
+        for ((field, value), f) in input.fields.iter().zip(&st_layout.fields) {
+            self.pack_field_layout(field, value, f, cursor)
+                .map_err(|e| e.at_label(field))?;
+        }
+
+        if st_layout.fields.len() > 1 {
+            for (i, f) in st_layout.fields.iter().enumerate() {
+                write_u32(&mut self.data.field_indices,
+                    st_layout.field_offset.saturating_add(4 * i as u32), f.index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pack a struct field name and associated value at its original place.
+    ///
+    /// Padding bytes of small values are kept, and resrefs are written
+    /// without changing their case.
+    fn pack_field_layout(&mut self, field_name: &str, field_value: &'input GffFieldValue,
+        f: &FieldLayout, cursor: &mut LayoutCursor)
+        -> Result<(), DataError>
+    {
+        let label_idx = match cursor.layout.labels.get(f.label as usize).and_then(label_name) {
+            Some(name) if name == field_name => f.label,
+            _ => self.pack_label(field_name)?,
+        };
+        let field_type = field_value.field_type() as u32;
+        let mut raw = if field_type == f.field_type {
+            f.data.to_le_bytes()
+        } else {
+            [0u8; 4]
+        };
+
+        match field_value {
+            GffFieldValue::Byte(val) => raw[0] = *val,
+            GffFieldValue::Char(val) => raw[0] = *val as u8,
+            GffFieldValue::Word(val) => raw[..2].copy_from_slice(&val.to_le_bytes()),
+            GffFieldValue::Short(val) => raw[..2].copy_from_slice(&val.to_le_bytes()),
+            GffFieldValue::DWord(val) => raw = val.to_le_bytes(),
+            GffFieldValue::Int(val) => raw = val.to_le_bytes(),
+            GffFieldValue::Float(val) => raw = val.to_le_bytes(),
+            GffFieldValue::Struct(st) => {
+                raw = cursor.peek_struct()?.index.to_le_bytes();
+                self.pack_struct_layout(st, cursor)?;
+            }
+            GffFieldValue::List(vec) => {
+                write_u32(&mut self.data.list_indices, f.data, vec.len() as u32)?;
+                for (i, st) in vec.iter().enumerate() {
+                    write_u32(&mut self.data.list_indices, f.data.saturating_add(4 * (i as u32 + 1)),
+                        cursor.peek_struct()?.index)?;
+                    self.pack_struct_layout(st, cursor)
+                        .map_err(|e| e.at_index(i))?;
+                }
+            }
+            _ => {
+                let data = self.encode_field_data(field_value, false)?.unwrap_or_default();
+                let range = f.data as usize..f.data as usize + data.len();
+                /* another field may share the data, and have written it already */
+                let in_place = field_type == f.field_type && data.len() == f.size as usize
+                    && cursor.written.get(range.clone()).is_some_and(|written|
+                        !written.contains(&true) || self.data.field_data[range.clone()] == data[..]);
+                if in_place {
+                    self.data.field_data[range.clone()].copy_from_slice(&data);
+                    cursor.written[range].fill(true);
+                } else {
+                    raw = (self.data.field_data.len() as u32).to_le_bytes();
+                    self.pack_data_slice(&data);
+                }
+            }
+        }
+
+        let entry = f.index.saturating_mul(12);
+        write_u32(&mut self.data.fields, entry, field_type)?;
+        write_u32(&mut self.data.fields, entry.saturating_add(4), label_idx)?;
+        write_u32(&mut self.data.fields, entry.saturating_add(8), u32::from_le_bytes(raw))?;
+        Ok(())
+    }
+
+    /* }}} */
+}
+
+/// Progress of [`Packer::pack_with_layout`]
+struct LayoutCursor<'l> {
+    layout: &'l GffLayout,
+    /// Index of the next struct placement
+    next_st: usize,
+    /// Bytes of the field data block written in place so far
+    written: Vec<bool>,
+}
+
+impl<'l> LayoutCursor<'l> {
+    fn peek_struct(&self) -> Result<&'l StructLayout, DataError> {
+        self.layout.structs.get(self.next_st)
+            .ok_or_else(|| DataError::new(DataErrorKind::InvalidLayout))
+    }
+
+    fn next_struct(&mut self) -> Result<&'l StructLayout, DataError> {
+        let st_layout = self.peek_struct()?;
+        self.next_st += 1;
+        Ok(st_layout)
+    }
+}
+
+/// Write an u32 at the given offset of a packed block.
+fn write_u32(block: &mut [u8], offset: u32, val: u32) -> Result<(), DataError> {
+    let offset = offset as usize;
+    block.get_mut(offset..offset + 4)
+        .ok_or_else(|| DataError::new(DataErrorKind::InvalidLayout))?
+        .copy_from_slice(&val.to_le_bytes());
+    Ok(())
+}
+
+/// Decode a label from the labels block, without its padding.
+fn label_name(label: &[u8; 16]) -> Option<&str> {
+    let len = label.iter().position(|&c| c == 0).unwrap_or(16);
+    std::str::from_utf8(&label[..len]).ok()
 }
 
 /* {{{ PackField implementations. */
 
+/// Sub-structs queued by [`PackField::pack_field`], waiting to be
+/// packed by [`Packer::pack_direct`].
+///
+/// Each struct is queued with its label path, so that errors
+/// in sub-structs can be located.
+pub struct StructQueue<'a, W: std::io::Write> {
+    queue: VecDeque<(QueuedStruct<'a, W>, LabelPath)>,
+    path: LabelPath,
+}
+
+/// Struct waiting in a [`StructQueue`]
+enum QueuedStruct<'a, W: std::io::Write> {
+    /// A struct of the packed input
+    Struct(&'a dyn PackStruct<'a, W>),
+    /// A local variable, built from a map entry
+    Variable(GffStruct),
+}
+
+impl<'a, W: std::io::Write> StructQueue<'a, W> {
+    /// Queue the struct stored in the field `label` of the struct being packed.
+    pub fn push(&mut self, st: &'a dyn PackStruct<'a, W>, label: &str) {
+        self.queue.push_back((QueuedStruct::Struct(st), self.path.join_label(label)));
+    }
+
+    /// Queue the struct at `index` in the list `label` of the struct being packed.
+    pub fn push_item(&mut self, st: &'a dyn PackStruct<'a, W>, label: &str, index: usize) {
+        self.queue.push_back((QueuedStruct::Struct(st), self.path.join_label(label).join_index(index)));
+    }
+
+    /// Queue the local variable at `index` in the map `label` of the struct being packed.
+    fn push_variable(&mut self, var: GffStruct, label: &str, index: usize) {
+        self.queue.push_back((QueuedStruct::Variable(var), self.path.join_label(label).join_index(index)));
+    }
+}
+
 /// Trait to directly pack a struct's field.
 ///
 /// This trait does not make use of the intermediary
 /// representation ([`GffFieldValue`]).
+///
+/// Returns the index of the packed field, and pushes sub-structs
+/// to `structs`, numbering them with `st_idx`.
 pub trait PackField<'a, W: std::io::Write> {
-    fn pack_field(&'a self, label: String, packer: &mut Packer<W>,
-        structs: &mut Vec<&'a dyn PackStruct<W>>, st_idx: &mut u32);
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>;
 }
 
 /// Trait to directly pack a struct
 ///
 /// This trait does not make use of the intermediary
 /// representation ([`GffStruct`]).
+///
+/// This trait can be automatically derived using
+/// `gff_derive::GFFStructPack`, and is used by [`Packer::pack_direct`].
 pub trait PackStruct<'a, W: std::io::Write> {
-    fn pack(&'a self, data: &mut Packer<W>, structs: &mut Vec<&'a dyn PackStruct<W>>,
-            st_idx: &mut u32);
+    fn pack(&'a self, packer: &mut Packer<W>, structs: &mut StructQueue<'a, W>,
+            st_idx: &mut u32)
+        -> Result<(), DataError>;
 }
 
-macro_rules! pack_field_1 {
-    ( $type:ident, $type_id:literal ) => {
+/// Trait to directly pack a field that holds no sub-struct.
+///
+/// Unlike [`PackField`], the value does not have to outlive the
+/// queue of sub-structs, so a value computed on the fly can be packed.
+pub trait PackValue<W: std::io::Write> {
+    fn pack_value(&self, label: &str, packer: &mut Packer<W>) -> Result<u32, DataError>;
+}
+
+macro_rules! pack_value_field {
+    ( $type:ident ) => {
         impl<'a, W: std::io::Write> PackField<'a, W> for $type {
-            fn pack_field(&'a self, label: String, packer: &mut Packer<W>,
-                _structs: &mut Vec<&'a dyn PackStruct<W>>, _st_idx: &mut u32)
+            fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+                _structs: &mut StructQueue<'a, W>, _st_idx: &mut u32)
+                -> Result<u32, DataError>
             {
-                let label_idx = packer.pack_label(&label).unwrap();
-                packer.pack_val_1(1, label_idx, *self as u8);
+                self.pack_value(label, packer)
             }
         }
     }
 }
 
+macro_rules! pack_field_1 {
+    ( $type:ident, $type_id:literal ) => {
+        impl<W: std::io::Write> PackValue<W> for $type {
+            fn pack_value(&self, label: &str, packer: &mut Packer<W>) -> Result<u32, DataError> {
+                let label_idx = packer.pack_label(label)?;
+                Ok(packer.pack_val_1($type_id, label_idx, *self as u8))
+            }
+        }
+        pack_value_field!($type);
+    }
+}
+
 pack_field_1!(u8, 0);
 pack_field_1!(i8, 1);
+pack_field_1!(bool, 0);
 
 macro_rules! pack_field_n {
     ( $type:ident, $pack_fn:ident, $type_id:literal ) => {
-        impl<'a, W: std::io::Write> PackField<'a, W> for $type {
-            fn pack_field(&'a self, label: String, packer: &mut Packer<W>,
-                _structs: &mut Vec<&'a dyn PackStruct<W>>, _st_idx: &mut u32)
-            {
-                let label_idx = packer.pack_label(&label).unwrap();
-                packer.$pack_fn(1, label_idx, &self.to_le_bytes());
+        impl<W: std::io::Write> PackValue<W> for $type {
+            fn pack_value(&self, label: &str, packer: &mut Packer<W>) -> Result<u32, DataError> {
+                let label_idx = packer.pack_label(label)?;
+                Ok(packer.$pack_fn($type_id, label_idx, &self.to_le_bytes()))
             }
         }
+        pack_value_field!($type);
     }
 }
 
@@ -495,18 +881,199 @@ pack_field_n!(f32, pack_val_4, 8);
 pack_field_n!(f64, pack_val_8, 9);
 
 impl<'a, W: std::io::Write> PackField<'a, W> for String {
-    fn pack_field(&'a self, label: String, packer: &mut Packer<W>,
-        _structs: &mut Vec<&'a dyn PackStruct<W>>, _st_idx: &mut u32)
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        _structs: &mut StructQueue<'a, W>, _st_idx: &mut u32)
+        -> Result<u32, DataError>
     {
-        let encodings = packer.encodings;
-        let encoding = encodings(None).unwrap();
-        let label_idx = packer.pack_label(&label).unwrap();
-
-        packer.pack_data_offset(10, label_idx);
-
-        let (str_data, _, _) = encoding.encode(self);
+        let label_idx = packer.pack_label(label)?;
+        let str_data = packer.encode(None, self)?;
+        let field_idx = packer.pack_data_offset(10, label_idx);
         packer.pack_data_u32(str_data.len() as u32);
         packer.pack_data_slice(&str_data);
+        Ok(field_idx)
+    }
+}
+
+impl<'a, W: std::io::Write> PackField<'a, W> for ResRef {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        _structs: &mut StructQueue<'a, W>, _st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let label_idx = packer.pack_label(label)?;
+        let mut data = vec![];
+        packer.encode_resref(self.as_str(), true, &mut data)?;
+        Ok(packer.pack_data_field(11, label_idx, &data))
+    }
+}
+
+impl<'a, W: std::io::Write> PackField<'a, W> for LocString {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        _structs: &mut StructQueue<'a, W>, _st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let label_idx = packer.pack_label(label)?;
+        let mut data = vec![];
+        packer.encode_locstring(self.str_ref, &self.strings, &mut data)?;
+        Ok(packer.pack_data_field(12, label_idx, &data))
+    }
+}
+
+impl<'a, W: std::io::Write> PackField<'a, W> for VoidData {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        _structs: &mut StructQueue<'a, W>, _st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let label_idx = packer.pack_label(label)?;
+        let field_idx = packer.pack_data_offset(13, label_idx);
+        packer.pack_data_u32(self.0.len() as u32);
+        packer.pack_data_slice(&self.0);
+        Ok(field_idx)
+    }
+}
+
+fn pack_list<'a, W: std::io::Write, T: PackStruct<'a, W>>(items: &'a [T], label: &str,
+    packer: &mut Packer<W>, structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+    -> Result<u32, DataError>
+{
+    let label_idx = packer.pack_label(label)?;
+    let field_idx = packer.pack_val_4(15, label_idx,
+        &packer.data.header.list_indices.1.to_le_bytes());
+    packer.pack_list_u32(items.len() as u32);
+    for (i, st) in items.iter().enumerate() {
+        *st_idx += 1;
+        packer.pack_list_u32(*st_idx);
+        structs.push_item(st, label, i);
+    }
+    Ok(field_idx)
+}
+
+impl<'a, W: std::io::Write, T: PackStruct<'a, W>> PackField<'a, W> for Vec<T> {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        pack_list(self, label, packer, structs, st_idx)
+    }
+}
+
+impl<'a, W: std::io::Write, T: PackStruct<'a, W>, const N: usize> PackField<'a, W> for [T; N] {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        pack_list(self, label, packer, structs, st_idx)
+    }
+}
+
+fn pack_map<W: std::io::Write>(vars: Vec<GffStruct>, label: &str,
+    packer: &mut Packer<W>, structs: &mut StructQueue<'_, W>, st_idx: &mut u32)
+    -> Result<u32, DataError>
+{
+    let label_idx = packer.pack_label(label)?;
+    let field_idx = packer.pack_val_4(15, label_idx,
+        &packer.data.header.list_indices.1.to_le_bytes());
+    packer.pack_list_u32(vars.len() as u32);
+    for (i, var) in vars.into_iter().enumerate() {
+        *st_idx += 1;
+        packer.pack_list_u32(*st_idx);
+        structs.push_variable(var, label, i);
+    }
+    Ok(field_idx)
+}
+
+/// A list of local variables, entries sorted by key as the map has no order
+impl<'a, W: std::io::Write, V: LocalVariable> PackField<'a, W> for HashMap<String, V> {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let vars = serialize_map(sorted_entries(self).into_iter());
+        pack_map(vars, label, packer, structs, st_idx)
+    }
+}
+
+/// A list of local variables, entries in insertion order
+impl<'a, W: std::io::Write, V: LocalVariable> PackField<'a, W> for IndexMap<String, V> {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let vars = serialize_map(self.iter());
+        pack_map(vars, label, packer, structs, st_idx)
+    }
+}
+
+impl<'a, W: std::io::Write, T: PackField<'a, W>> PackField<'a, W> for Box<T> {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        (**self).pack_field(label, packer, structs, st_idx)
+    }
+}
+
+impl<'a, W: std::io::Write, T: PackStruct<'a, W>> PackStruct<'a, W> for Box<T> {
+    fn pack(&'a self, packer: &mut Packer<W>, structs: &mut StructQueue<'a, W>,
+            st_idx: &mut u32)
+        -> Result<(), DataError>
+    {
+        (**self).pack(packer, structs, st_idx)
+    }
+}
+
+impl<'a, W: std::io::Write> PackField<'a, W> for GffStruct {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        let label_idx = packer.pack_label(label)?;
+        *st_idx += 1;
+        let field_idx = packer.pack_val_4(14, label_idx, &(*st_idx).to_le_bytes());
+        structs.push(self, label);
+        Ok(field_idx)
+    }
+}
+
+impl<'a, W: std::io::Write> PackStruct<'a, W> for GffStruct {
+    fn pack(&'a self, packer: &mut Packer<W>, structs: &mut StructQueue<'a, W>,
+            st_idx: &mut u32)
+        -> Result<(), DataError>
+    {
+        packer.begin_struct(self.st_type, self.fields.len() as u32);
+        let field_indices = self.fields.iter().map(|(label, value)| {
+            value.pack_field(label, packer, structs, st_idx)
+                .map_err(|e| e.at_label(label))
+        }).collect::<Result<Vec<u32>, DataError>>()?;
+        packer.end_struct(&field_indices);
+        Ok(())
+    }
+}
+
+impl<'a, W: std::io::Write> PackField<'a, W> for GffFieldValue {
+    fn pack_field(&'a self, label: &str, packer: &mut Packer<W>,
+        structs: &mut StructQueue<'a, W>, st_idx: &mut u32)
+        -> Result<u32, DataError>
+    {
+        match self {
+            GffFieldValue::Byte(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Char(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Word(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Short(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::DWord(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Int(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::DWord64(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Int64(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Float(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::Double(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::CExoString(val) => val.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::CResRef(_) | GffFieldValue::CExoLocString(_, _)
+            | GffFieldValue::Void(_) => {
+                let label_idx = packer.pack_label(label)?;
+                packer.pack_data_value(label_idx, self)
+            }
+            GffFieldValue::Struct(st) => st.pack_field(label, packer, structs, st_idx),
+            GffFieldValue::List(vec) => vec.pack_field(label, packer, structs, st_idx),
+        }
     }
 }
 
@@ -514,9 +1081,10 @@ impl<'a, W: std::io::Write> PackField<'a, W> for String {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
     use crate::packer::Packer;
     use crate::common::{
+        IndexMap,
+        GffFile,
         GffStruct,
         GffFieldValue,
         GffLang,
@@ -557,13 +1125,13 @@ mod tests {
     fn test_01_pack_1_simple_field() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"), GffFieldValue::Byte(1)),
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 1);
@@ -575,14 +1143,14 @@ mod tests {
     fn test_02_pack_2_simple_fields() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"), GffFieldValue::Byte(1)),
                 (String::from("field2"), GffFieldValue::Byte(2)),
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
         /* header indicates 1 struct stored */
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 2);
@@ -594,7 +1162,7 @@ mod tests {
     fn test_03_pack_all_simple_fields() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"), GffFieldValue::Byte(1)),
                 (String::from("field2"), GffFieldValue::Char(2)),
                 (String::from("field3"), GffFieldValue::Word(3)),
@@ -606,7 +1174,7 @@ mod tests {
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
         /* header indicates 1 struct stored */
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 7);
@@ -618,7 +1186,7 @@ mod tests {
     fn test_04_pack_all_8_byte_fields() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"), GffFieldValue::DWord64(1)),
                 (String::from("field2"), GffFieldValue::Int64(2)),
                 (String::from("field3"), GffFieldValue::Double(3.3)),
@@ -626,7 +1194,7 @@ mod tests {
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
         /* header indicates 1 struct stored */
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 3);
@@ -639,14 +1207,14 @@ mod tests {
     fn test_05_pack_simple_string() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"),
                  GffFieldValue::CExoString(String::from("test"))),
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 1);
@@ -656,7 +1224,7 @@ mod tests {
         assert_eq!(
             packer.data.field_data,
             vec![4u8, 0, 0, 0,
-                't' as u8, 'e' as u8, 's' as u8, 't' as u8]
+                b't', b'e', b's', b't']
         );
     }
 
@@ -664,14 +1232,14 @@ mod tests {
     fn test_06_pack_resref() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"),
                  GffFieldValue::CResRef(String::from("TeSt"))),
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 1);
@@ -680,26 +1248,26 @@ mod tests {
         assert_field_data_count(&packer, 1 + 4);
         assert_eq!(
             packer.data.field_data,
-            vec![4u8, 't' as u8, 'e' as u8, 's' as u8, 't' as u8]
+            vec![4u8, b't', b'e', b's', b't']
         );
     }
 
     #[test]
     fn test_07_pack_locstr() {
-        let langs = HashMap::from([
+        let langs = IndexMap::from([
             ((GffLang::English, GffGender::Male), String::from("Hello")),
             ((GffLang::French, GffGender::Male), String::from("Salut")),
         ]);
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"),
                 GffFieldValue::CExoLocString(0x1234, langs))
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 1);
@@ -712,14 +1280,14 @@ mod tests {
     fn test_08_pack_void() {
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"),
                 GffFieldValue::Void(b"test".to_vec()))
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 1);
         assert_field_count(&packer, 1);
@@ -727,26 +1295,26 @@ mod tests {
         assert_label_count(&packer, 1);
         assert_field_data_count(&packer, 4 + 4);
         assert_eq!(packer.data.field_data,
-            vec![0x04, 0x00, 0x00, 0x00, 't' as u8, 'e' as u8, 's' as u8, 't' as u8]);
+            vec![0x04, 0x00, 0x00, 0x00, b't', b'e', b's', b't']);
     }
 
     #[test]
     fn test_09_pack_sub_struct() {
         let input = GffStruct {
             st_type: 0x55555555,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("subfield1"), GffFieldValue::Byte(1))
             ]),
         };
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"), GffFieldValue::Struct(input))
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 2);
         assert_field_count(&packer, 2);
@@ -758,26 +1326,26 @@ mod tests {
     fn test_10_pack_list() {
         let sub1 = GffStruct {
             st_type: 0x55555555,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("subfield1"), GffFieldValue::Byte(1))
             ]),
         };
         let sub2 = GffStruct {
             st_type: 0xAAAAAAAA,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("subfield2"), GffFieldValue::Byte(2))
             ]),
         };
         let input = GffStruct {
             st_type: 0xFFFFFFFF,
-            fields: HashMap::from([
+            fields: IndexMap::from([
                 (String::from("field1"),
                 GffFieldValue::List(vec![sub1, sub2]))
             ]),
         };
         let output = Vec::new();
         let mut packer = Packer::new(output, &*Encodings::NeverwinterNights);
-        packer.pack(&input).unwrap();
+        packer.pack(&GffFile::new(*b"TEST", input)).unwrap();
 
         assert_struct_count(&packer, 3);
         assert_field_count(&packer, 3);
@@ -786,4 +1354,124 @@ mod tests {
         assert_field_data_count(&packer, 0);
         assert_list_count(&packer, 4 * 3); // 1 u32 for size, 2 for structs
     }
+
+    #[test]
+    fn test_11_pack_errors() {
+        let sub = GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([
+                (String::from("TemplateResRef"),
+                 GffFieldValue::CResRef(String::from("this_is_too_long_for_a_resref"))),
+            ]),
+        };
+        let input = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("ItemList"),
+                GffFieldValue::List(vec![sub]))
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        let err = packer.pack(&GffFile::new(*b"TEST", input)).unwrap_err();
+        assert_eq!(err.to_string(), "ItemList[0].TemplateResRef: resref is longer than 16 bytes");
+
+        let input = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("ThisLabelIsTooLong"), GffFieldValue::Byte(1)),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        let err = packer.pack(&GffFile::new(*b"TEST", input)).unwrap_err();
+        assert_eq!(err.to_string(), "ThisLabelIsTooLong: label is longer than 16 bytes");
+
+        let input = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Name"), GffFieldValue::CExoString(String::from("\u{4e2d}"))),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        let err = packer.pack(&GffFile::new(*b"TEST", input)).unwrap_err();
+        assert_eq!(err.to_string(), "Name: string cannot be encoded");
+
+        let sub = GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([
+                (String::from("ThisLabelIsTooLong"), GffFieldValue::Byte(1)),
+            ]),
+        };
+        let input = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Sub"), GffFieldValue::Struct(GffStruct {
+                    st_type: 1,
+                    fields: IndexMap::from([
+                        (String::from("ItemList"), GffFieldValue::List(vec![sub.clone(), sub])),
+                    ]),
+                })),
+            ]),
+        };
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        let err = packer.pack_direct(*b"TEST", *b"V3.2", &input).unwrap_err();
+        assert_eq!(err.to_string(),
+            "Sub.ItemList[0].ThisLabelIsTooLong: label is longer than 16 bytes");
+    }
+
+    #[test]
+    fn test_12_pack_direct() {
+        let leaf = |val| GffStruct {
+            st_type: 3,
+            fields: IndexMap::from([
+                (String::from("leaf"), GffFieldValue::Byte(val)),
+            ]),
+        };
+        let item = |val| GffStruct {
+            st_type: 2,
+            fields: IndexMap::from([
+                (String::from("Tag"), GffFieldValue::CExoString(format!("item{}", val))),
+                (String::from("Sub"), GffFieldValue::Struct(leaf(val))),
+                (String::from("Props"), GffFieldValue::List(vec![leaf(val), leaf(val + 1)])),
+            ]),
+        };
+        let input = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Byte"), GffFieldValue::Byte(1)),
+                (String::from("Char"), GffFieldValue::Char(-1)),
+                (String::from("Word"), GffFieldValue::Word(2)),
+                (String::from("Short"), GffFieldValue::Short(-2)),
+                (String::from("DWord"), GffFieldValue::DWord(3)),
+                (String::from("Int"), GffFieldValue::Int(-3)),
+                (String::from("DWord64"), GffFieldValue::DWord64(4)),
+                (String::from("Int64"), GffFieldValue::Int64(-4)),
+                (String::from("Float"), GffFieldValue::Float(5.5)),
+                (String::from("Double"), GffFieldValue::Double(-5.5)),
+                (String::from("CExoString"), GffFieldValue::CExoString(String::from("test"))),
+                (String::from("CResRef"), GffFieldValue::CResRef(String::from("TeSt"))),
+                (String::from("CExoLocString"), GffFieldValue::CExoLocString(0x1234, IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("Hello")),
+                    ((GffLang::French, GffGender::Female), String::from("Salut")),
+                ]))),
+                (String::from("Void"), GffFieldValue::Void(b"test".to_vec())),
+                (String::from("Empty"), GffFieldValue::Struct(GffStruct {
+                    st_type: 1,
+                    fields: IndexMap::new(),
+                })),
+                (String::from("Struct"), GffFieldValue::Struct(item(10))),
+                (String::from("List"), GffFieldValue::List(vec![item(20), item(30)])),
+            ]),
+        };
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack_direct(*b"TEST", *b"V3.3", &input).unwrap();
+        let direct = packer.writer.into_inner().unwrap();
+        assert_eq!(&direct[..8], b"TESTV3.3");
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile { file_type: *b"TEST", version: *b"V3.3", root: input }).unwrap();
+        let intermediary = packer.writer.into_inner().unwrap();
+
+        assert_eq!(direct, intermediary);
+    }
 }
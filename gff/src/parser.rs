@@ -1,10 +1,7 @@
 //! Parser for the GFF format
 
 use std::convert::TryFrom;
-use std::collections::HashMap;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-
+use std::collections::{HashMap, HashSet};
 
 use nom::{
     IResult,
@@ -13,24 +10,104 @@ use nom::{
         le_i8, le_i16, le_i32, le_i64,
         le_f32, le_f64,
     },
-    sequence::tuple,
-    multi::count,
-    bytes::complete::{ take, take_till },
-    combinator::{
-        map, map_res, map_parser, verify,
-        all_consuming,
-    },
 };
 
 use crate::common::{
+    IndexMap,
+    GffFile,
     GffStruct,
     GffFieldValue,
+    GffFieldType,
+    GffLayout,
+    StructLayout,
+    FieldLayout,
     GffHeader,
     GffGender,
     GffLang,
+    ResRef,
+    LocString,
+    VoidData,
     OffsetCount,
     EncodingFn,
+    LocalVariable,
+};
+use crate::error::{
+    DataError,
+    DataErrorKind,
+    GffError,
+    GffSection,
 };
+use crate::visit::{self, GffVisitor};
+
+/// Maximum depth of nested structs/lists accepted by the parser,
+/// and by the XML reader.
+///
+/// Since a struct can only be referenced once, nesting depth is
+/// already bounded by the struct count, but a crafted file could
+/// still exhaust the stack without this limit.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+type GResult<'io_data, T> = IResult<&'io_data [u8], T>;
+
+/// A data zone of a GFF file.
+///
+/// All reads are bounds-checked, and failures are reported
+/// with the section kind and absolute file offset.
+#[derive(Clone, Copy)]
+struct Section<'a> {
+    kind: GffSection,
+    /// Absolute offset of the section in the file
+    offset: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    /// Absolute file offset of a section-relative offset
+    fn pos(&self, offset: u32) -> u32 {
+        self.offset.saturating_add(offset)
+    }
+
+    fn truncated(&self, offset: u32) -> GffError {
+        GffError::Truncated { section: self.kind, offset: self.pos(offset) }
+    }
+
+    /// Run a nom parser at a section-relative offset
+    fn read<T>(&self, offset: u32, parser: fn(&'a [u8]) -> GResult<'a, T>)
+        -> Result<T, GffError>
+    {
+        self.data.get(offset as usize..)
+            .and_then(|input| parser(input).ok())
+            .map(|(_, val)| val)
+            .ok_or_else(|| self.truncated(offset))
+    }
+
+    /// Borrow `len` bytes at a section-relative offset
+    fn slice(&self, offset: u32, len: u64) -> Result<&'a [u8], GffError> {
+        let start = offset as u64;
+        let end = start + len;
+        if end > self.data.len() as u64 {
+            return Err(self.truncated(offset));
+        }
+        Ok(&self.data[start as usize..end as usize])
+    }
+
+    /// Borrow a sub-zone of this section
+    fn sub(&self, offset: u32, len: u64) -> Result<Section<'a>, GffError> {
+        Ok(Section {
+            kind: self.kind,
+            offset: self.pos(offset),
+            data: self.slice(offset, len)?,
+        })
+    }
+
+    /// Read `count` consecutive u32 at a section-relative offset
+    fn u32_array(&self, offset: u32, count: u32)
+        -> Result<impl Iterator<Item = u32> + 'a, GffError>
+    {
+        let data = self.slice(offset, count as u64 * 4)?;
+        Ok(data.chunks_exact(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+    }
+}
 
 /// Header and data blocks of GFF file.
 ///
@@ -38,419 +115,876 @@ use crate::common::{
 /// from the original packed data buffer for safety.
 struct Data<'a> {
     header: GffHeader,
-    structs: &'a [u8],
-    fields: &'a [u8],
-    labels: &'a [u8],
-    field_data: &'a [u8],
-    field_indices: &'a [u8],
-    list_indices: &'a [u8],
+    structs: Section<'a>,
+    fields: Section<'a>,
+    labels: Section<'a>,
+    field_data: Section<'a>,
+    field_indices: Section<'a>,
+    list_indices: Section<'a>,
+}
+
+impl<'a> Data<'a> {
+    /// Read a struct entry: (struct type, field index/offset, field count)
+    ///
+    /// `section` and `offset` locate the reference to this struct,
+    /// for error reporting.
+    fn struct_entry(&self, st_idx: u32, section: GffSection, offset: u32)
+        -> Result<(u32, u32, u32), GffError>
+    {
+        if st_idx >= self.header.structs.1 {
+            return Err(GffError::StructIndexOutOfRange { index: st_idx, section, offset });
+        }
+        let entry = 12 * st_idx;
+        Ok((
+            self.structs.read(entry, le_u32)?,
+            self.structs.read(entry + 4, le_u32)?,
+            self.structs.read(entry + 8, le_u32)?,
+        ))
+    }
+
+    /// Read a field entry: (field type, label index)
+    ///
+    /// The field data (or data offset) can then be read at
+    /// `12 * f_idx + 8` in the fields section.
+    fn field_entry(&self, f_idx: u32, section: GffSection, offset: u32)
+        -> Result<(u32, u32), GffError>
+    {
+        if f_idx >= self.header.fields.1 {
+            return Err(GffError::FieldIndexOutOfRange { index: f_idx, section, offset });
+        }
+        let entry = 12 * f_idx;
+        Ok((
+            self.fields.read(entry, le_u32)?,
+            self.fields.read(entry + 4, le_u32)?,
+        ))
+    }
+
+    /// Read a field entry, check its type and borrow its label
+    fn field(&self, f_idx: u32, section: GffSection, offset: u32)
+        -> Result<(GffFieldType, &'a str), GffError>
+    {
+        let (gff_type, lbl_idx) = self.field_entry(f_idx, section, offset)?;
+        let entry = 12 * f_idx;
+        let label = self.label(lbl_idx, GffSection::Fields, self.fields.pos(entry + 4))?;
+        let field_type = GffFieldType::try_from(gff_type).map_err(|_| GffError::UnknownFieldType {
+            field_type: gff_type,
+            section: GffSection::Fields,
+            offset: self.fields.pos(entry),
+        })?;
+        Ok((field_type, label))
+    }
+
+    /// Borrow a field label from its index
+    fn label(&self, lbl_idx: u32, section: GffSection, offset: u32)
+        -> Result<&'a str, GffError>
+    {
+        if lbl_idx >= self.header.labels.1 {
+            return Err(GffError::BadLabel { index: lbl_idx, section, offset });
+        }
+        let raw = self.labels.slice(16 * lbl_idx, 16)?;
+        let len = raw.iter().position(|&c| c == 0x00).unwrap_or(16);
+        std::str::from_utf8(&raw[..len]).map_err(|_| GffError::BadLabel {
+            index: lbl_idx,
+            section: GffSection::Labels,
+            offset: self.labels.pos(16 * lbl_idx),
+        })
+    }
+
+    /// Read the number of structs of a list from a list indices offset,
+    /// checking that their indices follow
+    fn list_len(&self, offset: u32) -> Result<u32, GffError> {
+        let list_size = self.list_indices.read(offset, le_u32)?;
+        self.list_indices.slice(offset.saturating_add(4), list_size as u64 * 4)?;
+        Ok(list_size)
+    }
+
+    /// Read the struct indices of a list from a list indices offset
+    fn list(&self, offset: u32) -> Result<impl Iterator<Item = u32> + 'a, GffError> {
+        let list_size = self.list_indices.read(offset, le_u32)?;
+        self.list_indices.u32_array(offset.saturating_add(4), list_size)
+    }
 }
 
 /// GFF format parser
 pub struct GffParser<'a> {
-    /// This HashSet ensures we only, to forbid infinite loops.
+    /// This HashSet ensures we only visit each struct once, to forbid infinite loops.
     visited_structs: HashSet<u32>,
+    /// Current struct nesting depth.
+    depth: usize,
     /// String encoding callback.
     encodings: &'a EncodingFn,
+    /// Layout of the file, when requested.
+    layout: Option<GffLayout>,
 }
 
-type GResult<'io_data, T> = IResult<&'io_data [u8], T>;
-
 impl <'data, 'parser> GffParser<'parser> {
-    /// Parse a byte array into [`GffStruct`] intermediary representation
+    /// Parse a byte array into [`GffFile`] intermediary representation
     pub fn parse(data: Vec<u8>, encodings: &'parser EncodingFn)
-        -> Result<GffStruct, String>
+        -> Result<GffFile, GffError>
+    {
+        let (file, _) = Self::parse_file(&data, encodings, false)?;
+        Ok(file)
+    }
+
+    /// Parse a byte array into [`GffFile`] intermediary representation,
+    /// and capture the layout of the packed data.
+    ///
+    /// The layout can then be given to [`crate::packer::Packer::pack_with_layout`]
+    /// to produce an identical file if nothing was modified.
+    pub fn parse_with_layout(data: Vec<u8>, encodings: &'parser EncodingFn)
+        -> Result<(GffFile, GffLayout), GffError>
     {
+        let (file, layout) = Self::parse_file(&data, encodings, true)?;
+        Ok((file, layout.unwrap_or_default()))
+    }
+
+    /// Parse a byte array directly into a type implementing [`UnpackStruct`]
+    ///
+    /// This does not build the [`GffStruct`] intermediary representation:
+    /// fields are decoded while walking the packed data, and fields that
+    /// the type does not declare are skipped.
+    pub fn unpack<T: UnpackStruct>(data: Vec<u8>, encodings: &'parser EncodingFn)
+        -> Result<T, DataError>
+    {
+        Self::unpack_with(data, encodings, T::unpack)
+    }
+
+    /// Walk a byte array with a [`StructReader`] on the root struct
+    ///
+    /// This is [`GffParser::unpack`], for readers that are not tied
+    /// to a type.
+    pub fn unpack_with<R>(data: Vec<u8>, encodings: &'parser EncodingFn,
+        f: impl FnOnce(&mut StructReader<'_, '_>) -> Result<R, DataError>)
+        -> Result<R, DataError>
+    {
+        let data = Self::parse_header(&data)?;
         let mut parser = GffParser {
             visited_structs: HashSet::new(),
+            depth: 0,
             encodings,
+            layout: None,
         };
-        let (_, data) = parser.parse_header(&data)
-            .map_err(|e| format!("error parsing header: {:#?}", e))?;
-        let (_, res) = parser.parse_struct(&data, 0)
-            .map_err(|e| format!("error parsing data: {:#?}", e))?;
-        Ok(res)
+        // the struct count is located at offset 12 of the header
+        parser.read_struct(&data, 0, GffSection::Header, 12, f)
+    }
+
+    /// Walk a byte array with a [`GffVisitor`]
+    ///
+    /// Fields are visited as they are read from the packed data, and
+    /// their values are only read if the visitor asks for them.
+    pub fn visit<V: GffVisitor + ?Sized>(data: Vec<u8>, encodings: &'parser EncodingFn, visitor: &mut V)
+        -> Result<(), DataError>
+    {
+        Self::unpack_with(data, encodings, |reader| visit::walk_reader(reader, visitor))
+    }
+
+    /// Read the header of a byte array, without parsing the structs
+    ///
+    /// Sections are checked to be contiguous and in bounds, as
+    /// with [`GffParser::parse`].
+    pub fn read_header(data: &[u8]) -> Result<GffHeader, GffError> {
+        Ok(Self::parse_header(data)?.header)
+    }
+
+    fn parse_file(data: &[u8], encodings: &'parser EncodingFn, capture_layout: bool)
+        -> Result<(GffFile, Option<GffLayout>), GffError>
+    {
+        let data = Self::parse_header(data)?;
+        let mut parser = GffParser {
+            visited_structs: HashSet::new(),
+            depth: 0,
+            encodings,
+            layout: None,
+        };
+        if capture_layout {
+            parser.layout = Some(GffLayout {
+                labels: data.labels.data.chunks_exact(16).map(|c| {
+                    let mut label = [0u8; 16];
+                    label.copy_from_slice(c);
+                    label
+                }).collect(),
+                struct_count: data.header.structs.1,
+                field_count: data.header.fields.1,
+                field_data_size: data.header.field_data.1,
+                field_indices_size: data.header.field_indices.1,
+                list_indices_size: data.header.list_indices.1,
+                structs: vec![],
+            });
+        }
+        // the struct count is located at offset 12 of the header
+        let root = parser.parse_struct(&data, 0, GffSection::Header, 12)?;
+        Ok((GffFile {
+            file_type: data.header.gff_type,
+            version: data.header.version,
+            root,
+        }, parser.layout))
     }
 
     /// Parse the GFF header
     ///
     /// This also borrows the different zones,
     /// and ensures they are contiguous.
-    fn parse_header(&self, data: &'data [u8])
-        -> GResult<'data, Data<'data>>
-    {
+    fn parse_header(data: &'data [u8]) -> Result<Data<'data>, GffError> {
         let header_size: u32 = 14 * 4;
-        let mut data_offset = header_size;
-        let st_size = 12;
-        let f_size = 12;
-        let lbl_size = 16;
-
-        let (input, header_data) = take(header_size as usize)(data)?;
-        let (header_data, gff_type) = take(4usize)(header_data)?;
-        let (header_data, version) = take(4usize)(header_data)?;
-        let (header_data, (st_offset, st_count)) = tuple((
-            verify(le_u32, |val: &u32| { *val == data_offset }),
-            le_u32
-        ))(header_data)?;
-        let (input, structs) = take(st_count * st_size)(input)?;
-        data_offset += structs.len() as u32;
-
-        let (header_data, (f_offset, f_count)) = tuple((
-                verify(le_u32, |val: &u32| { *val == data_offset }),
-                le_u32
-        ))(header_data)?;
-        let (input, fields) = take(f_count * f_size)(input)?;
-        data_offset += fields.len() as u32;
-
-        let (header_data, (lbl_offset, lbl_count)) = tuple((
-                verify(le_u32, |val: &u32| { *val == data_offset }),
-                le_u32
-        ))(header_data)?;
-        let (input, labels) = take(lbl_count * lbl_size)(input)?;
-        data_offset += labels.len() as u32;
-
-        let (header_data, (fd_offset, fd_count)) = tuple((
-                verify(le_u32, |val: &u32| { *val == data_offset }),
-                le_u32
-        ))(header_data)?;
-        let (input, field_data) = take(fd_count)(input)?;
-        data_offset += field_data.len() as u32;
-
-        let (header_data, (fi_offset, fi_count)) = tuple((
-                verify(le_u32, |val: &u32| { *val == data_offset }),
-                le_u32
-        ))(header_data)?;
-        let (input, field_indices) = take(fi_count)(input)?;
-        data_offset += field_indices.len() as u32;
-
-        let (_, (li_offset, li_count)) = all_consuming(tuple((
-                    verify(le_u32, |val: &u32| { *val == data_offset}),
-                    le_u32
-        )))(header_data)?;
-        let (input, list_indices) = all_consuming(take(li_count))(input)?;
+        let header = Section { kind: GffSection::Header, offset: 0, data };
+        let header_data = header.slice(0, header_size as u64)?;
 
-        let header = GffHeader {
-            gff_type: [gff_type[0], gff_type[1], gff_type[2], gff_type[3]],
-            version: [version[0], version[1],version[2],version[3]],
-            structs: OffsetCount(st_offset, st_count),
-            fields: OffsetCount(f_offset, f_count),
-            labels: OffsetCount(lbl_offset, lbl_count),
-            field_data: OffsetCount(fd_offset, fd_count),
-            field_indices: OffsetCount(fi_offset, fi_count),
-            list_indices: OffsetCount(li_offset, li_count),
-        };
+        let mut gff_type = [0u8; 4];
+        gff_type.copy_from_slice(&header_data[0..4]);
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&header_data[4..8]);
 
+        let zones = [
+            (GffSection::Structs, 12),
+            (GffSection::Fields, 12),
+            (GffSection::Labels, 16),
+            (GffSection::FieldData, 1),
+            (GffSection::FieldIndices, 1),
+            (GffSection::ListIndices, 1),
+        ];
+        let mut data_offset = header_size;
+        let mut counts = [OffsetCount::default(), OffsetCount::default(),
+            OffsetCount::default(), OffsetCount::default(),
+            OffsetCount::default(), OffsetCount::default()];
+        let mut sections = [header; 6];
 
-        Ok((input, Data {
-            header,
+        for (i, (kind, entry_size)) in zones.iter().enumerate() {
+            let entry = 8 + 8 * i as u32;
+            let offset = header.read(entry, le_u32)?;
+            let count = header.read(entry + 4, le_u32)?;
+            if offset != data_offset {
+                return Err(GffError::InvalidHeader { section: *kind, offset: entry });
+            }
+            let zone = header.sub(offset, count as u64 * entry_size)
+                .map_err(|_| GffError::Truncated { section: *kind, offset })?;
+            sections[i] = Section { kind: *kind, ..zone };
+            counts[i] = OffsetCount(offset, count);
+            data_offset += zone.data.len() as u32;
+        }
+        if data_offset as usize != data.len() {
+            // trailing data after the last section
+            return Err(GffError::InvalidHeader {
+                section: GffSection::ListIndices,
+                offset: 8 + 8 * 5,
+            });
+        }
+
+        let [structs, fields, labels, field_data, field_indices, list_indices] = counts;
+        let header = GffHeader {
+            gff_type,
+            version,
             structs,
             fields,
             labels,
             field_data,
             field_indices,
             list_indices,
-        }))
+        };
+
+        Ok(Data {
+            header,
+            structs: sections[0],
+            fields: sections[1],
+            labels: sections[2],
+            field_data: sections[3],
+            field_indices: sections[4],
+            list_indices: sections[5],
+        })
     }
 
-    /// Parse a GFF struct into intermediary representation
-    fn parse_struct(&mut self, data: &'data Data, st_idx: u32)
-        -> GResult<'data, GffStruct>
+    /// Check and read the entry of a struct before parsing its fields
+    ///
+    /// This increases the nesting depth, which must be decreased
+    /// once the fields have been parsed.
+    fn enter_struct(&mut self, data: &Data<'data>, st_idx: u32,
+        section: GffSection, offset: u32)
+        -> Result<(u32, u32, u32), GffError>
     {
-        assert!(st_idx < data.header.structs.1);
+        if !self.visited_structs.insert(st_idx) {
+            return Err(GffError::CyclicReference { index: st_idx, section, offset });
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(GffError::NestingTooDeep { section, offset });
+        }
+        let entry = data.struct_entry(st_idx, section, offset)?;
+        self.depth += 1;
+        Ok(entry)
+    }
 
-        let (input, _) = take(12 * st_idx as usize)(data.structs)?;
-        let (input, (st_type, field_offset, field_count)) = tuple((le_u32, le_u32, le_u32))(input)?;
+    /// Parse a GFF struct into intermediary representation
+    ///
+    /// `section` and `offset` locate the reference to this struct,
+    /// for error reporting.
+    fn parse_struct(&mut self, data: &Data<'data>, st_idx: u32,
+        section: GffSection, offset: u32)
+        -> Result<GffStruct, GffError>
+    {
+        let (st_type, field_offset, field_count) = self.enter_struct(data, st_idx, section, offset)?;
+        let entry_pos = data.structs.pos(12 * st_idx);
+        let layout_idx = self.layout.as_mut().map(|layout| {
+            layout.structs.push(StructLayout {
+                index: st_idx,
+                field_offset,
+                fields: vec![],
+            });
+            layout.structs.len() - 1
+        });
 
+        let mut fields = IndexMap::new();
         match field_count {
-            0 => Ok((input, GffStruct {
-                st_type,
-                fields: HashMap::new(),
-            })),
+            0 => {},
             1 => {
-                let (_, field) = self.parse_field(data, field_offset)?;
-                Ok((b"", GffStruct {
-                    st_type,
-                    fields: vec![field].into_iter().collect(),
-                }))
+                let (label, value) = self.parse_field(
+                    data, field_offset, GffSection::Structs, entry_pos + 4)?;
+                self.record_field(data, layout_idx, field_offset)?;
+                fields.insert(label, value);
             },
             _ => {
-                let (input, fields) = self.parse_field_indices(
-                    data, field_offset, field_count as usize)?;
-                Ok((input, GffStruct {
-                    st_type,
-                    fields,
-                }))
+                let indices = data.field_indices.u32_array(field_offset, field_count)?;
+                for (i, f_idx) in indices.enumerate() {
+                    let (label, value) = self.parse_field(
+                        data, f_idx, GffSection::FieldIndices,
+                        data.field_indices.pos(field_offset + 4 * i as u32))?;
+                    self.record_field(data, layout_idx, f_idx)?;
+                    fields.insert(label, value);
+                }
             },
         }
+        self.depth -= 1;
+
+        Ok(GffStruct {
+            st_type,
+            fields,
+        })
     }
 
     /// Parse a GFF field into intermediary representation
-    fn parse_field(&mut self, data: &'data Data, f_idx: u32)
-        -> GResult<'data, (String, GffFieldValue)>
+    fn parse_field(&mut self, data: &Data<'data>, f_idx: u32,
+        section: GffSection, offset: u32)
+        -> Result<(String, GffFieldValue), GffError>
     {
-        assert!(f_idx < data.header.fields.1);
-        let (input, _) = take(12 * f_idx)(data.fields)?;
-        let (input, (gff_type, lbl_idx)) = tuple((le_u32, le_u32))(input)?;
-        let value = match gff_type {
-            0 => {
-                let (_, val) = le_u8(input)?;
-                GffFieldValue::Byte(val)
-            },
-            1 => {
-                let (_, val) = le_i8(input)?;
-                GffFieldValue::Char(val)
+        let (field_type, label) = data.field(f_idx, section, offset)?;
+        let value = self.parse_value(data, f_idx, field_type)?;
+        Ok((label.to_string(), value))
+    }
+
+    /// Parse the value of a GFF field into intermediary representation
+    fn parse_value(&mut self, data: &Data<'data>, f_idx: u32, field_type: GffFieldType)
+        -> Result<GffFieldValue, GffError>
+    {
+        let value_pos = 12 * f_idx + 8;
+        let fields = &data.fields;
+
+        let value = match field_type {
+            GffFieldType::Byte => GffFieldValue::Byte(fields.read(value_pos, le_u8)?),
+            GffFieldType::Char => GffFieldValue::Char(fields.read(value_pos, le_i8)?),
+            GffFieldType::Word => GffFieldValue::Word(fields.read(value_pos, le_u16)?),
+            GffFieldType::Short => GffFieldValue::Short(fields.read(value_pos, le_i16)?),
+            GffFieldType::DWord => GffFieldValue::DWord(fields.read(value_pos, le_u32)?),
+            GffFieldType::Int => GffFieldValue::Int(fields.read(value_pos, le_i32)?),
+            GffFieldType::DWord64 => {
+                let offset = fields.read(value_pos, le_u32)?;
+                GffFieldValue::DWord64(data.field_data.read(offset, le_u64)?)
             },
-            2 => {
-                let (_, val) = le_u16(input)?;
-                GffFieldValue::Word(val)
+            GffFieldType::Int64 => {
+                let offset = fields.read(value_pos, le_u32)?;
+                GffFieldValue::Int64(data.field_data.read(offset, le_i64)?)
             },
-            3 => {
-                let (_, val) = le_i16(input)?;
-                GffFieldValue::Short(val)
+            GffFieldType::Float => GffFieldValue::Float(fields.read(value_pos, le_f32)?),
+            GffFieldType::Double => {
+                let offset = fields.read(value_pos, le_u32)?;
+                GffFieldValue::Double(data.field_data.read(offset, le_f64)?)
             },
-            4 => {
-                let (_, val) = le_u32(input)?;
-                GffFieldValue::DWord(val)
+            GffFieldType::CExoString => {
+                let offset = fields.read(value_pos, le_u32)?;
+                self.parse_cexostring(data, offset)?
             },
-            5 => {
-                let (_, val) = le_i32(input)?;
-                GffFieldValue::Int(val)
+            GffFieldType::CResRef => {
+                let offset = fields.read(value_pos, le_u32)?;
+                self.parse_cresref(data, offset)?
             },
-            6 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_dword64(data, offset)?;
-                val
+            GffFieldType::CExoLocString => {
+                let offset = fields.read(value_pos, le_u32)?;
+                self.parse_cexolocstring(data, offset)?
             },
-            7 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_int64(data, offset)?;
-                val
-            }
-            8 => {
-                let (_, val) = le_f32(input)?;
-                GffFieldValue::Float(val)
+            GffFieldType::Void => {
+                let offset = fields.read(value_pos, le_u32)?;
+                self.parse_void(data, offset)?
             },
-            9 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_double(data, offset)?;
-                val
-            }
-            10 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_cexostring(data, offset)?;
-                val
-            }
-            11 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_cresref(data, offset)?;
-                val
-            }
-            12 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_cexosloctring(data, offset)?;
-                val
-            }
-            13 => {
-                let (_, offset) = le_u32(input)?;
-                let (_, val) = self.parse_void(data, offset)?;
-                val
-            }
-            14 => {
-                let visited_structs = Arc::new(Mutex::new(&mut self.visited_structs));
-
-                let (_, st_idx) = verify(
-                    le_u32,
-                    |val: &u32| visited_structs.lock().unwrap().insert(*val))(input)?;
-                let (_, val) = self.parse_struct(data, st_idx)?;
+            GffFieldType::Struct => {
+                let st_idx = fields.read(value_pos, le_u32)?;
+                let val = self.parse_struct(
+                    data, st_idx, GffSection::Fields, fields.pos(value_pos))?;
                 GffFieldValue::Struct(val)
-            }
-            15 => {
-                let (_, li_idx) = le_u32(input)?;
-                let (_, val) = self.parse_list(data, li_idx)?;
-                GffFieldValue::List(val)
-            }
-            bad => {
-                println!("bad field type: {}", bad);
-                GffFieldValue::Invalid
-            }
+            },
+            GffFieldType::List => {
+                let offset = fields.read(value_pos, le_u32)?;
+                GffFieldValue::List(self.parse_list(data, offset)?)
+            },
         };
-        let (input, label) = self.parse_label(data, lbl_idx)?;
-        Ok((input, (label, value)))
+        Ok(value)
     }
 
-    fn parse_dword64(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Record the layout of an already parsed field into its struct layout
+    fn record_field(&mut self, data: &Data<'data>, layout_idx: Option<usize>, f_idx: u32)
+        -> Result<(), GffError>
     {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, val) = le_u64(input)?;
-        Ok((input, GffFieldValue::DWord64(val)))
+        let (layout, layout_idx) = match (self.layout.as_mut(), layout_idx) {
+            (Some(layout), Some(layout_idx)) => (layout, layout_idx),
+            _ => return Ok(()),
+        };
+        let entry = 12 * f_idx;
+        let field_type = data.fields.read(entry, le_u32)?;
+        let label = data.fields.read(entry + 4, le_u32)?;
+        let raw = data.fields.read(entry + 8, le_u32)?;
+        let size = match field_type {
+            6 | 7 | 9 => 8,
+            10 | 12 | 13 => 4 + data.field_data.read(raw, le_u32)?,
+            11 => 1 + data.field_data.read(raw, le_u8)? as u32,
+            15 => data.list_indices.read(raw, le_u32)?,
+            _ => 0,
+        };
+        layout.structs[layout_idx].fields.push(FieldLayout {
+            index: f_idx,
+            field_type,
+            label,
+            data: raw,
+            size,
+        });
+        Ok(())
     }
 
-    fn parse_double(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Parse a non-localized string, into intermediary representation
+    fn parse_cexostring(&self, data: &Data<'data>, offset: u32)
+        -> Result<GffFieldValue, GffError>
     {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, val) = le_f64(input)?;
-        Ok((input, GffFieldValue::Double(val)))
+        let encodings = self.encodings;
+        let encoding = encodings(None).map_err(|_| GffError::UnknownLanguage {
+            language: None,
+            section: GffSection::FieldData,
+            offset: data.field_data.pos(offset),
+        })?;
+        let len = data.field_data.read(offset, le_u32)?;
+        let slice = data.field_data.slice(offset.saturating_add(4), len as u64)?;
+        let (s, _, _) = encoding.decode(slice);
+        Ok(GffFieldValue::CExoString(s.to_string()))
     }
 
-    fn parse_int64(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Parse a reference string, into intermediary representation
+    fn parse_cresref(&self, data: &Data<'data>, offset: u32)
+        -> Result<GffFieldValue, GffError>
     {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, val) = le_i64(input)?;
-        Ok((input, GffFieldValue::Int64(val)))
+        let len = data.field_data.read(offset, le_u8)?;
+        let slice = data.field_data.slice(offset.saturating_add(1), len as u64)?;
+        let s = slice.iter().map(|&c| c as char).collect();
+        Ok(GffFieldValue::CResRef(s))
     }
 
-    /// Parse a non-localized string, into intermediary representation
-    fn parse_cexostring(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Parse a localized string, into intermediary representation
+    fn parse_cexolocstring(&self, data: &Data<'data>, offset: u32)
+        -> Result<GffFieldValue, GffError>
     {
+        let len = data.field_data.read(offset, le_u32)?;
+        let body = data.field_data.sub(offset.saturating_add(4), len as u64)?;
+        let tlk_ref = body.read(0, le_u32)?;
+        let str_count = body.read(4, le_u32)?;
+
         let encodings = self.encodings;
-        let encoding = encodings(None).unwrap();
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, len) = le_u32(input)?;
-        let (input, (s, _, _)) = map(
-            take(len as usize),
-            |slice: &[u8]| encoding.decode(slice)
-        )(input)?;
-        Ok((input, GffFieldValue::CExoString(s.to_string())))
+        let mut locs = IndexMap::new();
+        let mut pos: u32 = 8;
+        for _ in 0..str_count {
+            let id = body.read(pos, le_u32)?;
+            let unknown_lang = GffError::UnknownLanguage {
+                language: Some(id / 2),
+                section: GffSection::FieldData,
+                offset: body.pos(pos),
+            };
+            let gender = if id % 2 == 0 { GffGender::Male } else { GffGender::Female };
+            let lang = GffLang::try_from(id / 2).map_err(|_| unknown_lang.clone())?;
+            let encoding = encodings(Some(lang as u32)).map_err(|_| unknown_lang)?;
+
+            let str_len = body.read(pos + 4, le_u32)?;
+            let slice = body.slice(pos + 8, str_len as u64)?;
+            let (s, _, _) = encoding.decode(slice);
+            locs.insert((lang, gender), s.to_string());
+            // slice() ensures this stays within the u32 body
+            pos += 8 + str_len;
+        }
+        Ok(GffFieldValue::CExoLocString(tlk_ref, locs))
     }
 
-    /// Parse a reference string, into intermediary representation
-    fn parse_cresref(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Parse raw data from a data offset
+    fn parse_void(&self, data: &Data<'data>, offset: u32)
+        -> Result<GffFieldValue, GffError>
     {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, len) = le_u8(input)?;
-        let (input, s) = map(
-            take(len as usize),
-            |slice: &[u8]| slice.iter().map(|&c| c as char).collect()
-        )(input)?;
-        Ok((input, GffFieldValue::CResRef(s)))
+        let len = data.field_data.read(offset, le_u32)?;
+        let slice = data.field_data.slice(offset.saturating_add(4), len as u64)?;
+        Ok(GffFieldValue::Void(slice.to_vec()))
     }
 
-    /// Parse a localized string, into intermediary representation
-    fn parse_cexosloctring(&self, data: &'data Data, offset: u32)
-        -> GResult<'data, GffFieldValue>
+    /// Parse a list of structs from a list indices offset
+    fn parse_list(&mut self, data: &Data<'data>, offset: u32)
+        -> Result<Vec<GffStruct>, GffError>
     {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, len) = le_u32(input)?;
-        let (input, tlk_ref) = le_u32(input)?;
-        let (input, str_count) = le_u32(input)?;
+        let mut structs = vec![];
+        for (i, st_idx) in data.list(offset)?.enumerate() {
+            let st = self.parse_struct(
+                data, st_idx, GffSection::ListIndices,
+                data.list_indices.pos(offset + 4 + 4 * i as u32))?;
+            structs.push(st);
+        }
+        Ok(structs)
+    }
+}
 
-        let encodings = self.encodings;
+impl<'data> GffParser<'data> {
+    /// Read a GFF struct with a [`StructReader`]
+    ///
+    /// `section` and `offset` locate the reference to this struct,
+    /// for error reporting.
+    fn read_struct<R>(&mut self, data: &Data<'data>, st_idx: u32,
+        section: GffSection, offset: u32,
+        f: impl FnOnce(&mut StructReader<'_, 'data>) -> Result<R, DataError>)
+        -> Result<R, DataError>
+    {
+        let (st_type, field_offset, field_count) = self.enter_struct(data, st_idx, section, offset)?;
+        let mut reader = StructReader {
+            parser: self,
+            data,
+            st_idx,
+            st_type,
+            field_offset,
+            field_count,
+            next_field: 0,
+        };
+        let res = f(&mut reader);
+        self.depth -= 1;
+        res
+    }
+}
 
-        fn parse_substring<'data>(data: &'data [u8], encodings: &EncodingFn)
-            -> GResult<'data, (GffLang, GffGender, String)>
-        {
-            let (input, (lang, gender)) = map_res(le_u32, |val: u32|
-                -> Result<(GffLang, GffGender), num_enum::TryFromPrimitiveError<_>> {
-                    let gender = if val % 2 == 0 { GffGender::Male } else { GffGender::Female };
-                    let lang = GffLang::try_from(val / 2)?;
-                    Ok((lang, gender))
-                })(data)?;
-            let encoding = encodings(Some(lang as u32)).unwrap();
-            let (input, len) = le_u32(input)?;
-
-            let (input, slice) = take(len as usize)(input)?;
-            let (s, _, _) = encoding.decode(slice);
+/* {{{ Direct unpacking */
+
+/// Reader for the fields of a packed struct
+///
+/// This is given to [`UnpackStruct::unpack`], and reads the fields
+/// in the order they were packed, without building a [`GffStruct`].
+pub struct StructReader<'r, 'data> {
+    parser: &'r mut GffParser<'data>,
+    data: &'r Data<'data>,
+    st_idx: u32,
+    st_type: u32,
+    field_offset: u32,
+    field_count: u32,
+    next_field: u32,
+}
+
+impl<'r, 'data> StructReader<'r, 'data> {
+    /// Struct type id
+    pub fn st_type(&self) -> u32 {
+        self.st_type
+    }
+
+    /// Number of fields in the struct
+    pub fn field_count(&self) -> u32 {
+        self.field_count
+    }
 
-            Ok((input, (lang, gender, s.to_string())))
+    /// Read the next field, or `None` once all fields were read
+    pub fn next_field(&mut self) -> Result<Option<FieldReader<'_, 'data>>, DataError> {
+        Ok(self.next_field_entry()?.map(|entry| self.field(entry)))
+    }
+
+    /// Read the next field entry, to be read later with [`StructReader::field`]
+    pub(crate) fn next_field_entry(&mut self) -> Result<Option<FieldEntry<'data>>, DataError> {
+        if self.next_field >= self.field_count {
+            return Ok(None);
         }
+        let (f_idx, section, offset) = if self.field_count == 1 {
+            (self.field_offset, GffSection::Structs, self.data.structs.pos(12 * self.st_idx + 4))
+        } else {
+            let pos = self.field_offset.saturating_add(4 * self.next_field);
+            (self.data.field_indices.read(pos, le_u32)?,
+                GffSection::FieldIndices, self.data.field_indices.pos(pos))
+        };
+        self.next_field += 1;
 
-        let (input, s) = map_parser(
-            take(len as usize - 8),
-            count(|data| { parse_substring(data, encodings) }, str_count as usize)
-        )(input)?;
+        let (field_type, label) = self.data.field(f_idx, section, offset)?;
+        Ok(Some(FieldEntry { f_idx, field_type, label }))
+    }
 
-        let mut locs = HashMap::new();
-        for (lang, gender, subs) in s {
-            locs.insert((lang, gender), subs);
+    /// Reader for a field entry of this struct
+    pub(crate) fn field(&mut self, entry: FieldEntry<'data>) -> FieldReader<'_, 'data> {
+        FieldReader {
+            parser: &mut *self.parser,
+            data: self.data,
+            f_idx: entry.f_idx,
+            field_type: entry.field_type,
+            label: entry.label,
         }
-        Ok((input, GffFieldValue::CExoLocString(tlk_ref, locs)))
     }
+}
 
-    /// Parse raw data from a data offset
-    fn parse_void(&self, data: &'data Data, offset:u32)
-        -> GResult<'data, GffFieldValue>
-    {
-        let (input, _) = take(offset as usize)(data.field_data)?;
-        let (input, len) = le_u32(input)?;
-        let (input, data) = take(len as usize)(input)?;
-        Ok((input, GffFieldValue::Void(data.to_vec())))
+/// A field entry of a [`StructReader`], whose value was not read yet
+#[derive(Clone, Copy)]
+pub(crate) struct FieldEntry<'data> {
+    f_idx: u32,
+    field_type: GffFieldType,
+    pub(crate) label: &'data str,
+}
+
+/// Reader for a field of a packed struct, see [`StructReader`]
+///
+/// The label is borrowed from the packed data, so fields can be
+/// looked up and skipped without allocating.
+pub struct FieldReader<'r, 'data> {
+    parser: &'r mut GffParser<'data>,
+    data: &'r Data<'data>,
+    f_idx: u32,
+    field_type: GffFieldType,
+    label: &'data str,
+}
+
+impl<'r, 'data> FieldReader<'r, 'data> {
+    /// Field label
+    pub fn label(&self) -> &'data str {
+        self.label
     }
 
-    /// Parse a list of structs from a list indices offset
-    fn parse_list(&mut self, data: &'data Data, offset: u32)
-        -> GResult<'data, Vec<GffStruct>>
-    {
-        assert!(offset % 4 == 0);
-
-        let (input, _) = take(offset as usize)(data.list_indices)?;
-        let (input, list_size) = le_u32(input)?;
-
-        let wself = Arc::new(Mutex::new(self));
-        let (input, structs) =
-            count(
-                map_res(
-                    verify(le_u32, |val: &u32| {
-                        wself.lock().unwrap().visited_structs.insert(*val)
-                    }),
-                    |st_idx: u32| -> GResult<'data, GffStruct> {
-                        let (input, val) = wself.lock().unwrap().parse_struct(data, st_idx)?;
-                        Ok((input, val))
-                    }),
-                    list_size as usize
-            )(input)?;
-        let structs = structs.into_iter().map(|t| t.1).collect();
-        Ok((input, structs))
-    }
-
-    /// Parse multiple fields from a field indices offset
-    fn parse_field_indices(&mut self, data: &'data Data, offset: u32, f_count: usize)
-        -> GResult<'data, HashMap<String, GffFieldValue>>
+    /// Field type
+    pub fn field_type(&self) -> GffFieldType {
+        self.field_type
+    }
+
+    /// Read the field into intermediary representation
+    ///
+    /// Structs and lists are fully parsed into [`GffStruct`],
+    /// use [`FieldReader::unpack_struct`] and [`FieldReader::unpack_list`]
+    /// to avoid that.
+    pub fn value(self) -> Result<GffFieldValue, DataError> {
+        Ok(self.parser.parse_value(self.data, self.f_idx, self.field_type)?)
+    }
+
+    /// Read the field into intermediary representation, without
+    /// consuming the reader
+    pub(crate) fn read_value(&mut self) -> Result<GffFieldValue, DataError> {
+        Ok(self.parser.parse_value(self.data, self.f_idx, self.field_type)?)
+    }
+
+    /// Unpack a struct field into a type implementing [`UnpackStruct`]
+    pub fn unpack_struct<T: UnpackStruct>(self) -> Result<T, DataError> {
+        self.read_struct(T::unpack)
+    }
+
+    /// Unpack a list field into a vector of a type implementing [`UnpackStruct`]
+    pub fn unpack_list<T: UnpackStruct>(self) -> Result<Vec<T>, DataError> {
+        let mut list = self.list()?;
+        let mut structs = Vec::with_capacity(list.len());
+        while let Some(st) = list.next_struct(T::unpack)? {
+            structs.push(st);
+        }
+        Ok(structs)
+    }
+
+    /// Read a struct field with a [`StructReader`]
+    pub fn read_struct<R>(self,
+        f: impl FnOnce(&mut StructReader<'_, 'data>) -> Result<R, DataError>)
+        -> Result<R, DataError>
     {
-        assert!(offset % 4 == 0);
-        assert!(offset < data.header.field_indices.1);
-        let (input, _) = take(offset as usize)(data.field_indices)?;
-        let (input, fields) = count(
-            map_res(le_u32, |f_idx: u32|
-                    -> GResult<'data, (String, GffFieldValue)> {
-                let (input, val) = self.parse_field(data, f_idx)?;
-                Ok((input, val))
-            }),
-            f_count
-        )(input)?;
-
-        Ok((input, fields.into_iter().map(|t| t.1).collect()))
-    }
-
-    /// Parse a field label from its index
-    fn parse_label(&self, data: &'data Data, lbl_idx: u32)
-        -> GResult<'data, String>
+        self.expect(GffFieldType::Struct)?;
+        let value_pos = 12 * self.f_idx + 8;
+        let st_idx = self.data.fields.read(value_pos, le_u32)?;
+        self.parser.read_struct(self.data, st_idx,
+            GffSection::Fields, self.data.fields.pos(value_pos), f)
+    }
+
+    /// Read a list field, one struct at a time
+    pub fn list(self) -> Result<ListReader<'r, 'data>, DataError> {
+        self.expect(GffFieldType::List)?;
+        let offset = self.data.fields.read(12 * self.f_idx + 8, le_u32)?;
+        let len = self.data.list_len(offset)?;
+        Ok(ListReader {
+            parser: self.parser,
+            data: self.data,
+            offset,
+            len,
+            next: 0,
+        })
+    }
+
+    fn expect(&self, expected: GffFieldType) -> Result<(), DataError> {
+        if self.field_type != expected {
+            return Err(DataError::new(DataErrorKind::UnexpectedType {
+                expected,
+                found: self.field_type,
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Reader for the structs of a packed list, see [`FieldReader::list`]
+///
+/// Struct indices are read from the packed data as the structs are.
+pub struct ListReader<'r, 'data> {
+    parser: &'r mut GffParser<'data>,
+    data: &'r Data<'data>,
+    /// List indices offset of the list
+    offset: u32,
+    len: u32,
+    next: usize,
+}
+
+impl<'r, 'data> ListReader<'r, 'data> {
+    /// Number of structs in the list
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Whether the list is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Read the next struct with a [`StructReader`],
+    /// or `None` once all structs were read
+    ///
+    /// Errors are located at the index of the struct.
+    pub fn next_struct<R>(&mut self,
+        f: impl FnOnce(&mut StructReader<'_, 'data>) -> Result<R, DataError>)
+        -> Result<Option<R>, DataError>
     {
-        let (input, _) = take(lbl_idx as usize * 16)(data.labels)?;
-        let (input, s) = map_res(
-            map_parser(take(16usize), take_till(|c| c == 0x00)),
-            |slice: &[u8]| String::from_utf8(slice.to_vec())
-        )(input)?;
-        Ok((input, s))
+        let i = self.next;
+        if i >= self.len() {
+            return Ok(None);
+        }
+        self.next += 1;
+        let pos = self.offset + 4 + 4 * i as u32;
+        let st_idx = self.data.list_indices.read(pos, le_u32).map_err(|e| DataError::from(e).at_index(i))?;
+        self.parser.read_struct(
+            self.data, st_idx, GffSection::ListIndices,
+            self.data.list_indices.pos(pos), f
+        ).map(Some).map_err(|e| e.at_index(i))
+    }
+}
+
+/// Trait to directly unpack a struct
+///
+/// This trait does not make use of the intermediary
+/// representation ([`GffStruct`]).
+///
+/// This trait can be automatically derived using
+/// `gff_derive::GFFStructUnpack`, and is used by [`GffParser::unpack`].
+pub trait UnpackStruct: Sized {
+    fn unpack(reader: &mut StructReader<'_, '_>) -> Result<Self, DataError>;
+}
+
+/// Trait to directly unpack a struct's field.
+///
+/// This trait does not make use of the intermediary
+/// representation ([`GffFieldValue`]).
+pub trait UnpackField: Sized {
+    fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError>;
+}
+
+macro_rules! unpack_field_value {
+    ( $type:ty ) => {
+        impl UnpackField for $type {
+            fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+                Self::try_from(&field.value()?)
+            }
+        }
+    }
+}
+
+unpack_field_value!(bool);
+unpack_field_value!(u8);
+unpack_field_value!(i8);
+unpack_field_value!(u16);
+unpack_field_value!(i16);
+unpack_field_value!(u32);
+unpack_field_value!(i32);
+unpack_field_value!(u64);
+unpack_field_value!(i64);
+unpack_field_value!(f32);
+unpack_field_value!(f64);
+unpack_field_value!(String);
+unpack_field_value!(ResRef);
+unpack_field_value!(LocString);
+unpack_field_value!(VoidData);
+
+impl<T: UnpackStruct> UnpackField for Vec<T> {
+    fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+        field.unpack_list()
+    }
+}
+
+impl<T: UnpackStruct, const N: usize> UnpackField for [T; N] {
+    fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+        let v = field.unpack_list::<T>()?;
+        let found = v.len();
+        v.try_into().map_err(|_| DataError::new(DataErrorKind::UnexpectedLength {
+            expected: N,
+            found,
+        }))
+    }
+}
+
+impl<T: UnpackField> UnpackField for Option<T> {
+    fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+        T::unpack_field(field).map(Some)
+    }
+}
+
+impl<T: UnpackField> UnpackField for Box<T> {
+    fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+        T::unpack_field(field).map(Box::new)
+    }
+}
+
+impl<T: UnpackStruct> UnpackStruct for Box<T> {
+    fn unpack(reader: &mut StructReader<'_, '_>) -> Result<Self, DataError> {
+        T::unpack(reader).map(Box::new)
+    }
+}
+
+macro_rules! unpack_field_map {
+    ( $map:ident ) => {
+        impl<V: LocalVariable> UnpackField for $map<String, V> {
+            fn unpack_field(field: FieldReader<'_, '_>) -> Result<Self, DataError> {
+                Self::try_from(&field.value()?)
+            }
+        }
     }
 }
 
+unpack_field_map!(HashMap);
+unpack_field_map!(IndexMap);
+
+/* }}} */
+
 #[cfg(test)]
 mod tests {
     use std::io::prelude::*;
     use std::fs::File;
     use crate::parser::GffParser;
+    use crate::packer::Packer;
+    use crate::error::{
+        GffError,
+        GffSection,
+    };
     use crate::common::{
+        IndexMap,
+        GffFile,
         GffStruct,
+        GffFieldValue,
+        GffLang,
+        GffGender,
         Encodings,
     };
 
     fn test_parse(filename: &str)
-        -> std::result::Result<GffStruct, Box<dyn std::error::Error>>
+        -> std::result::Result<GffFile, Box<dyn std::error::Error>>
     {
         let mut f = File::open(filename)?;
         let mut buffer = Vec::new();
@@ -458,9 +992,149 @@ mod tests {
         let res = GffParser::parse(buffer, &*Encodings::NeverwinterNights)?;
         Ok(res)
     }
+
+    fn read_sample() -> Vec<u8> {
+        let mut f = File::open("test-data/test.bic").unwrap();
+        let mut buffer = Vec::new();
+        f.read_to_end(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn put_u32(data: &mut [u8], offset: usize, val: u32) {
+        data[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    /// Build a file with a single struct, holding a single field
+    fn single_field_file(field_type: u32, field_value: u32) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(b"TESTV3.2");
+        // structs, fields, labels, field data, field indices, list indices
+        for (offset, count) in [(56u32, 1u32), (68, 1), (80, 1), (96, 0), (96, 0), (96, 0)] {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+        }
+        for val in [0xFFFFFFFF, 0, 1, field_type, 0, field_value] {
+            data.extend_from_slice(&val.to_le_bytes());
+        }
+        data.extend_from_slice(b"field1\0\0\0\0\0\0\0\0\0\0");
+        data
+    }
+
     #[test]
     fn test_01_parse_gff_sample() {
         let res = test_parse("test-data/test.bic");
         assert!(res.is_ok())
     }
+
+    #[test]
+    fn test_02_parse_file_type() {
+        let res = test_parse("test-data/test.bic").unwrap();
+        assert_eq!(&res.file_type, b"BIC ");
+        assert_eq!(&res.version, b"V3.2");
+    }
+
+    #[test]
+    fn test_03_truncated_header() {
+        let res = GffParser::parse(b"BIC V3.2".to_vec(), &*Encodings::NeverwinterNights);
+        assert_eq!(res, Err(GffError::Truncated { section: GffSection::Header, offset: 0 }));
+    }
+
+    #[test]
+    fn test_04_bad_indices() {
+        let encodings = &*Encodings::NeverwinterNights;
+        assert!(GffParser::parse(single_field_file(0, 1), encodings).is_ok());
+
+        let res = GffParser::parse(single_field_file(14, 5), encodings);
+        assert_eq!(res, Err(GffError::StructIndexOutOfRange {
+            index: 5, section: GffSection::Fields, offset: 76,
+        }));
+
+        let res = GffParser::parse(single_field_file(14, 0), encodings);
+        assert_eq!(res, Err(GffError::CyclicReference {
+            index: 0, section: GffSection::Fields, offset: 76,
+        }));
+
+        let res = GffParser::parse(single_field_file(16, 0), encodings);
+        assert_eq!(res, Err(GffError::UnknownFieldType {
+            field_type: 16, section: GffSection::Fields, offset: 68,
+        }));
+
+        let res = GffParser::parse(single_field_file(10, 0), encodings);
+        assert_eq!(res, Err(GffError::Truncated {
+            section: GffSection::FieldData, offset: 96,
+        }));
+
+        let mut data = single_field_file(0, 1);
+        put_u32(&mut data, 72, 3);
+        let res = GffParser::parse(data, encodings);
+        assert_eq!(res, Err(GffError::BadLabel {
+            index: 3, section: GffSection::Fields, offset: 72,
+        }));
+
+        let mut data = single_field_file(0, 1);
+        put_u32(&mut data, 60, 2);
+        let res = GffParser::parse(data, encodings);
+        assert_eq!(res, Err(GffError::FieldIndexOutOfRange {
+            index: 2, section: GffSection::Structs, offset: 60,
+        }));
+    }
+
+    #[test]
+    fn test_05_corrupted_sample_does_not_panic() {
+        let encodings = &*Encodings::NeverwinterNights;
+        let sample = read_sample();
+
+        for len in (0..0x100).chain((0x100..sample.len()).step_by(997)) {
+            assert!(GffParser::parse(sample[..len].to_vec(), encodings).is_err());
+        }
+
+        // corrupt every dword of a small file using all field types
+        let sub = || GffStruct {
+            st_type: 1,
+            fields: IndexMap::from([
+                (String::from("byte"), GffFieldValue::Byte(1)),
+                (String::from("dword64"), GffFieldValue::DWord64(2)),
+            ]),
+        };
+        let input = GffFile::new(*b"TEST", GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("int64"), GffFieldValue::Int64(-1)),
+                (String::from("double"), GffFieldValue::Double(1.5)),
+                (String::from("string"), GffFieldValue::CExoString(String::from("str"))),
+                (String::from("resref"), GffFieldValue::CResRef(String::from("resref"))),
+                (String::from("locstring"), GffFieldValue::CExoLocString(1, IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("str")),
+                ]))),
+                (String::from("void"), GffFieldValue::Void(vec![1, 2, 3])),
+                (String::from("struct"), GffFieldValue::Struct(sub())),
+                (String::from("list"), GffFieldValue::List(vec![sub(), sub()])),
+            ]),
+        });
+        let mut packer = Packer::new(Vec::new(), encodings);
+        packer.pack(&input).unwrap();
+        let sample = packer.writer.into_inner().unwrap();
+        assert_eq!(GffParser::parse(sample.clone(), encodings).unwrap(), input);
+
+        for offset in 0..sample.len() - 4 {
+            for val in [0, 1, 15, 0x7FFFFFFF, 0xFFFFFFFF] {
+                let mut data = sample.clone();
+                put_u32(&mut data, offset, val);
+                let _ = GffParser::parse(data, encodings);
+            }
+        }
+    }
+
+    #[test]
+    fn test_06_read_header() {
+        let header = GffParser::read_header(&single_field_file(0, 1)).unwrap();
+        assert_eq!(&header.gff_type, b"TEST");
+        assert_eq!(&header.version, b"V3.2");
+        assert_eq!((header.structs.0, header.structs.1), (56, 1));
+        assert_eq!((header.labels.0, header.labels.1), (80, 1));
+        assert_eq!((header.list_indices.0, header.list_indices.1), (96, 0));
+
+        let res = GffParser::read_header(b"BIC V3.2");
+        assert_eq!(res.unwrap_err(), GffError::Truncated { section: GffSection::Header, offset: 0 });
+    }
 }
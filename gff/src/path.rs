@@ -0,0 +1,636 @@
+//! Selectors over a tree of structs
+//!
+//! A selector is a sequence of segments separated by `.`:
+//!
+//! - `Tag` selects a field by its label, `*` selects every field
+//! - `ItemList[2]` selects a struct of a list, `ItemList[*]` all of them
+//! - `ItemList[?BaseItem==27]` selects the structs of a list whose
+//!   `BaseItem` field is 27. Conditions are `?Label==value`,
+//!   `?Label!=value`, `?Label` (the field exists) and `?!Label` (the
+//!   field is missing), and can be chained. They also filter struct
+//!   fields, and the top-level struct if the selector starts with them.
+//! - `**` selects the current struct and all the structs below it,
+//!   and `**[?Label==value]` only those matching the conditions
+//!
+//! Values are numbers, or strings, quoted if they contain `]`.
+//! Resource references are compared ignoring case, and localized
+//! strings match their string reference or any of their strings.
+//!
+//! ```
+//! # use gff::common::{IndexMap, GffFieldValue, GffStruct};
+//! let item = |base: i32, tag: &str| GffStruct {
+//!     st_type: 0,
+//!     fields: IndexMap::from([
+//!         (String::from("BaseItem"), GffFieldValue::Int(base)),
+//!         (String::from("Tag"), GffFieldValue::CExoString(String::from(tag))),
+//!     ]),
+//! };
+//! let root = GffStruct {
+//!     st_type: 0xFFFFFFFF,
+//!     fields: IndexMap::from([
+//!         (String::from("ItemList"), GffFieldValue::List(vec![item(27, "a"), item(1, "b")])),
+//!     ]),
+//! };
+//! let tags = gff::path::select(&root, "ItemList[?BaseItem==27].Tag").unwrap();
+//! assert_eq!(tags.len(), 1);
+//! assert_eq!(tags[0].path.to_string(), "ItemList[0].Tag");
+//! assert_eq!(tags[0].get::<String>().unwrap(), "a");
+//! ```
+
+use std::str::FromStr;
+
+use crate::common::{Deserialize, GffFieldType, GffFieldValue, GffStruct};
+use crate::error::{DataError, DataErrorKind, LabelPath, PathElement};
+
+/* {{{ Selector */
+
+/// Condition on a field of a struct
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Equal(String, String),
+    NotEqual(String, String),
+    Exists(String),
+    Missing(String),
+}
+
+/// Bracketed part of a segment
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Index(usize),
+    All,
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    /// Fields with this label (any label if `None`)
+    Field(Option<String>, Vec<Filter>),
+    /// Conditions on the top-level struct
+    Root(Vec<Filter>),
+    /// The current struct and all the structs below it, with conditions
+    Descendants(Vec<Filter>),
+}
+
+/// A parsed selector, e.g. `ItemList[*].Tag`
+///
+/// See the [module documentation](self) for the syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector(Vec<Segment>);
+
+/// Characters of a selector, for parsing
+struct Scanner<'s> {
+    input: &'s str,
+    rest: &'s str,
+}
+
+impl<'s> Scanner<'s> {
+    fn error(&self) -> DataError {
+        DataError::new(DataErrorKind::InvalidPath(self.input.to_string()))
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        match self.rest.strip_prefix(prefix) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Characters up to one of `stops`, which must not be empty
+    fn word(&mut self, stops: &[char]) -> Result<&'s str, DataError> {
+        let end = self.rest.find(stops).unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        if word.is_empty() {
+            return Err(self.error());
+        }
+        self.rest = rest;
+        Ok(word)
+    }
+
+    /// Compared value, quoted or up to the closing bracket
+    fn value(&mut self) -> Result<String, DataError> {
+        if !self.eat("\"") {
+            let end = self.rest.find(']').ok_or_else(|| self.error())?;
+            let (value, rest) = self.rest.split_at(end);
+            self.rest = rest;
+            return Ok(value.to_string());
+        }
+        let mut value = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(value);
+                },
+                '\\' => value.push(chars.next().ok_or_else(|| self.error())?.1),
+                c => value.push(c),
+            }
+        }
+        Err(self.error())
+    }
+
+    fn filter(&mut self) -> Result<Filter, DataError> {
+        let filter = if self.eat("*") {
+            Filter::All
+        } else if self.eat("?!") {
+            Filter::Condition(Condition::Missing(self.word(&[']'])?.to_string()))
+        } else if self.eat("?") {
+            let label = self.word(&['=', '!', ']'])?.to_string();
+            Filter::Condition(if self.eat("==") {
+                Condition::Equal(label, self.value()?)
+            } else if self.eat("!=") {
+                Condition::NotEqual(label, self.value()?)
+            } else {
+                Condition::Exists(label)
+            })
+        } else {
+            Filter::Index(self.word(&[']'])?.parse().map_err(|_| self.error())?)
+        };
+        if !self.eat("]") {
+            return Err(self.error());
+        }
+        Ok(filter)
+    }
+
+    fn filters(&mut self) -> Result<Vec<Filter>, DataError> {
+        let mut filters = vec![];
+        while self.eat("[") {
+            filters.push(self.filter()?);
+        }
+        Ok(filters)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = DataError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scanner = Scanner { input: s, rest: s };
+        let mut segments = vec![];
+        if scanner.rest.starts_with('[') {
+            let filters = scanner.filters()?;
+            if filters.iter().any(|f| !matches!(f, Filter::Condition(_))) {
+                return Err(scanner.error());
+            }
+            segments.push(Segment::Root(filters));
+            if scanner.rest.is_empty() {
+                return Ok(Selector(segments));
+            }
+            if !scanner.eat(".") {
+                return Err(scanner.error());
+            }
+        }
+        loop {
+            if scanner.eat("**") {
+                let filters = scanner.filters()?;
+                if filters.iter().any(|f| !matches!(f, Filter::Condition(_))) {
+                    return Err(scanner.error());
+                }
+                // `**.**` is `**`
+                if !(filters.is_empty() && segments.last() == Some(&Segment::Descendants(vec![]))) {
+                    segments.push(Segment::Descendants(filters));
+                }
+            } else {
+                let label = if scanner.eat("*") {
+                    None
+                } else {
+                    Some(scanner.word(&['.', '['])?.to_string())
+                };
+                segments.push(Segment::Field(label, scanner.filters()?));
+            }
+            if scanner.rest.is_empty() {
+                break;
+            }
+            if !scanner.eat(".") {
+                return Err(scanner.error());
+            }
+        }
+        Ok(Selector(segments))
+    }
+}
+
+/* }}} */
+/* {{{ Matching */
+
+/// Whether a field value is equal to a selector value
+fn equals(value: &GffFieldValue, s: &str) -> bool {
+    macro_rules! number {
+        ($v:expr, $type:ty) => { s.parse::<$type>().is_ok_and(|n| n == $v) }
+    }
+    match value {
+        GffFieldValue::Byte(v) => number!(*v as i128, i128),
+        GffFieldValue::Char(v) => number!(*v as i128, i128),
+        GffFieldValue::Word(v) => number!(*v as i128, i128),
+        GffFieldValue::Short(v) => number!(*v as i128, i128),
+        GffFieldValue::DWord(v) => number!(*v as i128, i128),
+        GffFieldValue::Int(v) => number!(*v as i128, i128),
+        GffFieldValue::DWord64(v) => number!(*v as i128, i128),
+        GffFieldValue::Int64(v) => number!(*v as i128, i128),
+        GffFieldValue::Float(v) => number!(*v, f32),
+        GffFieldValue::Double(v) => number!(*v, f64),
+        GffFieldValue::CExoString(v) => v == s,
+        GffFieldValue::CResRef(v) => v.eq_ignore_ascii_case(s),
+        GffFieldValue::CExoLocString(str_ref, strings) =>
+            number!(*str_ref, u32) || strings.values().any(|v| v == s),
+        GffFieldValue::Void(_) | GffFieldValue::Struct(_) | GffFieldValue::List(_) => false,
+    }
+}
+
+fn test(st: &GffStruct, filter: &Filter) -> bool {
+    match filter {
+        Filter::Index(_) | Filter::All => false,
+        Filter::Condition(Condition::Equal(label, s)) =>
+            st.fields.get(label).is_some_and(|v| equals(v, s)),
+        Filter::Condition(Condition::NotEqual(label, s)) =>
+            !st.fields.get(label).is_some_and(|v| equals(v, s)),
+        Filter::Condition(Condition::Exists(label)) => st.fields.contains_key(label),
+        Filter::Condition(Condition::Missing(label)) => !st.fields.contains_key(label),
+    }
+}
+
+/// A selected field, or struct
+///
+/// Structs of lists, and structs selected by `**` or by conditions
+/// on the top-level struct are a [`Node::Struct`]. Struct fields
+/// selected by their label are a [`Node::Field`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node<'a> {
+    Struct(&'a GffStruct),
+    Field(&'a GffFieldValue),
+}
+
+/// A mutable selected field, or struct, see [`Node`]
+#[derive(Debug, PartialEq)]
+pub enum NodeMut<'a> {
+    Struct(&'a mut GffStruct),
+    Field(&'a mut GffFieldValue),
+}
+
+/// A selected node, with its path from the top-level struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    pub path: LabelPath,
+    pub node: Node<'a>,
+}
+
+/// A mutable selected node, with its path from the top-level struct
+#[derive(Debug, PartialEq)]
+pub struct MatchMut<'a> {
+    pub path: LabelPath,
+    pub node: NodeMut<'a>,
+}
+
+impl<'a> Node<'a> {
+    /// The selected field, `None` for a struct of a list
+    pub fn as_field(&self) -> Option<&'a GffFieldValue> {
+        match self {
+            Node::Field(value) => Some(value),
+            Node::Struct(_) => None,
+        }
+    }
+
+    /// The selected struct, either a struct of a list or a struct field
+    pub fn as_struct(&self) -> Option<&'a GffStruct> {
+        match *self {
+            Node::Struct(st) | Node::Field(GffFieldValue::Struct(st)) => Some(st),
+            Node::Field(_) => None,
+        }
+    }
+}
+
+impl<'a> Match<'a> {
+    /// Convert the selected field to a Rust type
+    pub fn get<T>(&self) -> Result<T, DataError>
+        where for<'b> T: TryFrom<&'b GffFieldValue, Error = DataError>
+    {
+        match self.node {
+            Node::Field(value) => T::try_from(value),
+            Node::Struct(st) => T::try_from(&GffFieldValue::Struct(st.clone())),
+        }.map_err(|e| e.at_path(&self.path))
+    }
+
+    /// Deserialize the selected struct
+    pub fn deserialize<T: Deserialize>(&self) -> Result<T, DataError> {
+        match self.node {
+            Node::Field(GffFieldValue::Struct(st)) | Node::Struct(st) => T::deserialize(st),
+            Node::Field(value) => Err(DataError::unexpected_type(GffFieldType::Struct, value)),
+        }.map_err(|e| e.at_path(&self.path))
+    }
+}
+
+impl Selector {
+    fn select_in<'a>(segments: &[Segment], st: &'a GffStruct, path: &LabelPath, out: &mut Vec<Match<'a>>) {
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            None => return out.push(Match { path: path.clone(), node: Node::Struct(st) }),
+        };
+        match segment {
+            Segment::Root(filters) => {
+                if filters.iter().all(|f| test(st, f)) {
+                    Self::select_in(rest, st, path, out);
+                }
+            },
+            Segment::Descendants(filters) => {
+                if filters.iter().all(|f| test(st, f)) {
+                    Self::select_in(rest, st, path, out);
+                }
+                for (label, value) in &st.fields {
+                    match value {
+                        GffFieldValue::Struct(sub) =>
+                            Self::select_in(segments, sub, &path.join_label(label), out),
+                        GffFieldValue::List(list) => for (i, sub) in list.iter().enumerate() {
+                            Self::select_in(segments, sub, &path.join_label(label).join_index(i), out);
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            Segment::Field(name, filters) => {
+                for (label, value) in &st.fields {
+                    if name.as_ref().is_some_and(|name| name != label) {
+                        continue;
+                    }
+                    let path = path.join_label(label);
+                    match (value, filters.first()) {
+                        (_, None) if rest.is_empty() =>
+                            out.push(Match { path, node: Node::Field(value) }),
+                        (GffFieldValue::Struct(sub), _) if filters.iter().all(|f| test(sub, f)) => {
+                            if rest.is_empty() {
+                                out.push(Match { path, node: Node::Field(value) });
+                            } else {
+                                Self::select_in(rest, sub, &path, out);
+                            }
+                        },
+                        (GffFieldValue::List(list), Some(first)) => {
+                            let (indices, conditions) = match first {
+                                Filter::Index(i) => (*i..i.saturating_add(1).min(list.len()), &filters[1..]),
+                                Filter::All => (0..list.len(), &filters[1..]),
+                                Filter::Condition(_) => (0..list.len(), &filters[..]),
+                            };
+                            for i in indices {
+                                if conditions.iter().all(|f| test(&list[i], f)) {
+                                    Self::select_in(rest, &list[i], &path.join_index(i), out);
+                                }
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            },
+        }
+    }
+
+    /// Select nodes of a struct
+    ///
+    /// Nodes are returned in the order of the fields. With `**`,
+    /// a node and some nodes below it can both be selected.
+    pub fn select<'a>(&self, st: &'a GffStruct) -> Vec<Match<'a>> {
+        let mut out = vec![];
+        Self::select_in(&self.0, st, &LabelPath::default(), &mut out);
+        out
+    }
+
+    /// Select mutable nodes of a struct
+    ///
+    /// This is [`Selector::select`], except that a node selected below
+    /// another selected node is left out, since both cannot be borrowed
+    /// at the same time.
+    pub fn select_mut<'a>(&self, st: &'a mut GffStruct) -> Vec<MatchMut<'a>> {
+        let mut targets: Vec<Target> = self.select(st).into_iter().map(|m| Target {
+            path: m.path,
+            is_struct: matches!(m.node, Node::Struct(_)),
+        }).collect();
+        targets.sort_by_key(|t| t.path.0.len());
+        let mut outer: Vec<Target> = vec![];
+        for target in targets {
+            if !outer.iter().any(|o| target.path.0.starts_with(&o.path.0)) {
+                outer.push(target);
+            }
+        }
+        let mut out = vec![];
+        borrow_targets(st, 0, &outer.iter().collect::<Vec<_>>(), &mut out);
+        out
+    }
+}
+
+/// Selected node to borrow mutably
+struct Target {
+    path: LabelPath,
+    /// Whether the node is a [`Node::Struct`]
+    is_struct: bool,
+}
+
+/// Borrow the nodes at disjoint paths, all starting with the `depth`
+/// elements leading to `st`
+fn borrow_targets<'a>(st: &'a mut GffStruct, depth: usize, targets: &[&Target], out: &mut Vec<MatchMut<'a>>) {
+    if let Some(target) = targets.iter().find(|t| t.path.0.len() == depth) {
+        return out.push(MatchMut { path: target.path.clone(), node: NodeMut::Struct(st) });
+    }
+    for (label, value) in st.fields.iter_mut() {
+        let targets: Vec<&Target> = targets.iter()
+            .filter(|t| matches!(&t.path.0[depth], PathElement::Label(l) if l == label))
+            .copied().collect();
+        if targets.is_empty() {
+            continue;
+        }
+        if let Some(target) = targets.iter().find(|t| t.path.0.len() == depth + 1) {
+            let node = match (target.is_struct, value) {
+                (true, GffFieldValue::Struct(sub)) => NodeMut::Struct(sub),
+                (_, value) => NodeMut::Field(value),
+            };
+            out.push(MatchMut { path: target.path.clone(), node });
+            continue;
+        }
+        match value {
+            GffFieldValue::Struct(sub) => borrow_targets(sub, depth + 1, &targets, out),
+            GffFieldValue::List(list) => for (i, sub) in list.iter_mut().enumerate() {
+                let targets: Vec<&Target> = targets.iter()
+                    .filter(|t| t.path.0[depth + 1] == PathElement::Index(i))
+                    .copied().collect();
+                if !targets.is_empty() {
+                    borrow_targets(sub, depth + 2, &targets, out);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Select nodes of a struct, see [`Selector::select`]
+pub fn select<'a>(st: &'a GffStruct, selector: &str) -> Result<Vec<Match<'a>>, DataError> {
+    Ok(selector.parse::<Selector>()?.select(st))
+}
+
+/// Select mutable nodes of a struct, see [`Selector::select_mut`]
+pub fn select_mut<'a>(st: &'a mut GffStruct, selector: &str) -> Result<Vec<MatchMut<'a>>, DataError> {
+    Ok(selector.parse::<Selector>()?.select_mut(st))
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Encodings, IndexMap, GffFieldValue, GffGender, GffLang, GffStruct, ResRef};
+    use crate::parser::GffParser;
+    use super::{select, select_mut, Node, NodeMut, Selector};
+
+    fn item(base: i32, tag: &str, properties: Vec<GffStruct>) -> GffStruct {
+        GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([
+                (String::from("BaseItem"), GffFieldValue::Int(base)),
+                (String::from("Tag"), GffFieldValue::CExoString(String::from(tag))),
+                (String::from("TemplateResRef"), GffFieldValue::CResRef(tag.to_uppercase())),
+                (String::from("PropertiesList"), GffFieldValue::List(properties)),
+            ]),
+        }
+    }
+
+    fn sample() -> GffStruct {
+        let property = |name: u16| GffStruct {
+            st_type: 0,
+            fields: IndexMap::from([(String::from("PropertyName"), GffFieldValue::Word(name))]),
+        };
+        GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("Tag"), GffFieldValue::CExoString(String::from("npc"))),
+                (String::from("Conversation"), GffFieldValue::CResRef(String::new())),
+                (String::from("FirstName"), GffFieldValue::CExoLocString(12, IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("Bob")),
+                ]))),
+                (String::from("Tint"), GffFieldValue::Struct(GffStruct {
+                    st_type: 0,
+                    fields: IndexMap::from([(String::from("a"), GffFieldValue::Byte(255))]),
+                })),
+                (String::from("ItemList"), GffFieldValue::List(vec![
+                    item(27, "sword", vec![property(1), property(2)]),
+                    item(1, "a]b", vec![]),
+                    item(27, "axe", vec![property(2)]),
+                ])),
+            ]),
+        }
+    }
+
+    fn paths(st: &GffStruct, selector: &str) -> Vec<String> {
+        select(st, selector).unwrap().iter().map(|m| m.path.to_string()).collect()
+    }
+
+    #[test]
+    fn test_01_parse() {
+        for selector in ["Tag", "*", "**", "**.Tag", "ItemList[2]", "ItemList[*].Tag", "A.B.C",
+            "ItemList[?BaseItem==27]", "ItemList[?Tag==\"a]\\\"b\"]", "ItemList[?Tag!=]",
+            "ItemList[*][?Stolen][?!Plot]", "[?Gold==0]", "[?Tag].ItemList[0]"]
+        {
+            assert!(selector.parse::<Selector>().is_ok(), "{}", selector);
+        }
+        for selector in ["", ".", "Tag.", "ItemList[", "ItemList[a]", "ItemList[0]Tag", "**Tag", "**[0]",
+            "ItemList[?Tag==\"a]", "[0]", "[*].Tag", "ItemList[?]", "A..B"]
+        {
+            assert_eq!(selector.parse::<Selector>().unwrap_err().to_string(),
+                format!("invalid path `{}`", selector), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn test_02_select() {
+        let st = sample();
+        assert_eq!(paths(&st, "Tag"), ["Tag"]);
+        assert_eq!(paths(&st, "Missing"), Vec::<String>::new());
+        assert_eq!(paths(&st, "*").len(), 5);
+        assert_eq!(paths(&st, "ItemList[1].Tag"), ["ItemList[1].Tag"]);
+        assert_eq!(paths(&st, "ItemList[3].Tag"), Vec::<String>::new());
+        assert_eq!(paths(&st, "ItemList[18446744073709551615]"), Vec::<String>::new());
+        assert_eq!(paths(&st, "ItemList[*]"), ["ItemList[0]", "ItemList[1]", "ItemList[2]"]);
+        assert_eq!(paths(&st, "ItemList[?BaseItem==27].Tag"), ["ItemList[0].Tag", "ItemList[2].Tag"]);
+        assert_eq!(paths(&st, "ItemList[?BaseItem!=27][?Tag==\"a]b\"]"), ["ItemList[1]"]);
+        assert_eq!(paths(&st, "ItemList[?TemplateResRef==axe]"), ["ItemList[2]"]);
+        assert_eq!(paths(&st, "ItemList[*].PropertiesList[?PropertyName==2]"),
+            ["ItemList[0].PropertiesList[1]", "ItemList[2].PropertiesList[0]"]);
+        assert_eq!(paths(&st, "**.Tag"), ["Tag", "ItemList[0].Tag", "ItemList[1].Tag", "ItemList[2].Tag"]);
+        assert_eq!(paths(&st, "**.PropertyName").len(), 3);
+        assert_eq!(paths(&st, "**[?a==255]"), ["Tint"]);
+        assert_eq!(paths(&st, "Tint[?a==255].a"), ["Tint.a"]);
+        assert_eq!(paths(&st, "Tint[?a==0].a"), Vec::<String>::new());
+        assert_eq!(paths(&st, "[?Conversation==\"\"]"), [""]);
+        assert_eq!(paths(&st, "[?FirstName==Bob].Tag"), ["Tag"]);
+        assert_eq!(paths(&st, "[?FirstName==12][?!Gold]"), [""]);
+        assert_eq!(paths(&st, "[?Gold]"), Vec::<String>::new());
+
+        let matches = select(&st, "ItemList[0].PropertiesList[0].PropertyName").unwrap();
+        assert_eq!(matches[0].node, Node::Field(&GffFieldValue::Word(1)));
+        assert_eq!(matches[0].get::<u16>().unwrap(), 1);
+        assert_eq!(matches[0].get::<String>().unwrap_err().to_string(),
+            "ItemList[0].PropertiesList[0].PropertyName: expected CExoString, found Word");
+        let matches = select(&st, "ItemList[*].TemplateResRef").unwrap();
+        assert_eq!(matches[2].get::<ResRef>().unwrap().as_str(), "AXE");
+        let matches = select(&st, "ItemList[2]").unwrap();
+        assert_eq!(matches[0].node.as_struct().unwrap().fields["Tag"],
+            GffFieldValue::CExoString(String::from("axe")));
+    }
+
+    #[test]
+    fn test_03_select_mut() {
+        let mut st = sample();
+        for m in select_mut(&mut st, "ItemList[?BaseItem==27].Tag").unwrap() {
+            if let NodeMut::Field(GffFieldValue::CExoString(tag)) = m.node {
+                tag.push_str("_27");
+            }
+        }
+        assert_eq!(paths(&st, "ItemList[?Tag==axe_27]"), ["ItemList[2]"]);
+
+        /* nested nodes are left out */
+        let matches = select_mut(&mut st, "**").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(matches!(&matches[0].node, NodeMut::Struct(root) if root.st_type == 0xFFFFFFFF));
+        let matches = select_mut(&mut st, "**.PropertiesList").unwrap();
+        assert_eq!(matches.len(), 3);
+        let matches = select_mut(&mut st, "**[?a]").unwrap();
+        assert!(matches!(&matches[0].node, NodeMut::Struct(tint) if tint.st_type == 0));
+        let matches = select_mut(&mut st, "Tint").unwrap();
+        assert!(matches!(&matches[0].node, NodeMut::Field(GffFieldValue::Struct(_))));
+
+        for m in select_mut(&mut st, "ItemList[*]").unwrap() {
+            if let NodeMut::Struct(item) = m.node {
+                item.fields.insert(String::from("Stolen"), GffFieldValue::Byte(1));
+            }
+        }
+        assert_eq!(paths(&st, "ItemList[?Stolen==1]").len(), 3);
+    }
+
+    #[test]
+    fn test_04_select_sample() {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let file = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap();
+
+        let all = select(&file.root, "**").unwrap();
+        let mut structs = 1;
+        let mut stack = vec![&file.root];
+        while let Some(st) = stack.pop() {
+            for value in st.fields.values() {
+                match value {
+                    GffFieldValue::Struct(sub) => stack.push(sub),
+                    GffFieldValue::List(list) => stack.extend(list.iter()),
+                    _ => continue,
+                }
+                structs += match value {
+                    GffFieldValue::List(list) => list.len(),
+                    _ => 1,
+                };
+            }
+        }
+        assert_eq!(all.len(), structs);
+
+        let tags = select(&file.root, "ItemList[*].Tag").unwrap();
+        assert_eq!(tags.len(), 75);
+        assert_eq!(tags[0].get::<String>().unwrap(), "si_marin1");
+        let resrefs = select(&file.root, "**.TemplateResRef").unwrap();
+        assert!(resrefs.len() > tags.len());
+        for m in &resrefs {
+            assert_eq!(file.root.get_path(&m.path).unwrap(), m.node.as_field().unwrap());
+        }
+    }
+}
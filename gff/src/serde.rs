@@ -0,0 +1,522 @@
+//! Support for [serde](https://serde.rs), with the `serde` feature
+//!
+//! Types implementing `serde::Serialize`/`serde::Deserialize` are mapped
+//! to GFF data as follows:
+//! - structs are GFF structs, and so are maps, with their keys as
+//!   labels, sorted
+//! - sequences and tuples are lists, whose elements must be structs
+//! - `None` fields are omitted, and missing fields are `None`
+//! - numbers are the GFF type of the same size, `bool` is a Byte,
+//!   strings are CExoStrings and bytes are Voids
+//! - enum variants are structs whose type id is the variant index: unit
+//!   variants are empty structs, newtype variants hold a struct, and
+//!   the variant index takes precedence over the struct's own type id
+//!
+//! Maps are not encoded as by the [`Serialize`](crate::common::Serialize)
+//! and [`Deserialize`](crate::common::Deserialize) traits and the
+//! `gff_derive` derives, which store a `HashMap` or `IndexMap` field as a
+//! list of local variables, the `Name`, `Type` and `Value` structs of a
+//! `VarTable`. With serde, a map is a struct, so its keys are limited to
+//! 16 bytes, and data written through one API cannot be read through the
+//! other. Use the derives to read or write local variables.
+//!
+//! [`ResRef`] and [`LocString`] are serialized as CResRef and
+//! CExoLocString, and [`VoidData`] as Void. A struct field named
+//! [`STRUCT_ID_FIELD`] holds the struct type id.
+//!
+//! The intermediary representation ([`GffStruct`](crate::common::GffStruct),
+//! [`GffFieldValue`](crate::common::GffFieldValue), [`GffLang`] and
+//! [`GffGender`]) also implements `Serialize`/`Deserialize`, to be stored
+//! in any serde format. Field values keep their exact GFF type.
+//!
+//! ```
+//! # use gff::common::Encodings;
+//! #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+//! #[allow(non_snake_case)]
+//! struct Creature {
+//!     Tag: String,
+//!     Appearance_Type: u16,
+//! }
+//!
+//! let creature = Creature { Tag: String::from("nw_bandit"), Appearance_Type: 6 };
+//! let data = gff::serde::to_bytes(*b"UTC ", &creature, &*Encodings::NeverwinterNights).unwrap();
+//! let res: Creature = gff::serde::from_bytes(data, &*Encodings::NeverwinterNights).unwrap();
+//! assert_eq!(res, creature);
+//! ```
+
+mod de;
+mod ser;
+mod value;
+
+use std::fmt;
+
+use crate::common::{
+    locstring_id,
+    locstring_lang,
+    IndexMap,
+    GffGender,
+    GffLang,
+    ResRef,
+    LocString,
+    VoidData,
+};
+use crate::error::{DataError, DataErrorKind};
+
+pub use de::{from_bytes, from_struct, ReaderDeserializer, StructDeserializer};
+pub use ser::{to_bytes, to_struct, Serializer};
+
+/// Name of a struct field holding the struct type id
+///
+/// When serializing, a `u32` field with this name sets the struct
+/// type id instead of adding a field. When deserializing, it is given
+/// the struct type id if the struct declares it.
+pub const STRUCT_ID_FIELD: &str = "__struct_id";
+
+/// Newtype struct names recognized by [`Serializer`]
+const RESREF_NAME: &str = "$gff::ResRef";
+const LOCSTRING_NAME: &str = "$gff::LocString";
+
+/// Key of the string reference in a serialized [`LocString`]
+const STR_REF_KEY: &str = "StrRef";
+
+impl ::serde::ser::Error for DataError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DataError::new(DataErrorKind::Custom(msg.to_string()))
+    }
+}
+
+impl ::serde::de::Error for DataError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DataError::new(DataErrorKind::Custom(msg.to_string()))
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        DataError::new(DataErrorKind::MissingField).at_label(field)
+    }
+}
+
+/* {{{ ResRef, LocString and VoidData */
+
+impl ::serde::Serialize for ResRef {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(RESREF_NAME, self.as_str())
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for ResRef {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ResRefVisitor;
+
+        impl<'de> ::serde::de::Visitor<'de> for ResRefVisitor {
+            type Value = ResRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a resref")
+            }
+
+            fn visit_str<E: ::serde::de::Error>(self, v: &str) -> Result<ResRef, E> {
+                ResRef::new(v).map_err(E::custom)
+            }
+
+            fn visit_newtype_struct<D: ::serde::Deserializer<'de>>(self, deserializer: D)
+                -> Result<ResRef, D::Error>
+            {
+                deserializer.deserialize_str(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(RESREF_NAME, ResRefVisitor)
+    }
+}
+
+/// Language and gender from a localized string key
+fn locstring_key(key: &str) -> Option<(GffLang, GffGender)> {
+    locstring_lang(key.parse().ok()?)
+}
+
+/// A [`LocString`], as a map with a [`STR_REF_KEY`] entry, then an
+/// entry per string, keyed by its language and gender id
+struct LocStringMap<'a>(u32, &'a IndexMap<(GffLang, GffGender), String>);
+
+impl<'a> ::serde::Serialize for LocStringMap<'a> {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ::serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.1.len() + 1))?;
+        map.serialize_entry(STR_REF_KEY, &self.0)?;
+        for ((lang, gender), s) in self.1 {
+            map.serialize_entry(&locstring_id(*lang, *gender).to_string(), s)?;
+        }
+        map.end()
+    }
+}
+
+impl ::serde::Serialize for LocString {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(LOCSTRING_NAME, &LocStringMap(self.str_ref, &self.strings))
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for LocString {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LocStringVisitor;
+
+        impl<'de> ::serde::de::Visitor<'de> for LocStringVisitor {
+            type Value = LocString;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a localized string")
+            }
+
+            fn visit_map<A: ::serde::de::MapAccess<'de>>(self, mut map: A)
+                -> Result<LocString, A::Error>
+            {
+                use ::serde::de::Error;
+
+                let mut res = LocString::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == STR_REF_KEY {
+                        res.str_ref = map.next_value()?;
+                        continue;
+                    }
+                    let lang_gender = locstring_key(&key)
+                        .ok_or_else(|| A::Error::custom(format!("unknown language id {}", key)))?;
+                    res.strings.insert(lang_gender, map.next_value()?);
+                }
+                Ok(res)
+            }
+
+            fn visit_newtype_struct<D: ::serde::Deserializer<'de>>(self, deserializer: D)
+                -> Result<LocString, D::Error>
+            {
+                deserializer.deserialize_map(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(LOCSTRING_NAME, LocStringVisitor)
+    }
+}
+
+impl ::serde::Serialize for VoidData {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> ::serde::Deserialize<'de> for VoidData {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VoidDataVisitor;
+
+        impl<'de> ::serde::de::Visitor<'de> for VoidDataVisitor {
+            type Value = VoidData;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "bytes")
+            }
+
+            fn visit_bytes<E: ::serde::de::Error>(self, v: &[u8]) -> Result<VoidData, E> {
+                Ok(VoidData(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: ::serde::de::Error>(self, v: Vec<u8>) -> Result<VoidData, E> {
+                Ok(VoidData(v))
+            }
+
+            fn visit_seq<A: ::serde::de::SeqAccess<'de>>(self, mut seq: A)
+                -> Result<VoidData, A::Error>
+            {
+                let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    data.push(byte);
+                }
+                Ok(VoidData(data))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(VoidDataVisitor)
+    }
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use serde::{Serialize, Deserialize};
+    use crate::common::{
+        IndexMap,
+        GffFieldValue,
+        GffStruct,
+        GffGender,
+        GffLang,
+        ResRef,
+        LocString,
+        VoidData,
+        Encodings,
+    };
+    use crate::packer::Packer;
+    use crate::parser::GffParser;
+    use crate::common::GffFile;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[allow(non_snake_case)]
+    struct Item {
+        __struct_id: u32,
+        Tag: String,
+        TemplateResRef: ResRef,
+        StackSize: u16,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Kind {
+        Creature,
+        Door,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[allow(non_snake_case)]
+    enum Object {
+        Creature { Tag: String },
+        Placeable(Item),
+        Door { __struct_id: u32, Tag: String },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[allow(non_snake_case)]
+    struct Creature {
+        Tag: String,
+        FirstName: LocString,
+        IsPC: bool,
+        Age: i32,
+        Gold: u32,
+        ChallengeRating: f32,
+        Kind: Kind,
+        Kinds: Vec<Kind>,
+        Portrait: Option<ResRef>,
+        Data: VoidData,
+        ItemList: Vec<Item>,
+        Objects: Vec<Object>,
+        Vars: HashMap<String, i64>,
+    }
+
+    fn creature() -> Creature {
+        Creature {
+            Tag: String::from("nw_bandit"),
+            FirstName: LocString {
+                str_ref: 0xFFFFFFFF,
+                strings: IndexMap::from([
+                    ((GffLang::English, GffGender::Male), String::from("Bandit")),
+                    ((GffLang::French, GffGender::Female), String::from("Bandite")),
+                ]),
+            },
+            IsPC: false,
+            Age: 32,
+            Gold: 150,
+            ChallengeRating: 1.5,
+            Kind: Kind::Creature,
+            Kinds: vec![Kind::Door, Kind::Creature],
+            Portrait: None,
+            Data: VoidData(vec![1, 2, 3]),
+            ItemList: vec![Item {
+                __struct_id: 0,
+                Tag: String::from("nw_it_gold001"),
+                TemplateResRef: ResRef::new("nw_it_gold001").unwrap(),
+                StackSize: 150,
+            }],
+            Objects: vec![
+                Object::Creature { Tag: String::from("nw_bandit") },
+                Object::Placeable(Item {
+                    __struct_id: 1,
+                    Tag: String::from("chest"),
+                    TemplateResRef: ResRef::new("plc_chest1").unwrap(),
+                    StackSize: 1,
+                }),
+            ],
+            Vars: HashMap::from([(String::from("quest_state"), 2), (String::from("kills"), 5)]),
+        }
+    }
+
+    #[test]
+    fn test_01_to_from_struct() {
+        let input = creature();
+        let st = super::to_struct(&input).unwrap();
+
+        assert_eq!(st.st_type, 0xFFFFFFFF);
+        assert_eq!(st.fields["Tag"], GffFieldValue::CExoString(String::from("nw_bandit")));
+        assert_eq!(st.fields["FirstName"], GffFieldValue::CExoLocString(0xFFFFFFFF, input.FirstName.strings.clone()));
+        assert_eq!(st.fields["IsPC"], GffFieldValue::Byte(0));
+        assert_eq!(st.fields["Gold"], GffFieldValue::DWord(150));
+        assert_eq!(st.fields["Kind"], GffFieldValue::Struct(GffStruct { st_type: 0, fields: IndexMap::new() }));
+        assert_eq!(st.fields["Kinds"], GffFieldValue::List(vec![
+            GffStruct { st_type: 1, fields: IndexMap::new() },
+            GffStruct { st_type: 0, fields: IndexMap::new() },
+        ]));
+        assert!(!st.fields.contains_key("Portrait"));
+        assert_eq!(st.fields["Data"], GffFieldValue::Void(vec![1, 2, 3]));
+        let items = match &st.fields["ItemList"] {
+            GffFieldValue::List(items) => items,
+            _ => unreachable!(),
+        };
+        assert_eq!(items[0].st_type, 0);
+        assert!(items[0].fields.keys().eq(["Tag", "TemplateResRef", "StackSize"]));
+        assert_eq!(items[0].fields["TemplateResRef"], GffFieldValue::CResRef(String::from("nw_it_gold001")));
+        let objects = match &st.fields["Objects"] {
+            GffFieldValue::List(objects) => objects,
+            _ => unreachable!(),
+        };
+        assert_eq!(objects[0].st_type, 0);
+        assert_eq!(objects[1].st_type, 1);
+        match &st.fields["Vars"] {
+            GffFieldValue::Struct(vars) => assert!(vars.fields.keys().eq(["kills", "quest_state"])),
+            _ => unreachable!(),
+        }
+
+        /* the variant index takes precedence over the struct type id */
+        let door = Object::Door { __struct_id: 7, Tag: String::from("door") };
+        let door = super::to_struct(&door).unwrap();
+        assert_eq!(door.st_type, 2);
+        assert!(door.fields.keys().eq(["Tag"]));
+
+        let output: Creature = super::from_struct(&st).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_02_to_from_bytes() {
+        let encoding = &*Encodings::NeverwinterNights;
+        let input = creature();
+        let data = super::to_bytes(*b"UTC ", &input, encoding).unwrap();
+
+        let mut packer = Packer::new(Vec::new(), encoding);
+        packer.pack(&GffFile::new(*b"UTC ", super::to_struct(&input).unwrap())).unwrap();
+        assert_eq!(data, packer.writer.into_inner().unwrap());
+
+        let output: Creature = super::from_bytes(data, encoding).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_03_errors() {
+        #[derive(Serialize)]
+        struct LongLabel {
+            this_label_is_too_long: u8,
+        }
+        let err = super::to_struct(&LongLabel { this_label_is_too_long: 0 }).unwrap_err();
+        assert_eq!(err.to_string(), "this_label_is_too_long: label is longer than 16 bytes");
+
+        #[derive(Serialize)]
+        struct NotStructs {
+            list: Vec<u8>,
+        }
+        let err = super::to_struct(&NotStructs { list: vec![1] }).unwrap_err();
+        assert_eq!(err.to_string(), "list[0]: expected Struct, found Byte");
+
+        let err = super::to_struct(&1u8).unwrap_err();
+        assert_eq!(err.to_string(), "expected Struct, found Byte");
+
+        let mut st = super::to_struct(&creature()).unwrap();
+        st.fields.insert(String::from("Gold"), GffFieldValue::Word(150));
+        let err = super::from_struct::<Creature>(&st).unwrap_err();
+        assert_eq!(err.to_string(), "Gold: expected DWord, found Word");
+
+        st.fields.shift_remove("Gold");
+        let err = super::from_struct::<Creature>(&st).unwrap_err();
+        assert_eq!(err.to_string(), "Gold: missing field");
+
+        let st = GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([(String::from("list"), GffFieldValue::List(vec![
+                GffStruct { st_type: 0, fields: IndexMap::new() },
+            ]))]),
+        };
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Items {
+            list: Vec<Item>,
+        }
+        let err = super::from_struct::<Items>(&st).unwrap_err();
+        assert_eq!(err.to_string(), "list[0].Tag: missing field");
+
+        let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+        packer.pack(&GffFile::new(*b"TEST", st)).unwrap();
+        let err = super::from_bytes::<Items>(packer.writer.into_inner().unwrap(),
+            &*Encodings::NeverwinterNights).unwrap_err();
+        assert_eq!(err.to_string(), "list[0].Tag: missing field");
+    }
+
+    #[test]
+    fn test_04_intermediary_representation() {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let file = GffParser::parse(data, &*Encodings::NeverwinterNights).unwrap();
+
+        let json = serde_json::to_string(&file.root).unwrap();
+        assert_eq!(serde_json::from_str::<GffStruct>(&json).unwrap(), file.root);
+        let bin = bincode::serialize(&file.root).unwrap();
+        assert_eq!(bincode::deserialize::<GffStruct>(&bin).unwrap(), file.root);
+
+        /* exact types, Void bytes and localized string keys */
+        let st = GffStruct {
+            st_type: 3,
+            fields: IndexMap::from([
+                (String::from("Word"), GffFieldValue::Word(5)),
+                (String::from("Int"), GffFieldValue::Int(5)),
+                (String::from("Data"), GffFieldValue::Void(vec![0, 255])),
+                (String::from("Name"), GffFieldValue::CExoLocString(12, IndexMap::from([
+                    ((GffLang::French, GffGender::Female), String::from("Bandite")),
+                    ((GffLang::English, GffGender::Male), String::from("Bandit")),
+                ]))),
+            ]),
+        };
+        let json = serde_json::to_value(&st).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "st_type": 3,
+            "fields": {
+                "Word": { "Word": 5 },
+                "Int": { "Int": 5 },
+                "Data": { "Void": [0, 255] },
+                "Name": { "CExoLocString": { "StrRef": 12, "3": "Bandite", "0": "Bandit" } },
+            },
+        }));
+        let res: GffStruct = serde_json::from_value(json).unwrap();
+        assert_eq!(res, st);
+        assert!(res.fields.keys().eq(["Word", "Int", "Data", "Name"]));
+        let bin = bincode::serialize(&st).unwrap();
+        assert_eq!(bincode::deserialize::<GffStruct>(&bin).unwrap(), st);
+
+        let err = serde_json::from_str::<GffFieldValue>(r#"{ "Dword": 5 }"#).unwrap_err();
+        assert!(err.to_string().starts_with("unknown variant `Dword`"));
+    }
+
+    #[test]
+    fn test_05_maps() {
+        #[derive(Serialize)]
+        #[allow(non_snake_case)]
+        struct Vars {
+            Vars: HashMap<String, i32>,
+        }
+        let vars = HashMap::from([(String::from("quest_state"), 2), (String::from("kills"), 5)]);
+
+        /* a struct with serde, local variables with the conversion traits */
+        let st = super::to_struct(&Vars { Vars: vars.clone() }).unwrap();
+        assert_eq!(st.fields["Vars"], GffFieldValue::Struct(GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::from([
+                (String::from("kills"), GffFieldValue::Int(5)),
+                (String::from("quest_state"), GffFieldValue::Int(2)),
+            ]),
+        }));
+        let var_table: GffFieldValue = (&vars).try_into().unwrap();
+        match &var_table {
+            GffFieldValue::List(entries) => assert!(entries.iter().all(|entry|
+                entry.fields.keys().eq(["Name", "Type", "Value"]))),
+            _ => unreachable!(),
+        }
+        assert_eq!(HashMap::<String, i32>::try_from(&var_table).unwrap(), vars);
+
+        /* keys are labels with serde */
+        let long = HashMap::from([(String::from("a_variable_name_too_long"), 1)]);
+        let err = super::to_struct(&Vars { Vars: long.clone() }).unwrap_err();
+        assert_eq!(err.to_string(), "Vars.a_variable_name_too_long: label is longer than 16 bytes");
+        assert!(TryInto::<GffFieldValue>::try_into(&long).is_ok());
+    }
+}
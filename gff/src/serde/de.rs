@@ -0,0 +1,728 @@
+//! serde Deserializers, reading a [`GffStruct`] or packed data
+
+use serde::de::{
+    self,
+    value::{BorrowedStrDeserializer, StrDeserializer, StringDeserializer, U32Deserializer},
+    Deserialize,
+    DeserializeOwned,
+    DeserializeSeed,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use crate::common::{
+    locstring_id,
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffGender,
+    GffLang,
+    GffStruct,
+    EncodingFn,
+};
+use crate::error::DataError;
+use crate::parser::{FieldEntry, FieldReader, GffParser, ListReader, StructReader};
+use super::{STR_REF_KEY, STRUCT_ID_FIELD};
+
+/// Deserialize a value from a [`GffStruct`]
+pub fn from_struct<'de, T: Deserialize<'de>>(st: &'de GffStruct) -> Result<T, DataError> {
+    T::deserialize(StructDeserializer::new(st))
+}
+
+/// Deserialize a value from a packed GFF file
+///
+/// This does not build the [`GffStruct`] intermediary representation,
+/// see [`GffParser::unpack`].
+pub fn from_bytes<T: DeserializeOwned>(data: Vec<u8>, encodings: &EncodingFn)
+    -> Result<T, DataError>
+{
+    GffParser::unpack_with(data, encodings, |reader| T::deserialize(ReaderDeserializer::new(reader)))
+}
+
+fn not_supported(what: &str) -> DataError {
+    de::Error::custom(format!("{} is not supported", what))
+}
+
+/* {{{ Intermediary representation */
+
+/// serde Deserializer for a [`GffStruct`]
+pub struct StructDeserializer<'de> {
+    st: &'de GffStruct,
+}
+
+impl<'de> StructDeserializer<'de> {
+    pub fn new(st: &'de GffStruct) -> Self {
+        StructDeserializer { st }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for StructDeserializer<'de> {
+    type Error = DataError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_map(StructAccess::new(self.st, false))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        visitor.visit_map(StructAccess::new(self.st, fields.contains(&STRUCT_ID_FIELD)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+        _variants: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map identifier ignored_any
+    }
+}
+
+/// A struct as an enum, whose variant index is the struct type id
+impl<'de> de::EnumAccess<'de> for StructDeserializer<'de> {
+    type Error = DataError;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), DataError> {
+        let variant = seed.deserialize(U32Deserializer::<DataError>::new(self.st.st_type))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for StructDeserializer<'de> {
+    type Error = DataError;
+
+    fn unit_variant(self) -> Result<(), DataError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, DataError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, DataError> {
+        Err(not_supported("tuple variant"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
+
+/// Field being read by a [`StructAccess`]
+enum Pending<'de> {
+    None,
+    StructId(u32),
+    Field(&'de str, &'de GffFieldValue),
+}
+
+/// Fields of a [`GffStruct`], with its type id first if requested
+struct StructAccess<'de> {
+    fields: indexmap::map::Iter<'de, String, GffFieldValue>,
+    st_type: Option<u32>,
+    pending: Pending<'de>,
+}
+
+impl<'de> StructAccess<'de> {
+    fn new(st: &'de GffStruct, with_struct_id: bool) -> Self {
+        StructAccess {
+            fields: st.fields.iter(),
+            st_type: with_struct_id.then_some(st.st_type),
+            pending: Pending::None,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for StructAccess<'de> {
+    type Error = DataError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+        -> Result<Option<K::Value>, DataError>
+    {
+        if let Some(st_type) = self.st_type.take() {
+            self.pending = Pending::StructId(st_type);
+            return seed.deserialize(StrDeserializer::new(STRUCT_ID_FIELD)).map(Some);
+        }
+        match self.fields.next() {
+            Some((label, value)) => {
+                self.pending = Pending::Field(label, value);
+                seed.deserialize(BorrowedStrDeserializer::new(label)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, DataError> {
+        match std::mem::replace(&mut self.pending, Pending::None) {
+            Pending::StructId(st_type) => seed.deserialize(U32Deserializer::<DataError>::new(st_type)),
+            Pending::Field(label, value) => seed.deserialize(ValueDeserializer::Borrowed(value))
+                .map_err(|e| e.at_label(label)),
+            Pending::None => Err(de::Error::custom("value without a key")),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len() + self.st_type.is_some() as usize)
+    }
+}
+
+/// Structs of a list
+struct ListAccess<'de> {
+    structs: std::iter::Enumerate<std::slice::Iter<'de, GffStruct>>,
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess<'de> {
+    type Error = DataError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S)
+        -> Result<Option<S::Value>, DataError>
+    {
+        match self.structs.next() {
+            Some((i, st)) => seed.deserialize(StructDeserializer::new(st))
+                .map(Some)
+                .map_err(|e| e.at_index(i)),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.structs.len())
+    }
+}
+
+/* }}} */
+/* {{{ Field values */
+
+/// Value of a field, borrowed from a [`GffStruct`], or read from
+/// packed data
+///
+/// Typed requests (e.g. `u16`) only accept the matching GFF type.
+enum ValueDeserializer<'de> {
+    Borrowed(&'de GffFieldValue),
+    Owned(GffFieldValue),
+}
+
+macro_rules! deserialize_value {
+    ( $( $fn:ident: $gff_type:ident => $visit:ident ),* ) => {
+        $(
+            fn $fn<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+                match self.value() {
+                    GffFieldValue::$gff_type(v) => visitor.$visit(*v),
+                    value => Err(DataError::unexpected_type(GffFieldType::$gff_type, value)),
+                }
+            }
+        )*
+    }
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn value(&self) -> &GffFieldValue {
+        match self {
+            ValueDeserializer::Borrowed(value) => value,
+            ValueDeserializer::Owned(value) => value,
+        }
+    }
+
+    /// Structs and lists can only be borrowed, packed ones are read
+    /// by [`FieldDeserializer`]
+    fn owned_struct() -> DataError {
+        de::Error::custom("cannot deserialize a struct from a value")
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = DataError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self.value() {
+            GffFieldValue::Byte(v) => return visitor.visit_u8(*v),
+            GffFieldValue::Char(v) => return visitor.visit_i8(*v),
+            GffFieldValue::Word(v) => return visitor.visit_u16(*v),
+            GffFieldValue::Short(v) => return visitor.visit_i16(*v),
+            GffFieldValue::DWord(v) => return visitor.visit_u32(*v),
+            GffFieldValue::Int(v) => return visitor.visit_i32(*v),
+            GffFieldValue::DWord64(v) => return visitor.visit_u64(*v),
+            GffFieldValue::Int64(v) => return visitor.visit_i64(*v),
+            GffFieldValue::Float(v) => return visitor.visit_f32(*v),
+            GffFieldValue::Double(v) => return visitor.visit_f64(*v),
+            GffFieldValue::CExoLocString(..) => return self.deserialize_map(visitor),
+            _ => {},
+        }
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::CExoString(s) | GffFieldValue::CResRef(s)) =>
+                visitor.visit_borrowed_str(s),
+            ValueDeserializer::Owned(GffFieldValue::CExoString(s) | GffFieldValue::CResRef(s)) =>
+                visitor.visit_string(s),
+            ValueDeserializer::Borrowed(GffFieldValue::Void(v)) => visitor.visit_borrowed_bytes(v),
+            ValueDeserializer::Owned(GffFieldValue::Void(v)) => visitor.visit_byte_buf(v),
+            ValueDeserializer::Borrowed(GffFieldValue::Struct(st)) =>
+                de::Deserializer::deserialize_any(StructDeserializer::new(st), visitor),
+            ValueDeserializer::Borrowed(GffFieldValue::List(structs)) =>
+                visitor.visit_seq(ListAccess { structs: structs.iter().enumerate() }),
+            _ => Err(Self::owned_struct()),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self.value() {
+            GffFieldValue::Byte(v) => visitor.visit_bool(*v != 0),
+            value => Err(DataError::unexpected_type(GffFieldType::Byte, value)),
+        }
+    }
+
+    deserialize_value!(
+        deserialize_u8: Byte => visit_u8,
+        deserialize_i8: Char => visit_i8,
+        deserialize_u16: Word => visit_u16,
+        deserialize_i16: Short => visit_i16,
+        deserialize_u32: DWord => visit_u32,
+        deserialize_i32: Int => visit_i32,
+        deserialize_u64: DWord64 => visit_u64,
+        deserialize_i64: Int64 => visit_i64,
+        deserialize_f32: Float => visit_f32,
+        deserialize_f64: Double => visit_f64
+    );
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::CExoString(s) | GffFieldValue::CResRef(s)) =>
+                visitor.visit_borrowed_str(s),
+            ValueDeserializer::Owned(GffFieldValue::CExoString(s) | GffFieldValue::CResRef(s)) =>
+                visitor.visit_string(s),
+            value => Err(DataError::unexpected_type(GffFieldType::CExoString, value.value())),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::Void(v)) => visitor.visit_borrowed_bytes(v),
+            ValueDeserializer::Owned(GffFieldValue::Void(v)) => visitor.visit_byte_buf(v),
+            value => Err(DataError::unexpected_type(GffFieldType::Void, value.value())),
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::List(structs)) =>
+                visitor.visit_seq(ListAccess { structs: structs.iter().enumerate() }),
+            ValueDeserializer::Owned(GffFieldValue::List(_)) => Err(Self::owned_struct()),
+            value => Err(DataError::unexpected_type(GffFieldType::List, value.value())),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+        visitor: V) -> Result<V::Value, DataError>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::CExoLocString(str_ref, strings)) =>
+                visitor.visit_map(LocStringAccess::new(*str_ref, strings)),
+            ValueDeserializer::Owned(GffFieldValue::CExoLocString(str_ref, strings)) =>
+                visitor.visit_map(LocStringAccess::new(str_ref, &strings)),
+            ValueDeserializer::Borrowed(GffFieldValue::Struct(st)) =>
+                visitor.visit_map(StructAccess::new(st, false)),
+            ValueDeserializer::Owned(GffFieldValue::Struct(_)) => Err(Self::owned_struct()),
+            value => Err(DataError::unexpected_type(GffFieldType::Struct, value.value())),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::Struct(st)) =>
+                de::Deserializer::deserialize_struct(StructDeserializer::new(st), name, fields, visitor),
+            value => value.deserialize_map(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+        variants: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        match self {
+            ValueDeserializer::Borrowed(GffFieldValue::Struct(st)) =>
+                de::Deserializer::deserialize_enum(StructDeserializer::new(st), name, variants, visitor),
+            ValueDeserializer::Owned(GffFieldValue::Struct(_)) => Err(Self::owned_struct()),
+            value => Err(DataError::unexpected_type(GffFieldType::Struct, value.value())),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_unit()
+    }
+}
+
+/// Value of a localized string entry, see [`LocStringAccess`]
+enum LocStringValue {
+    StrRef(u32),
+    String(String),
+}
+
+/// A localized string, as a map with a `StrRef` entry, then an entry
+/// per string, keyed by its language and gender id
+struct LocStringAccess {
+    str_ref: Option<u32>,
+    strings: std::vec::IntoIter<(u32, String)>,
+    pending: Option<LocStringValue>,
+}
+
+impl LocStringAccess {
+    fn new(str_ref: u32, strings: &IndexMap<(GffLang, GffGender), String>) -> Self {
+        LocStringAccess {
+            str_ref: Some(str_ref),
+            strings: strings.iter()
+                .map(|((lang, gender), s)| (locstring_id(*lang, *gender), s.clone()))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            pending: None,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for LocStringAccess {
+    type Error = DataError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+        -> Result<Option<K::Value>, DataError>
+    {
+        if let Some(str_ref) = self.str_ref.take() {
+            self.pending = Some(LocStringValue::StrRef(str_ref));
+            return seed.deserialize(StrDeserializer::new(STR_REF_KEY)).map(Some);
+        }
+        match self.strings.next() {
+            Some((id, s)) => {
+                self.pending = Some(LocStringValue::String(s));
+                seed.deserialize(StringDeserializer::new(id.to_string())).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, DataError> {
+        match self.pending.take() {
+            Some(LocStringValue::StrRef(str_ref)) => seed.deserialize(U32Deserializer::<DataError>::new(str_ref)),
+            Some(LocStringValue::String(s)) => seed.deserialize(StringDeserializer::new(s)),
+            None => Err(de::Error::custom("value without a key")),
+        }
+    }
+}
+
+/* }}} */
+/* {{{ Packed data */
+
+/// serde Deserializer for a packed struct, read with a [`StructReader`]
+///
+/// Fields are decoded while walking the packed data, and fields that
+/// the type does not declare are skipped.
+pub struct ReaderDeserializer<'a, 'r, 'data> {
+    reader: &'a mut StructReader<'r, 'data>,
+}
+
+impl<'a, 'r, 'data> ReaderDeserializer<'a, 'r, 'data> {
+    pub fn new(reader: &'a mut StructReader<'r, 'data>) -> Self {
+        ReaderDeserializer { reader }
+    }
+}
+
+impl<'de, 'a, 'r, 'data> de::Deserializer<'de> for ReaderDeserializer<'a, 'r, 'data> {
+    type Error = DataError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_map(ReaderAccess::new(self.reader, false))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        visitor.visit_map(ReaderAccess::new(self.reader, fields.contains(&STRUCT_ID_FIELD)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+        _variants: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map identifier
+    }
+}
+
+/// A struct as an enum, whose variant index is the struct type id
+impl<'de, 'a, 'r, 'data> de::EnumAccess<'de> for ReaderDeserializer<'a, 'r, 'data> {
+    type Error = DataError;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self), DataError> {
+        let variant = seed.deserialize(U32Deserializer::<DataError>::new(self.reader.st_type()))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a, 'r, 'data> de::VariantAccess<'de> for ReaderDeserializer<'a, 'r, 'data> {
+    type Error = DataError;
+
+    fn unit_variant(self) -> Result<(), DataError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value, DataError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, DataError> {
+        Err(not_supported("tuple variant"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
+
+/// Fields of a packed struct, with its type id first if requested
+struct ReaderAccess<'a, 'r, 'data> {
+    reader: &'a mut StructReader<'r, 'data>,
+    with_struct_id: bool,
+    pending: Option<FieldEntry<'data>>,
+}
+
+impl<'a, 'r, 'data> ReaderAccess<'a, 'r, 'data> {
+    fn new(reader: &'a mut StructReader<'r, 'data>, with_struct_id: bool) -> Self {
+        ReaderAccess { reader, with_struct_id, pending: None }
+    }
+}
+
+impl<'de, 'a, 'r, 'data> de::MapAccess<'de> for ReaderAccess<'a, 'r, 'data> {
+    type Error = DataError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+        -> Result<Option<K::Value>, DataError>
+    {
+        if self.with_struct_id {
+            return seed.deserialize(StrDeserializer::new(STRUCT_ID_FIELD)).map(Some);
+        }
+        match self.reader.next_field_entry()? {
+            Some(entry) => {
+                self.pending = Some(entry);
+                seed.deserialize(StrDeserializer::new(entry.label)).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value, DataError> {
+        if self.with_struct_id {
+            self.with_struct_id = false;
+            return seed.deserialize(U32Deserializer::<DataError>::new(self.reader.st_type()));
+        }
+        let entry = self.pending.take()
+            .ok_or_else(|| <DataError as de::Error>::custom("value without a key"))?;
+        seed.deserialize(FieldDeserializer { field: self.reader.field(entry) })
+            .map_err(|e| e.at_label(entry.label))
+    }
+}
+
+/// Structs of a packed list
+struct ReaderListAccess<'r, 'data> {
+    list: ListReader<'r, 'data>,
+}
+
+impl<'de, 'r, 'data> de::SeqAccess<'de> for ReaderListAccess<'r, 'data> {
+    type Error = DataError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(&mut self, seed: S)
+        -> Result<Option<S::Value>, DataError>
+    {
+        self.list.next_struct(|reader| seed.deserialize(ReaderDeserializer::new(reader)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.list.len())
+    }
+}
+
+/// A packed field, structs and lists being read in place
+struct FieldDeserializer<'r, 'data> {
+    field: FieldReader<'r, 'data>,
+}
+
+macro_rules! deserialize_field_value {
+    ( $( $fn:ident ),* ) => {
+        $(
+            fn $fn<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+                ValueDeserializer::Owned(self.field.value()?).$fn(visitor)
+            }
+        )*
+    }
+}
+
+impl<'de, 'r, 'data> de::Deserializer<'de> for FieldDeserializer<'r, 'data> {
+    type Error = DataError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self.field.field_type() {
+            GffFieldType::Struct => self.field.read_struct(|reader|
+                ReaderDeserializer::new(reader).deserialize_any(visitor)),
+            GffFieldType::List => visitor.visit_seq(ReaderListAccess { list: self.field.list()? }),
+            _ => ValueDeserializer::Owned(self.field.value()?).deserialize_any(visitor),
+        }
+    }
+
+    deserialize_field_value!(
+        deserialize_bool,
+        deserialize_u8, deserialize_i8, deserialize_u16, deserialize_i16,
+        deserialize_u32, deserialize_i32, deserialize_u64, deserialize_i64,
+        deserialize_f32, deserialize_f64,
+        deserialize_char, deserialize_str, deserialize_string, deserialize_identifier,
+        deserialize_bytes, deserialize_byte_buf, deserialize_unit
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self.field.field_type() {
+            GffFieldType::List => visitor.visit_seq(ReaderListAccess { list: self.field.list()? }),
+            found => Err(DataError::new(crate::error::DataErrorKind::UnexpectedType {
+                expected: GffFieldType::List,
+                found,
+            })),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V)
+        -> Result<V::Value, DataError>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize,
+        visitor: V) -> Result<V::Value, DataError>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        match self.field.field_type() {
+            GffFieldType::Struct => self.field.read_struct(|reader|
+                ReaderDeserializer::new(reader).deserialize_map(visitor)),
+            _ => ValueDeserializer::Owned(self.field.value()?).deserialize_map(visitor),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, name: &'static str,
+        fields: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        match self.field.field_type() {
+            GffFieldType::Struct => self.field.read_struct(|reader|
+                ReaderDeserializer::new(reader).deserialize_struct(name, fields, visitor)),
+            _ => ValueDeserializer::Owned(self.field.value()?).deserialize_map(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, name: &'static str,
+        variants: &'static [&'static str], visitor: V) -> Result<V::Value, DataError>
+    {
+        match self.field.field_type() {
+            GffFieldType::Struct => self.field.read_struct(|reader|
+                ReaderDeserializer::new(reader).deserialize_enum(name, variants, visitor)),
+            _ => ValueDeserializer::Owned(self.field.value()?).deserialize_enum(name, variants, visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DataError> {
+        visitor.visit_unit()
+    }
+}
+
+/* }}} */
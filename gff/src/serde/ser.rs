@@ -0,0 +1,505 @@
+//! serde Serializer, producing a [`GffStruct`]
+
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::common::{
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffFile,
+    GffStruct,
+    EncodingFn,
+};
+use crate::error::{DataError, DataErrorKind};
+use crate::packer::Packer;
+use super::{
+    locstring_key,
+    LOCSTRING_NAME,
+    RESREF_NAME,
+    STR_REF_KEY,
+    STRUCT_ID_FIELD,
+};
+
+/// Serialize a value into a [`GffStruct`]
+///
+/// The value must serialize to a struct or a map.
+pub fn to_struct<T: ?Sized + Serialize>(value: &T) -> Result<GffStruct, DataError> {
+    match value.serialize(Serializer)? {
+        Some(GffFieldValue::Struct(st)) => Ok(st),
+        Some(value) => Err(DataError::unexpected_type(GffFieldType::Struct, &value)),
+        None => Err(ser::Error::custom("nothing to serialize")),
+    }
+}
+
+/// Serialize a value into a packed GFF file
+///
+/// The value must serialize to a struct or a map.
+pub fn to_bytes<T: ?Sized + Serialize>(file_type: [u8; 4], value: &T, encodings: &EncodingFn)
+    -> Result<Vec<u8>, DataError>
+{
+    let mut packer = Packer::new(Vec::new(), encodings);
+    packer.pack(&GffFile::new(file_type, to_struct(value)?))?;
+    packer.writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// serde Serializer for a GFF field value
+///
+/// This produces `None` for values that are not stored, such as
+/// `None` options, so that the field is omitted.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = Impossible<Self::Ok, DataError>;
+    type SerializeMap = StructSerializer;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = StructSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Byte(v as u8)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Char(v)))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Short(v)))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Int(v)))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Int64(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Byte(v)))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Word(v)))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::DWord(v)))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::DWord64(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Float(v)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Double(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::CExoString(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::CExoString(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Void(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, DataError> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Self::Ok, DataError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, DataError> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::Struct(GffStruct {
+            st_type: 0xFFFFFFFF,
+            fields: IndexMap::new(),
+        })))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, variant_index: u32,
+        _variant: &'static str) -> Result<Self::Ok, DataError>
+    {
+        Ok(Some(GffFieldValue::Struct(GffStruct {
+            st_type: variant_index,
+            fields: IndexMap::new(),
+        })))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, name: &'static str, value: &T)
+        -> Result<Self::Ok, DataError>
+    {
+        let value = value.serialize(self)?;
+        match (name, value) {
+            (RESREF_NAME, Some(GffFieldValue::CExoString(s))) =>
+                Ok(Some(GffFieldValue::CResRef(s))),
+            (LOCSTRING_NAME, Some(GffFieldValue::Struct(st))) => {
+                let mut str_ref = 0xFFFFFFFF;
+                let mut strings = IndexMap::new();
+                for (key, value) in st.fields {
+                    match (key.as_str(), value) {
+                        (STR_REF_KEY, GffFieldValue::DWord(val)) => str_ref = val,
+                        (key, GffFieldValue::CExoString(s)) => {
+                            let lang_gender = locstring_key(key)
+                                .ok_or(DataError::new(DataErrorKind::UnknownLanguage(None)))?;
+                            strings.insert(lang_gender, s);
+                        },
+                        (key, value) => return Err(DataError::unexpected_type(
+                            GffFieldType::CExoString, &value).at_label(key)),
+                    }
+                }
+                Ok(Some(GffFieldValue::CExoLocString(str_ref, strings)))
+            },
+            (_, value) => Ok(value),
+        }
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str,
+        variant_index: u32, variant: &'static str, value: &T) -> Result<Self::Ok, DataError>
+    {
+        match value.serialize(self)? {
+            Some(GffFieldValue::Struct(mut st)) => {
+                st.st_type = variant_index;
+                Ok(Some(GffFieldValue::Struct(st)))
+            },
+            _ => Err(ser::Error::custom(format!("variant {} must hold a struct", variant))),
+        }
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, DataError> {
+        Ok(ListSerializer { structs: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, DataError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize)
+        -> Result<Self::SerializeTupleStruct, DataError>
+    {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, DataError>
+    {
+        Err(ser::Error::custom(format!("tuple variant {} is not supported", variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, DataError> {
+        Ok(StructSerializer::new(0xFFFFFFFF))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self::SerializeStruct, DataError>
+    {
+        Ok(StructSerializer::new(0xFFFFFFFF))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, variant_index: u32,
+        _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, DataError>
+    {
+        Ok(StructSerializer { variant: Some(variant_index), ..StructSerializer::new(0xFFFFFFFF) })
+    }
+}
+
+/// Serializer for a list of structs
+pub struct ListSerializer {
+    structs: Vec<GffStruct>,
+}
+
+impl ListSerializer {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        let i = self.structs.len();
+        match value.serialize(Serializer).map_err(|e| e.at_index(i))? {
+            Some(GffFieldValue::Struct(st)) => self.structs.push(st),
+            Some(value) => return Err(
+                DataError::unexpected_type(GffFieldType::Struct, &value).at_index(i)),
+            None => return Err(
+                DataError::new(DataErrorKind::MissingField).at_index(i)),
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for ListSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::List(self.structs)))
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::List(self.structs)))
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, DataError> {
+        Ok(Some(GffFieldValue::List(self.structs)))
+    }
+}
+
+/// Serializer for a struct, from a Rust struct, map or struct variant
+pub struct StructSerializer {
+    st_type: u32,
+    /// Variant index, which takes precedence over [`STRUCT_ID_FIELD`]
+    variant: Option<u32>,
+    fields: IndexMap<String, GffFieldValue>,
+    key: Option<String>,
+}
+
+impl StructSerializer {
+    fn new(st_type: u32) -> Self {
+        StructSerializer {
+            st_type,
+            variant: None,
+            fields: IndexMap::new(),
+            key: None,
+        }
+    }
+
+    fn insert<T: ?Sized + Serialize>(&mut self, label: &str, value: &T) -> Result<(), DataError> {
+        if label.len() > 16 {
+            return Err(DataError::new(DataErrorKind::LabelTooLong).at_label(label));
+        }
+        let value = value.serialize(Serializer).map_err(|e| e.at_label(label))?;
+        match value {
+            Some(GffFieldValue::DWord(st_type)) if label == STRUCT_ID_FIELD => self.st_type = st_type,
+            Some(value) if label == STRUCT_ID_FIELD => return Err(
+                DataError::unexpected_type(GffFieldType::DWord, &value).at_label(label)),
+            Some(value) => { self.fields.insert(label.to_string(), value); },
+            None => {},
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Option<GffFieldValue> {
+        Some(GffFieldValue::Struct(GffStruct {
+            st_type: self.variant.unwrap_or(self.st_type),
+            fields: self.fields,
+        }))
+    }
+}
+
+impl ser::SerializeMap for StructSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), DataError> {
+        self.key = Some(key.serialize(LabelSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), DataError> {
+        let key = self.key.take()
+            .ok_or_else(|| <DataError as ser::Error>::custom("value without a key"))?;
+        self.insert(&key, value)
+    }
+
+    /// Fields are sorted by label, as maps such as `HashMap` have no order
+    fn end(mut self) -> Result<Self::Ok, DataError> {
+        self.fields.sort_keys();
+        Ok(StructSerializer::end(self))
+    }
+}
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T)
+        -> Result<(), DataError>
+    {
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, DataError> {
+        Ok(StructSerializer::end(self))
+    }
+}
+
+impl ser::SerializeStructVariant for StructSerializer {
+    type Ok = Option<GffFieldValue>;
+    type Error = DataError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T)
+        -> Result<(), DataError>
+    {
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, DataError> {
+        Ok(StructSerializer::end(self))
+    }
+}
+
+/// Serializer for map keys, which must be strings or integers
+struct LabelSerializer;
+
+macro_rules! serialize_label_int {
+    ( $( $fn:ident: $type:ty ),* ) => {
+        $(
+            fn $fn(self, v: $type) -> Result<String, DataError> {
+                Ok(v.to_string())
+            }
+        )*
+    }
+}
+
+impl LabelSerializer {
+    fn unsupported() -> DataError {
+        ser::Error::custom("labels must be strings or integers")
+    }
+}
+
+impl ser::Serializer for LabelSerializer {
+    type Ok = String;
+    type Error = DataError;
+
+    type SerializeSeq = Impossible<String, DataError>;
+    type SerializeTuple = Impossible<String, DataError>;
+    type SerializeTupleStruct = Impossible<String, DataError>;
+    type SerializeTupleVariant = Impossible<String, DataError>;
+    type SerializeMap = Impossible<String, DataError>;
+    type SerializeStruct = Impossible<String, DataError>;
+    type SerializeStructVariant = Impossible<String, DataError>;
+
+    serialize_label_int!(
+        serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+        serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64
+    );
+
+    fn serialize_char(self, v: char) -> Result<String, DataError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, DataError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32,
+        variant: &'static str) -> Result<String, DataError>
+    {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T)
+        -> Result<String, DataError>
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_none(self) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_unit(self) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str,
+        _variant_index: u32, _variant: &'static str, _value: &T) -> Result<String, DataError>
+    {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleStruct, DataError>
+    {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32,
+        _variant: &'static str, _len: usize) -> Result<Self::SerializeTupleVariant, DataError>
+    {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, DataError> {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self::SerializeStruct, DataError>
+    {
+        Err(Self::unsupported())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32,
+        _variant: &'static str, _len: usize) -> Result<Self::SerializeStructVariant, DataError>
+    {
+        Err(Self::unsupported())
+    }
+}
@@ -0,0 +1,270 @@
+//! serde impls for the intermediary representation
+//!
+//! A [`GffFieldValue`] is serialized as an enum whose variants are named
+//! after the GFF types, so that every value comes back with its exact
+//! type, whatever the format.
+
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeStruct, Serializer};
+
+use crate::common::{
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffGender,
+    GffLang,
+    GffStruct,
+    LocString,
+    VoidData,
+};
+use super::LocStringMap;
+
+/// Variant names of a serialized [`GffFieldValue`], by GFF type id
+const FIELD_TYPES: &[&str] = &[
+    "Byte", "Char", "Word", "Short", "DWord", "Int", "DWord64", "Int64",
+    "Float", "Double", "CExoString", "CResRef", "CExoLocString", "Void",
+    "Struct", "List",
+];
+
+/// Fields of a serialized [`GffStruct`]
+const STRUCT_FIELDS: &[&str] = &["st_type", "fields"];
+
+/* {{{ GffLang and GffGender */
+
+impl Serialize for GffLang {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(*self as u32)
+    }
+}
+
+impl<'de> Deserialize<'de> for GffLang {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = u32::deserialize(deserializer)?;
+        GffLang::try_from(id)
+            .map_err(|_| de::Error::custom(format!("unknown language id {}", id)))
+    }
+}
+
+impl Serialize for GffGender {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for GffGender {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = u8::deserialize(deserializer)?;
+        GffGender::try_from(id)
+            .map_err(|_| de::Error::custom(format!("unknown gender id {}", id)))
+    }
+}
+
+/* }}} */
+/* {{{ GffFieldValue */
+
+/// Raw bytes of a Void field
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Serialize for Bytes<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for GffFieldValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ty = self.field_type() as u32;
+        macro_rules! variant {
+            ($value:expr) => {
+                serializer.serialize_newtype_variant("GffFieldValue", ty, FIELD_TYPES[ty as usize], $value)
+            }
+        }
+        match self {
+            GffFieldValue::Byte(v) => variant!(v),
+            GffFieldValue::Char(v) => variant!(v),
+            GffFieldValue::Word(v) => variant!(v),
+            GffFieldValue::Short(v) => variant!(v),
+            GffFieldValue::DWord(v) => variant!(v),
+            GffFieldValue::Int(v) => variant!(v),
+            GffFieldValue::DWord64(v) => variant!(v),
+            GffFieldValue::Int64(v) => variant!(v),
+            GffFieldValue::Float(v) => variant!(v),
+            GffFieldValue::Double(v) => variant!(v),
+            GffFieldValue::CExoString(v) => variant!(v),
+            GffFieldValue::CResRef(v) => variant!(v),
+            GffFieldValue::CExoLocString(str_ref, strings) => variant!(&LocStringMap(*str_ref, strings)),
+            GffFieldValue::Void(v) => variant!(&Bytes(v)),
+            GffFieldValue::Struct(v) => variant!(v),
+            GffFieldValue::List(v) => variant!(v),
+        }
+    }
+}
+
+/// Variant of a serialized [`GffFieldValue`], by name or type id
+struct FieldType(GffFieldType);
+
+impl<'de> Deserialize<'de> for FieldType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldTypeVisitor;
+
+        impl<'de> Visitor<'de> for FieldTypeVisitor {
+            type Value = FieldType;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a GFF field type")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<FieldType, E> {
+                u32::try_from(v).ok()
+                    .and_then(|v| GffFieldType::try_from(v).ok())
+                    .map(FieldType)
+                    .ok_or_else(|| E::custom(format!("unknown field type {}", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<FieldType, E> {
+                match FIELD_TYPES.iter().position(|name| *name == v) {
+                    Some(ty) => self.visit_u64(ty as u64),
+                    None => Err(E::unknown_variant(v, FIELD_TYPES)),
+                }
+            }
+        }
+
+        deserializer.deserialize_identifier(FieldTypeVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for GffFieldValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldValueVisitor;
+
+        impl<'de> Visitor<'de> for FieldValueVisitor {
+            type Value = GffFieldValue;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a GFF field value")
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<GffFieldValue, A::Error> {
+                let (FieldType(ty), v) = data.variant()?;
+                Ok(match ty {
+                    GffFieldType::Byte => GffFieldValue::Byte(v.newtype_variant()?),
+                    GffFieldType::Char => GffFieldValue::Char(v.newtype_variant()?),
+                    GffFieldType::Word => GffFieldValue::Word(v.newtype_variant()?),
+                    GffFieldType::Short => GffFieldValue::Short(v.newtype_variant()?),
+                    GffFieldType::DWord => GffFieldValue::DWord(v.newtype_variant()?),
+                    GffFieldType::Int => GffFieldValue::Int(v.newtype_variant()?),
+                    GffFieldType::DWord64 => GffFieldValue::DWord64(v.newtype_variant()?),
+                    GffFieldType::Int64 => GffFieldValue::Int64(v.newtype_variant()?),
+                    GffFieldType::Float => GffFieldValue::Float(v.newtype_variant()?),
+                    GffFieldType::Double => GffFieldValue::Double(v.newtype_variant()?),
+                    GffFieldType::CExoString => GffFieldValue::CExoString(v.newtype_variant()?),
+                    GffFieldType::CResRef => GffFieldValue::CResRef(v.newtype_variant()?),
+                    GffFieldType::CExoLocString => {
+                        let locstring: LocString = v.newtype_variant()?;
+                        GffFieldValue::CExoLocString(locstring.str_ref, locstring.strings)
+                    },
+                    GffFieldType::Void => GffFieldValue::Void(v.newtype_variant::<VoidData>()?.0),
+                    GffFieldType::Struct => GffFieldValue::Struct(v.newtype_variant()?),
+                    GffFieldType::List => GffFieldValue::List(v.newtype_variant()?),
+                })
+            }
+        }
+
+        deserializer.deserialize_enum("GffFieldValue", FIELD_TYPES, FieldValueVisitor)
+    }
+}
+
+/* }}} */
+/* {{{ GffStruct */
+
+/// Fields of a [`GffStruct`], as a map keeping their order
+struct Fields<'a>(&'a IndexMap<String, GffFieldValue>);
+
+impl<'a> Serialize for Fields<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (label, value) in self.0 {
+            map.serialize_entry(label, value)?;
+        }
+        map.end()
+    }
+}
+
+struct FieldsBuf(IndexMap<String, GffFieldValue>);
+
+impl<'de> Deserialize<'de> for FieldsBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = FieldsBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of GFF fields")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FieldsBuf, A::Error> {
+                let mut fields = IndexMap::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some((label, value)) = map.next_entry()? {
+                    fields.insert(label, value);
+                }
+                Ok(FieldsBuf(fields))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
+impl Serialize for GffStruct {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut st = serializer.serialize_struct("GffStruct", STRUCT_FIELDS.len())?;
+        st.serialize_field("st_type", &self.st_type)?;
+        st.serialize_field("fields", &Fields(&self.fields))?;
+        st.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for GffStruct {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StructVisitor;
+
+        impl<'de> Visitor<'de> for StructVisitor {
+            type Value = GffStruct;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a GFF struct")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<GffStruct, A::Error> {
+                let st_type = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let FieldsBuf(fields) = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(GffStruct { st_type, fields })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<GffStruct, A::Error> {
+                let mut st_type = None;
+                let mut fields = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "st_type" => st_type = Some(map.next_value()?),
+                        "fields" => fields = Some(map.next_value::<FieldsBuf>()?.0),
+                        key => return Err(de::Error::unknown_field(key, STRUCT_FIELDS)),
+                    }
+                }
+                Ok(GffStruct {
+                    st_type: st_type.ok_or_else(|| de::Error::missing_field("st_type"))?,
+                    fields: fields.ok_or_else(|| de::Error::missing_field("fields"))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_struct("GffStruct", STRUCT_FIELDS, StructVisitor)
+    }
+}
+
+/* }}} */
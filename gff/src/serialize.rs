@@ -1,15 +1,26 @@
 //! Implementation of serialization for basic types
 
+use std::collections::HashMap;
+
 use crate::common::{
+    IndexMap,
     GffFieldValue,
     GffStruct,
+    ResRef,
+    LocString,
+    VoidData,
     Serialize,
+    MAP_KEY,
+    MAP_TYPE,
+    MAP_VALUE,
+    LocalVariable,
 };
+use crate::error::DataError;
 
 macro_rules! gff_try_into {
     ( $gff_type:ident, $type:ident ) => {
         impl std::convert::TryInto<GffFieldValue> for &$type {
-            type Error = &'static str;
+            type Error = DataError;
 
             fn try_into(self) -> Result<GffFieldValue, Self::Error> {
                 Ok(GffFieldValue::$gff_type(*self))
@@ -29,25 +40,118 @@ gff_try_into!(DWord,   u32);
 gff_try_into!(Int64,   i64);
 gff_try_into!(DWord64, u64);
 
+/// A Byte, 1 for `true`
+impl std::convert::TryInto<GffFieldValue> for &bool {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::Byte(*self as u8))
+    }
+}
+
 impl std::convert::TryInto<GffFieldValue> for &String {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_into(self) -> Result<GffFieldValue, Self::Error> {
         Ok(GffFieldValue::CExoString(self.clone()))
     }
 }
 
+impl std::convert::TryInto<GffFieldValue> for &ResRef {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::CResRef(self.as_str().to_string()))
+    }
+}
+
+impl std::convert::TryInto<GffFieldValue> for &LocString {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::CExoLocString(self.str_ref, self.strings.clone()))
+    }
+}
+
+impl std::convert::TryInto<GffFieldValue> for &VoidData {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::Void(self.0.clone()))
+    }
+}
+
+fn serialize_list<T: Serialize>(items: &[T]) -> Result<GffFieldValue, DataError> {
+    let mut res: Vec<GffStruct> = vec![];
+
+    for (i, st) in items.iter().enumerate() {
+        res.push(st.serialize().map_err(|e| e.at_index(i))?);
+    }
+    Ok(
+        GffFieldValue::List(res)
+    )
+}
+
 impl<T> std::convert::TryInto<GffFieldValue> for &Vec<T> where T: Serialize {
-    type Error = &'static str;
+    type Error = DataError;
 
     fn try_into(self) -> Result<GffFieldValue, Self::Error> {
-        let mut res: Vec<GffStruct> = vec![];
+        serialize_list(self)
+    }
+}
 
-        for st in self {
-            res.push(st.serialize()?);
-        }
-        Ok(
-            GffFieldValue::List(res)
-        )
+impl<T, const N: usize> std::convert::TryInto<GffFieldValue> for &[T; N] where T: Serialize {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        serialize_list(self)
+    }
+}
+
+impl<T> Serialize for Box<T> where T: Serialize {
+    fn serialize(&self) -> Result<GffStruct, DataError> {
+        (**self).serialize()
+    }
+}
+
+/// A list of local variables, as in the `VarTable` of game objects
+///
+/// Each struct holds the key as a `Name` CExoString, the value as
+/// `Value`, and its local variable type as a `Type` DWord.
+pub(crate) fn serialize_map<'a, V: LocalVariable + 'a>(entries: impl Iterator<Item = (&'a String, &'a V)>)
+    -> Vec<GffStruct>
+{
+    entries.map(|(key, value)| GffStruct {
+        st_type: 0,
+        fields: IndexMap::from([
+            (MAP_KEY.to_string(), GffFieldValue::CExoString(key.clone())),
+            (MAP_TYPE.to_string(), GffFieldValue::DWord(V::VAR_TYPE)),
+            (MAP_VALUE.to_string(), value.value()),
+        ]),
+    }).collect()
+}
+
+/// Entries of a hash map, sorted by key as the map has no order
+pub(crate) fn sorted_entries<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<(&String, &V)> = map.iter().collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Entries sorted by key, as the map has no order
+impl<V: LocalVariable> std::convert::TryInto<GffFieldValue> for &HashMap<String, V> {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::List(serialize_map(sorted_entries(self).into_iter())))
+    }
+}
+
+/// Entries in insertion order
+impl<V: LocalVariable> std::convert::TryInto<GffFieldValue> for &IndexMap<String, V> {
+    type Error = DataError;
+
+    fn try_into(self) -> Result<GffFieldValue, Self::Error> {
+        Ok(GffFieldValue::List(serialize_map(self.iter())))
     }
 }
@@ -0,0 +1,605 @@
+//! Text representations of GFF documents, with the `yaml` and `toml`
+//! features
+//!
+//! A document is a map holding its type and version, then the fields
+//! of its top-level struct. Each field is annotated with its GFF type
+//! (a YAML tag, or a single-key table in TOML):
+//!
+//! ```yaml
+//! __data_type: 'UTC '
+//! __data_version: V3.2
+//! Tag: !cexostring nw_bandit
+//! Appearance_Type: !word 6
+//! FirstName: !cexolocstring
+//!   strref: 12345
+//!   english/male: Bandit
+//!   french/female: Bandite
+//! Data: !void AAEC/w==
+//! Tint_Hair: !struct
+//!   __struct_id: 0
+//!   a: !byte 255
+//! ItemList: !list
+//! - __struct_id: 0
+//!   InventoryRes: !resref nw_wswss001
+//! ```
+//!
+//! Structs hold their type id as `__struct_id`, omitted for the
+//! `0xFFFFFFFF` default. Localized strings are maps keyed by language
+//! and gender, with their string reference as `strref` (omitted if
+//! none). Void data is base64 encoded. Field and string order is
+//! preserved.
+
+#[cfg(feature = "yaml")]
+pub mod yaml;
+#[cfg(feature = "toml")]
+pub mod toml;
+
+use std::fmt;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::common::{
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffFile,
+    GffGender,
+    GffLang,
+    GffStruct,
+    DEFAULT_STRUCT_ID,
+    FIELD_TYPES,
+};
+use crate::error::{DataError, DataErrorKind};
+
+pub use crate::common::{DATA_TYPE_KEY, STRUCT_ID_KEY};
+
+/// Key of the document version
+pub const DATA_VERSION_KEY: &str = "__data_version";
+/// Key of the string reference of a localized string
+pub const STR_REF_KEY: &str = "strref";
+
+fn lang_name(lang: GffLang) -> &'static str {
+    match lang {
+        GffLang::English => "english",
+        GffLang::French => "french",
+        GffLang::German => "german",
+        GffLang::Italian => "italian",
+        GffLang::Spanish => "spanish",
+        GffLang::Polish => "polish",
+        GffLang::Korean => "korean",
+        GffLang::ChineseTrad => "chinese_trad",
+        GffLang::ChineseSimpl => "chinese_simpl",
+        GffLang::Japanese => "japanese",
+    }
+}
+
+fn gender_name(gender: GffGender) -> &'static str {
+    match gender {
+        GffGender::Male => "male",
+        GffGender::Female => "female",
+    }
+}
+
+/// Key of a localized string entry, e.g. `english/male`
+pub(crate) fn locstring_key(lang: GffLang, gender: GffGender) -> String {
+    format!("{}/{}", lang_name(lang), gender_name(gender))
+}
+
+/// Language and gender of a localized string entry key
+pub(crate) fn parse_locstring_key(key: &str) -> Option<(GffLang, GffGender)> {
+    let (lang, gender) = key.split_once('/')?;
+    let lang = [
+        GffLang::English, GffLang::French, GffLang::German, GffLang::Italian,
+        GffLang::Spanish, GffLang::Polish, GffLang::Korean, GffLang::ChineseTrad,
+        GffLang::ChineseSimpl, GffLang::Japanese,
+    ].into_iter().find(|l| lang_name(*l) == lang)?;
+    let gender = [GffGender::Male, GffGender::Female].into_iter()
+        .find(|g| gender_name(*g) == gender)?;
+    Some((lang, gender))
+}
+
+/// Name of a field type or of a localized string entry in the text
+/// formats, for tools taking them as arguments
+///
+/// ```
+/// # use gff::common::{GffFieldType, GffGender, GffLang};
+/// use gff::text::TextName;
+/// assert_eq!(GffFieldType::CResRef.text_name(), "resref");
+/// assert_eq!(<(GffLang, GffGender)>::from_text_name("french/female"),
+///     Some((GffLang::French, GffGender::Female)));
+/// ```
+pub trait TextName: Sized {
+    /// Name, e.g. `dword` or `english/male`
+    fn text_name(&self) -> String;
+    /// Value from its name
+    fn from_text_name(name: &str) -> Option<Self>;
+}
+
+impl TextName for GffFieldType {
+    fn text_name(&self) -> String {
+        FIELD_TYPES[*self as usize].to_string()
+    }
+
+    fn from_text_name(name: &str) -> Option<Self> {
+        let ty = FIELD_TYPES.iter().position(|ty| *ty == name)?;
+        GffFieldType::try_from(ty as u32).ok()
+    }
+}
+
+impl TextName for (GffLang, GffGender) {
+    fn text_name(&self) -> String {
+        locstring_key(self.0, self.1)
+    }
+
+    fn from_text_name(name: &str) -> Option<Self> {
+        parse_locstring_key(name)
+    }
+}
+
+/* {{{ Writing */
+
+/// A document, as a map of its type, version and fields
+struct Document<'a>(&'a GffFile);
+
+impl<'a> Serialize for Document<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry(DATA_TYPE_KEY, &String::from_utf8_lossy(&self.0.file_type))?;
+        map.serialize_entry(DATA_VERSION_KEY, &String::from_utf8_lossy(&self.0.version))?;
+        serialize_fields(&self.0.root, &mut map)?;
+        map.end()
+    }
+}
+
+/// A struct, as a map of its type id and fields
+struct Struct<'a>(&'a GffStruct);
+
+impl<'a> Serialize for Struct<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        serialize_fields(self.0, &mut map)?;
+        map.end()
+    }
+}
+
+fn serialize_fields<M: SerializeMap>(st: &GffStruct, map: &mut M) -> Result<(), M::Error> {
+    if st.st_type != DEFAULT_STRUCT_ID {
+        map.serialize_entry(STRUCT_ID_KEY, &st.st_type)?;
+    }
+    for (label, value) in &st.fields {
+        map.serialize_entry(label, &Field(value))?;
+    }
+    Ok(())
+}
+
+/// A field, as an enum variant named after its type
+struct Field<'a>(&'a GffFieldValue);
+
+impl<'a> Serialize for Field<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ty = self.0.field_type() as u32;
+        macro_rules! variant {
+            ($value:expr) => {
+                serializer.serialize_newtype_variant("Field", ty, FIELD_TYPES[ty as usize], $value)
+            }
+        }
+        match self.0 {
+            GffFieldValue::Byte(v) => variant!(v),
+            GffFieldValue::Char(v) => variant!(v),
+            GffFieldValue::Word(v) => variant!(v),
+            GffFieldValue::Short(v) => variant!(v),
+            GffFieldValue::DWord(v) => variant!(v),
+            GffFieldValue::Int(v) => variant!(v),
+            GffFieldValue::DWord64(v) => variant!(v),
+            GffFieldValue::Int64(v) => variant!(v),
+            GffFieldValue::Float(v) => variant!(v),
+            GffFieldValue::Double(v) => variant!(v),
+            GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => variant!(v),
+            GffFieldValue::CExoLocString(str_ref, strings) => variant!(&LocString(*str_ref, strings)),
+            GffFieldValue::Void(v) => variant!(&BASE64.encode(v)),
+            GffFieldValue::Struct(st) => variant!(&Struct(st)),
+            GffFieldValue::List(structs) =>
+                variant!(&structs.iter().map(Struct).collect::<Vec<_>>()),
+        }
+    }
+}
+
+/// A localized string, as a map of its string reference and strings
+struct LocString<'a>(u32, &'a IndexMap<(GffLang, GffGender), String>);
+
+impl<'a> Serialize for LocString<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        if self.0 != 0xFFFFFFFF {
+            map.serialize_entry(STR_REF_KEY, &self.0)?;
+        }
+        for ((lang, gender), s) in self.1 {
+            map.serialize_entry(&locstring_key(*lang, *gender), s)?;
+        }
+        map.end()
+    }
+}
+
+/* }}} */
+/* {{{ Reading */
+
+/// Document read from text
+struct DocumentBuf(GffFile);
+
+impl<'de> Deserialize<'de> for DocumentBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(StructVisitor { document: true })
+            .map(|(header, root)| {
+                let mut file = GffFile::new([0; 4], root);
+                file.file_type = header.file_type;
+                if let Some(version) = header.version {
+                    file.version = version;
+                }
+                DocumentBuf(file)
+            })
+    }
+}
+
+/// Struct read from text
+struct StructBuf(GffStruct);
+
+impl<'de> Deserialize<'de> for StructBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(StructVisitor { document: false })
+            .map(|(_, st)| StructBuf(st))
+    }
+}
+
+#[derive(Default)]
+struct Header {
+    file_type: [u8; 4],
+    version: Option<[u8; 4]>,
+}
+
+/// Reads the fields of a struct, and the header of a document
+struct StructVisitor {
+    document: bool,
+}
+
+/// Document type or version
+struct FourChars([u8; 4]);
+
+impl<'de> Deserialize<'de> for FourChars {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FourCharsVisitor;
+
+        impl<'de> Visitor<'de> for FourCharsVisitor {
+            type Value = FourChars;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a 4 characters string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<FourChars, E> {
+                <[u8; 4]>::try_from(v.as_bytes())
+                    .map(FourChars)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_str(FourCharsVisitor)
+    }
+}
+
+/// Field label, at most 16 bytes long
+struct Label(String);
+
+impl<'de> Deserialize<'de> for Label {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LabelVisitor;
+
+        impl<'de> Visitor<'de> for LabelVisitor {
+            type Value = Label;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a label")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Label, E> {
+                if v.len() > 16 {
+                    // the formats locate the error at the label
+                    return Err(E::custom(DataError::new(DataErrorKind::LabelTooLong)));
+                }
+                Ok(Label(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(LabelVisitor)
+    }
+}
+
+impl<'de> Visitor<'de> for StructVisitor {
+    type Value = (Header, GffStruct);
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a map of GFF fields")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut header = Header::default();
+        let mut file_type = None;
+        let mut st = GffStruct { st_type: DEFAULT_STRUCT_ID, fields: IndexMap::new() };
+        while let Some(Label(label)) = map.next_key()? {
+            match label.as_str() {
+                STRUCT_ID_KEY => st.st_type = map.next_value()?,
+                DATA_TYPE_KEY if self.document =>
+                    file_type = Some(map.next_value::<FourChars>()?.0),
+                DATA_VERSION_KEY if self.document =>
+                    header.version = Some(map.next_value::<FourChars>()?.0),
+                _ => {
+                    let FieldBuf(value) = map.next_value()?;
+                    st.fields.insert(label, value);
+                },
+            }
+        }
+        if self.document {
+            header.file_type = file_type.ok_or_else(|| de::Error::missing_field(DATA_TYPE_KEY))?;
+        }
+        Ok((header, st))
+    }
+}
+
+/// Field read from text
+struct FieldBuf(GffFieldValue);
+
+/// Field type, or its name if unknown
+struct FieldType(Result<GffFieldType, String>);
+
+impl<'de> Deserialize<'de> for FieldType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldTypeVisitor;
+
+        impl<'de> Visitor<'de> for FieldTypeVisitor {
+            type Value = FieldType;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a GFF field type")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<FieldType, E> {
+                Ok(FieldType(FIELD_TYPES.iter().position(|name| *name == v)
+                    .and_then(|ty| GffFieldType::try_from(ty as u32).ok())
+                    .ok_or_else(|| v.to_string())))
+            }
+        }
+
+        deserializer.deserialize_identifier(FieldTypeVisitor)
+    }
+}
+
+/// Error for a field of an unknown type
+///
+/// It is raised while reading the value, for the error to be located.
+struct UnknownType(String);
+
+impl<'de> DeserializeSeed<'de> for UnknownType {
+    type Value = FieldBuf;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<FieldBuf, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+macro_rules! unknown_type {
+    ( $( $fn:ident($ty:ty) ),* ) => {
+        $(
+            fn $fn<E: de::Error>(self, _v: $ty) -> Result<FieldBuf, E> {
+                Err(E::unknown_variant(&self.0, FIELD_TYPES))
+            }
+        )*
+    }
+}
+
+impl<'de> Visitor<'de> for UnknownType {
+    type Value = FieldBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a GFF field type")
+    }
+
+    unknown_type!(visit_bool(bool), visit_i64(i64), visit_u64(u64), visit_f64(f64), visit_str(&str));
+
+    fn visit_unit<E: de::Error>(self) -> Result<FieldBuf, E> {
+        Err(E::unknown_variant(&self.0, FIELD_TYPES))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, _seq: A) -> Result<FieldBuf, A::Error> {
+        Err(de::Error::unknown_variant(&self.0, FIELD_TYPES))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, _map: A) -> Result<FieldBuf, A::Error> {
+        Err(de::Error::unknown_variant(&self.0, FIELD_TYPES))
+    }
+}
+
+/// A DWord64, which may be written as a string in formats limited
+/// to `i64` integers
+struct DWord64(u64);
+
+impl<'de> Deserialize<'de> for DWord64 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DWord64Visitor;
+
+        impl<'de> Visitor<'de> for DWord64Visitor {
+            type Value = DWord64;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an unsigned 64 bits integer")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<DWord64, E> {
+                Ok(DWord64(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<DWord64, E> {
+                u64::try_from(v).map(DWord64)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<DWord64, E> {
+                v.parse().map(DWord64)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(DWord64Visitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldVisitor;
+
+        impl<'de> Visitor<'de> for FieldVisitor {
+            type Value = FieldBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a GFF field, annotated with its type")
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<FieldBuf, A::Error> {
+                let (FieldType(ty), v) = data.variant()?;
+                let ty = match ty {
+                    Ok(ty) => ty,
+                    Err(name) => return v.newtype_variant_seed(UnknownType(name)),
+                };
+                Ok(FieldBuf(match ty {
+                    GffFieldType::Byte => GffFieldValue::Byte(v.newtype_variant()?),
+                    GffFieldType::Char => GffFieldValue::Char(v.newtype_variant()?),
+                    GffFieldType::Word => GffFieldValue::Word(v.newtype_variant()?),
+                    GffFieldType::Short => GffFieldValue::Short(v.newtype_variant()?),
+                    GffFieldType::DWord => GffFieldValue::DWord(v.newtype_variant()?),
+                    GffFieldType::Int => GffFieldValue::Int(v.newtype_variant()?),
+                    GffFieldType::DWord64 => GffFieldValue::DWord64(v.newtype_variant::<DWord64>()?.0),
+                    GffFieldType::Int64 => GffFieldValue::Int64(v.newtype_variant()?),
+                    GffFieldType::Float => GffFieldValue::Float(v.newtype_variant()?),
+                    GffFieldType::Double => GffFieldValue::Double(v.newtype_variant()?),
+                    GffFieldType::CExoString => GffFieldValue::CExoString(v.newtype_variant()?),
+                    GffFieldType::CResRef => GffFieldValue::CResRef(v.newtype_variant()?),
+                    GffFieldType::CExoLocString => {
+                        let LocStringBuf(str_ref, strings) = v.newtype_variant()?;
+                        GffFieldValue::CExoLocString(str_ref, strings)
+                    },
+                    GffFieldType::Void => GffFieldValue::Void(v.newtype_variant::<VoidBuf>()?.0),
+                    GffFieldType::Struct => GffFieldValue::Struct(v.newtype_variant::<StructBuf>()?.0),
+                    GffFieldType::List => GffFieldValue::List(v.newtype_variant::<ListBuf>()?.0),
+                }))
+            }
+        }
+
+        deserializer.deserialize_enum("Field", FIELD_TYPES, FieldVisitor)
+    }
+}
+
+/// Void data read from text, base64 encoded
+struct VoidBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for VoidBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VoidVisitor;
+
+        impl<'de> Visitor<'de> for VoidVisitor {
+            type Value = VoidBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "base64 data")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<VoidBuf, E> {
+                BASE64.decode(v.trim())
+                    .map(VoidBuf)
+                    .map_err(|e| E::custom(format!("invalid base64 data: {}", e)))
+            }
+        }
+
+        deserializer.deserialize_str(VoidVisitor)
+    }
+}
+
+/// List read from text
+struct ListBuf(Vec<GffStruct>);
+
+impl<'de> Deserialize<'de> for ListBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ListVisitor;
+
+        impl<'de> Visitor<'de> for ListVisitor {
+            type Value = ListBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of structs")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ListBuf, A::Error> {
+                let mut structs = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(StructBuf(st)) = seq.next_element()? {
+                    structs.push(st);
+                }
+                Ok(ListBuf(structs))
+            }
+        }
+
+        deserializer.deserialize_seq(ListVisitor)
+    }
+}
+
+/// Localized string read from text
+struct LocStringBuf(u32, IndexMap<(GffLang, GffGender), String>);
+
+impl<'de> Deserialize<'de> for LocStringBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LocStringVisitor;
+
+        impl<'de> Visitor<'de> for LocStringVisitor {
+            type Value = LocStringBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of localized strings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<LocStringBuf, A::Error> {
+                let mut res = LocStringBuf(0xFFFFFFFF, IndexMap::new());
+                while let Some(key) = map.next_key::<String>()? {
+                    if key == STR_REF_KEY {
+                        res.0 = map.next_value()?;
+                        continue;
+                    }
+                    let lang_gender = parse_locstring_key(&key)
+                        .ok_or_else(|| de::Error::custom(format!("unknown language {}", key)))?;
+                    res.1.insert(lang_gender, map.next_value()?);
+                }
+                Ok(res)
+            }
+        }
+
+        deserializer.deserialize_map(LocStringVisitor)
+    }
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{GffGender, GffLang};
+
+    #[test]
+    fn test_01_locstring_keys() {
+        for id in 0..=263 {
+            if let Some((lang, gender)) = crate::common::locstring_lang(id) {
+                let key = super::locstring_key(lang, gender);
+                assert_eq!(super::parse_locstring_key(&key), Some((lang, gender)));
+            }
+        }
+        assert_eq!(super::locstring_key(GffLang::ChineseSimpl, GffGender::Female),
+            "chinese_simpl/female");
+        assert_eq!(super::parse_locstring_key("english"), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! TOML representation of GFF documents, see [`text`](super)
+//!
+//! Each field is an inline table holding a single key, its type. Lists
+//! are written with a struct per line:
+//!
+//! ```toml
+//! __data_type = "UTC "
+//! __data_version = "V3.2"
+//! Tag = { cexostring = "nw_bandit" }
+//! FirstName = { cexolocstring = { strref = 12345, "english/male" = "Bandit" } }
+//! ItemList = { list = [
+//!   { __struct_id = 0, InventoryRes = { resref = "nw_wswss001" } },
+//! ] }
+//! ```
+//!
+//! TOML integers being signed, DWord64 values above `i64::MAX` are
+//! written as strings.
+
+use std::fmt::Write;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+use serde_path_to_error::Segment;
+
+use crate::common::{GffFieldValue, GffFile, GffStruct, DEFAULT_STRUCT_ID, FIELD_TYPES};
+use crate::error::{DataError, LabelPath, PathElement};
+use super::{
+    locstring_key,
+    DocumentBuf,
+    DATA_TYPE_KEY,
+    DATA_VERSION_KEY,
+    STRUCT_ID_KEY,
+    STR_REF_KEY,
+};
+
+/* {{{ Writing */
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04X}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_key(out: &mut String, key: &str) {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        out.push_str(key);
+    } else {
+        write_string(out, key);
+    }
+}
+
+/// Debug gives the shortest representation, only NaN differs in TOML
+fn write_float(out: &mut String, repr: String) {
+    out.push_str(if repr == "NaN" { "nan" } else { &repr });
+}
+
+/// Write the fields of a struct, separated by `sep`
+fn write_fields(out: &mut String, st: &GffStruct, sep: &str, indent: usize) {
+    let mut first = true;
+    let mut next = |out: &mut String| {
+        if !first {
+            out.push_str(sep);
+        }
+        first = false;
+    };
+    if st.st_type != DEFAULT_STRUCT_ID {
+        next(out);
+        write!(out, "{} = {}", STRUCT_ID_KEY, st.st_type).unwrap();
+    }
+    for (label, value) in &st.fields {
+        next(out);
+        write_key(out, label);
+        out.push_str(" = ");
+        write_field(out, value, indent);
+    }
+}
+
+fn write_struct(out: &mut String, st: &GffStruct, indent: usize) {
+    if st.st_type == DEFAULT_STRUCT_ID && st.fields.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{ ");
+    write_fields(out, st, ", ", indent);
+    out.push_str(" }");
+}
+
+fn write_field(out: &mut String, value: &GffFieldValue, indent: usize) {
+    write!(out, "{{ {} = ", FIELD_TYPES[value.field_type() as usize]).unwrap();
+    match value {
+        GffFieldValue::Byte(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Char(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Word(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Short(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::DWord(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Int(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::DWord64(v) if *v > i64::MAX as u64 => write_string(out, &v.to_string()),
+        GffFieldValue::DWord64(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Int64(v) => write!(out, "{}", v).unwrap(),
+        GffFieldValue::Float(v) => write_float(out, format!("{:?}", v)),
+        GffFieldValue::Double(v) => write_float(out, format!("{:?}", v)),
+        GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => write_string(out, v),
+        GffFieldValue::CExoLocString(str_ref, strings) => {
+            let mut entries = Vec::new();
+            if *str_ref != 0xFFFFFFFF {
+                entries.push(format!("{} = {}", STR_REF_KEY, str_ref));
+            }
+            for ((lang, gender), s) in strings {
+                let mut entry = String::new();
+                write_key(&mut entry, &locstring_key(*lang, *gender));
+                entry.push_str(" = ");
+                write_string(&mut entry, s);
+                entries.push(entry);
+            }
+            if entries.is_empty() {
+                out.push_str("{}");
+            } else {
+                write!(out, "{{ {} }}", entries.join(", ")).unwrap();
+            }
+        },
+        GffFieldValue::Void(v) => write_string(out, &BASE64.encode(v)),
+        GffFieldValue::Struct(st) => write_struct(out, st, indent),
+        GffFieldValue::List(structs) if structs.is_empty() => out.push_str("[]"),
+        GffFieldValue::List(structs) => {
+            out.push_str("[\n");
+            for st in structs {
+                out.push_str(&" ".repeat(indent + 2));
+                write_struct(out, st, indent + 2);
+                out.push_str(",\n");
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push(']');
+        },
+    }
+    out.push_str(" }");
+}
+
+/// Convert a document to TOML
+pub fn to_string(file: &GffFile) -> String {
+    let mut out = String::new();
+    for (key, value) in [(DATA_TYPE_KEY, &file.file_type), (DATA_VERSION_KEY, &file.version)] {
+        write!(out, "{} = ", key).unwrap();
+        write_string(&mut out, &String::from_utf8_lossy(value));
+        out.push('\n');
+    }
+    write_fields(&mut out, &file.root, "\n", 0);
+    out.push('\n');
+    out
+}
+
+/* }}} */
+/* {{{ Reading */
+
+/// Path of the field holding an error, field types being enum variants
+fn label_path(path: &serde_path_to_error::Path) -> LabelPath {
+    LabelPath(path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Map { key } => Some(PathElement::Label(key.clone())),
+            Segment::Seq { index } => Some(PathElement::Index(*index)),
+            _ => None,
+        })
+        .collect())
+}
+
+fn syntax_error(s: &str, err: serde_path_to_error::Error<toml::de::Error>) -> DataError {
+    let path = label_path(err.path());
+    let err = err.into_inner();
+    let location = err.span().map(|span| {
+        let before = &s[..span.start];
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    });
+    let message = if path.0.is_empty() {
+        err.message().to_string()
+    } else {
+        format!("{}: {}", path, err.message())
+    };
+    crate::common::syntax_error(location, message)
+}
+
+/// Parse TOML to a document
+pub fn from_str(s: &str) -> Result<GffFile, DataError> {
+    serde_path_to_error::deserialize::<_, DocumentBuf>(toml::Deserializer::new(s))
+        .map(|doc| doc.0)
+        .map_err(|e| syntax_error(s, e))
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{round_trip_sample, sample};
+
+    #[test]
+    fn test_01_round_trip_sample() {
+        round_trip_sample(super::to_string, super::from_str);
+    }
+
+    #[test]
+    fn test_02_format() {
+        let file = sample();
+        let toml = super::to_string(&file);
+        assert_eq!(toml, r#"__data_type = "UTC "
+__data_version = "V3.2"
+Tag = { cexostring = "nw_bandit" }
+Appearance = { word = 6 }
+GoodEvil = { char = -12 }
+Experience = { dword64 = "18446744073709551615" }
+Scale = { float = 0.1 }
+Comment = { cexostring = "1234\n\"two\" lines" }
+Conversation = { resref = "" }
+FirstName = { cexolocstring = { strref = 12345, "french/female" = "Bandite", "english/male" = "Bandit" } }
+Data = { void = "AAEC/w==" }
+Tint_Hair = { struct = { __struct_id = 0, a = { byte = 255 } } }
+VarTable = { list = [] }
+ItemList = { list = [
+  { __struct_id = 0, InventoryRes = { resref = "nw_wswss001" } },
+  {},
+] }
+"#);
+        assert_eq!(super::from_str(&toml).unwrap(), file);
+    }
+
+    #[test]
+    fn test_03_errors() {
+        let err = super::from_str("__data_type = \"UTC \"\nTag = { string = \"a\" }\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 2, column 18: Tag: unknown variant `string`"));
+
+        let err = super::from_str("__data_type = \"UTC \"\nList = { list = [\n  { a = { byte = 300 } },\n] }\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 3, column 18: List[0].a: invalid value: integer `300`, expected u8");
+
+        let err = super::from_str("__data_type = \"UTC\"\n").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 1, column 15: __data_type: invalid value: string \"UTC\", expected a 4 characters string");
+
+        let err = super::from_str("__data_type = \"UTC \"\nName = { cexolocstring = { \"klingon/male\" = \"a\" } }\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 26: Name: unknown language klingon/male");
+
+        let err = super::from_str("__data_type = \"UTC \"\nthis_label_is_too_long = { byte = 1 }\n").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 2, column 1: this_label_is_too_long: label is longer than 16 bytes");
+
+        let err = super::from_str("__data_type = \"UTC \"\nTag = [\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 3, column 1: "));
+    }
+}
@@ -0,0 +1,98 @@
+//! YAML representation of GFF documents, see [`text`](super)
+
+use crate::common::GffFile;
+use crate::error::DataError;
+use super::{Document, DocumentBuf};
+
+fn syntax_error(err: serde_yaml::Error) -> DataError {
+    let location = err.location().map(|location| (location.line(), location.column()));
+    crate::common::syntax_error(location, err.to_string())
+}
+
+/// Convert a document to YAML
+pub fn to_string(file: &GffFile) -> String {
+    // the text representation only uses types supported by YAML
+    serde_yaml::to_string(&Document(file)).unwrap()
+}
+
+/// Parse YAML to a document
+pub fn from_str(s: &str) -> Result<GffFile, DataError> {
+    serde_yaml::from_str::<DocumentBuf>(s)
+        .map(|doc| doc.0)
+        .map_err(syntax_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{round_trip_sample, sample};
+
+    #[test]
+    fn test_01_round_trip_sample() {
+        round_trip_sample(super::to_string, super::from_str);
+    }
+
+    #[test]
+    fn test_02_format() {
+        let file = sample();
+        let yaml = super::to_string(&file);
+        assert_eq!(yaml, "\
+__data_type: 'UTC '
+__data_version: V3.2
+Tag: !cexostring nw_bandit
+Appearance: !word 6
+GoodEvil: !char -12
+Experience: !dword64 18446744073709551615
+Scale: !float 0.1
+Comment: !cexostring |-
+  1234
+  \"two\" lines
+Conversation: !resref ''
+FirstName: !cexolocstring
+  strref: 12345
+  french/female: Bandite
+  english/male: Bandit
+Data: !void AAEC/w==
+Tint_Hair: !struct
+  __struct_id: 0
+  a: !byte 255
+VarTable: !list []
+ItemList: !list
+- __struct_id: 0
+  InventoryRes: !resref nw_wswss001
+- {}
+");
+        assert_eq!(super::from_str(&yaml).unwrap(), file);
+    }
+
+    #[test]
+    fn test_03_errors() {
+        let err = super::from_str("__data_type: 'UTC '\nTag: !string a\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 2, column 6: Tag: unknown variant `string`"));
+
+        let err = super::from_str("__data_type: 'UTC '\nList: !list\n- a: !byte 300\n").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 3, column 6: List[0].a: invalid value: integer `300`, expected u8");
+
+        let err = super::from_str("__data_type: 'UTC '\nName: !cexolocstring\n  klingon/male: a\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 7: Name: unknown language klingon/male");
+
+        let err = super::from_str("__data_type: 'UTC '\nData: !void '%%'\n").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 2, column 7: Data: invalid base64 data: Invalid symbol 37, offset 0.");
+
+        let err = super::from_str("__data_type: 'UTC '\nthis_label_is_too_long: !byte 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 1: label is longer than 16 bytes");
+
+        let err = super::from_str("Tag: !byte 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1, column 1: missing field `__data_type`");
+
+        let err = super::from_str("__data_type: 'UTC '\nTag: [\n").unwrap_err();
+        assert!(err.to_string().starts_with("line 2, column 6: "));
+
+        /* no location */
+        let err = super::from_str("__data_type: 'UTC '\n---\n__data_type: 'UTC '\n").unwrap_err();
+        assert_eq!(err.to_string(),
+            "deserializing from YAML containing more than one document is not supported");
+    }
+}
@@ -0,0 +1,656 @@
+//! Visitors walking a tree of structs
+//!
+//! A [`GffVisitor`] is called when entering and leaving each struct
+//! and list, and on each other field, in the order of the fields. It
+//! can walk a [`GffStruct`] with [`walk`], or packed data with
+//! [`GffParser::visit`], which reads the fields as they are visited,
+//! without building the intermediary representation: field values are
+//! only read if the visitor asks for them.
+//!
+//! A [`GffVisitorMut`] walks a [`GffStruct`] with [`walk_mut`], and
+//! can change the structs, lists and fields it visits.
+//!
+//! Each callback gets the [`VisitPath`] of the visited node, and
+//! returns whether to go on with a [`Visit`]. Every callback does
+//! nothing by default.
+//!
+//! ```
+//! # use gff::common::{IndexMap, GffFieldValue, GffStruct};
+//! # use gff::error::DataError;
+//! use gff::visit::{walk, Field, GffVisitor, Visit, VisitPath};
+//!
+//! /// Tags of the items, but not of their contents
+//! struct Tags(Vec<String>);
+//!
+//! impl GffVisitor for Tags {
+//!     fn enter_list(&mut self, path: &VisitPath, _len: usize) -> Result<Visit, DataError> {
+//!         Ok(if path.depth() > 1 { Visit::Skip } else { Visit::Continue })
+//!     }
+//!
+//!     fn field(&mut self, path: &VisitPath, field: &mut Field) -> Result<Visit, DataError> {
+//!         if field.label() == "Tag" && path.depth() == 2 {
+//!             self.0.push(field.value()?.as_ref().try_into()?);
+//!         }
+//!         Ok(Visit::Continue)
+//!     }
+//! }
+//!
+//! let item = |tag: &str| GffStruct {
+//!     st_type: 0,
+//!     fields: IndexMap::from([(String::from("Tag"), GffFieldValue::CExoString(String::from(tag)))]),
+//! };
+//! let root = GffStruct {
+//!     st_type: 0xFFFFFFFF,
+//!     fields: IndexMap::from([
+//!         (String::from("ItemList"), GffFieldValue::List(vec![item("a"), item("b")])),
+//!     ]),
+//! };
+//! let mut tags = Tags(vec![]);
+//! walk(&root, &mut tags).unwrap();
+//! assert_eq!(tags.0, ["a", "b"]);
+//! ```
+//!
+//! [`GffParser::visit`]: crate::parser::GffParser::visit
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use crate::common::{GffFieldType, GffFieldValue, GffStruct};
+use crate::error::{DataError, LabelPath, PathElement};
+use crate::parser::{FieldReader, StructReader};
+
+/* {{{ Paths */
+
+/// Element of a [`VisitPath`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<'a> {
+    /// Field of a struct
+    Label(&'a str),
+    /// Struct of a list
+    Index(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Element {
+    /// Range of the label in the label buffer
+    Label(Range<usize>),
+    Index(usize),
+}
+
+/// Path of a visited node, from the top-level struct
+///
+/// This is a [`LabelPath`] whose labels share a single buffer, so that
+/// walking a tree does not allocate for each field. It is displayed in
+/// the same way, e.g. `ItemList[3].Tag`.
+#[derive(Debug, Clone, Default)]
+pub struct VisitPath {
+    labels: String,
+    elements: Vec<Element>,
+}
+
+impl VisitPath {
+    /// Nesting depth, the number of labels in the path
+    ///
+    /// The top-level struct is at depth 0, its fields at depth 1, and
+    /// so are the structs of its lists.
+    pub fn depth(&self) -> usize {
+        self.elements.iter().filter(|elem| matches!(elem, Element::Label(_))).count()
+    }
+
+    /// Whether this is the path of the top-level struct
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Elements of the path
+    pub fn steps(&self) -> impl Iterator<Item = Step<'_>> {
+        self.elements.iter().map(|elem| self.step(elem))
+    }
+
+    /// Last element of the path
+    pub fn last(&self) -> Option<Step<'_>> {
+        self.elements.last().map(|elem| self.step(elem))
+    }
+
+    /// Copy of the path, e.g. to keep it or to locate an error
+    pub fn to_label_path(&self) -> LabelPath {
+        LabelPath(self.steps().map(|step| match step {
+            Step::Label(label) => PathElement::Label(label.to_string()),
+            Step::Index(index) => PathElement::Index(index),
+        }).collect())
+    }
+
+    fn step(&self, elem: &Element) -> Step<'_> {
+        match elem {
+            Element::Label(range) => Step::Label(&self.labels[range.clone()]),
+            Element::Index(index) => Step::Index(*index),
+        }
+    }
+
+    fn push_label(&mut self, label: &str) {
+        let start = self.labels.len();
+        self.labels.push_str(label);
+        self.elements.push(Element::Label(start..self.labels.len()));
+    }
+
+    fn push_index(&mut self, index: usize) {
+        self.elements.push(Element::Index(index));
+    }
+
+    fn pop(&mut self) {
+        if let Some(Element::Label(range)) = self.elements.pop() {
+            self.labels.truncate(range.start);
+        }
+    }
+}
+
+impl fmt::Display for VisitPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, step) in self.steps().enumerate() {
+            match step {
+                Step::Label(label) if i == 0 => write!(f, "{}", label)?,
+                Step::Label(label) => write!(f, ".{}", label)?,
+                Step::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/* }}} */
+/* {{{ Visitors */
+
+/// What to do after a callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Go on with the walk
+    Continue,
+    /// Skip the fields of the entered struct, or the structs of the
+    /// entered list, which is still left. This is `Continue` for the
+    /// other callbacks.
+    Skip,
+    /// End the walk, without calling any other callback
+    Stop,
+}
+
+/// Source of the value of a [`Field`]
+enum Source<'a, 'data> {
+    Value(&'a GffFieldValue),
+    Packed(FieldReader<'a, 'data>),
+}
+
+/// A field visited by a [`GffVisitor`], other than a struct or a list
+///
+/// The value of a packed field is read when asked for.
+pub struct Field<'a, 'data> {
+    label: &'a str,
+    field_type: GffFieldType,
+    source: Source<'a, 'data>,
+}
+
+impl Field<'_, '_> {
+    /// Field label
+    pub fn label(&self) -> &str {
+        self.label
+    }
+
+    /// Field type
+    pub fn field_type(&self) -> GffFieldType {
+        self.field_type
+    }
+
+    /// Field value, borrowed from a [`GffStruct`] or read from packed data
+    pub fn value(&mut self) -> Result<Cow<'_, GffFieldValue>, DataError> {
+        match &mut self.source {
+            Source::Value(value) => Ok(Cow::Borrowed(*value)),
+            Source::Packed(reader) => reader.read_value().map(Cow::Owned),
+        }
+    }
+}
+
+/// Callbacks of a walk over a tree of structs
+///
+/// See the [module documentation](self).
+pub trait GffVisitor {
+    /// Called before the fields of a struct, which is the top-level
+    /// struct, a struct field or a struct of a list
+    fn enter_struct(&mut self, _path: &VisitPath, _st_type: u32) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called after the fields of a struct
+    fn exit_struct(&mut self, _path: &VisitPath, _st_type: u32) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called before the structs of a list
+    fn enter_list(&mut self, _path: &VisitPath, _len: usize) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called after the structs of a list
+    fn exit_list(&mut self, _path: &VisitPath, _len: usize) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called on each field that is not a struct or a list
+    fn field(&mut self, _path: &VisitPath, _field: &mut Field) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+}
+
+/// Callbacks of a walk over a tree of structs, that can change it
+///
+/// Changes made when entering a struct or a list are walked: fields
+/// added to a struct are visited, and removed ones are not.
+pub trait GffVisitorMut {
+    /// Called before the fields of a struct, see [`GffVisitor::enter_struct`]
+    fn enter_struct(&mut self, _path: &VisitPath, _st: &mut GffStruct) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called after the fields of a struct
+    fn exit_struct(&mut self, _path: &VisitPath, _st: &mut GffStruct) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called before the structs of a list
+    fn enter_list(&mut self, _path: &VisitPath, _list: &mut Vec<GffStruct>) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called after the structs of a list
+    fn exit_list(&mut self, _path: &VisitPath, _list: &mut Vec<GffStruct>) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+
+    /// Called on each field that is not a struct or a list
+    fn field(&mut self, _path: &VisitPath, _value: &mut GffFieldValue) -> Result<Visit, DataError> {
+        Ok(Visit::Continue)
+    }
+}
+
+/* }}} */
+/* {{{ Walks */
+
+/// Whether the walk was stopped
+type Stopped = bool;
+
+/// Walk a struct with a visitor
+///
+/// Errors of the visitor are located at the path of the visited node.
+pub fn walk<V: GffVisitor + ?Sized>(st: &GffStruct, visitor: &mut V) -> Result<(), DataError> {
+    walk_struct(st, &mut VisitPath::default(), visitor).map(drop)
+}
+
+fn walk_struct<V: GffVisitor + ?Sized>(st: &GffStruct, path: &mut VisitPath, visitor: &mut V)
+    -> Result<Stopped, DataError>
+{
+    match visitor.enter_struct(path, st.st_type)? {
+        Visit::Stop => return Ok(true),
+        Visit::Skip => {},
+        Visit::Continue => for (label, value) in &st.fields {
+            path.push_label(label);
+            let stopped = walk_field(label, value, path, visitor).map_err(|e| e.at_label(label))?;
+            path.pop();
+            if stopped {
+                return Ok(true);
+            }
+        },
+    }
+    Ok(visitor.exit_struct(path, st.st_type)? == Visit::Stop)
+}
+
+fn walk_field<V: GffVisitor + ?Sized>(label: &str, value: &GffFieldValue, path: &mut VisitPath, visitor: &mut V)
+    -> Result<Stopped, DataError>
+{
+    match value {
+        GffFieldValue::Struct(st) => walk_struct(st, path, visitor),
+        GffFieldValue::List(list) => {
+            match visitor.enter_list(path, list.len())? {
+                Visit::Stop => return Ok(true),
+                Visit::Skip => {},
+                Visit::Continue => for (i, st) in list.iter().enumerate() {
+                    path.push_index(i);
+                    let stopped = walk_struct(st, path, visitor).map_err(|e| e.at_index(i))?;
+                    path.pop();
+                    if stopped {
+                        return Ok(true);
+                    }
+                },
+            }
+            Ok(visitor.exit_list(path, list.len())? == Visit::Stop)
+        },
+        value => {
+            let mut field = Field { label, field_type: value.field_type(), source: Source::Value(value) };
+            Ok(visitor.field(path, &mut field)? == Visit::Stop)
+        },
+    }
+}
+
+/// Walk a struct with a mutable visitor
+///
+/// Errors of the visitor are located at the path of the visited node.
+pub fn walk_mut<V: GffVisitorMut + ?Sized>(st: &mut GffStruct, visitor: &mut V) -> Result<(), DataError> {
+    walk_struct_mut(st, &mut VisitPath::default(), visitor).map(drop)
+}
+
+fn walk_struct_mut<V: GffVisitorMut + ?Sized>(st: &mut GffStruct, path: &mut VisitPath, visitor: &mut V)
+    -> Result<Stopped, DataError>
+{
+    match visitor.enter_struct(path, st)? {
+        Visit::Stop => return Ok(true),
+        Visit::Skip => {},
+        Visit::Continue => for (label, value) in st.fields.iter_mut() {
+            path.push_label(label);
+            let stopped = walk_field_mut(value, path, visitor).map_err(|e| e.at_label(label))?;
+            path.pop();
+            if stopped {
+                return Ok(true);
+            }
+        },
+    }
+    Ok(visitor.exit_struct(path, st)? == Visit::Stop)
+}
+
+fn walk_field_mut<V: GffVisitorMut + ?Sized>(value: &mut GffFieldValue, path: &mut VisitPath, visitor: &mut V)
+    -> Result<Stopped, DataError>
+{
+    match value {
+        GffFieldValue::Struct(st) => walk_struct_mut(st, path, visitor),
+        GffFieldValue::List(list) => {
+            match visitor.enter_list(path, list)? {
+                Visit::Stop => return Ok(true),
+                Visit::Skip => {},
+                Visit::Continue => for (i, st) in list.iter_mut().enumerate() {
+                    path.push_index(i);
+                    let stopped = walk_struct_mut(st, path, visitor).map_err(|e| e.at_index(i))?;
+                    path.pop();
+                    if stopped {
+                        return Ok(true);
+                    }
+                },
+            }
+            Ok(visitor.exit_list(path, list)? == Visit::Stop)
+        },
+        value => Ok(visitor.field(path, value)? == Visit::Stop),
+    }
+}
+
+/// Walk a packed struct with a visitor, see [`GffParser::visit`]
+///
+/// [`GffParser::visit`]: crate::parser::GffParser::visit
+pub fn walk_reader<V: GffVisitor + ?Sized>(reader: &mut StructReader<'_, '_>, visitor: &mut V)
+    -> Result<(), DataError>
+{
+    walk_packed_struct(reader, &mut VisitPath::default(), visitor).map(drop)
+}
+
+fn walk_packed_struct<V: GffVisitor + ?Sized>(reader: &mut StructReader<'_, '_>, path: &mut VisitPath,
+    visitor: &mut V) -> Result<Stopped, DataError>
+{
+    match visitor.enter_struct(path, reader.st_type())? {
+        Visit::Stop => return Ok(true),
+        Visit::Skip => {},
+        Visit::Continue => while let Some(field) = reader.next_field()? {
+            let label = field.label();
+            path.push_label(label);
+            let stopped = walk_packed_field(field, path, visitor).map_err(|e| e.at_label(label))?;
+            path.pop();
+            if stopped {
+                return Ok(true);
+            }
+        },
+    }
+    Ok(visitor.exit_struct(path, reader.st_type())? == Visit::Stop)
+}
+
+fn walk_packed_field<V: GffVisitor + ?Sized>(field: FieldReader<'_, '_>, path: &mut VisitPath, visitor: &mut V)
+    -> Result<Stopped, DataError>
+{
+    match field.field_type() {
+        GffFieldType::Struct => field.read_struct(|reader| walk_packed_struct(reader, path, visitor)),
+        GffFieldType::List => {
+            let mut list = field.list()?;
+            let len = list.len();
+            match visitor.enter_list(path, len)? {
+                Visit::Stop => return Ok(true),
+                Visit::Skip => {},
+                Visit::Continue => for i in 0..len {
+                    path.push_index(i);
+                    let stopped = list.next_struct(|reader| walk_packed_struct(reader, path, visitor))?;
+                    path.pop();
+                    if stopped == Some(true) {
+                        return Ok(true);
+                    }
+                },
+            }
+            Ok(visitor.exit_list(path, len)? == Visit::Stop)
+        },
+        field_type => {
+            let label = field.label();
+            let mut field = Field { label, field_type, source: Source::Packed(field) };
+            Ok(visitor.field(path, &mut field)? == Visit::Stop)
+        },
+    }
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{Encodings, GffFieldValue, GffStruct};
+    use crate::error::{DataError, DataErrorKind};
+    use crate::parser::GffParser;
+    use super::{walk, walk_mut, Field, GffVisitor, GffVisitorMut, Step, Visit, VisitPath};
+
+    /// Visitor recording its callbacks, skipping or stopping at a path
+    struct Recorder {
+        events: Vec<String>,
+        skip: &'static str,
+        stop: &'static str,
+    }
+
+    impl Recorder {
+        fn new(skip: &'static str, stop: &'static str) -> Self {
+            Recorder { events: vec![], skip, stop }
+        }
+
+        fn record(&mut self, path: &VisitPath, event: String) -> Result<Visit, DataError> {
+            self.events.push(format!("{} {} {}", path.depth(), path, event));
+            let path = path.to_string();
+            Ok(if path == self.skip {
+                Visit::Skip
+            } else if path == self.stop {
+                Visit::Stop
+            } else {
+                Visit::Continue
+            })
+        }
+    }
+
+    impl GffVisitor for Recorder {
+        fn enter_struct(&mut self, path: &VisitPath, st_type: u32) -> Result<Visit, DataError> {
+            self.record(path, format!("{{ 0x{:x}", st_type))
+        }
+
+        fn exit_struct(&mut self, path: &VisitPath, st_type: u32) -> Result<Visit, DataError> {
+            self.record(path, format!("}} 0x{:x}", st_type))
+        }
+
+        fn enter_list(&mut self, path: &VisitPath, len: usize) -> Result<Visit, DataError> {
+            self.record(path, format!("[ {}", len))
+        }
+
+        fn exit_list(&mut self, path: &VisitPath, len: usize) -> Result<Visit, DataError> {
+            self.record(path, format!("] {}", len))
+        }
+
+        fn field(&mut self, path: &VisitPath, field: &mut Field) -> Result<Visit, DataError> {
+            assert_eq!(path.last(), Some(Step::Label(field.label())));
+            let value = format!("{:?}", field.value()?);
+            assert_eq!(field.field_type(), field.value()?.field_type());
+            self.record(path, value)
+        }
+    }
+
+    fn st(st_type: u32, fields: Vec<(&str, GffFieldValue)>) -> GffStruct {
+        GffStruct {
+            st_type,
+            fields: fields.into_iter().map(|(label, value)| (String::from(label), value)).collect(),
+        }
+    }
+
+    fn sample() -> GffStruct {
+        st(0xFFFFFFFF, vec![
+            ("Gold", GffFieldValue::DWord(10)),
+            ("Tint", GffFieldValue::Struct(st(1, vec![("r", GffFieldValue::Byte(2))]))),
+            ("ItemList", GffFieldValue::List(vec![
+                st(0, vec![("Tag", GffFieldValue::CExoString(String::from("a")))]),
+                st(0, vec![
+                    ("Tag", GffFieldValue::CExoString(String::from("b"))),
+                    ("PropertiesList", GffFieldValue::List(vec![])),
+                ]),
+            ])),
+            ("Age", GffFieldValue::Int(3)),
+        ])
+    }
+
+    fn events(st: &GffStruct, skip: &'static str, stop: &'static str) -> Vec<String> {
+        let mut recorder = Recorder::new(skip, stop);
+        walk(st, &mut recorder).unwrap();
+        recorder.events
+    }
+
+    #[test]
+    fn test_01_walk() {
+        assert_eq!(events(&sample(), "-", "-"), [
+            "0  { 0xffffffff",
+            "1 Gold DWord(10)",
+            "1 Tint { 0x1",
+            "2 Tint.r Byte(2)",
+            "1 Tint } 0x1",
+            "1 ItemList [ 2",
+            "1 ItemList[0] { 0x0",
+            "2 ItemList[0].Tag CExoString(\"a\")",
+            "1 ItemList[0] } 0x0",
+            "1 ItemList[1] { 0x0",
+            "2 ItemList[1].Tag CExoString(\"b\")",
+            "2 ItemList[1].PropertiesList [ 0",
+            "2 ItemList[1].PropertiesList ] 0",
+            "1 ItemList[1] } 0x0",
+            "1 ItemList ] 2",
+            "1 Age Int(3)",
+            "0  } 0xffffffff",
+        ]);
+
+        /* skipped nodes are still left */
+        assert_eq!(events(&sample(), "ItemList", "-")[5..8], [
+            "1 ItemList [ 2",
+            "1 ItemList ] 2",
+            "1 Age Int(3)",
+        ]);
+        assert_eq!(events(&sample(), "Tint", "-")[2..5], [
+            "1 Tint { 0x1",
+            "1 Tint } 0x1",
+            "1 ItemList [ 2",
+        ]);
+        assert_eq!(events(&sample(), "", "-"), ["0  { 0xffffffff", "0  } 0xffffffff"]);
+        assert_eq!(events(&sample(), "Gold", "-").len(), 17);
+
+        /* nothing is called once stopped */
+        assert_eq!(events(&sample(), "-", "ItemList[0].Tag").last().unwrap(), "2 ItemList[0].Tag CExoString(\"a\")");
+        assert_eq!(events(&sample(), "-", "ItemList[0]").len(), 7);
+        assert_eq!(events(&sample(), "-", "Tint").len(), 3);
+    }
+
+    #[test]
+    fn test_02_walk_packed() {
+        let data = std::fs::read("test-data/test.bic").unwrap();
+        let file = GffParser::parse(data.clone(), &*Encodings::NeverwinterNights).unwrap();
+
+        for (skip, stop) in [("-", "-"), ("ItemList", "-"), ("-", "ItemList[3].Tag"), ("ClassList[0]", "LvlStatList[2]")] {
+            let mut recorder = Recorder::new(skip, stop);
+            GffParser::visit(data.clone(), &*Encodings::NeverwinterNights, &mut recorder).unwrap();
+            assert_eq!(recorder.events, events(&file.root, skip, stop), "{} {}", skip, stop);
+        }
+
+        /* visitors need not read the values */
+        struct Count(usize);
+        impl GffVisitor for Count {
+            fn field(&mut self, _path: &VisitPath, _field: &mut Field) -> Result<Visit, DataError> {
+                self.0 += 1;
+                Ok(Visit::Continue)
+            }
+        }
+        let mut count = Count(0);
+        GffParser::visit(data, &*Encodings::NeverwinterNights, &mut count).unwrap();
+        assert!(count.0 > 5000);
+    }
+
+    #[test]
+    fn test_03_walk_mut() {
+        /// Uppercase the tags, drop the empty lists, and count the structs by depth
+        #[derive(Default)]
+        struct Edit(Vec<usize>);
+
+        impl GffVisitorMut for Edit {
+            fn enter_struct(&mut self, path: &VisitPath, st: &mut GffStruct) -> Result<Visit, DataError> {
+                st.fields.retain(|_, value| !matches!(value, GffFieldValue::List(list) if list.is_empty()));
+                self.0.resize(self.0.len().max(path.depth() + 1), 0);
+                self.0[path.depth()] += 1;
+                Ok(Visit::Continue)
+            }
+
+            fn field(&mut self, _path: &VisitPath, value: &mut GffFieldValue) -> Result<Visit, DataError> {
+                if let GffFieldValue::CExoString(s) = value {
+                    *s = s.to_uppercase();
+                }
+                Ok(Visit::Continue)
+            }
+        }
+
+        let mut st = sample();
+        let mut edit = Edit::default();
+        walk_mut(&mut st, &mut edit).unwrap();
+        assert_eq!(edit.0, [1, 3]);
+        assert_eq!(st.get_path(&"ItemList[1].Tag".parse().unwrap()).unwrap(),
+            &GffFieldValue::CExoString(String::from("B")));
+        assert!(st.get_path(&"ItemList[1].PropertiesList".parse().unwrap()).is_err());
+
+        /* errors are located at the visited node */
+        struct Fail;
+        impl GffVisitorMut for Fail {
+            fn enter_list(&mut self, path: &VisitPath, list: &mut Vec<GffStruct>) -> Result<Visit, DataError> {
+                match path.depth() {
+                    2 => Err(DataError::new(DataErrorKind::Custom(format!("{} structs", list.len())))),
+                    _ => Ok(Visit::Continue),
+                }
+            }
+        }
+        assert_eq!(walk_mut(&mut sample(), &mut Fail).unwrap_err().to_string(),
+            "ItemList[1].PropertiesList: 0 structs");
+    }
+
+    #[test]
+    fn test_04_path() {
+        let mut path = VisitPath::default();
+        assert!(path.is_empty());
+        assert_eq!(path.depth(), 0);
+        path.push_label("ItemList");
+        path.push_index(3);
+        path.push_label("PropertiesList");
+        path.push_index(0);
+        path.push_label("CostValue");
+        assert_eq!(path.depth(), 3);
+        assert_eq!(path.to_string(), "ItemList[3].PropertiesList[0].CostValue");
+        assert_eq!(path.to_label_path(), "ItemList[3].PropertiesList[0].CostValue".parse().unwrap());
+        path.pop();
+        path.pop();
+        path.pop();
+        path.push_label("Tag");
+        assert_eq!(path.steps().collect::<Vec<_>>(), [Step::Label("ItemList"), Step::Index(3), Step::Label("Tag")]);
+        assert_eq!(path.labels, "ItemListTag");
+    }
+}
@@ -0,0 +1,541 @@
+//! XML representation of GFF documents, with the `xml` feature
+//!
+//! This is the format of xoreos-tools (`gff2xml`/`xml2gff`). Each field
+//! is an element named after its type, with its label as attribute:
+//!
+//! ```xml
+//! <?xml version="1.0" encoding="UTF-8"?>
+//! <gff3 type="UTC ">
+//!   <struct id="4294967295">
+//!     <exostring label="Tag">nw_bandit</exostring>
+//!     <uint16 label="Appearance_Type">6</uint16>
+//!     <locstring label="FirstName" strref="4294967295">
+//!       <string language="0">Bandit</string>
+//!     </locstring>
+//!     <data label="Data">AAEC/w==</data>
+//!     <struct label="Tint" id="0">
+//!       <byte label="a">255</byte>
+//!     </struct>
+//!     <list label="ItemList">
+//!       <struct id="0">
+//!         <resref label="InventoryRes">nw_wswss001</resref>
+//!       </struct>
+//!     </list>
+//!   </struct>
+//! </gff3>
+//! ```
+//!
+//! Localized strings are keyed by their language and gender id. Void
+//! data is base64 encoded. The document version is only written if it
+//! is not the default one.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::common::{
+    locstring_id,
+    locstring_lang,
+    IndexMap,
+    GffFieldType,
+    GffFieldValue,
+    GffFile,
+    GffHeader,
+    GffStruct,
+};
+use crate::error::{DataError, DataErrorKind, LabelPath, PathElement};
+use crate::parser::MAX_DEPTH;
+
+/// Field element names, by GFF type id
+const FIELD_ELEMENTS: &[&str] = &[
+    "byte", "char", "uint16", "sint16", "uint32", "sint32", "uint64", "sint64",
+    "float", "double", "exostring", "resref", "locstring", "data",
+    "struct", "list",
+];
+
+/* {{{ Writing */
+
+fn escape(s: &str) -> String {
+    // carriage returns would be normalized by XML parsers
+    quick_xml::escape::escape(s).replace('\r', "&#13;")
+}
+
+fn indent(out: &mut String, depth: usize) {
+    out.push_str(&"  ".repeat(depth));
+}
+
+fn write_struct(out: &mut String, st: &GffStruct, label: Option<&str>, depth: usize) {
+    indent(out, depth);
+    out.push_str("<struct");
+    if let Some(label) = label {
+        write!(out, " label=\"{}\"", escape(label)).unwrap();
+    }
+    write!(out, " id=\"{}\"", st.st_type).unwrap();
+    if st.fields.is_empty() {
+        out.push_str("/>\n");
+        return;
+    }
+    out.push_str(">\n");
+    for (label, value) in &st.fields {
+        write_field(out, label, value, depth + 1);
+    }
+    indent(out, depth);
+    out.push_str("</struct>\n");
+}
+
+fn write_field(out: &mut String, label: &str, value: &GffFieldValue, depth: usize) {
+    let name = FIELD_ELEMENTS[value.field_type() as usize];
+    let text = match value {
+        GffFieldValue::Byte(v) => v.to_string(),
+        GffFieldValue::Char(v) => v.to_string(),
+        GffFieldValue::Word(v) => v.to_string(),
+        GffFieldValue::Short(v) => v.to_string(),
+        GffFieldValue::DWord(v) => v.to_string(),
+        GffFieldValue::Int(v) => v.to_string(),
+        GffFieldValue::DWord64(v) => v.to_string(),
+        GffFieldValue::Int64(v) => v.to_string(),
+        GffFieldValue::Float(v) => v.to_string(),
+        GffFieldValue::Double(v) => v.to_string(),
+        GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => escape(v),
+        GffFieldValue::Void(v) => BASE64.encode(v),
+        GffFieldValue::CExoLocString(str_ref, strings) => {
+            indent(out, depth);
+            write!(out, "<{} label=\"{}\" strref=\"{}\"", name, escape(label), str_ref).unwrap();
+            if strings.is_empty() {
+                out.push_str("/>\n");
+                return;
+            }
+            out.push_str(">\n");
+            for ((lang, gender), s) in strings {
+                indent(out, depth + 1);
+                writeln!(out, "<string language=\"{}\">{}</string>",
+                    locstring_id(*lang, *gender), escape(s)).unwrap();
+            }
+            indent(out, depth);
+            writeln!(out, "</{}>", name).unwrap();
+            return;
+        },
+        GffFieldValue::Struct(st) => {
+            write_struct(out, st, Some(label), depth);
+            return;
+        },
+        GffFieldValue::List(structs) => {
+            indent(out, depth);
+            write!(out, "<{} label=\"{}\"", name, escape(label)).unwrap();
+            if structs.is_empty() {
+                out.push_str("/>\n");
+                return;
+            }
+            out.push_str(">\n");
+            for st in structs {
+                write_struct(out, st, None, depth + 1);
+            }
+            indent(out, depth);
+            writeln!(out, "</{}>", name).unwrap();
+            return;
+        },
+    };
+    indent(out, depth);
+    if text.is_empty() {
+        writeln!(out, "<{} label=\"{}\"/>", name, escape(label)).unwrap();
+    } else {
+        writeln!(out, "<{} label=\"{}\">{}</{}>", name, escape(label), text, name).unwrap();
+    }
+}
+
+/// Convert a document to XML
+pub fn to_string(file: &GffFile) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    write!(out, "<gff3 type=\"{}\"", escape(&String::from_utf8_lossy(&file.file_type))).unwrap();
+    if file.version != GffHeader::default().version {
+        write!(out, " version=\"{}\"", escape(&String::from_utf8_lossy(&file.version))).unwrap();
+    }
+    out.push_str(">\n");
+    write_struct(&mut out, &file.root, None, 1);
+    out.push_str("</gff3>\n");
+    out
+}
+
+/* }}} */
+/* {{{ Reading */
+
+/// XML events, with the position of the current one, the path of
+/// the current field and the depth of the current struct
+struct XmlReader<'a> {
+    reader: Reader<&'a [u8]>,
+    input: &'a str,
+    pos: usize,
+    path: LabelPath,
+    depth: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    fn new(input: &'a str) -> Self {
+        XmlReader {
+            reader: Reader::from_str(input),
+            input,
+            pos: 0,
+            path: LabelPath::default(),
+            depth: 0,
+        }
+    }
+
+    /// Error at the start of the current event
+    fn error(&self, message: impl Into<String>) -> DataError {
+        let mut message = message.into();
+        if !self.path.0.is_empty() {
+            message = format!("{}: {}", self.path, message);
+        }
+        let before = &self.input[..self.pos.min(self.input.len())];
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        DataError::new(DataErrorKind::Syntax {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        })
+    }
+
+    /// Next event, skipping declarations, comments and processing
+    /// instructions
+    fn next(&mut self) -> Result<Event<'a>, DataError> {
+        loop {
+            self.pos = self.reader.buffer_position() as usize;
+            let event = self.reader.read_event().map_err(|e| {
+                self.pos = self.reader.error_position() as usize;
+                self.error(e.to_string())
+            })?;
+            match event {
+                Event::Decl(_) | Event::Comment(_) | Event::PI(_) | Event::DocType(_) => continue,
+                event => return Ok(event),
+            }
+        }
+    }
+
+    /// Next element, skipping blank text
+    ///
+    /// Returns the element and whether it is empty, or `None` at the
+    /// end of the parent element.
+    fn next_element(&mut self) -> Result<Option<(BytesStart<'a>, bool)>, DataError> {
+        loop {
+            match self.next()? {
+                Event::Start(e) => return Ok(Some((e, false))),
+                Event::Empty(e) => return Ok(Some((e, true))),
+                Event::End(_) => return Ok(None),
+                Event::Text(text) if text.iter().all(u8::is_ascii_whitespace) => continue,
+                Event::Eof => return Err(self.error("unexpected end of document")),
+                _ => return Err(self.error("unexpected text")),
+            }
+        }
+    }
+
+    /// Next child element of an element, `None` at its end
+    fn next_child(&mut self, empty: bool) -> Result<Option<(BytesStart<'a>, bool)>, DataError> {
+        if empty { Ok(None) } else { self.next_element() }
+    }
+
+    /// Text content of an element, up to its end
+    ///
+    /// Errors on the value are then located at the start of the text.
+    fn text(&mut self, empty: bool) -> Result<String, DataError> {
+        let mut res = String::new();
+        if empty {
+            return Ok(res);
+        }
+        let start = self.reader.buffer_position() as usize;
+        loop {
+            match self.next()? {
+                Event::Text(text) =>
+                    res.push_str(&text.unescape().map_err(|e| self.error(e.to_string()))?),
+                Event::CData(data) =>
+                    res.push_str(std::str::from_utf8(&data).map_err(|e| self.error(e.to_string()))?),
+                Event::End(_) => {
+                    self.pos = start;
+                    return Ok(res);
+                },
+                Event::Eof => return Err(self.error("unexpected end of document")),
+                _ => return Err(self.error("unexpected element")),
+            }
+        }
+    }
+
+    fn attribute(&self, e: &BytesStart<'a>, name: &str) -> Result<Option<String>, DataError> {
+        match e.try_get_attribute(name).map_err(|err| self.error(err.to_string()))? {
+            Some(attr) => attr.unescape_value()
+                .map(|value| Some(value.into_owned()))
+                .map_err(|err| self.error(err.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn required_attribute(&self, e: &BytesStart<'a>, name: &str) -> Result<String, DataError> {
+        self.attribute(e, name)?
+            .ok_or_else(|| self.error(format!("missing attribute {}", name)))
+    }
+
+    fn parse<T: FromStr>(&self, value: &str, what: &str) -> Result<T, DataError> {
+        value.trim().parse()
+            .map_err(|_| self.error(format!("invalid {} {:?}", what, value)))
+    }
+
+    fn read_struct(&mut self, e: &BytesStart<'a>, empty: bool) -> Result<GffStruct, DataError> {
+        if e.name().as_ref() != b"struct" {
+            return Err(self.error("expected a struct element"));
+        }
+        /* same limit as the parser, as deep documents would exhaust the stack */
+        if self.depth >= MAX_DEPTH {
+            return Err(self.error("structs nested too deep"));
+        }
+        self.depth += 1;
+        let st = self.read_fields(e, empty)?;
+        self.depth -= 1;
+        Ok(st)
+    }
+
+    fn read_fields(&mut self, e: &BytesStart<'a>, empty: bool) -> Result<GffStruct, DataError> {
+        let st_type = match self.attribute(e, "id")? {
+            Some(id) => self.parse(&id, "struct id")?,
+            None => 0xFFFFFFFF,
+        };
+        let mut st = GffStruct { st_type, fields: IndexMap::new() };
+        while let Some((e, empty)) = self.next_child(empty)? {
+            let label = self.required_attribute(&e, "label")?;
+            if label.len() > 16 {
+                return Err(self.error(format!("label {} is longer than 16 bytes", label)));
+            }
+            self.path.0.push(PathElement::Label(label.clone()));
+            let value = self.read_field(&e, empty)?;
+            self.path.0.pop();
+            st.fields.insert(label, value);
+        }
+        Ok(st)
+    }
+
+    fn read_field(&mut self, e: &BytesStart<'a>, empty: bool) -> Result<GffFieldValue, DataError> {
+        let name = e.name();
+        let field_type = FIELD_ELEMENTS.iter().position(|ty| ty.as_bytes() == name.as_ref())
+            .and_then(|ty| GffFieldType::try_from(ty as u32).ok())
+            .ok_or_else(|| self.error(format!("unknown field type {}",
+                String::from_utf8_lossy(name.as_ref()))))?;
+
+        Ok(match field_type {
+            GffFieldType::Struct => GffFieldValue::Struct(self.read_struct(e, empty)?),
+            GffFieldType::List => {
+                let mut structs = Vec::new();
+                while let Some((e, empty)) = self.next_child(empty)? {
+                    self.path.0.push(PathElement::Index(structs.len()));
+                    structs.push(self.read_struct(&e, empty)?);
+                    self.path.0.pop();
+                }
+                GffFieldValue::List(structs)
+            },
+            field_type => self.read_value(e, empty, field_type)?,
+        })
+    }
+
+    /// Value of a field other than a struct or a list
+    ///
+    /// This is kept out of [`Self::read_field`], so that nested structs
+    /// use as little stack as possible.
+    fn read_value(&mut self, e: &BytesStart<'a>, empty: bool, field_type: GffFieldType)
+        -> Result<GffFieldValue, DataError>
+    {
+        macro_rules! number {
+            ($variant:ident) => {{
+                let text = self.text(empty)?;
+                GffFieldValue::$variant(self.parse(&text, "number")?)
+            }}
+        }
+        Ok(match field_type {
+            GffFieldType::Byte => number!(Byte),
+            GffFieldType::Char => number!(Char),
+            GffFieldType::Word => number!(Word),
+            GffFieldType::Short => number!(Short),
+            GffFieldType::DWord => number!(DWord),
+            GffFieldType::Int => number!(Int),
+            GffFieldType::DWord64 => number!(DWord64),
+            GffFieldType::Int64 => number!(Int64),
+            GffFieldType::Float => number!(Float),
+            GffFieldType::Double => number!(Double),
+            GffFieldType::CExoString => GffFieldValue::CExoString(self.text(empty)?),
+            GffFieldType::CResRef => GffFieldValue::CResRef(self.text(empty)?),
+            GffFieldType::CExoLocString => {
+                let str_ref = match self.attribute(e, "strref")? {
+                    Some(str_ref) => self.parse(&str_ref, "string reference")?,
+                    None => 0xFFFFFFFF,
+                };
+                let mut strings = IndexMap::new();
+                while let Some((e, empty)) = self.next_child(empty)? {
+                    if e.name().as_ref() != b"string" {
+                        return Err(self.error("expected a string element"));
+                    }
+                    let id = self.required_attribute(&e, "language")?;
+                    let lang_gender = self.parse(&id, "language")
+                        .ok().and_then(locstring_lang)
+                        .ok_or_else(|| self.error(format!("unknown language {}", id)))?;
+                    strings.insert(lang_gender, self.text(empty)?);
+                }
+                GffFieldValue::CExoLocString(str_ref, strings)
+            },
+            GffFieldType::Void => {
+                let mut data = self.text(empty)?;
+                data.retain(|c| !c.is_ascii_whitespace());
+                GffFieldValue::Void(BASE64.decode(data)
+                    .map_err(|err| self.error(format!("invalid base64 data: {}", err)))?)
+            },
+            GffFieldType::Struct | GffFieldType::List => unreachable!(),
+        })
+    }
+
+    fn read_document(&mut self) -> Result<GffFile, DataError> {
+        let e = match self.next_element()? {
+            Some((e, false)) if e.name().as_ref() == b"gff3" => e,
+            _ => return Err(self.error("expected a gff3 element")),
+        };
+        let four_chars = |value: String| <[u8; 4]>::try_from(value.as_bytes())
+            .map_err(|_| self.error(format!("expected a 4 characters string, found {:?}", value)));
+        let file_type = four_chars(self.required_attribute(&e, "type")?)?;
+        let version = match self.attribute(&e, "version")? {
+            Some(version) => four_chars(version)?,
+            None => GffHeader::default().version,
+        };
+
+        let root = match self.next_element()? {
+            Some((e, empty)) => self.read_struct(&e, empty)?,
+            None => return Err(self.error("expected a struct element")),
+        };
+        if self.next_element()?.is_some() {
+            return Err(self.error("expected the end of the gff3 element"));
+        }
+        Ok(GffFile { file_type, version, root })
+    }
+}
+
+/// Parse XML to a document
+pub fn from_str(s: &str) -> Result<GffFile, DataError> {
+    XmlReader::new(s).read_document()
+}
+
+/* }}} */
+
+#[cfg(test)]
+mod tests {
+    use crate::common::{IndexMap, GffFieldValue};
+    use crate::tests::{round_trip_sample, sample};
+
+    #[test]
+    fn test_01_round_trip_sample() {
+        round_trip_sample(super::to_string, super::from_str);
+    }
+
+    #[test]
+    fn test_02_format() {
+        let mut file = sample();
+        /* characters to escape */
+        file.root.fields.insert(String::from("Comment"),
+            GffFieldValue::CExoString(String::from("<1234>\r\n \"two\" lines ")));
+        let xml = super::to_string(&file);
+        assert_eq!(xml, "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<gff3 type=\"UTC \">
+  <struct id=\"4294967295\">
+    <exostring label=\"Tag\">nw_bandit</exostring>
+    <uint16 label=\"Appearance\">6</uint16>
+    <char label=\"GoodEvil\">-12</char>
+    <uint64 label=\"Experience\">18446744073709551615</uint64>
+    <float label=\"Scale\">0.1</float>
+    <exostring label=\"Comment\">&lt;1234&gt;&#13;
+ &quot;two&quot; lines </exostring>
+    <resref label=\"Conversation\"/>
+    <locstring label=\"FirstName\" strref=\"12345\">
+      <string language=\"3\">Bandite</string>
+      <string language=\"0\">Bandit</string>
+    </locstring>
+    <data label=\"Data\">AAEC/w==</data>
+    <struct label=\"Tint_Hair\" id=\"0\">
+      <byte label=\"a\">255</byte>
+    </struct>
+    <list label=\"VarTable\"/>
+    <list label=\"ItemList\">
+      <struct id=\"0\">
+        <resref label=\"InventoryRes\">nw_wswss001</resref>
+      </struct>
+      <struct id=\"4294967295\"/>
+    </list>
+  </struct>
+</gff3>
+");
+        assert_eq!(super::from_str(&xml).unwrap(), file);
+
+        file.version = *b"V3.3";
+        let xml = super::to_string(&file);
+        assert!(xml.contains("<gff3 type=\"UTC \" version=\"V3.3\">"));
+        assert_eq!(super::from_str(&xml).unwrap().version, *b"V3.3");
+    }
+
+    #[test]
+    fn test_03_lenient_input() {
+        let file = super::from_str("\
+<!-- edited by hand -->
+<gff3 type='UTC '><struct>
+  <exostring label='Tag'><![CDATA[<a>]]></exostring>
+  <data label='Data'>
+    AAEC
+    /w==
+  </data>
+  <list label='ItemList'></list>
+  <locstring label='Name'/>
+</struct></gff3>").unwrap();
+        assert_eq!(file.root.st_type, 0xFFFFFFFF);
+        assert_eq!(file.root.fields["Tag"], GffFieldValue::CExoString(String::from("<a>")));
+        assert_eq!(file.root.fields["Data"], GffFieldValue::Void(vec![0, 1, 2, 255]));
+        assert_eq!(file.root.fields["ItemList"], GffFieldValue::List(vec![]));
+        assert_eq!(file.root.fields["Name"], GffFieldValue::CExoLocString(0xFFFFFFFF, IndexMap::new()));
+    }
+
+    #[test]
+    fn test_04_errors() {
+        let err = super::from_str("<gff3 type=\"UTC \">\n<struct>\n  <string label=\"Tag\">a</string>\n")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 3, column 3: Tag: unknown field type string");
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct>\n<list label=\"List\">\n\
+            <struct><byte label=\"a\">300</byte></struct></list></struct></gff3>").unwrap_err();
+        assert_eq!(err.to_string(), "line 3, column 25: List[0].a: invalid number \"300\"");
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct>\n\
+            <locstring label=\"Name\"><string language=\"300\">a</string></locstring></struct></gff3>")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "line 2, column 25: Name: unknown language 300");
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct><data label=\"Data\">%%</data></struct></gff3>")
+            .unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 1, column 46: Data: invalid base64 data: Invalid symbol 37, offset 0.");
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct>\n\
+            <byte label=\"this_label_is_too_long\">1</byte></struct></gff3>").unwrap_err();
+        assert_eq!(err.to_string(),
+            "line 2, column 1: label this_label_is_too_long is longer than 16 bytes");
+
+        let err = super::from_str("<gff3><struct/></gff3>").unwrap_err();
+        assert_eq!(err.to_string(), "line 1, column 1: missing attribute type");
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct>\n<byte label=\"a\">1</char>").unwrap_err();
+        assert!(err.to_string().starts_with("line 2, column "));
+
+        let err = super::from_str("<gff3 type=\"UTC \"><struct>").unwrap_err();
+        assert_eq!(err.to_string(), "line 1, column 27: unexpected end of document");
+
+        /* the root struct is at depth 1, and each nested struct adds 1 */
+        let nested = |depth: usize| format!("<gff3 type=\"UTC \">\n<struct>{}{}</struct></gff3>",
+            "<struct label=\"a\">".repeat(depth - 1), "</struct>".repeat(depth - 1));
+        assert!(super::from_str(&nested(super::MAX_DEPTH)).is_ok());
+        let err = super::from_str(&nested(super::MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(err.to_string(), format!("line 2, column {}: {}: structs nested too deep",
+            9 + 18 * (super::MAX_DEPTH - 1), vec!["a"; super::MAX_DEPTH].join(".")));
+    }
+}
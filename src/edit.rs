@@ -0,0 +1,120 @@
+//! Field values on the command line, for `gff edit`
+//!
+//! Types use the names of the text formats (`dword`, `cexostring`...).
+//! Values are written as in those formats, except for localized
+//! strings, which take a string reference or a `english/male=Text`
+//! entry.
+
+use std::io::Write;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use gff::common::{IndexMap, GffFieldType, GffFieldValue, GffGender, GffLang, GffStruct, ResRef};
+use gff::text::TextName;
+
+use crate::Result;
+
+/// Field type from its name
+pub fn parse_type(name: &str) -> Result<GffFieldType> {
+    GffFieldType::from_text_name(&name.to_ascii_lowercase()).ok_or_else(|| {
+        let names: Vec<String> = (0u32..16).filter_map(|ty| GffFieldType::try_from(ty).ok())
+            .map(|ty| ty.text_name())
+            .collect();
+        format!("unknown field type {}, expected one of {}", name, names.join(", ")).into()
+    })
+}
+
+/// Field value from its type and text
+///
+/// A localized string entry is added to the `previous` value, if it
+/// is a localized string. Structs take their type id, and keep the
+/// fields of the `previous` value if it is a struct. Lists take no
+/// value.
+pub fn parse_value(ty: GffFieldType, value: Option<&str>, previous: Option<&GffFieldValue>)
+    -> Result<GffFieldValue>
+{
+    let text = match (ty, value) {
+        (GffFieldType::List, None) => return Ok(GffFieldValue::List(vec![])),
+        (GffFieldType::List, Some(_)) => return Err("a list field does not take a value".into()),
+        (GffFieldType::Struct, _) => {
+            let mut st = match previous {
+                Some(GffFieldValue::Struct(st)) => st.clone(),
+                _ => GffStruct { st_type: 0, fields: IndexMap::new() },
+            };
+            if let Some(text) = value {
+                st.st_type = text.parse().map_err(|e| format!("invalid struct id {:?}: {}", text, e))?;
+            }
+            return Ok(GffFieldValue::Struct(st));
+        },
+        (_, Some(text)) => text,
+        (_, None) => return Err(format!("a {} field needs a value", ty.text_name()).into()),
+    };
+    macro_rules! number {
+        ($variant:ident) => {
+            GffFieldValue::$variant(text.parse()
+                .map_err(|e| format!("invalid {} {:?}: {}", ty.text_name(), text, e))?)
+        }
+    }
+    Ok(match ty {
+        GffFieldType::Byte => number!(Byte),
+        GffFieldType::Char => number!(Char),
+        GffFieldType::Word => number!(Word),
+        GffFieldType::Short => number!(Short),
+        GffFieldType::DWord => number!(DWord),
+        GffFieldType::Int => number!(Int),
+        GffFieldType::DWord64 => number!(DWord64),
+        GffFieldType::Int64 => number!(Int64),
+        GffFieldType::Float => number!(Float),
+        GffFieldType::Double => number!(Double),
+        GffFieldType::CExoString => GffFieldValue::CExoString(text.to_string()),
+        GffFieldType::CResRef => GffFieldValue::CResRef(ResRef::new(text)?.into()),
+        GffFieldType::CExoLocString => {
+            let (mut str_ref, mut strings) = match previous {
+                Some(GffFieldValue::CExoLocString(str_ref, strings)) => (*str_ref, strings.clone()),
+                _ => (0xFFFFFFFF, IndexMap::new()),
+            };
+            match text.split_once('=') {
+                Some((key, s)) => {
+                    let lang = <(GffLang, GffGender)>::from_text_name(key)
+                        .ok_or_else(|| format!("unknown language {}", key))?;
+                    strings.insert(lang, s.to_string());
+                },
+                None => str_ref = text.parse()
+                    .map_err(|_| format!("expected a string reference or LANG/GENDER=TEXT, found {:?}", text))?,
+            }
+            GffFieldValue::CExoLocString(str_ref, strings)
+        },
+        GffFieldType::Void => GffFieldValue::Void(BASE64.decode(text)
+            .map_err(|e| format!("invalid base64 data: {}", e))?),
+        GffFieldType::Struct | GffFieldType::List => unreachable!(),
+    })
+}
+
+/// Print a field value, as it would be given to [`parse_value`]
+///
+/// Structs and lists are printed as a tree.
+pub fn print_value(out: &mut impl Write, value: &GffFieldValue) -> Result<()> {
+    match value {
+        GffFieldValue::Byte(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Char(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Word(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Short(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::DWord(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Int(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::DWord64(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Int64(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Float(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::Double(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::CExoString(v) | GffFieldValue::CResRef(v) => writeln!(out, "{}", v)?,
+        GffFieldValue::CExoLocString(str_ref, strings) => {
+            writeln!(out, "{}", str_ref)?;
+            for ((lang, gender), s) in strings {
+                writeln!(out, "{}={}", (*lang, *gender).text_name(), s)?;
+            }
+        },
+        GffFieldValue::Void(v) => writeln!(out, "{}", BASE64.encode(v))?,
+        GffFieldValue::Struct(st) => writeln!(out, "{:#?}", st)?,
+        GffFieldValue::List(structs) => writeln!(out, "{:#?}", structs)?,
+    }
+    Ok(())
+}
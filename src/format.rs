@@ -0,0 +1,125 @@
+//! Reading and writing documents in the supported formats
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use gff::common::{Encodings, GffFile};
+use gff::packer::Packer;
+use gff::parser::GffParser;
+
+use crate::Result;
+
+/// Format of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Packed GFF data
+    Gff,
+    /// JSON, in the layout of neverwinter.nim
+    Json,
+    /// Type-annotated YAML
+    Yaml,
+    /// Type-annotated TOML
+    Toml,
+    /// XML, as used by xoreos-tools
+    Xml,
+}
+
+impl Format {
+    /// Guess the format from a file extension
+    ///
+    /// GFF files use many extensions (`.bic`, `.utc`, `.are`...),
+    /// so anything that is not a known text format is packed data.
+    pub fn from_path(path: &Path) -> Format {
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            Some("toml") => Format::Toml,
+            Some("xml") => Format::Xml,
+            _ => Format::Gff,
+        }
+    }
+}
+
+/// Read a document, in the given format or the one of its extension
+pub fn read(path: &Path, format: Option<Format>) -> Result<GffFile> {
+    let format = format.unwrap_or_else(|| Format::from_path(path));
+    let data = fs::read(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    decode(data, format)
+        .map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Parse a document from raw data
+pub fn decode(data: Vec<u8>, format: Format) -> Result<GffFile> {
+    Ok(match format {
+        Format::Gff => GffParser::parse(data, &*Encodings::NeverwinterNights)?,
+        Format::Json => gff::json::from_str(&String::from_utf8(data)?)?,
+        Format::Yaml => gff::text::yaml::from_str(&String::from_utf8(data)?)?,
+        Format::Toml => gff::text::toml::from_str(&String::from_utf8(data)?)?,
+        Format::Xml => gff::xml::from_str(&String::from_utf8(data)?)?,
+    })
+}
+
+/// Convert a document to raw data
+pub fn encode(file: &GffFile, format: Format) -> Result<Vec<u8>> {
+    Ok(match format {
+        Format::Gff => {
+            let mut packer = Packer::new(Vec::new(), &*Encodings::NeverwinterNights);
+            packer.pack(file)?;
+            packer.writer.into_inner()?
+        },
+        Format::Json => gff::json::to_string(file).into_bytes(),
+        Format::Yaml => gff::text::yaml::to_string(file).into_bytes(),
+        Format::Toml => gff::text::toml::to_string(file).into_bytes(),
+        Format::Xml => gff::xml::to_string(file).into_bytes(),
+    })
+}
+
+/// Write a document to a file, or to the standard output
+///
+/// The file is replaced atomically: the document is written to a
+/// temporary file next to it, then renamed, so that an interrupted
+/// write never leaves a truncated file.
+pub fn write(file: &GffFile, path: Option<&Path>, format: Format) -> Result<()> {
+    let data = encode(file, format)?;
+    match path {
+        Some(path) => replace(path, &data)
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => std::io::stdout().write_all(&data)?,
+    }
+    Ok(())
+}
+
+fn replace(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(name);
+    let written = fs::File::create(&tmp)
+        .and_then(|mut f| f.write_all(data).and_then(|_| f.sync_all()))
+        .and_then(|_| match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(&tmp, metadata.permissions()),
+            Err(_) => Ok(()),
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Name of the packed file for a text file, `creature.utc.json`
+/// giving `creature.utc`
+pub fn packed_path(path: &Path) -> Option<PathBuf> {
+    if Format::from_path(path) == Format::Gff {
+        return None;
+    }
+    let stem = Path::new(path.file_stem()?);
+    stem.extension()?;
+    Some(path.with_file_name(stem))
+}
@@ -0,0 +1,291 @@
+//! `gff` command-line tool
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use gff::common::{IndexMap, GffFile, GffStruct};
+use gff::diff::DiffOptions;
+use gff::error::{LabelPath, PathElement};
+use gff::parser::GffParser;
+
+mod edit;
+mod format;
+
+use format::Format;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Inspect and convert Bioware GFF files
+#[derive(Parser)]
+#[command(name = "gff", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the fields of a document as a tree
+    Dump {
+        file: PathBuf,
+        /// Format of the document, instead of guessing from its extension
+        #[arg(short, long)]
+        format: Option<Format>,
+    },
+    /// Convert a document to another format
+    Convert {
+        input: PathBuf,
+        /// Output file, the standard output if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Format of the input, instead of guessing from its extension
+        #[arg(short, long)]
+        from: Option<Format>,
+        /// Format of the output, instead of guessing from its extension
+        #[arg(short, long, required_unless_present = "output")]
+        to: Option<Format>,
+    },
+    /// Pack a text document to GFF
+    Pack {
+        input: PathBuf,
+        /// Output file, `creature.utc` for `creature.utc.json` if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Format of the input, instead of guessing from its extension
+        #[arg(short, long)]
+        from: Option<Format>,
+    },
+    /// Print the header of a GFF file
+    Info {
+        file: PathBuf,
+    },
+    /// Get or change fields by path, e.g. `ItemList[2].Tag`
+    ///
+    /// The file is changed in place, keeping its format, type and version.
+    Edit {
+        file: PathBuf,
+        /// Write the changed document to this file instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Format of the document, instead of guessing from its extension
+        #[arg(short, long)]
+        format: Option<Format>,
+        #[command(subcommand)]
+        action: EditAction,
+    },
+    /// Print the fields that differ between two documents, by path
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Match the structs of lists by a key field instead of their
+        /// index, e.g. `Tag` for every list or `ItemList=Tag` for one
+        #[arg(short, long = "key", value_name = "[LIST=]FIELD")]
+        keys: Vec<String>,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Merge the changes made to a base document in two others
+    ///
+    /// Fields changed on one side take the changed value, and structs
+    /// and lists changed on both sides are merged. Other fields changed
+    /// on both sides are conflicts: they keep our value, are printed, and
+    /// make the command fail. The result is written to OURS, so that this
+    /// is a git merge driver: `gff merge %O %A %B`.
+    Merge {
+        base: PathBuf,
+        ours: PathBuf,
+        theirs: PathBuf,
+        /// Write the merged document to this file instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Format of the documents, instead of guessing from their extension
+        #[arg(short, long)]
+        format: Option<Format>,
+        /// Match the structs of lists by a key field instead of aligning
+        /// them, e.g. `Tag` for every list or `ItemList=Tag` for one
+        #[arg(short, long = "key", value_name = "[LIST=]FIELD")]
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum EditAction {
+    /// Print a field, or a struct of a list
+    Get {
+        path: LabelPath,
+    },
+    /// Set a field, replacing it if it exists
+    ///
+    /// Localized strings take a string reference, or a `LANG/GENDER=TEXT`
+    /// entry that is added to the existing ones. Structs take their id
+    /// (0 by default) and keep the fields of an existing struct, lists
+    /// no value.
+    Set {
+        path: LabelPath,
+        /// Field type: byte, char, word, short, dword, int, dword64, int64,
+        /// float, double, cexostring, resref, cexolocstring, void, struct, list
+        #[arg(value_name = "TYPE")]
+        field_type: String,
+        value: Option<String>,
+    },
+    /// Delete a field, or a struct of a list
+    Delete {
+        path: LabelPath,
+    },
+    /// Insert an empty struct in a list, e.g. at `ItemList[3]`
+    Insert {
+        path: LabelPath,
+        /// Type id of the struct
+        #[arg(long, default_value_t = 0)]
+        id: u32,
+    },
+}
+
+fn dump(out: &mut impl Write, file: &GffFile) -> Result<()> {
+    writeln!(out, "{} {}", String::from_utf8_lossy(&file.file_type), String::from_utf8_lossy(&file.version))?;
+    writeln!(out, "{:#?}", file.root)?;
+    Ok(())
+}
+
+fn info(out: &mut impl Write, path: &Path) -> Result<()> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let header = GffParser::read_header(&data)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    writeln!(out, "type:    {}", String::from_utf8_lossy(&header.gff_type))?;
+    writeln!(out, "version: {}", String::from_utf8_lossy(&header.version))?;
+    writeln!(out, "size:    {}", data.len())?;
+    writeln!(out)?;
+    writeln!(out, "{:<14} {:>10} {:>10}", "section", "offset", "count")?;
+    for (name, section) in [
+        ("structs", &header.structs),
+        ("fields", &header.fields),
+        ("labels", &header.labels),
+        ("field data", &header.field_data),
+        ("field indices", &header.field_indices),
+        ("list indices", &header.list_indices),
+    ] {
+        writeln!(out, "{:<14} {:>10} {:>10}", name, section.0, section.1)?;
+    }
+    Ok(())
+}
+
+fn edit(file: &mut GffFile, action: EditAction) -> Result<()> {
+    match action {
+        EditAction::Get { path } => {
+            let mut out = std::io::stdout().lock();
+            match path.0.last() {
+                Some(PathElement::Index(_)) => writeln!(out, "{:#?}", file.root.struct_at(&path)?)?,
+                _ => edit::print_value(&mut out, file.root.get_path(&path)?)?,
+            }
+        },
+        EditAction::Set { path, field_type, value } => {
+            let field_type = edit::parse_type(&field_type)?;
+            let value = edit::parse_value(field_type, value.as_deref(), file.root.get_path(&path).ok())
+                .map_err(|e| format!("{}: {}", path, e))?;
+            file.root.set_path(&path, value)?;
+        },
+        EditAction::Delete { path } => {
+            file.root.remove_path(&path)?;
+        },
+        EditAction::Insert { path, id } => {
+            file.root.insert_path(&path, GffStruct { st_type: id, fields: IndexMap::new() })?;
+        },
+    }
+    Ok(())
+}
+
+/// List keys from `--key` arguments
+fn diff_options(keys: Vec<String>) -> DiffOptions {
+    keys.into_iter().fold(DiffOptions::default(), |options, key| match key.split_once('=') {
+        Some((list, field)) => options.key(list, field),
+        None => options.key(gff::diff::ANY_LIST, key),
+    })
+}
+
+fn diff(out: &mut impl Write, old: &GffFile, new: &GffFile, options: &DiffOptions, json: bool) -> Result<()> {
+    let diffs = gff::diff::diff_with(&old.root, &new.root, options);
+    if json {
+        writeln!(out, "{:#}", gff::diff::to_json(&diffs))?;
+    } else {
+        for diff in &diffs {
+            writeln!(out, "{}", diff)?;
+        }
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Dump { file, format } => {
+            dump(&mut std::io::stdout().lock(), &format::read(&file, format)?)?;
+        },
+        Command::Convert { input, output, from, to } => {
+            let file = format::read(&input, from)?;
+            let to = match (to, &output) {
+                (Some(to), _) => to,
+                (None, Some(output)) => Format::from_path(output),
+                (None, None) => unreachable!("required by the argument parser"),
+            };
+            format::write(&file, output.as_deref(), to)?;
+        },
+        Command::Pack { input, output, from } => {
+            let output = output
+                .or_else(|| format::packed_path(&input))
+                .ok_or_else(|| format!("{}: cannot guess the output name, use --output", input.display()))?;
+            let file = format::read(&input, from)?;
+            format::write(&file, Some(&output), Format::Gff)?;
+        },
+        Command::Info { file } => info(&mut std::io::stdout().lock(), &file)?,
+        Command::Edit { file: path, output, format, action } => {
+            let read_only = matches!(action, EditAction::Get { .. });
+            let mut file = format::read(&path, format)?;
+            edit(&mut file, action)?;
+            if !read_only {
+                let output = output.unwrap_or(path);
+                let format = format.unwrap_or_else(|| Format::from_path(&output));
+                format::write(&file, Some(&output), format)?;
+            }
+        },
+        Command::Diff { old, new, keys, json } => {
+            let (old, new) = (format::read(&old, None)?, format::read(&new, None)?);
+            diff(&mut std::io::stdout().lock(), &old, &new, &diff_options(keys), json)?;
+        },
+        Command::Merge { base, ours, theirs, output, format, keys } => {
+            let mut file = format::read(&ours, format)?;
+            let merged = gff::merge::merge_with(
+                &format::read(&base, format)?.root,
+                &file.root,
+                &format::read(&theirs, format)?.root,
+                &diff_options(keys));
+            file.root = merged.result;
+            let output = output.unwrap_or(ours);
+            format::write(&file, Some(&output), format.unwrap_or_else(|| Format::from_path(&output)))?;
+            if !merged.conflicts.is_empty() {
+                for conflict in &merged.conflicts {
+                    eprintln!("conflict: {}", conflict);
+                }
+                let count = merged.conflicts.len();
+                return Err(format!("{}: {} conflict{}, our values were kept", output.display(),
+                    count, if count == 1 { "" } else { "s" }).into());
+            }
+        },
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gff: {}", e);
+            ExitCode::FAILURE
+        },
+    }
+}
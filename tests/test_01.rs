@@ -0,0 +1,204 @@
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Output};
+    use gff::common::{IndexMap, Encodings, GffFieldValue, GffGender, GffLang, GffStruct};
+    use gff::parser::GffParser;
+
+    const SAMPLE: &str = "gff/test-data/test.bic";
+
+    fn gff(args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_gff")).args(args).output().unwrap()
+    }
+
+    fn stdout(output: Output) -> String {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Empty scratch directory for a test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gff-cli-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn parse(path: &Path) -> gff::common::GffFile {
+        GffParser::parse(std::fs::read(path).unwrap(), &*Encodings::NeverwinterNights).unwrap()
+    }
+
+    #[test]
+    fn test_01_info() {
+        let out = stdout(gff(&["info", SAMPLE]));
+        assert_eq!(out, "type:    BIC \n\
+version: V3.2
+size:    201632
+
+section            offset      count
+structs                56       1303
+fields              15692       9098
+labels             124868        274
+field data         129252      30868
+field indices      160120      33956
+list indices       194076       7556
+");
+    }
+
+    #[test]
+    fn test_02_dump() {
+        let out = stdout(gff(&["dump", SAMPLE]));
+        assert!(out.starts_with("BIC  V3.2\nGffStruct (0xffffffff) {\n    Age: Int(\n        120,\n    ),\n"));
+    }
+
+    #[test]
+    fn test_03_convert_and_pack() {
+        let dir = scratch("convert");
+        let sample = parse(Path::new(SAMPLE));
+        for ext in ["json", "yaml", "toml", "xml"] {
+            let text = dir.join(format!("test.bic.{}", ext));
+            stdout(gff(&["convert", SAMPLE, "-o", text.to_str().unwrap()]));
+            stdout(gff(&["pack", text.to_str().unwrap()]));
+            assert_eq!(parse(&dir.join("test.bic")), sample, "{}", ext);
+
+            /* same text on the standard output */
+            let out = stdout(gff(&["convert", SAMPLE, "--to", ext]));
+            assert_eq!(out, std::fs::read_to_string(&text).unwrap());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_04_edit() {
+        let dir = scratch("edit");
+        let file = dir.join("test.bic");
+        std::fs::copy(SAMPLE, &file).unwrap();
+        let file = file.to_str().unwrap();
+        let get = |path: &str| stdout(gff(&["edit", file, "get", path]));
+
+        assert_eq!(get("Gold"), "27826\n");
+        assert_eq!(get("ItemList[0].Tag"), "si_marin1\n");
+        assert_eq!(get("FirstName"), "4294967295\nenglish/male=Exalia\n");
+
+        stdout(gff(&["edit", file, "set", "Gold", "dword", "0"]));
+        stdout(gff(&["edit", file, "set", "Deity", "cexostring", "Tyr"]));
+        stdout(gff(&["edit", file, "set", "FirstName", "cexolocstring", "french/female=Exalie"]));
+        stdout(gff(&["edit", file, "set", "ItemList[0].Stolen", "byte", "1"]));
+        stdout(gff(&["edit", file, "set", "CombatInfo", "struct", "3"]));
+        stdout(gff(&["edit", file, "insert", "ItemList[0]", "--id", "7"]));
+        stdout(gff(&["edit", file, "set", "ItemList[0].Tag", "cexostring", "new"]));
+        stdout(gff(&["edit", file, "delete", "ClassList[0]"]));
+
+        let mut expected = parse(Path::new(SAMPLE));
+        let root = &mut expected.root;
+        root.set_path(&"Gold".parse().unwrap(), GffFieldValue::DWord(0)).unwrap();
+        root.set_path(&"Deity".parse().unwrap(), GffFieldValue::CExoString(String::from("Tyr"))).unwrap();
+        if let Ok(GffFieldValue::CExoLocString(_, strings)) = root.get_path_mut(&"FirstName".parse().unwrap()) {
+            strings.insert((GffLang::French, GffGender::Female), String::from("Exalie"));
+        }
+        root.set_path(&"ItemList[0].Stolen".parse().unwrap(), GffFieldValue::Byte(1)).unwrap();
+        /* setting a struct only changes its id */
+        root.struct_at_mut(&"CombatInfo".parse().unwrap()).unwrap().st_type = 3;
+        root.insert_path(&"ItemList[0]".parse().unwrap(), GffStruct {
+            st_type: 7,
+            fields: IndexMap::from([(String::from("Tag"), GffFieldValue::CExoString(String::from("new")))]),
+        }).unwrap();
+        root.remove_path(&"ClassList[0]".parse().unwrap()).unwrap();
+        assert_eq!(parse(Path::new(file)), expected);
+
+        /* text documents keep their format */
+        let yaml = dir.join("test.bic.yaml");
+        stdout(gff(&["convert", file, "-o", yaml.to_str().unwrap()]));
+        stdout(gff(&["edit", yaml.to_str().unwrap(), "set", "Gold", "dword", "5"]));
+        assert!(std::fs::read_to_string(&yaml).unwrap().contains("\nGold: !dword 5\n"));
+        /* files are replaced through a temporary file */
+        let mut names: Vec<_> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["test.bic", "test.bic.yaml"]);
+
+        let output = gff(&["edit", file, "set", "Gold", "word", "x"]);
+        assert_eq!(String::from_utf8_lossy(&output.stderr),
+            "gff: Gold: invalid word \"x\": invalid digit found in string\n");
+        let output = gff(&["edit", file, "get", "ItemList[99].Tag"]);
+        assert_eq!(String::from_utf8_lossy(&output.stderr),
+            "gff: ItemList[99]: index 99 out of range for a list of 76 structs\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_05_diff() {
+        let dir = scratch("diff");
+        let file = dir.join("test.bic.json");
+        stdout(gff(&["convert", SAMPLE, "-o", file.to_str().unwrap()]));
+        let file = file.to_str().unwrap();
+        assert_eq!(stdout(gff(&["diff", SAMPLE, file])), "");
+
+        stdout(gff(&["edit", file, "set", "Gold", "int", "0"]));
+        stdout(gff(&["edit", file, "set", "ItemList[1].Stolen", "byte", "1"]));
+        stdout(gff(&["edit", file, "delete", "ItemList[0]"]));
+        let out = stdout(gff(&["diff", SAMPLE, file, "--key", "ItemList=ObjectId"]));
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "! Gold: DWord(27826) -> Int(0)");
+        assert_eq!(lines[1], "~ ItemList[1].Stolen: Byte(0) -> Byte(1)");
+        assert!(lines[2].starts_with("- ItemList[0]: Struct(0x0, "));
+        assert_eq!(lines.len(), 3);
+
+        let out = stdout(gff(&["diff", SAMPLE, file, "-k", "ObjectId", "--json"]));
+        assert!(out.starts_with("[\n  {\n    \"path\": \"Gold\",\n    \"change\": \"type_changed\",\n"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_06_merge() {
+        let dir = scratch("merge");
+        /* git gives temporary files without extension */
+        let [base, ours, theirs] = ["base", "ours", "theirs"].map(|name| {
+            let file = dir.join(name);
+            std::fs::copy(SAMPLE, &file).unwrap();
+            file.to_str().unwrap().to_string()
+        });
+        stdout(gff(&["edit", &ours, "set", "Gold", "dword", "0"]));
+        stdout(gff(&["edit", &ours, "delete", "ItemList[0]"]));
+        stdout(gff(&["edit", &theirs, "set", "ItemList[1].Stolen", "byte", "1"]));
+        stdout(gff(&["edit", &theirs, "set", "Deity", "cexostring", "Tyr"]));
+        /* without a key, the removed struct does not shift their change */
+        stdout(gff(&["merge", &base, &ours, &theirs]));
+
+        let mut expected = parse(Path::new(SAMPLE));
+        let root = &mut expected.root;
+        root.set_path(&"Gold".parse().unwrap(), GffFieldValue::DWord(0)).unwrap();
+        root.set_path(&"ItemList[1].Stolen".parse().unwrap(), GffFieldValue::Byte(1)).unwrap();
+        root.remove_path(&"ItemList[0]".parse().unwrap()).unwrap();
+        root.set_path(&"Deity".parse().unwrap(), GffFieldValue::CExoString(String::from("Tyr"))).unwrap();
+        assert_eq!(parse(Path::new(&ours)), expected);
+
+        /* conflicts keep our values and fail */
+        stdout(gff(&["edit", &theirs, "set", "Gold", "dword", "5"]));
+        let output = dir.join("merged");
+        let output = gff(&["merge", &base, &ours, &theirs, "-k", "ObjectId", "-o", output.to_str().unwrap()]);
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr), format!("\
+conflict: Gold: ours DWord(0), theirs DWord(5), base DWord(27826)
+gff: {}: 1 conflict, our values were kept
+", dir.join("merged").display()));
+        assert_eq!(parse(&dir.join("merged")), expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_07_errors() {
+        let output = gff(&["pack", "missing.json"]);
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stderr),
+            "gff: missing.json: cannot guess the output name, use --output\n");
+
+        let output = gff(&["info", "Cargo.toml"]);
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("gff: Cargo.toml: "));
+
+        let output = gff(&["convert", SAMPLE]);
+        assert!(!output.status.success());
+    }
+}